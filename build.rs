@@ -5,6 +5,10 @@ use std::path::Path;
 #[path = "src/constants.rs"]
 mod constants;
 
+#[allow(dead_code)]
+#[path = "src/units.rs"]
+mod units;
+
 #[allow(dead_code)]
 #[path = "src/cli/zk.rs"]
 mod zk;
@@ -77,6 +81,18 @@ fn main() -> std::io::Result<()> {
     man_core.render(&mut buffer)?;
     fs::write(out_dir.join("0k-core.1"), buffer)?;
 
+    // Embed the commit for `version --json` (skipped outside a git checkout)
+    if let Ok(out) = std::process::Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output()
+        && out.status.success()
+    {
+        println!("cargo:rustc-env=ZK_GIT_HASH={}", String::from_utf8_lossy(&out.stdout).trim());
+        for git_file in [".git/HEAD", ".git/index"] {
+            if Path::new(git_file).exists() {
+                println!("cargo:rerun-if-changed={}", git_file);
+            }
+        }
+    }
+
     println!("cargo:rerun-if-changed=src/cli/zk.rs");
     println!("cargo:rerun-if-changed=src/cli/core.rs");
     println!("cargo:rerun-if-changed=src/constants.rs");
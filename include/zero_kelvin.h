@@ -0,0 +1,135 @@
+#ifndef ZERO_KELVIN_H
+#define ZERO_KELVIN_H
+
+/*
+ * C ABI of libzero_kelvin.so (src/ffi.rs), kept in step with it by hand; a unit test in
+ * src/ffi.rs checks that every function and code below is declared here.
+ *
+ * Build the library with:
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Every operation takes its options as one UTF-8 JSON object and, on success, hands back
+ * the versioned report `0k ... --json-events` ends with ("schema_version", "operation", then
+ * the fields of that operation). The caller keeps `options_json`; `*report_json` belongs to
+ * the caller and is released with zk_free_report (never free). On error `*report_json` is
+ * NULL and zk_last_error_message describes the error.
+ *
+ * Unknown keys are rejected, and so are keys the matching `0k` command does not take
+ * together (e.g. "overwrite" with "skip_existing"). Every key is optional unless marked
+ * required; booleans default to false, lists to empty, the others to unset. Keys are only
+ * ever added.
+ */
+
+#include <stdint.h>
+
+/* The operation succeeded and `*report_json` holds its report. */
+#define ZK_OK 0
+
+/* A NULL pointer, options that are not UTF-8, or JSON that does not match the options. */
+#define ZK_ERR_INVALID_ARGUMENT 1
+
+/* The operation failed; see zk_last_error_message. */
+#define ZK_ERR_FAILED 2
+
+/* The operation needs root (or read access the caller lacks); rerun it elevated. */
+#define ZK_ERR_PERMISSION_DENIED 3
+
+/* Tools the operation runs (mksquashfs, squashfuse, ...) are not installed. */
+#define ZK_ERR_MISSING_DEPENDENCIES 4
+
+/* A bug: the library panicked. The message is the panic's. */
+#define ZK_ERR_PANIC 5
+
+/*
+ * Freezes "targets" into "output" (`0k freeze`).
+ *
+ *   "targets"                 array of paths, required
+ *   "output"                  path, required
+ *   "encrypt"                 bool     --encrypt
+ *   "overwrite_files"         bool     --overwrite-files
+ *   "overwrite_luks_content"  bool     --overwrite-luks-content
+ *   "compression"             integer  --compression (zstd level)
+ *   "dereference"             bool     --dereference
+ *   "exclude"                 array of globs, --exclude
+ *   "no_ignore_files"         bool     --no-ignore-files
+ *   "skip_unreadable"         bool     --skip-unreadable
+ *   "no_xattrs"               bool     --no-xattrs
+ *   "no_space_check"          bool     --no-space-check
+ *   "reserve"                 string   --reserve (e.g. "5%" or "2G")
+ *   "yes"                     bool     --yes
+ *   "checksums"               bool     --checksums
+ *   "mode"                    integer  --mode (permission bits of the archive, e.g. 384)
+ *   "threads"                 integer  --threads
+ *   "integrity_token"         bool     --integrity-token, needs "encrypt"
+ *   "preserve_order"          bool     --preserve-order
+ *
+ * Safety: `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or
+ * writable.
+ */
+int zk_freeze(const char *options_json, char **report_json);
+
+/*
+ * Compares "archive" with the live files (`0k check`).
+ *
+ *   "archive"             path, required
+ *   "use_cmp"             bool     --use-cmp
+ *   "delete"              bool     --delete
+ *   "force_delete"        bool     --force-delete, needs "delete"
+ *   "prune_dirs"          bool     --prune-dirs, needs "delete"
+ *   "show_extra"          bool     --show-extra
+ *   "exclude"             array of globs, --exclude
+ *   "quick"               bool     --quick, not with "use_cmp" or "delete"
+ *   "checksums"           bool     --checksums, not with "quick"
+ *   "no_manifest_target"  path     --no-manifest --target, not with "quick",
+ *                                  "checksums" or "remap"
+ *   "remap"               array of "OLD=NEW" strings, --remap
+ *   "report"              true or path, --report or --report=PATH
+ *
+ * Safety: `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or
+ * writable.
+ */
+int zk_check(const char *options_json, char **report_json);
+
+/*
+ * Restores "archive" (`0k unfreeze`).
+ *
+ *   "archive"               path, required
+ *   "overwrite"             bool     --overwrite
+ *   "skip_existing"         bool     --skip-existing, not with "overwrite"
+ *   "force_unfreeze"        bool     --force-unfreeze
+ *   "verify"                bool     --verify
+ *   "follow_dest_symlinks"  bool     --follow-dest-symlinks
+ *   "no_times"              bool     --no-times
+ *   "no_xattrs"             bool     --no-xattrs
+ *   "no_restorecon"         bool     --no-restorecon
+ *   "parent_mode"           integer  --parent-mode (permission bits, e.g. 448)
+ *   "no_manifest_target"    path     --no-manifest --target, not with "verify",
+ *                                    "resume", "restart", "allow_fs_change",
+ *                                    "target_dir", "only", "remap" or "dry_run"
+ *   "resume"                bool     --resume, not with "restart"
+ *   "restart"               bool     --restart
+ *   "allow_fs_change"       bool     --allow-fs-change
+ *   "target_dir"            path     --target-dir
+ *   "only"                  array of ids or names, --only
+ *   "remap"                 array of "OLD=NEW" strings, --remap
+ *   "dry_run"               bool     --dry-run, not with "resume" or "restart"
+ *
+ * Safety: `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or
+ * writable.
+ */
+int zk_unfreeze(const char *options_json, char **report_json);
+
+/*
+ * Releases a report returned by zk_freeze, zk_check or zk_unfreeze. NULL is ignored.
+ *
+ * Safety: `report_json` is NULL or a report from this library that was not freed yet.
+ */
+void zk_free_report(char *report_json);
+
+/*
+ * The error of the last failed zk_* call on this thread, or NULL after a success. The
+ * string belongs to the library and stays valid until the thread's next zk_* call.
+ */
+const char *zk_last_error_message(void);
+
+#endif /* ZERO_KELVIN_H */
@@ -3,19 +3,27 @@ use zero_kelvin::error::ZkError;
 
 use zero_kelvin::cli::core::{Args, Commands};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, OnceLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use rand::Rng;
 use zero_kelvin::constants::{
-    ALLOWED_ROOT_CMDS, LUKS_HEADER_SIZE, LUKS_SAFETY_BUFFER,
-    LUKS_MAPPER_PREFIX, PROC_SCAN_LIMIT,
+    ALLOWED_ROOT_CMDS, DEFAULT_ARCHIVE_MODE, LUKS_HEADER_SIZE, LUKS_PASSPHRASE_ATTEMPTS, LUKS_SAFETY_BUFFER,
+    LUKS_UNCOMPRESSED_OVERHEAD_PERCENT,
+    LUKS_MAPPER_PREFIX, UMOUNT_RETRY_ATTEMPTS, UMOUNT_RETRY_DELAY_MS,
+    EXIT_CODE_BUSY, MAPPER_BASENAME_MAX_LEN, MOUNT_POINT_LISTING_LIMIT,
 };
 use zero_kelvin::executor::{CommandExecutor, RealSystem};
+use zero_kelvin::luks;
+use zero_kelvin::luks_token;
+use zero_kelvin::overwrite::{Action, OutputKind, OverwritePolicy};
+use zero_kelvin::passphrase;
+use zero_kelvin::space::{self, Reserve, SpaceProbe};
+use zero_kelvin::squashfs_info::{self, SquashfsInfo};
+use zero_kelvin::trim_journal;
+use zero_kelvin::utils::suggest_command;
 
 /// Global path for cleanup on interrupt (SIGINT/SIGTERM)
 /// Used by ctrlc handler to remove incomplete output files
@@ -47,14 +55,7 @@ fn load_root_cmd_config() -> Option<RootCmdConfig> {
     use std::os::unix::fs::MetadataExt;
     use std::os::unix::fs::PermissionsExt;
 
-    let config_dir = env::var("XDG_CONFIG_HOME")
-        .ok()
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| {
-            let home = env::var("HOME").unwrap_or_default();
-            format!("{}/.config", home)
-        });
-    let config_path = PathBuf::from(config_dir).join("0k/allowed_root_cmds.yaml");
+    let config_path = zero_kelvin::config::config_dir().join("allowed_root_cmds.yaml");
 
     if !config_path.exists() {
         return None;
@@ -258,16 +259,19 @@ fn cleanup_on_interrupt() {
         None
     };
 
-    // 1. Close mapper if exists (must happen BEFORE file removal)
-    if let Some(mapper) = mapper_name {
-        eprintln!("\nInterrupted! Closing LUKS mapper: {}", mapper);
-        
-        // Critical: Kill child processes (mksquashfs) which might be holding the device open
+    // 0. Kill child processes (mksquashfs): they might be holding the device open, and unlike
+    //    Ctrl+C a SIGTERM reaches only this process, so they would keep writing otherwise
+    if mapper_name.is_some() || file_path.is_some() {
         let my_pid = process::id();
         let _ = process::Command::new("pkill")
             .arg("-P")
             .arg(my_pid.to_string())
             .status();
+    }
+
+    // 1. Close mapper if exists (must happen BEFORE file removal)
+    if let Some(mapper) = mapper_name {
+        eprintln!("\nInterrupted! Closing LUKS mapper: {}", mapper);
 
         // Give a moment for the kernel/device release
         std::thread::sleep(Duration::from_millis(200));
@@ -292,22 +296,58 @@ fn cleanup_on_interrupt() {
         }
     }
 
-    // 2. Remove file
+    // 2. Remove file, after detaching loop devices still backed by it (left by a cryptsetup
+    //    open that was killed halfway, or a close that failed above)
     if let Some(path) = file_path {
         if path.exists() {
+            detach_loop_devices(&path);
             eprintln!("Interrupted! Cleaning up file: {:?}", path);
-            if let Err(e) = fs::remove_file(&path) {
+            // The main thread's transaction may have removed it meanwhile
+            if let Err(e) = fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
                 eprintln!("Error cleaning up file {:?}: {}", path, e);
             }
         }
     }
 }
 
+/// Detaches the loop devices backed by `path` (`losetup -j`, then `losetup -d` as root).
+/// Plain archives have none, so nothing is escalated for them.
+fn detach_loop_devices(path: &Path) {
+    let Ok(output) = process::Command::new("losetup").arg("-j").arg(path).output() else {
+        return;
+    };
+    let devices = loop_devices(&String::from_utf8_lossy(&output.stdout));
+    if devices.is_empty() {
+        return;
+    }
+    let root_cmds = get_effective_root_cmd();
+    for device in devices {
+        eprintln!("Interrupted! Detaching loop device: {}", device);
+        let mut args = root_cmds.clone();
+        args.extend(["losetup".to_string(), "-d".to_string(), device]);
+        let prog = args.remove(0);
+        let _ = process::Command::new(prog).args(args).status();
+    }
+}
+
+/// The devices in `losetup -j` output (`/dev/loop3: [2049]:1311 (/path/out.sqfs)` per line).
+fn loop_devices(losetup_output: &str) -> Vec<String> {
+    losetup_output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(device, _)| device.trim().to_string())
+        .filter(|device| device.starts_with("/dev/loop"))
+        .collect()
+}
 
 #[derive(Debug, PartialEq)]
 enum CompressionMode {
     None,
     Zstd(u32),
+    /// Default gzip level: the fallback for kernels built without zstd SquashFS
+    Gzip,
 }
 
 impl CompressionMode {
@@ -331,17 +371,68 @@ impl CompressionMode {
                 args.push("-Xcompression-level".to_string());
                 args.push(level.to_string());
             }
+            Self::Gzip => {
+                args.push("-comp".to_string());
+                args.push("gzip".to_string());
+            }
         }
     }
 
-    fn get_tar2sqfs_compressor_flag(&self) -> Result<String, ZkError> {
+    fn get_tar2sqfs_compressor_args(&self) -> Result<[&'static str; 2], ZkError> {
         match self {
             Self::None => Err(ZkError::CompressionError("Archive repacking does not support uncompressed mode (tar2sqfs limitation)".to_string())),
-            Self::Zstd(_) => Ok("-c zstd".to_string()),
+            Self::Zstd(_) => Ok(["-c", "zstd"]),
+            Self::Gzip => Ok(["-c", "gzip"]),
         }
     }
 }
 
+/// Compression of a container the kernel will mount: zstd stays unless the kernel is known
+/// to lack it (`CONFIG_SQUASHFS_ZSTD`), then it is gzip with `--auto-fallback-compression`,
+/// else a warning.
+fn kernel_compression(comp_mode: CompressionMode, zstd_supported: Option<bool>, auto_fallback: bool) -> CompressionMode {
+    if !matches!(comp_mode, CompressionMode::Zstd(_)) || zstd_supported != Some(false) {
+        return comp_mode;
+    }
+    if auto_fallback {
+        eprintln!("Note: this kernel cannot mount zstd SquashFS, compressing with gzip (--auto-fallback-compression).");
+        return CompressionMode::Gzip;
+    }
+    eprintln!("Warning: this kernel was built without zstd SquashFS (CONFIG_SQUASHFS_ZSTD): it will not");
+    eprintln!("         mount this container. Use -c 0, or --auto-fallback-compression for gzip.");
+    comp_mode
+}
+
+/// mksquashfs flag for extended attributes (SELinux labels, ACLs): stored unless opted out.
+fn mksquashfs_xattrs_flag(no_xattrs: bool) -> &'static str {
+    if no_xattrs { "-no-xattrs" } else { "-xattrs" }
+}
+
+
+/// mksquashfs `-mem` argument (whole MiB; mksquashfs reads the suffix).
+fn mksquashfs_mem_arg(bytes: u64) -> String {
+    format!("{}M", (bytes >> 20).max(1))
+}
+
+/// `--mem` if given, else the cap for small machines (see [`zero_kelvin::utils::default_mksquashfs_mem`]).
+fn resolve_mksquashfs_mem(flag: Option<u64>) -> Result<Option<u64>, ZkError> {
+    if let Some(bytes) = flag {
+        log::info!("mksquashfs memory: {} (--mem)", mksquashfs_mem_arg(bytes));
+        return Ok(Some(bytes));
+    }
+    let Some(meminfo) = zero_kelvin::utils::read_meminfo() else {
+        return Ok(None);
+    };
+    let mem = zero_kelvin::utils::default_mksquashfs_mem(meminfo);
+    if let Some(bytes) = mem {
+        eprintln!(
+            "Note: less than 4 GiB of RAM, limiting mksquashfs to {} (25% of available memory; see --mem).",
+            mksquashfs_mem_arg(bytes)
+        );
+        log::info!("mksquashfs memory: {} (auto)", mksquashfs_mem_arg(bytes));
+    }
+    Ok(mem)
+}
 
 /// Helper to ensure LUKS resources are cleaned up on failure (RAII)
 struct LuksTransaction<'a, E: CommandExecutor + ?Sized> {
@@ -349,6 +440,8 @@ struct LuksTransaction<'a, E: CommandExecutor + ?Sized> {
     mapper_name: Option<String>,
     output_path: &'a PathBuf,
     success: bool,
+    /// The container is being created by us (removed on failure or Ctrl+C)
+    owns_output: bool,
 }
 
 impl<'a, E: CommandExecutor + ?Sized> LuksTransaction<'a, E> {
@@ -360,6 +453,19 @@ impl<'a, E: CommandExecutor + ?Sized> LuksTransaction<'a, E> {
             mapper_name: None,
             output_path,
             success: false,
+            owns_output: true,
+        }
+    }
+
+    /// For an existing container that is only read or updated in place: the mapper is closed
+    /// on drop, the container itself is never removed.
+    fn for_existing(executor: &'a E, image: &'a PathBuf) -> Self {
+        Self {
+            executor,
+            mapper_name: None,
+            output_path: image,
+            success: true,
+            owns_output: false,
         }
     }
 
@@ -377,7 +483,9 @@ impl<'a, E: CommandExecutor + ?Sized> LuksTransaction<'a, E> {
 impl<'a, E: CommandExecutor + ?Sized> Drop for LuksTransaction<'a, E> {
     fn drop(&mut self) {
         // Clear global cleanup registration
-        clear_cleanup_path();
+        if self.owns_output {
+            clear_cleanup_path();
+        }
         clear_cleanup_mapper();
 
         if let Some(mapper) = &self.mapper_name {
@@ -430,7 +538,7 @@ impl<'a, E: CommandExecutor + ?Sized> Drop for LuksTransaction<'a, E> {
             }
         }
         
-        if !self.success {
+        if !self.success && self.owns_output {
              // Remove the file if we failed
              if self.output_path.exists() {
                  let _ = fs::remove_file(self.output_path);
@@ -439,11 +547,57 @@ impl<'a, E: CommandExecutor + ?Sized> Drop for LuksTransaction<'a, E> {
     }
 }
 
+/// Recovery files mksquashfs writes while appending (`squashfs_recovery_<archive name>_<pid>`):
+/// in $HOME since mksquashfs 4.4, in the working directory before. Those already there when
+/// watching starts belong to other runs and are never touched.
+struct RecoveryFiles {
+    prefix: String,
+    dirs: Vec<PathBuf>,
+    before: Vec<PathBuf>,
+}
+
+impl RecoveryFiles {
+    fn watch(archive: &Path) -> Self {
+        let dirs = std::env::var_os("HOME").map(PathBuf::from).into_iter().chain(std::env::current_dir().ok());
+        Self::watch_in(archive, dirs.collect())
+    }
+
+    fn watch_in(archive: &Path, dirs: Vec<PathBuf>) -> Self {
+        let name = archive.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut files = RecoveryFiles { prefix: format!("squashfs_recovery_{}_", name), dirs, before: Vec::new() };
+        files.before = files.matching();
+        files
+    }
+
+    fn matching(&self) -> Vec<PathBuf> {
+        let mut found: Vec<PathBuf> = self
+            .dirs
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&self.prefix))
+            .map(|e| e.path())
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// The recovery files written since [`RecoveryFiles::watch`]
+    fn new_files(&self) -> Vec<PathBuf> {
+        self.matching().into_iter().filter(|p| !self.before.contains(p)).collect()
+    }
+}
+
 /// Helper to ensure output files are cleaned up on failure or interruption (RAII)
-/// Used for plain (non-LUKS) archive creation
+/// Used for plain (non-LUKS) archive creation. An archive being appended to is never removed:
+/// it is the user's data, and a failed append can be undone with its recovery file.
 struct CreateTransaction {
     output_path: PathBuf,
     success: bool,
+    appending: bool,
+    recovery: Option<RecoveryFiles>,
 }
 
 impl CreateTransaction {
@@ -453,6 +607,19 @@ impl CreateTransaction {
         Self {
             output_path,
             success: false,
+            appending: false,
+            recovery: None,
+        }
+    }
+
+    /// Appending to an existing archive; `recovery`: the mksquashfs recovery files to clean
+    /// up on success (None with -no-recovery)
+    fn append(output_path: PathBuf, recovery: Option<RecoveryFiles>) -> Self {
+        Self {
+            output_path,
+            success: false,
+            appending: true,
+            recovery,
         }
     }
 
@@ -465,8 +632,22 @@ impl Drop for CreateTransaction {
     fn drop(&mut self) {
         // Clear the global cleanup path first
         clear_cleanup_path();
-        
-        if !self.success {
+
+        let recovery_files = self.recovery.as_ref().map(RecoveryFiles::new_files).unwrap_or_default();
+        if self.success {
+            // mksquashfs removes its recovery file itself; one left behind is of no use now
+            for file in &recovery_files {
+                let _ = fs::remove_file(file);
+            }
+        } else if self.appending {
+            eprintln!("\nAppend to {:?} failed; the archive may be incomplete.", self.output_path);
+            for file in &recovery_files {
+                eprintln!(
+                    "To restore it as it was before, run: {}",
+                    suggest_command(&["mksquashfs".as_ref(), "-recover".as_ref(), file.as_os_str(), self.output_path.as_os_str()])
+                );
+            }
+        } else {
             // Remove the incomplete file if we failed
             if self.output_path.exists() {
                 eprintln!("\nCleaning up incomplete file: {:?}", self.output_path);
@@ -476,6 +657,42 @@ impl Drop for CreateTransaction {
     }
 }
 
+/// Packing log requested via --log-file / --debug-log.
+/// Removed on drop once packing succeeded, unless --keep-log was passed.
+struct PackingLog {
+    path: PathBuf,
+    keep: bool,
+    success: bool,
+}
+
+impl PackingLog {
+    fn new(path: PathBuf, keep: bool) -> Self {
+        Self { path, keep, success: false }
+    }
+
+    fn set_success(&mut self) {
+        self.success = true;
+    }
+
+    /// Suffix appended to failure errors so the user knows where the full output went
+    fn hint(&self) -> String {
+        format!("\nFull packing log: {}", self.path.display())
+    }
+}
+
+impl Drop for PackingLog {
+    fn drop(&mut self) {
+        if self.success && !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Returns the packing log hint (or an empty string) for failure messages
+fn log_hint(log: &Option<PackingLog>) -> String {
+    log.as_ref().map(|l| l.hint()).unwrap_or_default()
+}
+
 fn get_fs_overhead_percentage(path: &PathBuf, executor: &impl CommandExecutor) -> u32 {
     // stat -f -c %T <path>
     // Output:
@@ -506,6 +723,17 @@ fn get_fs_overhead_percentage(path: &PathBuf, executor: &impl CommandExecutor) -
 }
 
 
+/// Size of a new LUKS container for `raw_size` bytes of input: input plus `overhead_percent`,
+/// the LUKS header and a safety buffer, aligned to 1 MiB so the loop device covers exactly
+/// this size (partial sectors could be dropped by the kernel).
+fn luks_container_size(raw_size: u64, overhead_percent: u32) -> u64 {
+    let overhead_bytes = raw_size / 100 * u64::from(overhead_percent)
+        + raw_size % 100 * u64::from(overhead_percent) / 100;
+    let unaligned_size = raw_size + overhead_bytes + LUKS_HEADER_SIZE + LUKS_SAFETY_BUFFER;
+    let align_size = 1024 * 1024;
+    unaligned_size.div_ceil(align_size) * align_size
+}
+
 fn main() -> std::process::ExitCode {
     let result = run_app();
 
@@ -520,7 +748,14 @@ fn main() -> std::process::ExitCode {
             // Already printed by clap — just propagate exit code
             std::process::ExitCode::from(code)
         }
+        Err(e @ ZkError::Busy(_)) => {
+            eprintln!("Error: {}", e);
+            std::process::ExitCode::from(EXIT_CODE_BUSY)
+        }
         Err(e) => {
+            if let Some(friendly) = e.friendly_message() {
+                eprintln!("Suggestion: {}", friendly);
+            }
             eprintln!("Error: {}", e);
             std::process::ExitCode::FAILURE
         }
@@ -533,7 +768,7 @@ fn run_app() -> Result<(), ZkError> {
     }
     env_logger::init();
 
-    // Set up Ctrl+C handler for cleanup.
+    // Set up the Ctrl+C and SIGTERM (systemd, timeout) handler for cleanup.
     // We set the INTERRUPTED flag instead of calling process::exit() so that RAII destructors
     // (LuksTransaction, CreateTransaction, etc.) run properly when the main thread unwinds.
     ctrlc::set_handler(|| {
@@ -551,6 +786,9 @@ fn run_app() -> Result<(), ZkError> {
          println!();
          return Ok(());
     }
+    if zero_kelvin::version::is_json_version_request(&args_raw) {
+        return zero_kelvin::version::print_json("0k-core");
+    }
 
     // Use try_parse_from to catch --help and handle it with build_command if necessary
     // Actually, clap's FromArgMatches trait allows us to map matches back to the struct.
@@ -612,28 +850,107 @@ fn run_app() -> Result<(), ZkError> {
 
 
 
-/// Generate mapper name from image basename (sanitized).
-/// Checks /dev/mapper for collisions and appends a numeric suffix if needed.
-/// Generate a sanitized mapper name from the image filename.
-/// Returns the base name (e.g., `sq_backup_sqfs`). Does NOT check /dev/mapper/ for collisions.
-/// Collision handling is done atomically at the `cryptsetup open` call site.
+/// Generate the base mapper name for an image: sanitized basename plus a short hash
+/// of the canonical image path (e.g., `zrklvbackup_sqfs_1a2b3c4d`), so identically
+/// named images in different directories never compete for the same name.
+/// Does NOT check /dev/mapper/ for collisions: that is done at the `cryptsetup open` call site.
 fn generate_mapper_name(image_path: &PathBuf) -> String {
     let basename = image_path
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("unknown");
 
-    // Sanitize: replace dots with underscores, keep alphanumeric and underscore
-    let sanitized: String = basename
-        .chars()
-        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
-        .collect();
+    // Sanitize: replace dots with underscores, keep alphanumeric and underscore.
+    // Truncated so the full name stays well below the 127-byte device-mapper limit.
+    let sanitized = zero_kelvin::utils::sanitize_name_part(basename, &[], MAPPER_BASENAME_MAX_LEN);
+
+    let canonical = fs::canonicalize(image_path).unwrap_or_else(|_| image_path.clone());
+    format!(
+        "{}{}_{}",
+        LUKS_MAPPER_PREFIX,
+        sanitized,
+        zero_kelvin::utils::short_path_hash(&canonical)
+    )
+}
+
+/// A mapper for the image left open by a crashed run keeps the container busy, so it
+/// cannot be opened again: closes it, unless something is still mounted from it.
+fn release_stale_mapper(
+    executor: &impl CommandExecutor,
+    root_cmd: &[String],
+    mapper_name: &str,
+    mounts: &[zero_kelvin::mounts::MountInfo],
+) -> Result<(), ZkError> {
+    let in_use = mounts.iter().find(|m| {
+        matches!(&m.backend, zero_kelvin::mounts::MountBackend::LuksMapper(name) if name == mapper_name)
+    });
+    if let Some(m) = in_use {
+        return Err(ZkError::LuksError(format!(
+            "Mapper {} for this container is in use: mounted at {}. Unmount it first: {}",
+            mapper_name,
+            m.mount_point.display(),
+            suggest_command(&["0k-core".as_ref(), "umount".as_ref(), m.mount_point.as_os_str()])
+        )));
+    }
+
+    println!("Closing stale mapper {} left by an earlier run...", mapper_name);
+    let mut close_args = root_cmd.to_vec();
+    close_args.extend(["cryptsetup".to_string(), "close".to_string(), mapper_name.to_string()]);
+    let prog = close_args.remove(0);
+    let args_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
+    let (status, stderr) = executor.run_and_capture_error(&prog, &args_refs).map_err(ZkError::IoError)?;
+    if !status.success() {
+        return Err(ZkError::LuksError(format!(
+            "Failed to close stale mapper {}: {}",
+            mapper_name,
+            stderr.trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Candidate mapper names in the order they are tried: the base name, `_2` ... `_10`,
+/// and finally a timestamp + random suffix (virtually unique).
+fn mapper_name_candidates(base_mapper_name: &str) -> Vec<String> {
+    let mut v: Vec<String> = Vec::with_capacity(11);
+    v.push(base_mapper_name.to_string());
+    for i in 2..=10 {
+        v.push(format!("{}_{}", base_mapper_name, i));
+    }
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let rnd: u32 = rand::Rng::random_range(&mut rand::rng(), 1000..9999);
+    v.push(format!("{}_{}_{}", base_mapper_name, ts, rnd));
+    v
+}
 
-    format!("{}{}", LUKS_MAPPER_PREFIX, sanitized)
+/// Reserves the first candidate (starting at `start`) that is neither an existing
+/// /dev/mapper device nor reserved by a concurrent invocation.
+/// Returns the candidate index and its reservation.
+fn reserve_free_mapper_name(
+    registry: &Path,
+    candidates: &[String],
+    start: usize,
+) -> Result<Option<(usize, zero_kelvin::utils::MapperReservation)>, ZkError> {
+    for (i, name) in candidates.iter().enumerate().skip(start) {
+        if Path::new("/dev/mapper").join(name).exists() {
+            continue;
+        }
+        if let Some(reservation) = zero_kelvin::utils::reserve_mapper_name(registry, name)? {
+            return Ok(Some((i, reservation)));
+        }
+    }
+    Ok(None)
 }
 
-/// Open a LUKS container with automatic retry on mapper name collision.
-/// Tries the base name first, then appends `_2`, `_3`, ..., and finally a random suffix.
+/// Open a LUKS container, avoiding mapper name races with concurrent invocations.
+/// Each candidate is first reserved in the mapper registry (flock-protected), then opened;
+/// the next numeric suffix is only tried after a confirmed collision.
+/// A wrong passphrase (detected from stderr) is retried up to `passphrase_attempts` times
+/// before the error is returned, so callers only start their cleanup after the final failure.
+/// `readonly` opens the mapper with `--readonly` (everything except `create`), which protects
+/// the archive and also works on write-protected media.
+/// A `passphrase` from a passphrase agent is fed to cryptsetup on stdin (`--key-file=-`);
+/// without one cryptsetup prompts itself.
 /// Returns the mapper name that was successfully opened.
 ///
 /// cryptsetup exit codes:
@@ -645,35 +962,77 @@ fn open_luks_container(
     root_cmd: &[String],
     image_path_str: &str,
     base_mapper_name: &str,
+    passphrase_attempts: u32,
+    readonly: bool,
+    passphrase: Option<&str>,
 ) -> Result<String, ZkError> {
-    let candidates: Vec<String> = {
-        let mut v: Vec<String> = Vec::with_capacity(12);
-        v.push(base_mapper_name.to_string());
-        for i in 2..=10 {
-            v.push(format!("{}_{}", base_mapper_name, i));
-        }
-        // Final fallback: timestamp + random (virtually unique)
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
-        let rnd: u32 = rand::Rng::random_range(&mut rand::rng(), 1000..9999);
-        v.push(format!("{}_{}_{}", base_mapper_name, ts, rnd));
-        v
+    let candidates = mapper_name_candidates(base_mapper_name);
+    // Without a registry we still fall back to the cryptsetup exit code 5 retry below
+    let registry = match zero_kelvin::utils::get_mapper_registry_dir() {
+        Ok(dir) => Some(dir),
+        Err(e) => {
+            eprintln!("Warning: Mapper name registry unavailable ({}), continuing without reservation", e);
+            None
+        }
     };
 
-    for (i, mapper_name) in candidates.iter().enumerate() {
+    let mut next = 0;
+    while next < candidates.len() {
+        // Held until this attempt is over: on success the mapper itself marks the name as taken
+        let (i, _reservation) = match &registry {
+            Some(dir) => match reserve_free_mapper_name(dir, &candidates, next)? {
+                Some((i, reservation)) => (i, Some(reservation)),
+                None => break,
+            },
+            None => (next, None),
+        };
+        next = i + 1;
+        let mapper_name = &candidates[i];
+
         let mut open_args: Vec<String> = root_cmd.to_vec();
+        // --tries 1: passphrase retries are handled below, so the count is ours to control
         open_args.extend([
             "cryptsetup".to_string(),
             "open".to_string(),
-            image_path_str.to_string(),
-            mapper_name.clone(),
+            "--tries".to_string(),
+            "1".to_string(),
         ]);
+        if readonly {
+            open_args.push("--readonly".to_string());
+        }
+        if passphrase.is_some() {
+            open_args.push("--key-file=-".to_string());
+        }
+        open_args.extend([image_path_str.to_string(), mapper_name.clone()]);
 
         let prog = open_args.remove(0);
         let args_refs: Vec<&str> = open_args.iter().map(|s| s.as_str()).collect();
 
-        let status = executor
-            .run_interactive(&prog, &args_refs)
-            .map_err(|e| ZkError::IoError(e))?;
+        let mut attempt = 1;
+        let (status, stderr) = loop {
+            let (status, stderr) = match passphrase {
+                Some(passphrase) => {
+                    let output = executor.run_with_stdin(&prog, &args_refs, passphrase.as_bytes())?;
+                    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                    (output.status, String::from_utf8_lossy(&output.stderr).into_owned())
+                }
+                None => executor.run_and_capture_error(&prog, &args_refs)?,
+            };
+            // Wrong passphrase: ask again on the same mapper name (nothing to clean up yet)
+            if !status.success()
+                && attempt < passphrase_attempts
+                && zero_kelvin::utils::is_bad_passphrase_error(&stderr)
+            {
+                eprintln!(
+                    "Incorrect passphrase, please try again (attempt {} of {}).",
+                    attempt + 1,
+                    passphrase_attempts
+                );
+                attempt += 1;
+                continue;
+            }
+            break (status, stderr);
+        };
 
         if status.success() {
             if i > 0 {
@@ -690,7 +1049,13 @@ fn open_luks_container(
             continue;
         }
 
-        // Any other error (wrong password, corrupt header, etc.) — stop immediately
+        // Any other error (last passphrase attempt failed, corrupt header, etc.) — stop immediately
+        if zero_kelvin::utils::is_bad_passphrase_error(&stderr) {
+            return Err(ZkError::LuksError(format!(
+                "cryptsetup open failed: incorrect passphrase ({} attempt(s))",
+                attempt
+            )));
+        }
         return Err(ZkError::LuksError(format!(
             "cryptsetup open failed (exit code: {:?})",
             status.code()
@@ -703,6 +1068,232 @@ fn open_luks_container(
 }
 
 
+/// Checks the SquashFS inside an open LUKS mapper: the superblock (`unsquashfs -s`) and, with
+/// `depth` > 0, the directory tree. `unsquashfs -l` still reads every directory and inode
+/// table; its listing is streamed, and only the entries down to `depth` levels are counted
+/// (in the log), so a huge archive costs time, not memory. Reading the mapper needs root.
+fn verify_mapper(
+    executor: &impl CommandExecutor,
+    root_cmd: &[String],
+    mapper_path: &str,
+    depth: u32,
+) -> Result<SquashfsInfo, ZkError> {
+    let as_root = |args: &[&str]| {
+        let mut full: Vec<String> = root_cmd.to_vec();
+        full.extend(args.iter().map(|a| a.to_string()));
+        let prog = full.remove(0);
+        (prog, full)
+    };
+    let (prog, args) = as_root(&["unsquashfs", "-s", mapper_path]);
+    let superblock = executor.run(&prog, &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+    let info = squashfs_info::from_output(Path::new(mapper_path), &superblock)?;
+    if depth > 0 {
+        let mut entries = 0u64;
+        let mut count = |line: &str| {
+            if line.starts_with("squashfs-root") && line.matches('/').count() <= depth as usize {
+                entries += 1;
+            }
+        };
+        let (prog, args) = as_root(&["unsquashfs", "-l", mapper_path]);
+        let listing = executor.run_each_line(&prog, &args.iter().map(String::as_str).collect::<Vec<_>>(), &mut count)?;
+        if !listing.status.success() {
+            return Err(ZkError::OperationFailed(format!(
+                "Cannot walk the SquashFS in {}: {}",
+                mapper_path,
+                String::from_utf8_lossy(&listing.stderr).trim()
+            )));
+        }
+        log::info!("{}: {} entries within {} level(s)", mapper_path, entries, depth);
+    }
+    Ok(info)
+}
+
+/// Opens `image` read-only, verifies its payload ([`verify_mapper`]) and closes it again.
+/// Used by `create --verify`, and exposed as the hidden `verify-mapper` subcommand.
+fn verify_luks_payload(image: &Path, executor: &impl CommandExecutor, depth: u32) -> Result<SquashfsInfo, ZkError> {
+    let image = image.to_path_buf();
+    let image_str = image.to_str().ok_or(ZkError::InvalidPath(image.clone()))?;
+    let root_cmd = get_effective_root_cmd();
+    // Closes the mapper on every path out of here; never touches the container
+    let passphrase = passphrase_agent()?.ask(executor, &format!("Passphrase for {}", image.display()))?;
+    let mut transaction = LuksTransaction::for_existing(executor, &image);
+    let mapper_name = open_luks_container(
+        executor,
+        &root_cmd,
+        image_str,
+        &generate_mapper_name(&image),
+        effective_passphrase_attempts(LUKS_PASSPHRASE_ATTEMPTS),
+        true,
+        passphrase.as_deref(),
+    )?;
+    transaction.set_mapper(mapper_name.clone());
+    verify_mapper(executor, &root_cmd, &format!("/dev/mapper/{}", mapper_name), depth)
+}
+
+/// Builds `[root_cmd..] mount -t squashfs -o ro <mapper> <target>` for an opened LUKS mapper.
+fn luks_mount_command(root_cmd: &[String], mapper_path: &str, target: &Path) -> Result<Vec<String>, ZkError> {
+    let target_str = target.to_str().ok_or_else(|| ZkError::InvalidPath(target.to_path_buf()))?;
+    let mut args = root_cmd.to_vec();
+    args.extend([
+        "mount".to_string(),
+        "-t".to_string(),
+        "squashfs".to_string(),
+        "-o".to_string(),
+        "ro".to_string(),
+        mapper_path.to_string(),
+        target_str.to_string(),
+    ]);
+    Ok(args)
+}
+
+/// How LUKS passphrases are asked: cryptsetup prompts on a terminal, otherwise the
+/// configured agent is used (unit tests never ask an agent).
+fn passphrase_agent() -> Result<passphrase::Agent, ZkError> {
+    #[cfg(not(test))]
+    {
+        use std::io::IsTerminal;
+        passphrase::Agent::select(&zero_kelvin::config::Config::load(), std::io::stdin().is_terminal())
+    }
+    #[cfg(test)]
+    Ok(passphrase::Agent::Tty)
+}
+
+/// Passphrase retries only make sense when someone can type a new one:
+/// with a non-interactive stdin a single attempt is made.
+fn effective_passphrase_attempts(configured: u32) -> u32 {
+    use std::io::IsTerminal;
+    if std::io::stdin().is_terminal() { configured.max(1) } else { 1 }
+}
+
+/// Prints which processes keep `target` busy: `fuser -vm` if available,
+/// otherwise our own scan of /proc/*/fd.
+fn print_mount_holders(target: &Path, executor: &impl CommandExecutor) {
+    let target_str = target.to_string_lossy();
+    if which::which("fuser").is_ok()
+        && let Ok(output) = executor.run("fuser", &["-vm", &target_str])
+    {
+        // fuser -v prints its table to stderr
+        let report = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stderr),
+            String::from_utf8_lossy(&output.stdout)
+        );
+        if !report.trim().is_empty() {
+            eprintln!("Processes using {}:\n{}", target.display(), report.trim_end());
+            return;
+        }
+    }
+
+    let holders = zero_kelvin::utils::find_mount_holders(target);
+    if holders.is_empty() {
+        eprintln!("Could not determine which processes are using {} (try running as root).", target.display());
+    } else {
+        eprintln!("Processes using {}:", target.display());
+        for h in holders {
+            eprintln!("  {:>7} {:<16} {}", h.pid, h.command, h.path.display());
+        }
+    }
+}
+
+/// Runs an unmount command (`tool` names it in error messages, `program` may be a root runner),
+/// retrying with backoff while the target is busy and printing the processes holding it
+/// between attempts.
+/// Returns ZkError::Busy (distinct exit code) if it is still busy after all retries.
+fn unmount_with_retry(
+    executor: &impl CommandExecutor,
+    tool: &str,
+    program: &str,
+    args: &[&str],
+    target: &Path,
+) -> Result<(), ZkError> {
+    let output = zero_kelvin::executor::run_with_retry(
+        executor,
+        program,
+        args,
+        UMOUNT_RETRY_ATTEMPTS,
+        Duration::from_millis(UMOUNT_RETRY_DELAY_MS),
+        |out| zero_kelvin::utils::is_busy_error(&String::from_utf8_lossy(&out.stderr)),
+        |attempt| {
+            eprintln!(
+                "{} is busy (attempt {}/{}), retrying...",
+                target.display(),
+                attempt,
+                UMOUNT_RETRY_ATTEMPTS
+            );
+            print_mount_holders(target, executor);
+        },
+    )?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if zero_kelvin::utils::is_busy_error(&stderr) {
+        print_mount_holders(target, executor);
+        return Err(ZkError::Busy(format!(
+            "{} is still in use after {} attempts: {}\nClose the programs listed above, or detach it lazily with: {}",
+            target.display(),
+            UMOUNT_RETRY_ATTEMPTS,
+            stderr.trim(),
+            suggest_command(&["0k-core".as_ref(), "umount".as_ref(), "--lazy".as_ref(), target.as_os_str()])
+        )));
+    }
+    Err(ZkError::OperationFailed(format!("{} failed for {:?}: {}", tool, target, stderr)))
+}
+
+/// `create` arguments after validation and output resolution, shared by the packing strategies.
+struct CreateOptions {
+    input_path: PathBuf,
+    /// Resolved archive path (auto-generated when OUTPUT was a directory)
+    output: PathBuf,
+    compression: u32,
+    no_progress: bool,
+    vanilla_progress: bool,
+    alfa_progress: bool,
+    /// What happens to the output ([`check_existing_output`])
+    action: Action,
+    /// Permissions of a newly created archive
+    mode: u32,
+    passphrase_attempts: u32,
+    exclude_file: Option<PathBuf>,
+    /// Leave extended attributes (SELinux labels, ACLs) out of the archive
+    no_xattrs: bool,
+    /// Space to leave free on the destination
+    reserve: Reserve,
+    /// Skip the free-space checks of the destination
+    no_space_check: bool,
+    /// Extra mksquashfs arguments (`--mksquashfs-arg`), already validated
+    mksquashfs_args: Vec<String>,
+    /// mksquashfs `-mem` in bytes (`--mem` or the small-machine default)
+    mem: Option<u64>,
+    /// mksquashfs `-processors` / tar2sqfs `--num-jobs` (`--threads`, config, or physical cores)
+    threads: u32,
+    /// Compress with gzip if the kernel cannot mount zstd SquashFS (encrypted only)
+    auto_fallback_compression: bool,
+    /// Whether the kernel can mount zstd SquashFS, probed once per boot for encrypted zstd
+    /// containers (see [`zero_kelvin::kernel_squashfs`]); None if unknown or not asked
+    kernel_zstd_supported: Option<bool>,
+    /// Plain appends: pass -no-recovery instead of keeping mksquashfs's recovery file
+    no_recovery: bool,
+    /// Encrypted: store the payload digest as a LUKS2 token (see [`zero_kelvin::luks_token`])
+    integrity_token: bool,
+}
+
+struct MountOptions {
+    image: PathBuf,
+    mount_point: Option<PathBuf>,
+    passphrase_attempts: u32,
+    list: bool,
+    nonempty: bool,
+}
+
+struct UmountOptions {
+    /// Mount point directory or image file
+    target: PathBuf,
+    lazy: bool,
+}
+
 /// Main logic entry point with dependency injection
 pub fn run(args: Args, executor: &impl CommandExecutor) -> Result<(), ZkError> {
     match args.command {
@@ -716,6 +1307,22 @@ pub fn run(args: Args, executor: &impl CommandExecutor) -> Result<(), ZkError> {
             alfa_progress,
             overwrite_files,
             overwrite_luks_content,
+            log_file,
+            debug_log,
+            keep_log,
+            mode,
+            passphrase_attempts,
+            exclude_file,
+            no_xattrs,
+            reserve,
+            no_space_check,
+            mksquashfs_arg,
+            mem,
+            threads,
+            auto_fallback_compression,
+            verify,
+            no_recovery,
+            integrity_token,
         } => {
             // 0. Validate compression level
             if compression > 22 {
@@ -725,15 +1332,43 @@ pub fn run(args: Args, executor: &impl CommandExecutor) -> Result<(), ZkError> {
                 )));
             }
 
-            // 1. Check if input exists (First validation)
-            if !input_path.exists() {
-                return Err(ZkError::InvalidPath(input_path.clone()));
+            // 0.1 Validate output permissions
+            let mode = match mode {
+                Some(m) => zero_kelvin::utils::parse_octal_mode(&m)?,
+                None => DEFAULT_ARCHIVE_MODE,
+            };
+
+            let reserve = Reserve::resolve(reserve.as_deref())?;
+
+            // 1-2. Input must exist and suit the requested mode
+            validate_create_input(&input_path, encrypt, exclude_file.as_deref())?;
+            zero_kelvin::utils::validate_mksquashfs_args(&mksquashfs_arg)?;
+            if !mksquashfs_arg.is_empty() && !input_path.is_dir() {
+                return Err(ZkError::OperationFailed(
+                    "--mksquashfs-arg supports only DIRECTORY input (archives are repacked by tar2sqfs).".to_string(),
+                ));
             }
+            if mem.is_some() && !input_path.is_dir() {
+                return Err(ZkError::OperationFailed(
+                    "--mem supports only DIRECTORY input (archives are repacked by tar2sqfs).".to_string(),
+                ));
+            }
+            if compression == 0 && !input_path.is_dir() {
+                return Err(ZkError::CompressionError(
+                    "-c 0 (no compression) supports only DIRECTORY input: tar2sqfs always compresses a repacked archive. Use a level of 1-22, or extract the archive and pack the directory.".to_string(),
+                ));
+            }
+            let mem = if input_path.is_dir() { resolve_mksquashfs_mem(mem)? } else { None };
 
-            // 2. Check Input Type for Encryption
-            // Currently, we only support encrypting DIRECTORIES, not single files/archives
-            if encrypt && !input_path.is_dir() {
-                 return Err(ZkError::OperationFailed("Encrypted mode (-e) currently supports only DIRECTORIES.\nPlease extract the archive first and point to the directory.".to_string()));
+            // 2.1 Required tools: mksquashfs for a directory, tar2sqfs to repack an archive
+            #[cfg(not(test))]
+            {
+                use zero_kelvin::utils::Dependency;
+                let mut deps = vec![if input_path.is_dir() { Dependency::Mksquashfs } else { Dependency::Tar2sqfs }];
+                if encrypt {
+                    deps.push(Dependency::Cryptsetup);
+                }
+                zero_kelvin::utils::check_dependencies(&deps)?;
             }
 
             // 3. Check Privilege for LUKS
@@ -752,1057 +1387,1184 @@ pub fn run(args: Args, executor: &impl CommandExecutor) -> Result<(), ZkError> {
                 }
             }
 
-            // Define compression strategy
-            let comp_mode = CompressionMode::from_level(compression);
+            // 4. Output path (auto-generated inside a directory)
+            let auto_generated = output_path.as_ref().is_some_and(|p| p.is_dir());
+            let final_output = resolve_create_output(output_path.as_deref(), &input_path, encrypt)?;
 
-            // 0. Handle Output Path (Auto-generation if directory or omitted)
-            // Logic:
-            // If output_path is None -> Error (or current dir? Spec says "Output path required" in table, but zks passes it)
-            // Wait, old table for SM said "Output path required" for empty input.
-            // But new requirement: "0k-core create <src> <existing_dir>" -> Auto-gen filename
-            // So we need to handle output_path.
-            
-            let final_output = match &output_path {
-                Some(p) => {
-                    if p.is_dir() {
-                        // Auto-generate filename inside this directory
-                        // Format: prefix_unixtime_random.extension
-                        // prefix = input dir name
-                        let prefix = input_path.file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("archive");
-                        
-                        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
-                        let rnd: u32 = rand::rng().random_range(100000..999999);
-                        
-                        let ext = if encrypt { "sqfs_luks.img" } else { "sqfs" };
-                        let filename = format!("{}_{}_{}.{}", prefix, timestamp, rnd, ext);
-                        
-                        let final_path = p.join(filename);
-                        println!("Auto-generated output filename: {}", final_path.display());
-                        final_path
-                    } else {
-                        // It's a file path (existing or not)
-                        p.to_path_buf()
-                    }
-                },
-                None => return Err(ZkError::MissingTarget("Output path required".to_string())),
+            // Optional packing log (tee of mksquashfs/tar2sqfs output)
+            let mut packing_log = zero_kelvin::utils::resolve_log_path(log_file, debug_log, &final_output)
+                .map(|path| PackingLog::new(path, keep_log));
+            if let Some(log) = &packing_log {
+                println!("Packing log: {}", log.path.display());
+            }
+
+            // 5. Existing output: only updated/replaced when asked to
+            let policy = OverwritePolicy { encrypt, overwrite_files, overwrite_luks_content };
+            let action = check_existing_output(&final_output, &policy)?;
+
+            let opts = CreateOptions {
+                input_path,
+                output: final_output,
+                compression,
+                no_progress,
+                vanilla_progress,
+                alfa_progress,
+                action,
+                mode,
+                passphrase_attempts,
+                exclude_file,
+                no_xattrs,
+                reserve,
+                no_space_check,
+                mksquashfs_args: mksquashfs_arg,
+                mem,
+                threads: zero_kelvin::utils::resolve_threads(threads),
+                auto_fallback_compression,
+                kernel_zstd_supported: (encrypt && matches!(CompressionMode::from_level(compression), CompressionMode::Zstd(_)))
+                    .then(|| zero_kelvin::kernel_squashfs::zstd_supported(executor))
+                    .flatten(),
+                no_recovery,
+                integrity_token,
             };
-            
-            // 0.1 Check for Existing Output
-            if final_output.exists() {
-                let is_luks = zero_kelvin::utils::is_luks_image(&final_output, executor);
-                // Check valid SquashFS signature (magic number)
-                let is_sqfs = match zero_kelvin::utils::get_file_type(&final_output) {
-                     Ok(zero_kelvin::utils::ArchiveType::Squashfs) => true,
-                     _ => false,
-                };
 
-                if !overwrite_files && !overwrite_luks_content {
-                     return Err(ZkError::OperationFailed("Output file exists.\nUse --overwrite-files to update content (append).\nUse --overwrite-luks-content to replace LUKS container payload.".to_string()));
-                }
-                
-                if overwrite_files {
-                    // Supported for Plain SQFS and LUKS
-                    if !is_luks && !is_sqfs {
-                         return Err(ZkError::OperationFailed("Target exists but is not a valid SquashFS or LUKS container. Cannot update.".to_string()));
-                    }
-                    // Logic continues below...
-                    // For LUKS: we open it and then append.
-                    // For Plain: we just run mksquashfs (default is append).
-                }
-                
-                if overwrite_luks_content {
-                    if !is_luks {
-                         return Err(ZkError::LuksError("--overwrite-luks-content requires a valid LUKS container target.".to_string()));
-                    }
-                    // Logic continues below...
-                    // We open LUKS, then run mksquashfs with -noappend.
-                }
+            // 6. The destination must be able to hold the archive (a LUKS container is
+            //    checked when it is allocated)
+            if !encrypt && !no_space_check {
+                ensure_output_fits(&space::StatvfsSpace, &opts.input_path, &opts.output, compression)?;
             }
 
             if encrypt {
-                // ENCRYPTED FLOW
-                // ...
-                // CRITICAL CHANGE: Disable archive support for LUKS due to persistent I/O errors
-                if !input_path.is_dir() {
-                    return Err(ZkError::OperationFailed("Encrypted mode (-e) currently supports only DIRECTORIES.\nPlease extract the archive first and point to the directory.".to_string()));
-                }
+                cmd_create_encrypted(executor, &opts, &packing_log)?;
+            } else if opts.input_path.is_file() {
+                cmd_create_repack(executor, &opts, &packing_log)?;
+            } else {
+                cmd_create_plain(executor, &opts, &packing_log)?;
+            }
 
-                // Determine raw size (now strictly for directories)
-                // du -sb
-                let raw_size_bytes = if let Ok(output) = executor.run("du", &["-sb", input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?]) {
-                    if output.status.success() {
-                        let out_str = String::from_utf8_lossy(&output.stdout);
-                        out_str.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0)
-                    } else {
-                        0
-                    }
-                } else { 0 };
+            if verify {
+                let info = if encrypt {
+                    println!("Verifying the container (passphrase required)...");
+                    verify_luks_payload(&opts.output, executor, 1)?
+                } else {
+                    squashfs_info::read(&opts.output, executor)?
+                };
+                println!("Verified: {}", info.summary());
+            }
 
-                if raw_size_bytes == 0 {
-                    return Err(ZkError::OperationFailed("Could not determine input directory size or empty input".to_string()));
+            if let Some(log) = packing_log.as_mut() {
+                log.set_success();
+            }
+            if auto_generated {
+                println!("{}", zero_kelvin::utils::archive_result_line(&opts.output));
+            }
+            Ok(())
+        }
+        Commands::Mount { image, mount_point, passphrase_attempts, list, nonempty } => {
+            // Required tools (--list only reads the mount table)
+            #[cfg(not(test))]
+            {
+                if !list && image.exists() {
+                    let encrypted = zero_kelvin::utils::is_luks_image(&image);
+                    zero_kelvin::utils::check_dependencies(zero_kelvin::utils::mount_dependencies(encrypted))?;
                 }
+            }
+            cmd_mount(executor, MountOptions { image, mount_point, passphrase_attempts, list, nonempty })
+        }
+        Commands::Version { json } => {
+            if json {
+                zero_kelvin::version::print_json("0k-core")?;
+            } else {
+                print!("{}", Args::build_command().render_version());
+            }
+            Ok(())
+        }
+        Commands::Umount { mount_point, lazy } => {
+            cmd_umount(executor, UmountOptions { target: mount_point, lazy })
+        }
+        Commands::VerifyMapper { image, depth } => {
+            if !zero_kelvin::utils::is_luks_image(&image) {
+                return Err(ZkError::LuksError(format!("{} is not a LUKS container", image.display())));
+            }
+            let info = verify_luks_payload(&image, executor, depth)?;
+            println!("OK: {}", info.summary());
+            Ok(())
+        }
+    }
+}
 
-                let output_buf = &final_output; // Use resolved path
-                
-                // If appending/replacing, we don't recreate the container file
-                // UNLESS --overwrite-luks-content? No, that replaces CONTENT, not container.
-                // Actually, if we want to replace *container*, user should delete it.
-                // The flag is --overwrite-luks-content (payload).
-                
-                // If file exists, skip creation/formatting
-                if !final_output.exists() { 
-                    // ... Normal creation logic ...
-                    
-                    // Overhead calc
-                    let fs_overhead = get_fs_overhead_percentage(output_buf, executor);
-                    let overhead_percent = fs_overhead;
-                    
-                    let overhead_bytes = (raw_size_bytes as f64 * (overhead_percent as f64 / 100.0)) as u64;
-                    let luks_header_bytes = LUKS_HEADER_SIZE;
-                    let safety_buffer = LUKS_SAFETY_BUFFER;
-                    
-                    let unaligned_size = raw_size_bytes + overhead_bytes + luks_header_bytes + safety_buffer;
-                    
-                    // Align to 1MB (1024*1024) to ensure loop device creates exactly this size
-                    // (avoiding partial sectors which could be dropped by kernel)
-                    let align_size = 1024 * 1024;
-                    let container_size = (unaligned_size + align_size - 1) / align_size * align_size;
-
-                    if std::env::var("RUST_LOG").is_ok() {
-                        eprintln!("DEBUG: Encrypting directory. Input: {} bytes. Overhead: {}%. Allocating: {} bytes.", 
-                            raw_size_bytes, overhead_percent, container_size);
-                    }
+/// Checks that the `create` input exists and fits the mode: encryption and exclusions
+/// work on directories only.
+fn validate_create_input(input_path: &Path, encrypt: bool, exclude_file: Option<&Path>) -> Result<(), ZkError> {
+    if !input_path.exists() {
+        return Err(ZkError::InvalidPath(input_path.to_path_buf()));
+    }
 
-                    // 1. Create container file with actual allocated space
-                    // Using fallocate instead of sparse file (set_len) because:
-                    // - Loop devices may fail to write to unallocated sparse regions
-                    // - Some filesystems don't support sparse writes through loop
-                    // fallocate -l <size> <file>
-                    let size_str = container_size.to_string();
-                    let output_str_create = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
-                    
-                    
-                    // Fallback to dd if fallocate failed (non-success status) OR if we fell through above
-                    // Re-check fallocate success? 
-                    // Refactoring for clarity:
-                    
-                    let mut created = false;
-                    let mut fallocate_stderr = String::new();
-                    
-                    let fallocate_res = executor.run("fallocate", &["-l", &size_str, output_str_create]);
-                    
-                    if let Ok(out) = fallocate_res {
-                        if out.status.success() {
-                            created = true;
-                        } else {
-                            fallocate_stderr = String::from_utf8_lossy(&out.stderr).to_string();
-                            if std::env::var("RUST_LOG").is_ok() {
-                                eprintln!("DEBUG: fallocate failed, try dd. Stderr: {}", fallocate_stderr);
-                            }
-                        }
-                    } else if let Err(e) = fallocate_res {
-                         fallocate_stderr = e.to_string();
-                    }
-                    
-                    if !created {
-                        let count = (container_size / (1024 * 1024)) + 1;
-                        let dd_output = executor.run("dd", &[
-                            "if=/dev/zero",
-                            &format!("of={}", output_str_create),
-                            "bs=1M",
-                            &format!("count={}", count),
-                            "status=none"
-                        ])?;
-                        
-                        if !dd_output.status.success() {
-                            let dd_err = String::from_utf8_lossy(&dd_output.stderr);
-                            return Err(ZkError::OperationFailed(format!("Failed to create container file. fallocate error: '{}'. dd error: '{}'", fallocate_stderr.trim(), dd_err.trim())));
-                        }
-                    }
-                
-                } // End if !exists
+    // Currently, we only support encrypting DIRECTORIES, not single files/archives
+    if encrypt && !input_path.is_dir() {
+         return Err(ZkError::OperationFailed("Encrypted mode (-e) currently supports only DIRECTORIES.\nPlease extract the archive first and point to the directory.".to_string()));
+    }
 
-                // Start Transaction for cleanup
-                let mut transaction = LuksTransaction::new(executor, output_buf);
+    // Exclusions are handed to mksquashfs, which only sees them for directory input
+    if let Some(ef) = exclude_file {
+        if !input_path.is_dir() {
+            return Err(ZkError::OperationFailed("--exclude-file currently supports only DIRECTORY input.".to_string()));
+        }
+        if !ef.is_file() {
+            return Err(ZkError::InvalidPath(ef.to_path_buf()));
+        }
+    }
+    Ok(())
+}
 
-                let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
-                
-                // 2. Format LUKS (Only if new)
-                let root_cmd = get_effective_root_cmd();
-
-                if !final_output.exists() || (!overwrite_files && !overwrite_luks_content) {
-                    // Original Creation Logic
-                    println!("Initializing LUKS container...");
-                    eprintln!("Note: LUKS has built-in rate limiting. After several incorrect password attempts,");
-                    eprintln!("      there will be increasing delays between attempts (up to 60 seconds).");
-                    // Construct command: [sudo] cryptsetup luksFormat -q output
-                    let mut luks_args = root_cmd.clone();
-                    luks_args.extend(vec!["cryptsetup".to_string(), "luksFormat".to_string(), "-q".to_string(), output_str.to_string()]);
-                    
-                    let prog = luks_args.remove(0);
-                    let args_refs: Vec<&str> = luks_args.iter().map(|s| s.as_str()).collect();
-
-                    let status = executor.run_interactive(&prog, &args_refs)
-                        .map_err(|e| ZkError::IoError(e))?;
-
-                    if !status.success() {
-                        return Err(ZkError::LuksError("luksFormat failed".to_string()));
-                    }
-                } else {
-                     println!("Opening existing LUKS container for update...");
-                }
+/// Resolves the archive path: an existing directory gets a unique auto-generated name
+/// (`<input name>_<time>_<rand>.sqfs`, or `.sqfs_luks.img` when encrypting), anything else
+/// is used as given.
+fn resolve_create_output(output_path: Option<&Path>, input_path: &Path, encrypt: bool) -> Result<PathBuf, ZkError> {
+    let p = output_path.ok_or_else(|| ZkError::MissingTarget("Output path required".to_string()))?;
+    if !p.is_dir() {
+        // It's a file path (existing or not)
+        return Ok(p.to_path_buf());
+    }
 
-                // 3. Open (with atomic retry on mapper name collision)
-                let base_mapper_name = generate_mapper_name(&output_buf);
-                println!("Opening LUKS container...");
-                let mapper_name = open_luks_container(
-                    executor,
-                    &root_cmd,
-                    &output_str,
-                    &base_mapper_name,
-                )?;
-                
-                transaction.set_mapper(mapper_name.clone());
-                let mapper_path = format!("/dev/mapper/{}", mapper_name);
-
-                // 4. Pack Data
-                // Execute mksquashfs to mapper_path
-                let pack_result = {
-                    let mut cmd_args = vec![
-                         input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?.to_string(),
-                         mapper_path.clone(),
-                         "-no-recovery".to_string(),
-                    ];
-                    
-                    // Logic for -noappend usage in LUKS:
-                    // - Brand new file: Use -noappend (standard)
-                    // - overwrite-luks-content: Use -noappend (overwrite internal FS)
-                    // - overwrite-files: Do NOT use -noappend (append mode)
-                    
-                    let is_new_file = !final_output.exists() || (!overwrite_files && !overwrite_luks_content);
-                    // Actually, if we just created it (is_new_file logic above in block 1), it is new.
-                    // If we opened existing, we only append if overwrite_files.
-                    
-                    if is_new_file || overwrite_luks_content {
-                         cmd_args.push("-noappend".to_string());
-                    }
-                    // Else if overwrite_files, we omit -noappend to allow appending
-                    if no_progress { cmd_args.push("-no-progress".to_string()); }
-                    let level_str = compression.to_string();
-                    
-                    // Helper to adapt Vec<String> to Vec<&str> API of CompressionMode
-                    // We need to temporarily hold the strings?
-                    // compression mode just pushes &str literals usually.
-                    // But wait, `apply_to_mksquashfs` implementation in `lib.rs` takes `&mut Vec<&str>`.
-                    // We have `Vec<String>`.
-                    // We should change `apply_to_mksquashfs` to simple push logic OR handle manual push here.
-                    // Or... convert our Vec<String> to Vec<&str> first? No, we can't push to Vec<&str> if backing string is new.
-                    // Simpler: Apply args manually or refactor `apply_to_mksquashfs` is generic?
-                    // "comp_mode" implementation is simple.
-                    // Let's just manually apply logic here since `apply_to...` is restrictive for String owner.
-                    match comp_mode {
-                         CompressionMode::None => cmd_args.push("-no-compression".to_string()),
-                         CompressionMode::Zstd(_) => {
-                              cmd_args.push("-comp".to_string());
-                              cmd_args.push("zstd".to_string());
-                              cmd_args.push("-Xcompression-level".to_string());
-                              cmd_args.push(level_str.to_string());
-                         },
-                         // other modes...
-                    }
-                    
-                    // Construct: [sudo] mksquashfs ...
-                    let mut mk_args = root_cmd.clone();
-                    mk_args.extend(vec!["mksquashfs".to_string()]);
-                    mk_args.extend(cmd_args);
-                    
-                    let mk_prog = mk_args.remove(0);
-                    let mk_refs: Vec<&str> = mk_args.iter().map(|s| s.as_str()).collect();
-
-                    // Progress bar logic based on flags
-                    let output = if no_progress {
-                        // No progress at all - just run silently
-                        executor.run(&mk_prog, &mk_refs)?
-                    } else if alfa_progress {
-                        // EXPERIMENTAL: Custom progress bar - parse stdout for percentages (currently broken)
-                        // Get directory size for display
-                        let dir_size = if let Ok(du_output) = executor.run("du", &["-sb", input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?]) {
-                            if du_output.status.success() {
-                                let out_str = String::from_utf8_lossy(&du_output.stdout);
-                                out_str.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0)
-                            } else { 0 }
-                        } else { 0 };
-                        let dir_size_mb = dir_size as f64 / 1024.0 / 1024.0;
-                        
-                        let pb = ProgressBar::new(100);
-                        pb.set_style(
-                            ProgressStyle::with_template(
-                                "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}% {msg}"
-                            )
-                            .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
-                            .progress_chars("█▓▒░  ")
-                        );
-                        pb.set_message("Encrypting → SquashFS+LUKS");
-                        pb.enable_steady_tick(Duration::from_millis(100));
-                        
-                        let result = executor.run_with_stdout_progress(&mk_prog, &mk_refs, &pb)?;
-                        
-                        if result.status.success() {
-                            pb.finish_with_message(format!("✓ Encrypted {:.1} MB", dir_size_mb));
-                        } else {
-                            pb.finish_with_message("✗ Failed");
-                        }
-                        result
-                    } else {
-                        // DEFAULT: Use mksquashfs native progress (interactive mode)
-                        let status = executor.run_interactive(&mk_prog, &mk_refs)?;
-                        std::process::Output {
-                            status,
-                            stdout: vec![],
-                            stderr: vec![],
-                        }
-                    };
+    let raw_prefix = input_path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let prefix = zero_kelvin::utils::sanitize_prefix(&raw_prefix);
+    if prefix != raw_prefix {
+        println!("Archive name prefix normalized: {:?} -> {:?}", raw_prefix, prefix);
+    }
+    let ext = if encrypt { "sqfs_luks.img" } else { "sqfs" };
+    let final_path = zero_kelvin::utils::generate_unique_path(
+        p,
+        zero_kelvin::utils::DEFAULT_NAME_TEMPLATE,
+        &prefix,
+        ext,
+    )?;
+    println!("Auto-generated output filename: {}", final_path.display());
+    Ok(final_path)
+}
 
-                    if !output.status.success() {
-                         Err(ZkError::OperationFailed(format!("mksquashfs failed: {}", String::from_utf8_lossy(&output.stderr))))
-                    } else { Ok(()) }
-                };
+/// Existing-output policy ([`OverwritePolicy`]): a file at the output path is only touched
+/// with --overwrite-files or --overwrite-luks-content, and only if it is the right kind of
+/// image for them and for -e.
+fn check_existing_output(output: &Path, policy: &OverwritePolicy) -> Result<Action, ZkError> {
+    let exists = output.exists();
+    let kind = if exists { OutputKind::detect(output) } else { OutputKind::Other };
+    match policy.resolve(exists, kind) {
+        Action::Refuse(refusal) => Err(refusal.error(output)),
+        action => Ok(action),
+    }
+}
 
-                if let Err(e) = pack_result {
-                    return Err(e);
-                }
+/// Refuses a user-supplied mount point that already has entries (mounting would hide them),
+/// listing the first few. A missing directory is fine: it is created.
+fn ensure_mount_point_empty(mount_point: &Path) -> Result<(), ZkError> {
+    let entries = match fs::read_dir(mount_point) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(ZkError::IoError(e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    if names.is_empty() {
+        return Ok(());
+    }
+    names.sort();
 
-                // 5. Trim logic
-                // Need unsquashfs (sudo usually not needed for read, but reading from /dev/mapper requires root)
-                let mut trim_size: Option<u64> = None;
-                
-                // Get FS Size - we're already root in LUKS context, run directly
-                // unsquashfs -s /dev/mapper/...
-                match executor.run("unsquashfs", &["-s", &mapper_path]) {
-                    Ok(out) => {
-                        let out_str = String::from_utf8_lossy(&out.stdout);
-                        
-                        // unsquashfs -s output format:
-                        // "Filesystem size 248 bytes (0.24 Kbytes / 0.00 Mbytes)"
-                        // parts[0]="Filesystem" parts[1]="size" parts[2]="248" parts[3]="bytes"
-                        // We need to find line where parts[3] == "bytes" and parts[2] is an integer
-                        let mut fs_bytes: Option<u64> = None;
-                        for line in out_str.lines() {
-                            if line.contains("Filesystem size") && line.contains(" bytes ") {
-                                let parts: Vec<&str> = line.split_whitespace().collect();
-                                // parts[0]="Filesystem" parts[1]="size" parts[2]="248" parts[3]="bytes"
-                                if parts.len() >= 4 && parts[3] == "bytes" {
-                                    // Only accept if parts[2] is a pure integer (not "0.24")
-                                    if let Ok(bytes) = parts[2].parse::<u64>() {
-                                        fs_bytes = Some(bytes);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        
-                        if let Some(bytes) = fs_bytes {
-                            // Get Offset - we're already root
-                            match executor.run("cryptsetup", &["luksDump", output_str]) {
-                                Ok(dump) => {
-                                    let dump_str = String::from_utf8_lossy(&dump.stdout);
-                                    let mut offset: u64 = 0;
-                                    // LUKS2: "offset: 16777216 [bytes]"
-                                    for line in dump_str.lines() {
-                                        if line.trim().starts_with("offset:") && line.contains("bytes") {
-                                            if let Some(val_str) = line.split_whitespace().nth(1) {
-                                                if let Ok(val) = val_str.parse::<u64>() {
-                                                    offset = val;
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                        // LUKS1: "Payload offset: 4096" (sectors)
-                                        if line.trim().starts_with("Payload offset:") {
-                                            if let Some(val_str) = line.split_whitespace().nth(2) {
-                                                if let Ok(sect) = val_str.parse::<u64>() {
-                                                    offset = sect * 512;
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    
-                                    if offset > 0 {
-                                        // Calc total
-                                        let raw_trim = bytes + offset + 1024*1024; // +1MB safety margin
-                                        // Align to 4096
-                                        let aligned = ((raw_trim + 4095) / 4096) * 4096;
-                                        trim_size = Some(aligned);
-                                    }
-                                },
-                                Err(_) => {}, // luksDump failed, skip trim
-                            }
-                        }
-                    },
-                    Err(_) => {}, // unsquashfs failed, skip trim
-                }
+    let mut listing: Vec<String> = names
+        .iter()
+        .take(MOUNT_POINT_LISTING_LIMIT)
+        .map(|n| format!("  {}", n))
+        .collect();
+    if names.len() > MOUNT_POINT_LISTING_LIMIT {
+        listing.push(format!("  ... and {} more", names.len() - MOUNT_POINT_LISTING_LIMIT));
+    }
+    Err(ZkError::OperationFailed(format!(
+        "Mount point {} is not empty; mounting would hide its {} entr{}:\n{}\nUse --nonempty to mount over it anyway.",
+        mount_point.display(),
+        names.len(),
+        if names.len() == 1 { "y" } else { "ies" },
+        listing.join("\n")
+    )))
+}
 
-                // 6. Close and Finish Transaction
-                // We set success (preventing file deletion) and drop the transaction to trigger correct mapper closing
-                // This uses the robust logic in LuksTransaction::drop (sync, settle, retries, root rights)
-                transaction.set_success();
-                drop(transaction);
-                
-                // 7. Truncate (Safe now that mapper is closed)
-                if let Some(size) = trim_size {
-                    let container_file = fs::File::options().write(true).open(output_buf).map_err(|e| ZkError::IoError(e))?;
-                    let current_len = container_file.metadata()?.len();
-                    if size < current_len {
-                        println!(" Optimizing container size: {:.1}MB -> {:.1}MB", 
-                            current_len as f64 / 1024.0 / 1024.0, size as f64 / 1024.0 / 1024.0);
-                        container_file.set_len(size)?;
-                    }
-                }
+/// Apparent size of a directory in bytes (`du -sb`), 0 if it cannot be determined.
+fn dir_size_bytes(executor: &impl CommandExecutor, path: &str) -> u64 {
+    match executor.run("du", &["-sb", path]) {
+        Ok(output) if output.status.success() => {
+            let out_str = String::from_utf8_lossy(&output.stdout);
+            out_str.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
 
+/// Free-space pre-flight for a plain archive of `input` (a directory, or a tarball to repack):
+/// refuses when `output`'s filesystem cannot hold even a well-compressed archive.
+fn ensure_output_fits<P: SpaceProbe>(probe: &P, input: &Path, output: &Path, compression: u32) -> Result<(), ZkError> {
+    let needed = space::estimated_archive_size(space::tree_bytes(input), compression);
+    space::ensure_archive_fits(probe, output, needed)
+}
 
-                return Ok(());
-            }
+/// Encrypted flow: directory -> SquashFS inside a fresh (or existing) LUKS container.
+fn cmd_create_encrypted(
+    executor: &impl CommandExecutor,
+    opts: &CreateOptions,
+    packing_log: &Option<PackingLog>,
+) -> Result<(), ZkError> {
+    let input_path = &opts.input_path;
+    let final_output = &opts.output;
+    let (no_progress, alfa_progress) = (opts.no_progress, opts.alfa_progress);
+    // An existing container is opened, never formatted: appended to or its payload replaced
+    let existing = match opts.action {
+        Action::CreateNew => false,
+        Action::AppendLuks | Action::ReplaceLuksPayload => true,
+        Action::AppendPlain | Action::Refuse(_) => {
+            return Err(ZkError::OperationFailed(format!(
+                "{} cannot be packed encrypted ({:?})",
+                final_output.display(),
+                opts.action
+            )));
+        }
+    };
+    let (compression, mode, passphrase_attempts) = (opts.compression, opts.mode, opts.passphrase_attempts);
+    let exclude_file = &opts.exclude_file;
+    let comp_mode = kernel_compression(
+        CompressionMode::from_level(compression),
+        opts.kernel_zstd_supported,
+        opts.auto_fallback_compression,
+    );
 
+    // ...
+    // CRITICAL CHANGE: Disable archive support for LUKS due to persistent I/O errors
+    if !input_path.is_dir() {
+        return Err(ZkError::OperationFailed("Encrypted mode (-e) currently supports only DIRECTORIES.\nPlease extract the archive first and point to the directory.".to_string()));
+    }
 
-            // 1. Check if input exists - Moved to top
-            // if !input_path.exists() {
-            //     return Err(ZkError::InvalidPath(input_path.clone()));
-            // }
+    // Determine raw size (now strictly for directories)
+    // du -sb
+    let raw_size_bytes = dir_size_bytes(executor, input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?);
 
-            // 2. Archive Repacking (File -> SquashFS)
-            if input_path.is_file() {
-                let input_str = input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?;
-                // Use final_output resolved earlier
-                let output_buf = &final_output;
-                let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(final_output.clone()))?;
+    if raw_size_bytes == 0 {
+        return Err(ZkError::OperationFailed("Could not determine input directory size or empty input".to_string()));
+    }
 
-                // Determine decompressor
-                // Determine decompressor using infer (magic numbers)
-                use zero_kelvin::utils::ArchiveType;
-                let kind = zero_kelvin::utils::get_file_type(&input_path)?;
-                
-                let decompressor = match kind {
-                    ArchiveType::Tar => "cat",
-                    ArchiveType::Gzip => "gzip -dc",
-                    ArchiveType::Bzip2 => "bzip2 -dc",
-                    ArchiveType::Xz => "xz -dc",
-                    ArchiveType::Zstd => "zstd -dc",
-                    ArchiveType::Zip => "unzip -p",
-                    ArchiveType::SevenZ => "7z x -so",
-                    ArchiveType::Rar => "unrar p -inul",
-                    _ => {
-                         // Fallback to extension check if unknown (e.g. .tgz might detect as gzip, but maybe something eluded infer)
-                         // But for now, let's trust infer. If unknown, it's unsupported.
-                         return Err(ZkError::CompressionError(format!("Unsupported or unknown archive format for: {:?}", input_path)));
-                    }
-                };
+    let output_buf = final_output; // Use resolved path
 
-                // Determine compressor flag for tar2sqfs
-                let compressor_flag = comp_mode.get_tar2sqfs_compressor_flag()?;
-
-                // Construct pipeline: decompressor input | tar2sqfs options output
-                // Using explicit quoting for paths to handle spaces safely in sh -c
-                // Fixed: Do not pass compression level to -j (threads), use -c <compressor>
-                // SECURITY: all interpolated values are shell-quoted.
-                // compressor_flag is currently hardcoded but quoted defensively
-                // to prevent injection if it ever becomes configurable.
-                let cmd = format!(
-                    "{decompressor} '{input}' | tar2sqfs --quiet --no-skip --force {flag} '{output}'",
-                    decompressor = decompressor,
-                    input = input_str.replace("'", "'\\''"),
-                    flag = compressor_flag.replace("'", "'\\''"),
-                    output = output_str.replace("'", "'\\''")
-                );
+    // Appending/replacing: the container file is kept as it is (--overwrite-luks-content
+    // replaces its payload, not the container)
+    if existing {
+        recover_interrupted_trim(final_output);
+    } else {
+        // ... Normal creation logic ...
+        
+        // Overhead calc
+        let overhead_percent = match comp_mode {
+            // Stored as is: the image is the input plus its metadata
+            CompressionMode::None => LUKS_UNCOMPRESSED_OVERHEAD_PERCENT,
+            CompressionMode::Zstd(_) | CompressionMode::Gzip => get_fs_overhead_percentage(output_buf, executor),
+        };
+        let container_size = luks_container_size(raw_size_bytes, overhead_percent);
+        if !opts.no_space_check
+            && let Some(dest_dir) = zero_kelvin::utils::existing_ancestor(output_buf)
+        {
+            let what = format!("LUKS container {}", output_buf.display());
+            space::ensure_fits(container_size, space::filesystem_space(dest_dir)?, &opts.reserve, &what)?;
+        }
 
+        if std::env::var("RUST_LOG").is_ok() {
+            eprintln!("DEBUG: Encrypting directory. Input: {} bytes. Overhead: {}%. Allocating: {} bytes.", 
+                raw_size_bytes, overhead_percent, container_size);
+        }
+
+        // 1. Create container file with actual allocated space
+        // Using fallocate instead of sparse file (set_len) because:
+        // - Loop devices may fail to write to unallocated sparse regions
+        // - Some filesystems don't support sparse writes through loop
+        // fallocate -l <size> <file>
+        let size_str = container_size.to_string();
+        let output_str_create = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
+        
+        
+        // Fallback to dd if fallocate failed (non-success status) OR if we fell through above
+        // Re-check fallocate success? 
+        // Refactoring for clarity:
+        
+        let mut created = false;
+        let mut fallocate_stderr = String::new();
+        
+        let fallocate_res = executor.run("fallocate", &["-l", &size_str, output_str_create]);
+        
+        if let Ok(out) = fallocate_res {
+            if out.status.success() {
+                created = true;
+            } else {
+                fallocate_stderr = String::from_utf8_lossy(&out.stderr).to_string();
                 if std::env::var("RUST_LOG").is_ok() {
-                    eprintln!("DEBUG: Executing pipeline: {}", cmd);
+                    eprintln!("DEBUG: fallocate failed, try dd. Stderr: {}", fallocate_stderr);
                 }
+            }
+        } else if let Err(e) = fallocate_res {
+             fallocate_stderr = e.to_string();
+        }
+        
+        if !created {
+            let count = (container_size / (1024 * 1024)) + 1;
+            let dd_output = executor.run("dd", &[
+                "if=/dev/zero",
+                &format!("of={}", output_str_create),
+                "bs=1M",
+                &format!("count={}", count),
+                "status=none"
+            ])?;
+            
+            if !dd_output.status.success() {
+                let dd_err = String::from_utf8_lossy(&dd_output.stderr);
+                return Err(ZkError::OperationFailed(format!("Failed to create container file. fallocate error: '{}'. dd error: '{}'", fallocate_stderr.trim(), dd_err.trim())));
+            }
+        }
 
-                // Get input file size for display
-                let input_size = fs::metadata(&input_path)
-                    .map(|m| m.len())
-                    .unwrap_or(0);
-                let input_size_mb = input_size as f64 / 1024.0 / 1024.0;
+        // Restrict access right after creation (fallocate/dd use the umask)
+        zero_kelvin::utils::set_file_mode(output_buf, mode)?;
+    
+    } // End if !exists
 
-                // Create transaction for cleanup on failure
-                let output_buf = &final_output;
-                let mut transaction = CreateTransaction::new(output_buf.clone());
+    // Start Transaction for cleanup (a failed update closes the mapper but keeps the container)
+    let mut transaction = if existing {
+        LuksTransaction::for_existing(executor, output_buf)
+    } else {
+        LuksTransaction::new(executor, output_buf)
+    };
 
-                // Use 'set -o pipefail' so that if decompressor fails, the whole pipeline fails
-                let full_cmd = format!("set -o pipefail; {}", cmd);
-                
-                if no_progress {
-                    // Silent mode
-                    let output = executor.run("sh", &["-c", &full_cmd])?;
-                    if !output.status.success() {
-                        let stderr = String::from_utf8_lossy(&output.stderr);
-                        return Err(ZkError::OperationFailed(format!("Archive repack failed: {}", stderr)));
-                    }
-                } else {
-                    // Progress mode: show filling progress bar
-                    let pb = ProgressBar::new(input_size);
-                    pb.set_style(
-                        ProgressStyle::with_template(
-                            "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}"
-                        )
-                        .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
-                        .progress_chars("█▓▒░  ")
-                    );
-                    pb.set_message("Repacking archive → SquashFS");
-                    pb.enable_steady_tick(Duration::from_millis(100));
-
-                    let output = executor.run_with_file_progress(
-                        "sh",
-                        &["-c", &full_cmd],
-                        output_buf,
-                        &pb,
-                        Duration::from_millis(100),
-                    )?;
-                    
-                    if output.status.success() {
-                        pb.finish_with_message(format!(
-                            "✓ Repacked {:.1} MB successfully",
-                            input_size_mb
-                        ));
-                    } else {
-                        pb.finish_with_message("✗ Failed");
-                        let stderr = String::from_utf8_lossy(&output.stderr);
-                        return Err(ZkError::OperationFailed(format!("Archive repack failed: {}", stderr)));
-                    }
-                }
-                
-                transaction.set_success();
-                return Ok(());
+    let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
+    
+    // 2. Format LUKS (Only if new)
+    let root_cmd = get_effective_root_cmd();
+
+    let agent = passphrase_agent()?;
+    let passphrase = if !existing {
+        // Original Creation Logic
+        println!("Initializing LUKS container...");
+        eprintln!("Note: LUKS has built-in rate limiting. After several incorrect password attempts,");
+        eprintln!("      there will be increasing delays between attempts (up to 60 seconds).");
+        // Construct command: [sudo] cryptsetup luksFormat -q output
+        let mut luks_args = root_cmd.clone();
+        luks_args.extend(vec!["cryptsetup".to_string(), "luksFormat".to_string(), "-q".to_string(), output_str.to_string()]);
+        // Label the container with the archive name (shown by lsblk/blkid), if cryptsetup can
+        let label = luks::label_for(&final_output.file_name().unwrap_or_default().to_string_lossy());
+        if let Some(warning) =
+            luks::push_optional(&mut luks_args, luks::probe(executor), luks::OptionalFlag::Label, &[label])
+        {
+            eprintln!("{}", warning);
+        }
+        let passphrase = agent.ask_new(executor, &format!("New passphrase for {}", final_output.display()))?;
+        if passphrase.is_some() {
+            luks_args.push("--key-file=-".to_string());
+        }
+        
+        let prog = luks_args.remove(0);
+        let args_refs: Vec<&str> = luks_args.iter().map(|s| s.as_str()).collect();
+
+        let status = match &passphrase {
+            Some(passphrase) => {
+                let output = executor.run_with_stdin(&prog, &args_refs, passphrase.as_bytes())?;
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                output.status
             }
+            None => executor.run_interactive(&prog, &args_refs)?,
+        };
 
-            // 3. Standard Directory Packing (Directory -> SquashFS)
-            {
-                let output_buf = &final_output; // Use resolved path
-                let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
-                let input_str = input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?;
-                
-                // Transaction for cleanup
-                let mut transaction = CreateTransaction::new(output_buf.clone());
-
-                // 1. Pack Directory
-                let mk_result = {
-                    let mut cmd_args = vec![input_str, output_str];
-                    
-                    if no_progress {
-                        cmd_args.push("-no-progress");
-                    }
-                    
-                    
-                    let mut mksquashfs_args: Vec<String> = cmd_args.iter().map(|s: &&str| s.to_string()).collect();
-                    
-                    
-                    if !final_output.exists() {
-                         mksquashfs_args.push("-noappend".to_string());
-                    }
-                    // Else if existing (and we are here, meaning overwrite_files is true), we omit -noappend (default is append).
-
-                    
-                    // Compression
-                    comp_mode.apply_to_mksquashfs(&mut mksquashfs_args);
-                    
-                    // Convert back to Vec<&str> for execution args
-                    // This is a bit clumsy but safer given we modified Vec<String>
-                    // We need to pass &str to executor
-                    
-                    let mk_prog = "mksquashfs";
-                    
-                    // Helper to run with progress
-                    let run_with_progress = |args: &[String]| -> Result<(), ZkError> {
-                         let refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                         
-                        if no_progress {
-                             let output = executor.run(mk_prog, &refs)?;
-                             if !output.status.success() { 
-                                 let stderr = String::from_utf8_lossy(&output.stderr);
-                                 return Err(ZkError::OperationFailed(format!("mksquashfs failed: {}", stderr))); 
-                             }
-                        } else if vanilla_progress {
-                             let status = executor.run_interactive(mk_prog, &refs)?;
-                             if !status.success() { return Err(ZkError::OperationFailed("mksquashfs failed".to_string())); }
-                        } else if alfa_progress {
-                             // Fallback
-                             let output = executor.run_interactive(mk_prog, &refs)?;
-                             if !output.success() { return Err(ZkError::OperationFailed("mksquashfs failed".to_string())); }
-                        } else {
-                             // Default Custom Progress
-                             // Get directory size
-                             let dir_size = if let Ok(output) = executor.run("du", &["-sb", input_str]) {
-                                if output.status.success() {
-                                    let out_str = String::from_utf8_lossy(&output.stdout);
-                                    out_str.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0)
-                                } else { 0 }
-                            } else { 0 };
-                            let dir_size_mb = dir_size as f64 / 1024.0 / 1024.0;
-                            
-                            let pb = ProgressBar::new(dir_size);
-                            pb.set_style(
-                                ProgressStyle::with_template(
-                                    "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}"
-                                )
-                                .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
-                                .progress_chars("█▓▒░  ")
-                            );
-                            pb.set_message("Packing directory → SquashFS");
-                            pb.enable_steady_tick(Duration::from_millis(100));
-                            
-                            let output = executor.run_with_file_progress(
-                                mk_prog,
-                                &refs,
-                                output_buf,
-                                &pb,
-                                Duration::from_millis(100),
-                            )?;
-                            
-                            if output.status.success() {
-                                pb.finish_with_message(format!(
-                                    "✓ Packed {:.1} MB successfully",
-                                    dir_size_mb
-                                ));
-                            } else {
-                                pb.finish_with_message("✗ Failed");
-                                let stderr = String::from_utf8_lossy(&output.stderr);
-                                return Err(ZkError::OperationFailed(format!("mksquashfs failed: {}", stderr)));
-                            }
-                        }
-                        Ok(())
-                    };
-                    
-                    run_with_progress(&mksquashfs_args)
-                }; // block result
-                
-                if let Err(e) = mk_result {
-                    return Err(e);
-                }
+        if !status.success() {
+            return Err(ZkError::LuksError("luksFormat failed".to_string()));
+        }
+        passphrase
+    } else {
+         println!("Opening existing LUKS container for update...");
+         agent.ask(executor, &format!("Passphrase for {}", final_output.display()))?
+    };
 
-                transaction.set_success();
-                Ok(())
-            }
-        } // End Create
-        Commands::Mount { image, mount_point } => {
-            if !image.exists() {
-                return Err(ZkError::InvalidPath(image));
-            }
-            // Always use absolute path to ensure losetup/detection works reliably
-            let image = fs::canonicalize(image).map_err(|e| ZkError::IoError(e))?;
-
-            let target_mount_point = match mount_point {
-                Some(path) => path,
-                None => {
-                    // Auto-generate mount point
-                    let prefix = image.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("sqfs_image");
-                    
-                    let timestamp = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs();
-                    
-                    let random_suffix: u32 = rand::rng().random_range(100000..999999);
-                    let dir_name = format!("mount_{}_{}_{}", prefix, timestamp, random_suffix);
-                    
-                    // Use /tmp/0k-cache-<uid> for reliability (avoids FUSE-on-FUSE/Network issues)
-                    let zks_tmp = zero_kelvin::utils::get_0k_temp_dir()
-                        .map_err(|e| ZkError::StagingError(format!("Failed to get temp dir: {}", e)))?;
-                    
-                    let path = zks_tmp.join(dir_name);
-                    
-                    println!("No mount point specified. Using secure local path for stability: {}", path.display());
-                    path
-                }
-            };
+    // 3. Open (with atomic retry on mapper name collision)
+    let base_mapper_name = generate_mapper_name(output_buf);
+    if Path::new("/dev/mapper").join(&base_mapper_name).exists() {
+        let mounts = zero_kelvin::mounts::find_mounts_for_image(output_buf);
+        release_stale_mapper(executor, &root_cmd, &base_mapper_name, &mounts)?;
+    }
+    println!("Opening LUKS container...");
+    let mapper_name = open_luks_container(
+        executor,
+        &root_cmd,
+        output_str,
+        &base_mapper_name,
+        effective_passphrase_attempts(passphrase_attempts),
+        false,
+        passphrase.as_deref(),
+    )?;
+    
+    transaction.set_mapper(mapper_name.clone());
+    let mapper_path = format!("/dev/mapper/{}", mapper_name);
+
+    // 4. Pack Data
+    // Execute mksquashfs to mapper_path
+    let pack_result = {
+        let mut cmd_args = vec![
+             input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?.to_string(),
+             mapper_path.clone(),
+             "-no-recovery".to_string(),
+        ];
+        
+        // -noappend for a new container and for --overwrite-luks-content (the payload is
+        // packed anew); appending (--overwrite-files) omits it
+        if opts.action != Action::AppendLuks {
+             cmd_args.push("-noappend".to_string());
+        }
+        if no_progress { cmd_args.push("-no-progress".to_string()); }
+        if let Some(ef) = &exclude_file {
+            cmd_args.push("-ef".to_string());
+            cmd_args.push(ef.to_str().ok_or(ZkError::InvalidPath(ef.clone()))?.to_string());
+        }
+        cmd_args.extend(["-processors".to_string(), opts.threads.to_string()]);
+        comp_mode.apply_to_mksquashfs(&mut cmd_args);
+        cmd_args.push(mksquashfs_xattrs_flag(opts.no_xattrs).to_string());
+        if let Some(mem) = opts.mem {
+            cmd_args.extend(["-mem".to_string(), mksquashfs_mem_arg(mem)]);
+        }
+        cmd_args.extend(opts.mksquashfs_args.iter().cloned());
+        
+        // Construct: [sudo] mksquashfs ...
+        let mut mk_args = root_cmd.clone();
+        mk_args.extend(vec!["mksquashfs".to_string()]);
+        mk_args.extend(cmd_args);
+        log::info!("Running: {}", mk_args.join(" "));
+        
+        let mk_prog = mk_args.remove(0);
+        let mk_refs: Vec<&str> = mk_args.iter().map(|s| s.as_str()).collect();
+
+        // Progress bar logic based on flags
+        let output = if let Some(log) = &packing_log {
+            // Logging: output is teed to terminal and log file
+            executor.run_with_log(&mk_prog, &mk_refs, &log.path)?
+        } else if no_progress {
+            // No progress at all - just run silently
+            executor.run(&mk_prog, &mk_refs)?
+        } else if alfa_progress {
+            // EXPERIMENTAL: Custom progress bar - parse stdout for percentages (currently broken)
+            // Get directory size for display
+            let dir_size = dir_size_bytes(executor, input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?);
+            let dir_size_mb = dir_size as f64 / 1024.0 / 1024.0;
             
-            fs::create_dir_all(&target_mount_point).map_err(|e| ZkError::IoError(e))?;
+            let pb = ProgressBar::new(100);
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}% {msg}"
+                )
+                .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
+                .progress_chars("█▓▒░  ")
+            );
+            pb.set_message("Encrypting → SquashFS+LUKS");
+            pb.enable_steady_tick(Duration::from_millis(100));
             
-            // Check if this is a LUKS container
-            if zero_kelvin::utils::is_luks_image(&image, executor) {
-                if !zero_kelvin::utils::is_root().unwrap_or(false) {
-                    if let Some(runner) = zero_kelvin::utils::check_root_or_get_runner(
-                        "Mounting LUKS archives requires root privileges. Retrying with elevation...",
-                    )? {
-                        // RE-EXEC with EXPLICIT ARGUMENTS
-                        // We must pass the resolved mount point (owned by user) to the root process
-                        // so it doesn't auto-generate a new one in /tmp/0k-cache-0/.
-                        let args = vec![
-                            "mount".to_string(),
-                            image.to_string_lossy().into_owned(),
-                            target_mount_point.to_string_lossy().into_owned(),
-                        ];
-                        return zero_kelvin::utils::re_exec_with_runner_custom_args(&runner, &args);
-                    }
-                }
-                println!("Detected LUKS container. Opening encrypted image...");
-                
-                let mapper_name = generate_mapper_name(&image);
-                let mapper_path = format!("/dev/mapper/{}", mapper_name);
-                let root_cmd = get_effective_root_cmd();
+            let result = executor.run_with_stdout_progress(&mk_prog, &mk_refs, &pb)?;
+            
+            if result.status.success() {
+                pb.finish_with_message(format!("✓ Encrypted {:.1} MB", dir_size_mb));
+            } else {
+                pb.finish_with_message("✗ Failed");
+            }
+            result
+        } else {
+            // DEFAULT: Use mksquashfs native progress (interactive mode)
+            let status = executor.run_interactive(&mk_prog, &mk_refs)?;
+            std::process::Output {
+                status,
+                stdout: vec![],
+                stderr: vec![],
+            }
+        };
+
+        if !output.status.success() {
+             Err(ZkError::OperationFailed(format!("mksquashfs failed: {}{}", String::from_utf8_lossy(&output.stderr), log_hint(packing_log))))
+        } else { Ok(()) }
+    };
+
+    pack_result?;
+
+    // 4.1 --integrity-token: hash the payload while the mapper is open (stored after the trim)
+    let payload_digest = if opts.integrity_token {
+        luks_token::payload_sha256(Path::new(&mapper_path))
+            .inspect_err(|e| eprintln!("Warning: cannot hash the payload in {}: {}; no integrity token stored", mapper_path, e))
+            .ok()
+    } else {
+        None
+    };
+
+    // 5. Trim logic
+    // Need unsquashfs (sudo usually not needed for read, but reading from /dev/mapper requires root)
+    let mut trim_size: Option<u64> = None;
+    
+    // Get FS Size - we're already root in LUKS context, run directly
+    // unsquashfs -s /dev/mapper/..., then the offset from luksDump (either failed: skip trim)
+    if let Ok(info) = verify_mapper(executor, &[], &mapper_path, 0)
+        && let Some(bytes) = info.fs_size
+        && let Ok(dump) = executor.run("cryptsetup", &["luksDump", output_str])
+    {
+        let dump_str = String::from_utf8_lossy(&dump.stdout);
+        let mut offset: u64 = 0;
+        // LUKS2: "offset: 16777216 [bytes]"
+        for line in dump_str.lines() {
+            if line.trim().starts_with("offset:")
+                && line.contains("bytes")
+                && let Some(val_str) = line.split_whitespace().nth(1)
+                && let Ok(val) = val_str.parse::<u64>()
+            {
+                offset = val;
+                break;
+            }
+            // LUKS1: "Payload offset: 4096" (sectors)
+            if line.trim().starts_with("Payload offset:")
+                && let Some(val_str) = line.split_whitespace().nth(2)
+                && let Ok(sect) = val_str.parse::<u64>()
+            {
+                offset = sect * 512;
+                break;
+            }
+        }
+
+        if offset > 0 {
+            // Calc total
+            let raw_trim = bytes + offset + 1024*1024; // +1MB safety margin
+            // Align to 4096
+            let aligned = raw_trim.div_ceil(4096) * 4096;
+            trim_size = Some(aligned);
+        }
+    }
+
+    // 6. Close and Finish Transaction
+    // We set success (preventing file deletion) and drop the transaction to trigger correct mapper closing
+    // This uses the robust logic in LuksTransaction::drop (sync, settle, retries, root rights)
+    transaction.set_success();
+    drop(transaction);
+    
+    // 7. Truncate (Safe now that mapper is closed)
+    // Journaled: an interrupted trim is finished by the next operation on this archive
+    if let Some(size) = trim_size {
+        let current_len = fs::metadata(output_buf)?.len();
+        if size < current_len {
+            println!(" Optimizing container size: {:.1}MB -> {:.1}MB", 
+                current_len as f64 / 1024.0 / 1024.0, size as f64 / 1024.0 / 1024.0);
+            trim_journal::truncate(output_buf, size)?;
+        }
+    }
+
+    // 8. Payload digest into the header; an append keeps the archive id
+    if payload_digest.is_none() && existing {
+        // The payload changed: a digest recorded for the old one would fail every verify
+        match luks_token::remove(executor, &root_cmd, output_buf) {
+            Ok(true) => println!("Payload SHA-256 of the previous content removed (not recomputed without --integrity-token)"),
+            Ok(false) => {}
+            Err(e) => eprintln!("Warning: cannot remove the outdated payload SHA-256: {}", e),
+        }
+    }
+    if let Some(digest) = payload_digest {
+        let archive_id = luks_token::export(executor, output_buf)
+            .ok()
+            .flatten()
+            .map(|token| token.archive_id)
+            .unwrap_or_else(luks_token::new_archive_id);
+        match luks_token::store(executor, &root_cmd, output_buf, &luks_token::MetaToken::new(digest, archive_id)) {
+            Ok(luks_token::DigestSource::LuksToken) => println!("Payload SHA-256 stored in the LUKS2 header"),
+            Ok(luks_token::DigestSource::Sidecar) => println!(
+                "Payload SHA-256 written to {} (LUKS1 headers hold no tokens)",
+                luks_token::sidecar_path(output_buf).display()
+            ),
+            Err(e) => eprintln!("Warning: cannot store the payload SHA-256: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Finishes (or warns about) a LUKS container trim that was interrupted on `image`.
+fn recover_interrupted_trim(image: &Path) {
+    match trim_journal::recover(image) {
+        Ok(Some(recovery)) => eprintln!("{}", trim_journal::describe(image, &recovery)),
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: cannot check the trim journal of {}: {}", image.display(), e),
+    }
+}
+
+/// Archive repacking: tar (optionally compressed) -> SquashFS through tar2sqfs.
+fn cmd_create_repack(
+    executor: &impl CommandExecutor,
+    opts: &CreateOptions,
+    packing_log: &Option<PackingLog>,
+) -> Result<(), ZkError> {
+    let input_path = &opts.input_path;
+    let final_output = &opts.output;
+    let (no_progress, mode) = (opts.no_progress, opts.mode);
+    let comp_mode = CompressionMode::from_level(opts.compression);
+
+    let input_str = input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?;
+    // Use final_output resolved earlier
+    let output_buf = final_output;
+    let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(final_output.clone()))?;
+
+    // Determine decompressor
+    // Determine decompressor using infer (magic numbers)
+    use zero_kelvin::utils::ArchiveType;
+    let kind = zero_kelvin::utils::get_file_type(input_path)?;
+    
+    let decompressor: &[&str] = match kind {
+        ArchiveType::Tar => &["cat"],
+        ArchiveType::Gzip => &["gzip", "-dc"],
+        ArchiveType::Bzip2 => &["bzip2", "-dc"],
+        ArchiveType::Xz => &["xz", "-dc"],
+        ArchiveType::Zstd => &["zstd", "-dc"],
+        ArchiveType::Zip => &["unzip", "-p"],
+        ArchiveType::SevenZ => &["7z", "x", "-so"],
+        ArchiveType::Rar => &["unrar", "p", "-inul"],
+        _ => {
+             // Fallback to extension check if unknown (e.g. .tgz might detect as gzip, but maybe something eluded infer)
+             // But for now, let's trust infer. If unknown, it's unsupported.
+             return Err(ZkError::CompressionError(format!("Unsupported or unknown archive format for: {:?}", input_path)));
+        }
+    };
+
+    // Pipeline: decompressor input | tar2sqfs options output
+    // Both are spawned directly and connected by a pipe, so paths need no quoting
+    // and a failing decompressor is noticed without relying on `set -o pipefail`.
+    // Fixed: Do not pass compression level to -j (threads), use -c <compressor>
+    let mut first: Vec<&str> = decompressor.to_vec();
+    first.push(input_str);
+    let mut second = vec!["tar2sqfs", "--quiet", "--no-skip", "--force"];
+    if opts.no_xattrs {
+        second.push("--no-xattr");
+    }
+    second.extend(comp_mode.get_tar2sqfs_compressor_args()?);
+    let threads = opts.threads.to_string();
+    second.extend(["--num-jobs", threads.as_str()]);
+    second.push(output_str);
+
+    if std::env::var("RUST_LOG").is_ok() {
+        eprintln!("DEBUG: Executing pipeline: {} | {}", first.join(" "), second.join(" "));
+    }
+
+    // Get input file size for display
+    let input_size = fs::metadata(input_path)
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let input_size_mb = input_size as f64 / 1024.0 / 1024.0;
+
+    // Create transaction for cleanup on failure
+    let output_buf = final_output;
+    let mut transaction = CreateTransaction::new(output_buf.clone());
+
+    // Pre-create a new output with restrictive permissions; tar2sqfs --force keeps them
+    if !output_buf.exists() {
+        zero_kelvin::utils::create_file_with_mode(output_buf, mode)?;
+    }
+
+    if let Some(log) = &packing_log {
+        // Logging mode: tee pipeline output to terminal and log file
+        let output = executor.run_piped(&first, &second, Some(&log.path), None, output_buf)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ZkError::OperationFailed(format!("Archive repack failed: {}{}", stderr, log.hint())));
+        }
+    } else if no_progress {
+        // Silent mode
+        let output = executor.run_piped(&first, &second, None, None, output_buf)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ZkError::OperationFailed(format!("Archive repack failed: {}", stderr)));
+        }
+    } else {
+        // Progress mode: show filling progress bar
+        let pb = ProgressBar::new(input_size);
+        pb.set_style(
+            ProgressStyle::with_template(
+                "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}"
+            )
+            .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
+            .progress_chars("█▓▒░  ")
+        );
+        pb.set_message("Repacking archive → SquashFS");
+        pb.enable_steady_tick(Duration::from_millis(100));
+
+        let output = executor.run_piped(&first, &second, None, Some(&pb), output_buf)?;
+        
+        if output.status.success() {
+            pb.finish_with_message(format!(
+                "✓ Repacked {:.1} MB successfully",
+                input_size_mb
+            ));
+        } else {
+            pb.finish_with_message("✗ Failed");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ZkError::OperationFailed(format!("Archive repack failed: {}", stderr)));
+        }
+    }
+    
+    transaction.set_success();
+    Ok(())
+}
+
+/// Standard directory packing: directory -> SquashFS with mksquashfs.
+fn cmd_create_plain(
+    executor: &impl CommandExecutor,
+    opts: &CreateOptions,
+    packing_log: &Option<PackingLog>,
+) -> Result<(), ZkError> {
+    let input_path = &opts.input_path;
+    let final_output = &opts.output;
+    let (no_progress, vanilla_progress, alfa_progress) = (opts.no_progress, opts.vanilla_progress, opts.alfa_progress);
+    let mode = opts.mode;
+    let exclude_file = &opts.exclude_file;
+    let comp_mode = CompressionMode::from_level(opts.compression);
+
+    let output_buf = final_output; // Use resolved path
+    let output_str = output_buf.to_str().ok_or(ZkError::InvalidPath(output_buf.clone()))?;
+    let input_str = input_path.to_str().ok_or(ZkError::InvalidPath(input_path.clone()))?;
+    
+    // Transaction for cleanup (an append keeps the archive, and tracks the recovery file)
+    let appending = opts.action == Action::AppendPlain;
+    let mut transaction = if !appending {
+        CreateTransaction::new(output_buf.clone())
+    } else if opts.no_recovery {
+        CreateTransaction::append(output_buf.clone(), None)
+    } else {
+        CreateTransaction::append(output_buf.clone(), Some(RecoveryFiles::watch(output_buf)))
+    };
+
+    // 1. Pack Directory
+    let mk_result = {
+        let mut cmd_args = vec![input_str, output_str];
+        
+        if no_progress {
+            cmd_args.push("-no-progress");
+        }
+        
+        
+        let mut mksquashfs_args: Vec<String> = cmd_args.iter().map(|s: &&str| s.to_string()).collect();
+        
+        
+        if appending && opts.no_recovery {
+            mksquashfs_args.push("-no-recovery".to_string());
+        }
+        if !appending {
+             mksquashfs_args.push("-noappend".to_string());
+             // Pre-create with restrictive permissions; mksquashfs -noappend keeps them
+             zero_kelvin::utils::create_file_with_mode(output_buf, mode)?;
+        }
+        // Else appending (--overwrite-files): no -noappend, mksquashfs appends by default.
+
+        
+        if let Some(ef) = &exclude_file {
+            mksquashfs_args.push("-ef".to_string());
+            mksquashfs_args.push(ef.to_str().ok_or(ZkError::InvalidPath(ef.clone()))?.to_string());
+        }
+
+        // Compression
+        mksquashfs_args.extend(["-processors".to_string(), opts.threads.to_string()]);
+        comp_mode.apply_to_mksquashfs(&mut mksquashfs_args);
+        mksquashfs_args.push(mksquashfs_xattrs_flag(opts.no_xattrs).to_string());
+        if let Some(mem) = opts.mem {
+            mksquashfs_args.extend(["-mem".to_string(), mksquashfs_mem_arg(mem)]);
+        }
+        mksquashfs_args.extend(opts.mksquashfs_args.iter().cloned());
+        log::info!("Running: mksquashfs {}", mksquashfs_args.join(" "));
+        
+        // Convert back to Vec<&str> for execution args
+        // This is a bit clumsy but safer given we modified Vec<String>
+        // We need to pass &str to executor
+        
+        let mk_prog = "mksquashfs";
+        
+        // Helper to run with progress
+        let run_with_progress = |args: &[String]| -> Result<(), ZkError> {
+             let refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+             
+            if let Some(log) = &packing_log {
+                 let output = executor.run_with_log(mk_prog, &refs, &log.path)?;
+                 if !output.status.success() {
+                     let stderr = String::from_utf8_lossy(&output.stderr);
+                     return Err(ZkError::OperationFailed(format!("mksquashfs failed: {}{}", stderr, log.hint())));
+                 }
+            } else if no_progress {
+                 let output = executor.run(mk_prog, &refs)?;
+                 if !output.status.success() { 
+                     let stderr = String::from_utf8_lossy(&output.stderr);
+                     return Err(ZkError::OperationFailed(format!("mksquashfs failed: {}", stderr))); 
+                 }
+            } else if vanilla_progress {
+                 let status = executor.run_interactive(mk_prog, &refs)?;
+                 if !status.success() { return Err(ZkError::OperationFailed("mksquashfs failed".to_string())); }
+            } else if alfa_progress {
+                 // Fallback
+                 let output = executor.run_interactive(mk_prog, &refs)?;
+                 if !output.success() { return Err(ZkError::OperationFailed("mksquashfs failed".to_string())); }
+            } else {
+                 // Default Custom Progress
+                 // Get directory size
+                 let dir_size = dir_size_bytes(executor, input_str);
+                let dir_size_mb = dir_size as f64 / 1024.0 / 1024.0;
                 
-                // Check if mapper already exists
-                if PathBuf::from(&mapper_path).exists() {
-                    println!("Mapper device already exists. Attempting to mount...");
-                    
-                    // Try mounting existing mapper
-                    let mut mount_args = root_cmd.clone();
-                    mount_args.extend(vec![
-                        "mount".to_string(),
-                        "-t".to_string(),
-                        "squashfs".to_string(),
-                        mapper_path.clone(),
-                        target_mount_point.to_str().ok_or(ZkError::InvalidPath(target_mount_point.clone()))?.to_string(),
-                    ]);
-                    
-                    let prog = mount_args.remove(0);
-                    let args_refs: Vec<&str> = mount_args.iter().map(|s| s.as_str()).collect();
-                    
-                    if let Ok(output) = executor.run(&prog, &args_refs) {
-                        if output.status.success() {
-                            println!("Mounted at {}", target_mount_point.display());
-                            return Ok(());
-                        }
-                    }
-                    
-                    // Stale mapper - close and retry
-                    println!("Mount failed (stale mapper?). Closing and retrying...");
-                    let mut close_args = root_cmd.clone();
-                    close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name.clone()]);
-                    
-                    let close_prog = close_args.remove(0);
-                    let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
-                    let _ = executor.run(&close_prog, &close_refs);
-                }
+                let pb = ProgressBar::new(dir_size);
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.cyan} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}"
+                    )
+                    .map_err(|e| ZkError::OperationFailed(format!("Progress bar template error: {}", e)))?
+                    .progress_chars("█▓▒░  ")
+                );
+                pb.set_message("Packing directory → SquashFS");
+                pb.enable_steady_tick(Duration::from_millis(100));
                 
-                // Open LUKS container (with atomic retry on name collision)
-                println!("Opening encrypted container (password required)...");
-                eprintln!("Note: LUKS has built-in rate limiting. After several incorrect password attempts,");
-                eprintln!("      there will be increasing delays between attempts (up to 60 seconds).");
-                let image_str = image.to_str().ok_or(ZkError::InvalidPath(image.clone()))?;
-                let mapper_name = open_luks_container(
-                    executor,
-                    &root_cmd,
-                    image_str,
-                    &mapper_name,
+                let output = executor.run_with_file_progress(
+                    mk_prog,
+                    &refs,
+                    output_buf,
+                    &pb,
+                    Duration::from_millis(100),
                 )?;
-                let mapper_path = format!("/dev/mapper/{}", mapper_name);
-                
-                // Mount the mapper device
-                let mut mount_args = root_cmd.clone();
-                mount_args.extend(vec![
-                    "mount".to_string(),
-                    "-t".to_string(),
-                    "squashfs".to_string(),
-                    mapper_path.clone(),
-                    target_mount_point.to_str().ok_or(ZkError::InvalidPath(target_mount_point.clone()))?.to_string(),
-                ]);
                 
-                let mount_prog = mount_args.remove(0);
-                let mount_refs: Vec<&str> = mount_args.iter().map(|s| s.as_str()).collect();
-                
-                let output = executor.run(&mount_prog, &mount_refs)?;
-                
-                if !output.status.success() {
+                if output.status.success() {
+                    pb.finish_with_message(format!(
+                        "✓ Packed {:.1} MB successfully",
+                        dir_size_mb
+                    ));
+                } else {
+                    pb.finish_with_message("✗ Failed");
                     let stderr = String::from_utf8_lossy(&output.stderr);
-                    // Cleanup: close the mapper we just opened
-                    let mut close_args = root_cmd.clone();
-                    close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name]);
-                    let close_prog = close_args.remove(0);
-                    let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
-                    let _ = executor.run(&close_prog, &close_refs);
-                    
-                    return Err(ZkError::OperationFailed(format!("Mount failed: {}", stderr)));
+                    return Err(ZkError::OperationFailed(format!("mksquashfs failed: {}", stderr)));
                 }
-                
+            }
+            Ok(())
+        };
+        
+        run_with_progress(&mksquashfs_args)
+    }; // block result
+    
+    mk_result?;
+
+    transaction.set_success();
+    Ok(())
+}
+
+/// Mounts a plain image with squashfuse or a LUKS container read-only (`--list`: only reports).
+fn cmd_mount(executor: &impl CommandExecutor, opts: MountOptions) -> Result<(), ZkError> {
+    let MountOptions { image, mount_point, passphrase_attempts, list, nonempty } = opts;
+
+    if !image.exists() {
+        return Err(ZkError::InvalidPath(image));
+    }
+    // Always use absolute path to ensure losetup/detection works reliably
+    let image = fs::canonicalize(image).map_err(ZkError::IoError)?;
+
+    let existing = zero_kelvin::mounts::find_mounts_for_image(&image);
+    if list {
+        for found in &existing {
+            println!("{}\t{}", found.mount_point.display(), found.backend);
+        }
+        return Ok(());
+    }
+    recover_interrupted_trim(&image);
+    // Double mount: allowed (check/unfreeze mount their own copy), but worth knowing
+    for found in &existing {
+        eprintln!(
+            "Note: {} is already mounted at {} ({})",
+            image.display(),
+            found.mount_point.display(),
+            found.backend
+        );
+    }
+
+    let target_mount_point = match mount_point {
+        Some(path) => {
+            if !nonempty {
+                ensure_mount_point_empty(&path)?;
+            }
+            path
+        }
+        // Freshly created below: nothing to hide
+        None => {
+            // Auto-generate mount point
+            let prefix = image.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("sqfs_image");
+            
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            
+            // Use /tmp/0k-cache-<uid> for reliability (avoids FUSE-on-FUSE/Network issues);
+            // same private mount_<tag>_<random> scheme as the temporary mounts of check/unfreeze
+            let path = zero_kelvin::utils::create_temp_mount_point(&format!("{}_{}", prefix, timestamp))
+                .map_err(|e| ZkError::StagingError(format!("Failed to create mount point: {}", e)))?;
+            
+            println!("No mount point specified. Using secure local path for stability: {}", path.display());
+            path
+        }
+    };
+    
+    fs::create_dir_all(&target_mount_point).map_err(ZkError::IoError)?;
+    
+    // Check if this is a LUKS container
+    if zero_kelvin::utils::is_luks_image(&image) {
+        if !zero_kelvin::utils::is_root().unwrap_or(false)
+            && let Some(runner) = zero_kelvin::utils::check_root_or_get_runner(
+                "Mounting LUKS archives requires root privileges. Retrying with elevation...",
+            )?
+        {
+            // RE-EXEC with EXPLICIT ARGUMENTS
+            // We must pass the resolved mount point (owned by user) to the root process
+            // so it doesn't auto-generate a new one in /tmp/0k-cache-0/.
+            let args = vec![
+                "mount".to_string(),
+                image.to_string_lossy().into_owned(),
+                target_mount_point.to_string_lossy().into_owned(),
+                "--passphrase-attempts".to_string(),
+                passphrase_attempts.to_string(),
+                // Already checked (and possibly approved) here
+                "--nonempty".to_string(),
+            ];
+            return zero_kelvin::utils::re_exec_with_runner_custom_args(&runner, &args);
+        }
+        println!("Detected LUKS container. Opening encrypted image...");
+        
+        let mapper_name = generate_mapper_name(&image);
+        let mapper_path = format!("/dev/mapper/{}", mapper_name);
+        let root_cmd = get_effective_root_cmd();
+        
+        // Check if mapper already exists
+        if PathBuf::from(&mapper_path).exists() {
+            println!("Mapper device already exists. Attempting to mount...");
+            
+            // Try mounting existing mapper
+            let mut mount_args = luks_mount_command(&root_cmd, &mapper_path, &target_mount_point)?;
+            
+            let prog = mount_args.remove(0);
+            let args_refs: Vec<&str> = mount_args.iter().map(|s| s.as_str()).collect();
+            
+            if let Ok(output) = executor.run(&prog, &args_refs)
+                && output.status.success()
+            {
                 println!("Mounted at {}", target_mount_point.display());
                 return Ok(());
             }
             
-            // Plain SquashFS - use squashfuse (no root required)
-            let mp_str = target_mount_point.to_str().ok_or(ZkError::InvalidPath(target_mount_point.clone()))?;
-            let img_str = image.to_str().ok_or(ZkError::InvalidPath(image.clone()))?;
-            
-            // Added -o nonempty to allow mounting over non-empty directories
-            let output = executor.run("squashfuse", &["-o", "nonempty", img_str, mp_str])?;
+            // Stale mapper - close and retry
+            println!("Mount failed (stale mapper?). Closing and retrying...");
+            let mut close_args = root_cmd.clone();
+            close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name.clone()]);
             
-             if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(ZkError::OperationFailed(format!("squashfuse failed: {}", stderr)));
+            let close_prog = close_args.remove(0);
+            let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
+            let _ = executor.run(&close_prog, &close_refs);
+        }
+        
+        // Open LUKS container (with atomic retry on name collision)
+        println!("Opening encrypted container (password required)...");
+        eprintln!("Note: LUKS has built-in rate limiting. After several incorrect password attempts,");
+        eprintln!("      there will be increasing delays between attempts (up to 60 seconds).");
+        let image_str = image.to_str().ok_or(ZkError::InvalidPath(image.clone()))?;
+        let passphrase = passphrase_agent()?.ask(executor, &format!("Passphrase for {}", image.display()))?;
+        let mapper_name = open_luks_container(
+            executor,
+            &root_cmd,
+            image_str,
+            &mapper_name,
+            effective_passphrase_attempts(passphrase_attempts),
+            true, // read-only: mounted archives are never written to
+            passphrase.as_deref(),
+        )?;
+        let mapper_path = format!("/dev/mapper/{}", mapper_name);
+        let close_mapper = || {
+            let mut close_args = root_cmd.clone();
+            close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name.clone()]);
+            let close_prog = close_args.remove(0);
+            let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
+            let _ = executor.run(&close_prog, &close_refs);
+        };
+
+        // Pre-check: the right passphrase on a container without a SquashFS inside
+        match verify_mapper(executor, &root_cmd, &mapper_path, 0) {
+            Ok(info) => log::info!("{}: {}", image.display(), info.summary()),
+            Err(ZkError::IoError(e)) => log::debug!("Mount pre-check skipped (unsquashfs not available?): {}", e),
+            Err(e) => {
+                close_mapper();
+                return Err(e);
             }
+        }
+        
+        // Mount the mapper device
+        let mut mount_args = luks_mount_command(&root_cmd, &mapper_path, &target_mount_point)?;
+        
+        let mount_prog = mount_args.remove(0);
+        let mount_refs: Vec<&str> = mount_args.iter().map(|s| s.as_str()).collect();
+        
+        let output = executor.run(&mount_prog, &mount_refs)?;
+        
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // Cleanup: close the mapper we just opened
+            close_mapper();
             
-            Ok(())
-        },
+            return Err(ZkError::OperationFailed(format!("Mount failed: {}", stderr)));
+        }
+        
+        println!("Mounted at {}", target_mount_point.display());
+        return Ok(());
+    }
+    
+    // Plain SquashFS - use squashfuse (no root required)
+    // Pre-check: a clear error for files that are not SquashFS at all, instead of squashfuse's
+    match squashfs_info::read(&image, executor) {
+        Ok(info) => log::info!("{}: {}", image.display(), info.summary()),
+        Err(ZkError::IoError(e)) => log::debug!("Mount pre-check skipped (unsquashfs not available?): {}", e),
+        Err(e) => return Err(e),
+    }
+    let mp_str = target_mount_point.to_str().ok_or(ZkError::InvalidPath(target_mount_point.clone()))?;
+    let img_str = image.to_str().ok_or(ZkError::InvalidPath(image.clone()))?;
+    
+    // FUSE refuses non-empty mount points unless told otherwise; only do so on request
+    let mut fuse_args = Vec::new();
+    if nonempty {
+        fuse_args.extend(["-o", "nonempty"]);
+    }
+    fuse_args.extend([img_str, mp_str]);
+    let output = executor.run("squashfuse", &fuse_args)?;
+    
+     if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ZkError::OperationFailed(format!("squashfuse failed: {}", stderr)));
+    }
+    
+    Ok(())
+}
 
+/// Unmounts a mount point, or every mount of an image, closing LUKS mappers.
+fn cmd_umount(executor: &impl CommandExecutor, opts: UmountOptions) -> Result<(), ZkError> {
+    let UmountOptions { target: mount_point, lazy } = opts;
 
-        Commands::Umount { mount_point } => {
-            let path = &mount_point;
-            let root_cmd = get_effective_root_cmd();
-            
-            if !path.exists() {
-                return Err(ZkError::InvalidPath(path.clone()));
-            }
+    let path = &mount_point;
+    let root_cmd = get_effective_root_cmd();
+    
+    if !path.exists() {
+        return Err(ZkError::InvalidPath(path.clone()));
+    }
 
-            let mut targets = Vec::new();
+    let mut targets = Vec::new();
 
-            if path.is_dir() {
-                targets.push(path.clone());
-            } else if path.is_file() {
-                // It's an image file. Find matching squashfuse processes.
-                let abs_path = fs::canonicalize(path)
-                    .map_err(|e| ZkError::IoError(e))?;
-                let abs_path_str = abs_path.to_str().unwrap_or("");
-                
-                if std::env::var("RUST_LOG").is_ok() {
-                    eprintln!("DEBUG: Scanning processes for image: '{}'", abs_path_str);
-                }
+    if path.is_dir() {
+        targets.push(path.clone());
+    } else if path.is_file() {
+        // It's an image file. Find where it is mounted.
+        let abs_path = fs::canonicalize(path)
+            .map_err(ZkError::IoError)?;
+        let abs_path_str = abs_path.to_str().unwrap_or("");
+        
+        if std::env::var("RUST_LOG").is_ok() {
+            eprintln!("DEBUG: Scanning processes for image: '{}'", abs_path_str);
+        }
 
-                // Iterate over /proc (with limit for DoS protection)
-                let proc_dir = fs::read_dir("/proc").map_err(|e| ZkError::IoError(e))?;
-                let mut scan_count = 0;
-                
-                for entry in proc_dir {
-                    // DoS protection: limit number of processes scanned
-                    scan_count += 1;
-                    if scan_count > PROC_SCAN_LIMIT {
-                        eprintln!("Warning: /proc scan limit ({}) reached, some mounts may not be found", PROC_SCAN_LIMIT);
-                        break;
-                    }
-                    if let Ok(entry) = entry {
-                        let file_name = entry.file_name();
-                        let file_name_str = file_name.to_str().unwrap_or("");
-                        
-                        // Check if it's a PID (all digits)
-                        if file_name_str.chars().all(|c| c.is_ascii_digit()) {
-                             let cmdline_path = entry.path().join("cmdline");
-                             if let Ok(cmdline) = fs::read_to_string(cmdline_path) {
-                                 // cmdline is null-separated
-                                 let args: Vec<&str> = cmdline.split('\0').collect();
-                                 
-                                 if args.is_empty() { continue; }
-                                 
-                                 // Check if process name contains squashfuse
-                                 let prog_name = args[0];
-                                 if prog_name.contains("squashfuse") {
-                                     // Look for the image path in arguments
-                                     // squashfuse [options] IMAGE MOUNTPOINT
-                                     
-                                     for (i, arg) in args.iter().enumerate() {
-                                         // Skip empty args and options
-                                         if arg.is_empty() || arg.starts_with('-') {
-                                             continue;
-                                         }
-                                         
-                                         // Try to canonicalize the argument to handle:
-                                         // 1. Relative paths (./image.sqfs vs /full/path/image.sqfs)
-                                         // 2. Symlinks (/home/user vs /home/share/user)
-                                         let arg_path = PathBuf::from(arg);
-                                         let matches = if let Ok(arg_canonical) = fs::canonicalize(&arg_path) {
-                                             arg_canonical == abs_path
-                                         } else {
-                                             // If canonicalize fails, fall back to string comparison
-                                             *arg == abs_path_str
-                                         };
-                                         
-                                         if matches {
-                                             if i + 1 < args.len() {
-                                                 let potential_mount = args[i+1];
-                                                 if !potential_mount.starts_with('-') && !potential_mount.is_empty() {
-                                                     if std::env::var("RUST_LOG").is_ok() {
-                                                         eprintln!("DEBUG: Found match! pid {} mountpoint '{}'", file_name_str, potential_mount);
-                                                     }
-                                                     targets.push(PathBuf::from(potential_mount));
-                                                 }
-                                             }
-                                         }
-                                     }
-                                 }
-                             }
-                        }
-                    }
-                }
-                
-                // If no squashfuse found, check for LUKS mounts
-                // LUKS images are mounted via loop device -> cryptsetup -> /dev/mapper/sq_* -> mount
-                if targets.is_empty() {
-                    if std::env::var("RUST_LOG").is_ok() {
-                        eprintln!("DEBUG: No squashfuse found, checking for LUKS mounts...");
-                    }
-                    
-                    // Find loop device(s) associated with this file
-                    // losetup -j <file> shows: /dev/loop0: []: (<file>)
-                    // We try regular user first, then root if needed
-                    let mut losetup_output = executor.run("losetup", &["-j", abs_path_str]);
-                    
-                    // Fallback to root only if failed (permission denied), not if just empty (no loops found)
-                    if let Ok(ref out) = losetup_output {
-                        if !out.status.success() {
-                            let mut args = root_cmd.clone();
-                            args.extend(vec!["losetup".to_string(), "-j".to_string(), abs_path_str.to_string()]);
-                            let prog = args.remove(0);
-                            let refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                            losetup_output = executor.run(&prog, &refs);
-                        }
-                    }
+        // squashfuse processes, LUKS mappers and loop mounts of this image
+        for found in zero_kelvin::mounts::try_find_mounts_for_image(&abs_path)? {
+            if std::env::var("RUST_LOG").is_ok() {
+                eprintln!("DEBUG: Found {} mount at '{}'", found.backend, found.mount_point.display());
+            }
+            targets.push(found.mount_point);
+        }
+        
+        if targets.is_empty() {
+            return Err(ZkError::OperationFailed(format!("Image is not mounted (no squashfuse or LUKS mount found): {:?}", path)));
+        }
+    } else {
+         return Err(ZkError::InvalidPath(path.clone()));
+    }
+    
+    for target in targets {
+        let target_str = target.to_str().ok_or(ZkError::InvalidPath(target.clone()))?;
+        
+        // Detect source device using findmnt (doesn't need root - just reads /proc/mounts)
+        let mut source_device: Option<String> = None;
+        
+        if let Ok(output) = executor.run("findmnt", &["-n", "-o", "SOURCE", target_str])
+            && output.status.success()
+        {
+            source_device = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+        
+        // Get root_cmd only if needed (for LUKS unmount operations)
+        // root_cmd is now retrieved at function scope
+        // let root_cmd = get_effective_root_cmd();
 
-                    if let Ok(output) = losetup_output {
-                        if output.status.success() {
-                            let out_str = String::from_utf8_lossy(&output.stdout);
-                            for line in out_str.lines() {
-                                // Parse /dev/loopX from the output
-                                if let Some(loop_dev) = line.split(':').next() {
-                                    let loop_dev = loop_dev.trim();
-                                    if std::env::var("RUST_LOG").is_ok() {
-                                        eprintln!("DEBUG: Found loop device: {}", loop_dev);
-                                    }
-                                    
-                                    // Now find mounts from /dev/mapper/sq_* that use this loop device
-                                    // Read /proc/mounts to find mount points for sq_* mappers
-                                    if let Ok(mounts) = fs::read_to_string("/proc/mounts") {
-                                        for mount_line in mounts.lines() {
-                                            let parts: Vec<&str> = mount_line.split_whitespace().collect();
-                                            if parts.len() >= 2 {
-                                                let source = parts[0];
-                                                let mount_point = &zero_kelvin::engine::unescape_mountinfo_octal(parts[1]);
-                                                
-                                                // Check if it's a LUKS mapper (uses configured prefix)
-                                                let mapper_prefix_path = format!("/dev/mapper/{}", LUKS_MAPPER_PREFIX);
-                                                if source.starts_with(&mapper_prefix_path) {
-                                                    // Verify this mapper uses our loop device
-                                                    // dmsetup table sq_* shows the backing device
-                                                    let mapper_name = source.trim_start_matches("/dev/mapper/");
-                                                    
-                                                    // Try dmsetup (user -> root fallback)
-                                                    let mut dm_output = executor.run("dmsetup", &["deps", "-o", "devname", mapper_name]);
-                                                    
-                                                    if let Ok(ref out) = dm_output {
-                                                        if !out.status.success() {
-                                                             let mut args = root_cmd.clone();
-                                                             args.extend(vec!["dmsetup".to_string(), "deps".to_string(), "-o".to_string(), "devname".to_string(), mapper_name.to_string()]);
-                                                             let prog = args.remove(0);
-                                                             let refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                                                             dm_output = executor.run(&prog, &refs);
-                                                        }
-                                                    }
-
-                                                    if let Ok(dm_output) = dm_output {
-                                                        if dm_output.status.success() {
-                                                            let dm_str = String::from_utf8_lossy(&dm_output.stdout);
-                                                            // Output like: 1 dependencies  : (loop0)
-                                                            let loop_name = loop_dev.trim_start_matches("/dev/");
-                                                            if dm_str.contains(loop_name) {
-                                                                if std::env::var("RUST_LOG").is_ok() {
-                                                                    eprintln!("DEBUG: Found LUKS mount: {} at {}", source, mount_point);
-                                                                }
-                                                                targets.push(PathBuf::from(mount_point));
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                
-                if targets.is_empty() {
-                    return Err(ZkError::OperationFailed(format!("Image is not mounted (no squashfuse or LUKS mount found): {:?}", path)));
-                }
-            } else {
-                 return Err(ZkError::InvalidPath(path.clone()));
+        
+        // Determine unmount method based on source device
+        let mapper_prefix_path = format!("/dev/mapper/{}", LUKS_MAPPER_PREFIX);
+        let is_luks_mapper = source_device.as_ref()
+            .map(|dev| dev.starts_with(&mapper_prefix_path))
+            .unwrap_or(false);
+        
+        if is_luks_mapper {
+            // LUKS mount - use sudo umount
+            println!("Unmounting LUKS mapper...");
+            let mut umount_args = root_cmd.clone();
+            umount_args.push("umount".to_string());
+            if lazy {
+                umount_args.push("-l".to_string());
             }
+            umount_args.push(target_str.to_string());
             
-            for target in targets {
-                let target_str = target.to_str().ok_or(ZkError::InvalidPath(target.clone()))?;
-                
-                // Detect source device using findmnt (doesn't need root - just reads /proc/mounts)
-                let mut source_device: Option<String> = None;
+            let prog = umount_args.remove(0);
+            let args_refs: Vec<&str> = umount_args.iter().map(|s| s.as_str()).collect();
+            
+            unmount_with_retry(executor, "umount", &prog, &args_refs, &target)?;
+            
+            // Close LUKS mapper
+            if let Some(dev) = source_device {
+                let mapper_name = dev.trim_start_matches("/dev/mapper/");
+                println!("Closing LUKS container {}...", mapper_name);
                 
-                if let Ok(output) = executor.run("findmnt", &["-n", "-o", "SOURCE", target_str]) {
-                    if output.status.success() {
-                        source_device = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
-                    }
-                }
+                let mut close_args = root_cmd.clone();
+                close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name.to_string()]);
                 
-                // Get root_cmd only if needed (for LUKS unmount operations)
-                // root_cmd is now retrieved at function scope
-                // let root_cmd = get_effective_root_cmd();
-
+                let close_prog = close_args.remove(0);
+                let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
                 
-                // Determine unmount method based on source device
-                let mapper_prefix_path = format!("/dev/mapper/{}", LUKS_MAPPER_PREFIX);
-                let is_luks_mapper = source_device.as_ref()
-                    .map(|dev| dev.starts_with(&mapper_prefix_path))
-                    .unwrap_or(false);
+                let output = executor.run(&close_prog, &close_refs)?;
                 
-                if is_luks_mapper {
-                    // LUKS mount - use sudo umount
-                    println!("Unmounting LUKS mapper...");
-                    let mut umount_args = root_cmd.clone();
-                    umount_args.extend(vec!["umount".to_string(), target_str.to_string()]);
-                    
-                    let prog = umount_args.remove(0);
-                    let args_refs: Vec<&str> = umount_args.iter().map(|s| s.as_str()).collect();
-                    
-                    let output = executor.run(&prog, &args_refs)?;
-                    
-                    if !output.status.success() {
-                        let stderr = String::from_utf8_lossy(&output.stderr);
-                        return Err(ZkError::OperationFailed(format!("umount failed for {:?}: {}", target, stderr)));
-                    }
-                    
-                    // Close LUKS mapper
-                    if let Some(dev) = source_device {
-                        let mapper_name = dev.trim_start_matches("/dev/mapper/");
-                        println!("Closing LUKS container {}...", mapper_name);
-                        
-                        let mut close_args = root_cmd.clone();
-                        close_args.extend(vec!["cryptsetup".to_string(), "close".to_string(), mapper_name.to_string()]);
-                        
-                        let close_prog = close_args.remove(0);
-                        let close_refs: Vec<&str> = close_args.iter().map(|s| s.as_str()).collect();
-                        
-                        let output = executor.run(&close_prog, &close_refs)?;
-                        
-                        if !output.status.success() {
-                            let stderr = String::from_utf8_lossy(&output.stderr);
-                            eprintln!("Warning: Failed to close LUKS mapper: {}", stderr);
-                        }
-                    }
-                } else {
-                    // Plain squashfuse mount - use fusermount -u
-                    let output = executor.run("fusermount", &["-u", target_str])?;
-                                        if !output.status.success() {
-                          let stderr = String::from_utf8_lossy(&output.stderr);
-                          return Err(ZkError::OperationFailed(format!("fusermount failed for {:?}: {}", target, stderr)));
-                     }
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    eprintln!("Warning: Failed to close LUKS mapper: {}", stderr);
                 }
-                
-                // Post-unmount cleanup: remove directory if empty
-                let _ = fs::remove_dir(&target);
             }
-
-            Ok(())
+        } else {
+            // Plain squashfuse mount - use fusermount -u
+            let fuse_args: &[&str] = if lazy { &["-u", "-z", target_str] } else { &["-u", target_str] };
+            unmount_with_retry(executor, "fusermount", "fusermount", fuse_args, &target)?;
         }
+        
+        // Post-unmount cleanup: remove directory if empty
+        let _ = fs::remove_dir(&target);
     }
+
+    Ok(())
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::os::unix::fs::PermissionsExt;
     use std::os::unix::process::ExitStatusExt;
     use std::path::Path;
     use std::process::Output;
+    use std::env;
     use zero_kelvin::executor::MockCommandExecutor;
-    use zero_kelvin::constants::DEFAULT_ZSTD_COMPRESSION;
+    use zero_kelvin::constants::{DEFAULT_ZSTD_COMPRESSION, LUKS_PASSPHRASE_ATTEMPTS};
     use mockall::predicate::*;
 
 
@@ -1819,21 +2581,27 @@ mod tests {
         let input_path = temp_dir.path().to_path_buf();
         let input_path_str = input_path.to_str().unwrap();
         let input_path_check = input_path_str.to_string();
+        let out_dir = tempfile::tempdir().unwrap();
+        let output_path = out_dir.path().join("output.sqfs");
+        let output_path_check = output_path.to_str().unwrap().to_string();
 
         let mut mock = MockCommandExecutor::new();
-        // Expectation: mksquashfs input_dir output.sqfs -no-progress -comp zstd -Xcompression-level <DEFAULT_ZSTD_COMPRESSION>
+        // Expectation: mksquashfs input_dir output.sqfs -no-progress -noappend -processors 2 -comp zstd -Xcompression-level <DEFAULT_ZSTD_COMPRESSION> -xattrs
         mock.expect_run()
             .withf(move |program, args: &[&str]| {
                  program == "mksquashfs" &&
-                 args.len() == 8 &&
+                 args.len() == 11 &&
                  args[0] == input_path_check &&
-                 args[1] == "output.sqfs" &&
+                 args[1] == output_path_check &&
                  args[2] == "-no-progress" &&
                  args[3] == "-noappend" &&
-                 args[4] == "-comp" &&
-                 args[5] == "zstd" &&
-                 args[6] == "-Xcompression-level" &&
-                 args[7] == DEFAULT_ZSTD_COMPRESSION.to_string()
+                 args[4] == "-processors" &&
+                 args[5] == "2" &&
+                 args[6] == "-comp" &&
+                 args[7] == "zstd" &&
+                 args[8] == "-Xcompression-level" &&
+                 args[9] == DEFAULT_ZSTD_COMPRESSION.to_string() &&
+                 args[10] == "-xattrs"
             })
             .times(1)
             .returning(|_, _| Ok(Output {
@@ -1845,7 +2613,7 @@ mod tests {
         let args = Args {
             command: Commands::Create {
                 input_path: input_path,
-                output_path: Some(PathBuf::from("output.sqfs")),
+                output_path: Some(output_path.clone()),
                 encrypt: false,
                 compression: DEFAULT_ZSTD_COMPRESSION,
                 no_progress: true,
@@ -1853,14 +2621,70 @@ mod tests {
                 alfa_progress: false,
                 overwrite_files: false,
                 overwrite_luks_content: false,
+                log_file: None,
+                debug_log: false,
+                keep_log: false,
+                mode: None,
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: false,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
             },
         };
 
         run(args, &mock).unwrap();
+        let perms = fs::metadata(&output_path).unwrap().permissions();
+        assert_eq!(perms.mode() & 0o777, DEFAULT_ARCHIVE_MODE, "New plain archive must be private");
     }
 
-    #[test]
-    fn test_create_encrypted_flow() {
+    /// `create` options packing `input_path` into `output` at the default level, with no
+    /// progress, 2 threads and a kernel that mounts zstd; tests change what they exercise.
+    fn create_options(input_path: PathBuf, output: PathBuf, action: Action) -> CreateOptions {
+        CreateOptions {
+            input_path,
+            output,
+            compression: DEFAULT_ZSTD_COMPRESSION,
+            no_progress: true,
+            vanilla_progress: false,
+            alfa_progress: false,
+            action,
+            mode: DEFAULT_ARCHIVE_MODE,
+            passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+            exclude_file: None,
+            no_xattrs: false,
+            reserve: Reserve::default(),
+            no_space_check: false,
+            mksquashfs_args: vec![],
+            mem: None,
+            threads: 2,
+            auto_fallback_compression: false,
+            kernel_zstd_supported: Some(true),
+            no_recovery: false,
+            integrity_token: false,
+        }
+    }
+
+    /// Runs the encrypted create through the mocked LUKS flow, with `cryptsetup --version`
+    /// printing `cryptsetup` and the options from [`create_options`] changed by `configure`;
+    /// returns the luksFormat arguments (without a runner), the mksquashfs arguments and the
+    /// size passed to fallocate.
+    fn run_encrypted_create(
+        cryptsetup: &'static str,
+        configure: impl FnOnce(&mut CreateOptions),
+    ) -> (Vec<String>, Vec<String>, u64) {
+        use std::sync::{Arc, Mutex};
+        let format_args = Arc::new(Mutex::new(Vec::new()));
+        let mk_args = Arc::new(Mutex::new(Vec::new()));
+        let allocated = Arc::new(Mutex::new(0u64));
+
         // Setup
         let temp_dir = tempfile::tempdir().unwrap();
         let input_path = temp_dir.path().join("input_dir");
@@ -1871,6 +2695,10 @@ mod tests {
         let output_path = temp_dir.path().join("encrypted.sqfs");
         let output_str = output_path.to_str().unwrap().to_string();
 
+        let mut opts = create_options(input_path.clone(), output_path.clone(), Action::CreateNew);
+        configure(&mut opts);
+        let compression = opts.compression;
+
         let mut mock = MockCommandExecutor::new();
         
         // 1. du -sb (Size calc)
@@ -1886,13 +2714,13 @@ mod tests {
                 stderr: vec![],
             }));
 
-        // 2. stat -f -c %T (Overhead calc)
+        // 2. stat -f -c %T (Overhead calc; not needed for uncompressed payloads)
         let parent = temp_dir.path().to_str().unwrap().to_string();
         mock.expect_run()
             .withf(move |program, args| {
                 program == "stat" && args == vec!["-f", "-c", "%T", parent.as_str()]
             })
-            .times(1)
+            .times(if compression == 0 { 0 } else { 1 })
             .returning(|_, _| Ok(Output {
                 status: std::process::ExitStatus::from_raw(0),
                 stdout: b"ext2/ext3\n".to_vec(),
@@ -1907,57 +2735,69 @@ mod tests {
                 program == "fallocate" && args.len() == 3 && args[0] == "-l"
             })
             .times(1)
-            .returning(move |_, args| {
-                // Create the file that fallocate would create
-                let file_path = args[2];
-                let _ = fs::File::create(file_path);
-                Ok(Output {
-                    status: std::process::ExitStatus::from_raw(0),
-                    stdout: vec![],
-                    stderr: vec![],
-                })
+            .returning({
+                let allocated = allocated.clone();
+                move |_, args| {
+                    *allocated.lock().unwrap() = args[1].parse().unwrap();
+                    // Create the file that fallocate would create
+                    let file_path = args[2];
+                    let _ = fs::File::create(file_path);
+                    Ok(Output {
+                        status: std::process::ExitStatus::from_raw(0),
+                        stdout: vec![],
+                        stderr: vec![],
+                    })
+                }
             });
 
-        // 3. luksFormat
-        let output_str_3 = output_str.clone();
+        // 3. cryptsetup --version (which optional luksFormat flags it takes), then luksFormat
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args == ["--version"])
+            .times(1)
+            .returning(move |_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: cryptsetup.as_bytes().to_vec(),
+                stderr: vec![],
+            }));
         mock.expect_run_interactive()
-            .withf(move |program, args| {
+            .withf(|program, args| {
                  // Check if program is a known runner or direct call
                  let is_runner = ["sudo", "doas", "run0"].contains(&program);
-                 let is_direct = program == "cryptsetup";
-                 
-                 if is_direct {
-                     args == vec!["luksFormat", "-q", output_str_3.as_str()]
-                 } else if is_runner {
-                     args == vec!["cryptsetup", "luksFormat", "-q", output_str_3.as_str()]
-                 } else {
-                     false
-                 }
+                 (program == "cryptsetup" || is_runner && args.first() == Some(&"cryptsetup")) && args.contains(&"luksFormat")
             })
             .times(1)
-            .returning(|_, _| Ok(std::process::ExitStatus::from_raw(0)));
+            .returning({
+                let format_args = format_args.clone();
+                move |_, args| {
+                    let args = args.strip_prefix(&["cryptsetup"]).unwrap_or(args);
+                    *format_args.lock().unwrap() = args.iter().map(|a| a.to_string()).collect();
+                    Ok(std::process::ExitStatus::from_raw(0))
+                }
+            });
 
         // 4. open
         let output_str_4 = output_str.clone();
-        mock.expect_run_interactive()
+        mock.expect_run_and_capture_error()
             .withf(move |program, args| {
                 let is_runner = ["sudo", "doas", "run0"].contains(&program);
                 let is_direct = program == "cryptsetup";
                 
-                let check_args = |a: &&[&str]| a.contains(&"open") && a.contains(&output_str_4.as_str());
+                // create writes into the mapper: it must never be opened read-only
+                let check_args = |a: &&[&str]| a.contains(&"open") && a.contains(&output_str_4.as_str())
+                    && !a.contains(&"--readonly");
 
                 if is_direct {
                     check_args(&args)
                 } else if is_runner {
                     // Args should contain cryptsetup, open, path... 
                     // But args to runner are ["cryptsetup", "open", ...]
-                    args.contains(&"cryptsetup") && args.contains(&"open") && args.contains(&output_str_4.as_str())
+                    args.contains(&"cryptsetup") && check_args(&args)
                 } else {
                     false
                 }
             })
             .times(1)
-            .returning(|_, _| Ok(std::process::ExitStatus::from_raw(0)));
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
 
         // 5. mksquashfs
         // output to /dev/mapper/...
@@ -1977,11 +2817,17 @@ mod tests {
                  }
             })
             .times(1)
-            .returning(|_, _| Ok(Output {
-                status: std::process::ExitStatus::from_raw(0),
-                stdout: vec![],
-                stderr: vec![],
-            }));
+            .returning({
+                let mk_args = mk_args.clone();
+                move |_, args| {
+                    *mk_args.lock().unwrap() = args.iter().map(|a| a.to_string()).collect();
+                    Ok(Output {
+                        status: std::process::ExitStatus::from_raw(0),
+                        stdout: vec![],
+                        stderr: vec![],
+                    })
+                }
+            });
             
         // 6. unsquashfs -s (Trim size) - called directly without sudo
         mock.expect_run()
@@ -2045,22 +2891,72 @@ mod tests {
                 stderr: vec![],
             }));
 
-        let args = Args {
-            command: Commands::Create {
-                input_path,
-                output_path: Some(output_path),
-                encrypt: true,
-                compression: DEFAULT_ZSTD_COMPRESSION,
-                no_progress: true,
-                vanilla_progress: false,
-                alfa_progress: false,
-                overwrite_files: false,
-                overwrite_luks_content: false,
-            },
+        cmd_create_encrypted(&mock, &opts, &None).unwrap();
+        let perms = fs::metadata(&output_path).unwrap().permissions();
+        assert_eq!(perms.mode() & 0o777, DEFAULT_ARCHIVE_MODE, "New LUKS container must be private");
+
+        let format_args = format_args.lock().unwrap().clone();
+        let mk_args = mk_args.lock().unwrap().clone();
+        let allocated = *allocated.lock().unwrap();
+        (format_args, mk_args, allocated)
+    }
+
+    #[test]
+    fn test_create_encrypted_flow() {
+        let (format_args, mk_args, allocated) = run_encrypted_create("cryptsetup 2.7.0 flags: UDEV\n", |_| {});
+        assert_eq!(format_args[3..], ["--type", "luks2", "--label", "encrypted.sqfs"], "{:?}", format_args);
+        assert!(mk_args.windows(2).any(|w| w == ["-comp", "zstd"]), "{:?}", mk_args);
+        assert!(mk_args.contains(&"-xattrs".to_string()), "{:?}", mk_args);
+        // 1 MiB input on ext2/ext3 (50% overhead)
+        assert_eq!(allocated, luks_container_size(1048576, 50));
+    }
+
+    #[test]
+    fn test_create_encrypted_with_no_compression() {
+        let (_, mk_args, allocated) = run_encrypted_create("cryptsetup 2.7.0\n", |opts| opts.compression = 0);
+        assert!(mk_args.contains(&"-no-compression".to_string()), "{:?}", mk_args);
+        assert!(!mk_args.contains(&"-comp".to_string()), "{:?}", mk_args);
+        assert_eq!(allocated, luks_container_size(1048576, LUKS_UNCOMPRESSED_OVERHEAD_PERCENT));
+    }
+
+    #[test]
+    fn test_create_encrypted_on_kernel_without_zstd() {
+        let no_zstd = |auto_fallback: bool| {
+            move |opts: &mut CreateOptions| {
+                opts.kernel_zstd_supported = Some(false);
+                opts.auto_fallback_compression = auto_fallback;
+            }
         };
+        let (_, mk_args, _) = run_encrypted_create("cryptsetup 2.7.0\n", no_zstd(true));
+        assert!(mk_args.windows(2).any(|w| w == ["-comp", "gzip"]), "{:?}", mk_args);
+        // Without --auto-fallback-compression it only warns
+        let (_, mk_args, _) = run_encrypted_create("cryptsetup 2.7.0\n", no_zstd(false));
+        assert!(mk_args.windows(2).any(|w| w == ["-comp", "zstd"]), "{:?}", mk_args);
+    }
 
-        run(args, &mock).unwrap();
+    #[test]
+    fn test_create_encrypted_without_label_on_old_cryptsetup() {
+        // Debian oldstable's cryptsetup has no --label; an unparsable version is treated alike
+        for cryptsetup in ["cryptsetup 2.0.6\n", "cryptsetup version unknown\n"] {
+            let (format_args, _, _) = run_encrypted_create(cryptsetup, |_| {});
+            assert_eq!(format_args[..2], ["luksFormat", "-q"]);
+            assert!(!format_args.contains(&"--label".to_string()), "{:?}", format_args);
+        }
+    }
+
+    #[test]
+    fn test_luks_container_size() {
+        let mib = 1024 * 1024;
+        let fixed = LUKS_HEADER_SIZE + LUKS_SAFETY_BUFFER;
+        assert_eq!(luks_container_size(100 * mib, 10), 110 * mib + fixed);
+        assert_eq!(luks_container_size(100 * mib, 50), 150 * mib + fixed);
+        // Rounded up to whole MiB
+        assert_eq!(luks_container_size(1, 10) % mib, 0);
+        assert!(luks_container_size(1, 10) > fixed);
+        // No overflow for huge inputs
+        assert!(luks_container_size(u64::MAX / 4, 50) > u64::MAX / 4);
     }
+
     #[test]
     fn test_mount_auto_gen_path() {
         // We can't easily mock env::current_dir or SystemTime in this simple setup without more refactoring/creates.
@@ -2075,26 +2971,23 @@ mod tests {
 
         let mut mock = MockCommandExecutor::new();
         
-        // 0. cryptsetup isLuks (LUKS detection) - returns failure (not LUKS)
+        
+        // 1. unsquashfs -s (mount pre-check)
         mock.expect_run()
-            .withf(|program, args: &[&str]| {
-                program == "cryptsetup" && args.len() == 2 && args[0] == "isLuks"
-            })
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args[0] == "-s")
             .times(1)
             .returning(|_, _| Ok(Output {
-                status: std::process::ExitStatus::from_raw(256), // exit code 1 = not LUKS
-                stdout: vec![],
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: b"Compression zstd\nBlock size 131072\nNumber of inodes 3\n".to_vec(),
                 stderr: vec![],
             }));
-        
-        // 1. squashfuse (for plain SquashFS)
+
+        // 2. squashfuse (for plain SquashFS)
         mock.expect_run()
             .withf(move |program, args: &[&str]| {
                 program == "squashfuse" &&
-                args.len() == 4 && // -o nonempty image mountpoint
-                args[0] == "-o" &&
-                args[1] == "nonempty" &&
-                args[2] == image_path_str
+                args.len() == 2 && // image mountpoint (fresh directory: no -o nonempty)
+                args[0] == image_path_str
                 // args[3] is the auto-generated path, hard to match exact string due to randomness/time
             })
             .times(1)
@@ -2108,6 +3001,9 @@ mod tests {
             command: Commands::Mount {
                 image: image_path,
                 mount_point: None,
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                list: false,
+                nonempty: false,
             },
         };
         
@@ -2143,7 +3039,7 @@ mod tests {
         mode_none.apply_to_mksquashfs(&mut args);
         assert_eq!(args, vec!["-no-compression"]);
 
-        assert!(mode_none.get_tar2sqfs_compressor_flag().is_err());
+        assert!(mode_none.get_tar2sqfs_compressor_args().is_err());
 
         // Test Zstd
         let mode_zstd = CompressionMode::from_level(15);
@@ -2152,38 +3048,212 @@ mod tests {
         let mut args2 = vec![];
         mode_zstd.apply_to_mksquashfs(&mut args2);
         assert_eq!(args2, vec!["-comp", "zstd", "-Xcompression-level", "15"]);
-        assert_eq!(mode_zstd.get_tar2sqfs_compressor_flag().unwrap(), "-c zstd");
+        assert_eq!(mode_zstd.get_tar2sqfs_compressor_args().unwrap(), ["-c", "zstd"]);
+
+        let mut args3 = vec![];
+        CompressionMode::Gzip.apply_to_mksquashfs(&mut args3);
+        assert_eq!(args3, vec!["-comp", "gzip"]);
+    }
+
+    #[test]
+    fn test_kernel_compression_fallback() {
+        assert_eq!(kernel_compression(CompressionMode::Zstd(19), Some(true), true), CompressionMode::Zstd(19));
+        // Unknown support: nothing to go on, the requested level stays
+        assert_eq!(kernel_compression(CompressionMode::Zstd(19), None, true), CompressionMode::Zstd(19));
+        assert_eq!(kernel_compression(CompressionMode::Zstd(19), Some(false), false), CompressionMode::Zstd(19));
+        assert_eq!(kernel_compression(CompressionMode::Zstd(19), Some(false), true), CompressionMode::Gzip);
+        assert_eq!(kernel_compression(CompressionMode::None, Some(false), true), CompressionMode::None);
+    }
+
+    #[test]
+    fn test_create_directory_with_no_compression() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input_path = temp_dir.path().to_path_buf();
+        let input_path_check = input_path.to_str().unwrap().to_string();
+        let out_dir = tempfile::tempdir().unwrap();
+        let output_path = out_dir.path().join("output_no_comp.sqfs");
+        let output_path_check = output_path.to_str().unwrap().to_string();
+
+        let mut mock = MockCommandExecutor::new();
+        // Expectation: mksquashfs input output -no-progress -no-compression -no-xattrs
+        mock.expect_run()
+            .withf(move |program, args: &[&str]| {
+                 program == "mksquashfs" &&
+                 args.len() == 8 && // input, output, -no-progress, -noappend, -processors 2, -no-compression, -no-xattrs
+                 args[0] == input_path_check &&
+                 args[1] == output_path_check &&
+                 args[2] == "-no-progress" &&
+                 args[3] == "-noappend" &&
+                 args[4] == "-processors" &&
+                 args[5] == "2" &&
+                 args[6] == "-no-compression" &&
+                 args[7] == "-no-xattrs"
+            })
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+
+        let args = Args {
+            command: Commands::Create {
+                input_path,
+                output_path: Some(output_path),
+                encrypt: false,
+                compression: 0,
+                no_progress: true,
+                vanilla_progress: false,
+                alfa_progress: false,
+                overwrite_files: false,
+                overwrite_luks_content: false,
+                log_file: None,
+                debug_log: false,
+                keep_log: false,
+                mode: None,
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: true,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
+            },
+        };
+
+        run(args, &mock).unwrap();
+    }
+
+    #[test]
+    fn test_create_appends_mksquashfs_args() {
+        use clap::Parser;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input_path = temp_dir.path().join("input");
+        fs::create_dir(&input_path).unwrap();
+        let output_path = temp_dir.path().join("out.sqfs");
+
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "mksquashfs" && args.ends_with(&["-xattrs", "-mem", "512M", "-nopad", "-b", "1M"]))
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+
+        let input = input_path.to_str().unwrap();
+        let output = output_path.to_str().unwrap();
+        let argv = ["0k-core", "create", input, output, "--no-progress", "--mem", "512M", "--mksquashfs-arg=-nopad",
+                    "--mksquashfs-arg", "-b", "--mksquashfs-arg", "1M"];
+        run(Args::parse_from(argv), &mock).unwrap();
+
+        // Validation runs before anything is executed
+        let other = temp_dir.path().join("other.sqfs");
+        let argv = ["0k-core", "create", input, other.to_str().unwrap(), "--mksquashfs-arg=/tmp/x"];
+        assert!(run(Args::parse_from(argv), &MockCommandExecutor::new()).is_err());
+        let argv = ["0k-core", "create", input, other.to_str().unwrap(), "--mem", "lots"];
+        let err = Args::try_parse_from(argv).unwrap_err().to_string();
+        assert!(err.contains("powers of 1024"), "{}", err);
+        // tar2sqfs has no -mem
+        let tar = temp_dir.path().join("data.tar");
+        fs::write(&tar, b"tar").unwrap();
+        let argv = ["0k-core", "create", tar.to_str().unwrap(), other.to_str().unwrap(), "--mem", "512M"];
+        let err = run(Args::parse_from(argv), &MockCommandExecutor::new()).unwrap_err();
+        assert!(err.to_string().contains("--mem"), "{}", err);
+        // ... nor an uncompressed mode
+        let argv = ["0k-core", "create", tar.to_str().unwrap(), other.to_str().unwrap(), "-c", "0"];
+        let err = run(Args::parse_from(argv), &MockCommandExecutor::new()).unwrap_err();
+        assert!(err.to_string().contains("-c 0 (no compression) supports only DIRECTORY input"), "{}", err);
+    }
+
+    #[test]
+    fn test_create_with_debug_log_removes_log_on_success() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input_path = temp_dir.path().join("input");
+        fs::create_dir(&input_path).unwrap();
+        let output_path = temp_dir.path().join("out.sqfs");
+        let expected_log = temp_dir.path().join("out.sqfs.log");
+        let expected_log_check = expected_log.clone();
+
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_with_log()
+            .withf(move |program, _args: &[&str], log: &Path| {
+                program == "mksquashfs" && log == expected_log_check.as_path()
+            })
+            .times(1)
+            .returning(|_, _, log| {
+                fs::write(log, "[0.000] stdout: Parallel mksquashfs\n").unwrap();
+                Ok(Output {
+                    status: std::process::ExitStatus::from_raw(0),
+                    stdout: vec![],
+                    stderr: vec![],
+                })
+            });
+
+        let args = Args {
+            command: Commands::Create {
+                input_path,
+                output_path: Some(output_path),
+                encrypt: false,
+                compression: 0,
+                no_progress: true,
+                vanilla_progress: false,
+                alfa_progress: false,
+                overwrite_files: false,
+                overwrite_luks_content: false,
+                log_file: None,
+                debug_log: true,
+                keep_log: false,
+                mode: None,
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: false,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
+            },
+        };
+
+        run(args, &mock).unwrap();
+        assert!(!expected_log.exists(), "Log must be removed after successful packing");
     }
 
     #[test]
-    fn test_create_directory_with_no_compression() {
+    fn test_create_with_log_file_failure_mentions_log() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let input_path = temp_dir.path().to_path_buf();
-        let input_path_check = input_path.to_str().unwrap().to_string();
+        let input_path = temp_dir.path().join("input");
+        fs::create_dir(&input_path).unwrap();
+        let output_path = temp_dir.path().join("out.sqfs");
+        let log_path = temp_dir.path().join("pack.log");
 
         let mut mock = MockCommandExecutor::new();
-        // Expectation: mksquashfs input output -no-progress -no-compression
-        mock.expect_run()
-            .withf(move |program, args: &[&str]| {
-                 program == "mksquashfs" &&
-                 args.len() == 5 && // input, output, -no-progress, -noappend, -no-compression
-                 args[0] == input_path_check &&
-                 args[1] == "output_no_comp.sqfs" &&
-                 args[2] == "-no-progress" &&
-                 args[3] == "-noappend" &&
-                 args[4] == "-no-compression"
-            })
+        mock.expect_run_with_log()
             .times(1)
-            .returning(|_, _| Ok(Output {
-                status: std::process::ExitStatus::from_raw(0),
-                stdout: vec![],
-                stderr: vec![],
-            }));
+            .returning(|_, _, log| {
+                fs::write(log, "[0.000] stderr: Unrecognised xattr prefix\n").unwrap();
+                Ok(Output {
+                    status: std::process::ExitStatus::from_raw(256),
+                    stdout: vec![],
+                    stderr: b"Unrecognised xattr prefix".to_vec(),
+                })
+            });
 
         let args = Args {
             command: Commands::Create {
                 input_path,
-                output_path: Some(PathBuf::from("output_no_comp.sqfs")),
+                output_path: Some(output_path),
                 encrypt: false,
                 compression: 0,
                 no_progress: true,
@@ -2191,10 +3261,81 @@ mod tests {
                 alfa_progress: false,
                 overwrite_files: false,
                 overwrite_luks_content: false,
+                log_file: Some(log_path.clone()),
+                debug_log: false,
+                keep_log: false,
+                mode: None,
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: false,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
             },
         };
 
-        run(args, &mock).unwrap();
+        let err = run(args, &mock).unwrap_err().to_string();
+        assert!(err.contains("Unrecognised xattr"), "Error should carry stderr: {}", err);
+        assert!(err.contains(log_path.to_str().unwrap()), "Error should mention log path: {}", err);
+        assert!(log_path.exists(), "Log must be kept on failure");
+    }
+
+    #[test]
+    fn test_create_transaction_recovery_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("out.sqfs");
+        fs::write(&archive, b"hsqs").unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir(&home).unwrap();
+        let other_run = home.join("squashfs_recovery_out.sqfs_1");
+        fs::write(&other_run, b"r").unwrap();
+
+        // Success: the recovery file of this append goes, another run's stays
+        let ours = home.join("squashfs_recovery_out.sqfs_4242");
+        let mut transaction = CreateTransaction::append(archive.clone(), Some(RecoveryFiles::watch_in(&archive, vec![home.clone()])));
+        fs::write(&ours, b"r").unwrap();
+        fs::write(home.join("squashfs_recovery_other.sqfs_4242"), b"r").unwrap();
+        transaction.set_success();
+        drop(transaction);
+        assert!(!ours.exists());
+        assert!(other_run.exists());
+        assert!(home.join("squashfs_recovery_other.sqfs_4242").exists());
+        assert!(archive.exists());
+
+        // Failure: the archive and the recovery file are both kept for mksquashfs -recover
+        let transaction = CreateTransaction::append(archive.clone(), Some(RecoveryFiles::watch_in(&archive, vec![home.clone()])));
+        fs::write(&ours, b"r").unwrap();
+        drop(transaction);
+        assert!(ours.exists());
+        assert!(archive.exists());
+    }
+
+    #[test]
+    fn test_create_plain_append_no_recovery() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out.sqfs");
+        fs::write(&output, b"hsqs").unwrap();
+
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| {
+                program == "mksquashfs" && args.contains(&"-no-recovery") && !args.contains(&"-noappend")
+            })
+            .times(1)
+            .returning(|_, _| Ok(Output { status: std::process::ExitStatus::from_raw(0), stdout: vec![], stderr: vec![] }));
+
+        let mut opts = create_options(temp_dir.path().to_path_buf(), output.clone(), Action::AppendPlain);
+        opts.compression = 0;
+        opts.no_space_check = true;
+        opts.no_recovery = true;
+        cmd_create_plain(&mock, &opts, &None).unwrap();
+        assert!(output.exists());
     }
 
     #[test]
@@ -2229,23 +3370,15 @@ mod tests {
         let mut mock = MockCommandExecutor::new();
         
         // 2. Expect pipeline execution
-        // We know `infer` + `get_file_type` should detect Gzip -> "gzip -dc"
-        // Compressor: default zstd -> "-c zstd"
-        // Cmd: "gzip -dc 'input.tar.gz' | tar2sqfs --quiet --no-skip --force -c zstd 'output.sqfs'"
-        
-        // input_size needed for progress bar?
-        // It calls metadata().len().
-        
-        // EXPECTATION:
-        mock.expect_run_with_file_progress()
-            .withf(move |program, args: &[&str], output_file: &Path, _, _| {
-                 program == "sh" &&
-                 args.len() == 2 &&
-                 args[0] == "-c" &&
-                 args[1].contains("gzip -dc") &&
-                 args[1].contains(&input_str) && // input path
-                 args[1].contains("tar2sqfs") &&
-                 args[1].contains("-c zstd") &&
+        // We know `infer` + `get_file_type` should detect Gzip -> gzip -dc
+        // Compressor: default zstd -> -c zstd
+        // Pipeline: gzip -dc input.tar.gz | tar2sqfs --quiet --no-skip --force -c zstd output.sqfs
+        mock.expect_run_piped()
+            .withf(move |first: &[&str], second: &[&str], log_file, progress, output_file: &Path| {
+                 first == ["gzip", "-dc", input_str.as_str()] &&
+                 second == ["tar2sqfs", "--quiet", "--no-skip", "--force", "-c", "zstd", "--num-jobs", "2", output_str.as_str()] &&
+                 log_file.is_none() &&
+                 progress.is_some() && // Default is progress bar
                  output_file.to_str().unwrap() == output_str
             })
             .times(1)
@@ -2258,7 +3391,7 @@ mod tests {
         let args = Args {
             command: Commands::Create {
                 input_path: input_tar_gz,
-                output_path: Some(output_sqfs),
+                output_path: Some(output_sqfs.clone()),
                 encrypt: false,
                 compression: DEFAULT_ZSTD_COMPRESSION,
                 no_progress: false, // Default is progress bar
@@ -2266,44 +3399,192 @@ mod tests {
                 alfa_progress: false,
                 overwrite_files: false,
                 overwrite_luks_content: false,
+                log_file: None,
+                debug_log: false,
+                keep_log: false,
+                mode: Some("640".to_string()),
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: false,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
             },
         };
         
         run(args, &mock).unwrap();
+        let perms = fs::metadata(&output_sqfs).unwrap().permissions();
+        assert_eq!(perms.mode() & 0o777, 0o640, "--mode must override the default");
+    }
+
+    #[test]
+    fn test_create_rejects_invalid_mode() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mock = MockCommandExecutor::new();
+        let args = Args {
+            command: Commands::Create {
+                input_path: temp_dir.path().to_path_buf(),
+                output_path: Some(temp_dir.path().join("out.sqfs")),
+                encrypt: false,
+                compression: DEFAULT_ZSTD_COMPRESSION,
+                no_progress: true,
+                vanilla_progress: false,
+                alfa_progress: false,
+                overwrite_files: false,
+                overwrite_luks_content: false,
+                log_file: None,
+                debug_log: false,
+                keep_log: false,
+                mode: Some("u+rw".to_string()),
+                passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS,
+                exclude_file: None,
+                no_xattrs: false,
+                reserve: None,
+                no_space_check: false,
+                mksquashfs_arg: vec![],
+                mem: None,
+                threads: Some(2),
+                auto_fallback_compression: false,
+                verify: false,
+                no_recovery: false,
+                integrity_token: false,
+            },
+        };
+        let err = run(args, &mock).unwrap_err();
+        assert!(err.to_string().contains("Invalid mode"), "{}", err);
     }
 
     #[test]
     fn test_generate_mapper_name_sanitization() {
+        let hash = |p: &str| zero_kelvin::utils::short_path_hash(Path::new(p));
         assert_eq!(
             generate_mapper_name(&PathBuf::from("/path/to/backup.sqfs")),
-            "zrklvbackup_sqfs"
+            format!("zrklvbackup_sqfs_{}", hash("/path/to/backup.sqfs"))
         );
         assert_eq!(
             generate_mapper_name(&PathBuf::from("/path/to/my-data.sqfs_luks.img")),
-            "zrklvmy_data_sqfs_luks_img"
+            format!("zrklvmy_data_sqfs_luks_img_{}", hash("/path/to/my-data.sqfs_luks.img"))
         );
         assert_eq!(
             generate_mapper_name(&PathBuf::from("simple")),
-            "zrklvsimple"
+            format!("zrklvsimple_{}", hash("simple"))
         );
     }
 
+    #[test]
+    fn test_generate_mapper_name_distinguishes_directories() {
+        let a = generate_mapper_name(&PathBuf::from("/home/user/data.sqfs"));
+        let b = generate_mapper_name(&PathBuf::from("/mnt/backup/data.sqfs"));
+        assert_ne!(a, b);
+        assert!(a.starts_with("zrklvdata_sqfs_") && b.starts_with("zrklvdata_sqfs_"));
+
+        let long = generate_mapper_name(&PathBuf::from(format!("/x/{}.sqfs", "a".repeat(300))));
+        assert!(long.len() < 127, "Mapper name too long: {}", long.len());
+    }
+
+    #[test]
+    fn test_ensure_output_fits() {
+        use zero_kelvin::space::{FakeSpace, FsSpace};
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::create_dir(&input).unwrap();
+        fs::write(input.join("data.bin"), vec![0u8; 4000]).unwrap();
+        let output = dir.path().join("out.sqfs");
+        let probe = FakeSpace(FsSpace { available: 1000, total: 1 << 30 });
+
+        // zstd: a quarter of the input is enough to try; stored as is: all of it
+        assert!(ensure_output_fits(&probe, &input, &output, 19).is_ok());
+        let err = ensure_output_fits(&probe, &input, &output, 0).unwrap_err().to_string();
+        assert!(err.contains("4000 bytes") && err.contains("1000 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn test_reserve_free_mapper_name_concurrent_threads() {
+        let registry = tempfile::tempdir().unwrap();
+        let candidates = mapper_name_candidates("zrklvtest_concurrent");
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let registry = registry.path().to_path_buf();
+                let candidates = candidates.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    reserve_free_mapper_name(&registry, &candidates, 0).unwrap().unwrap()
+                })
+            })
+            .collect();
+        let reservations: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        let mut indices: Vec<usize> = reservations.iter().map(|(i, _)| *i).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1], "Concurrent allocations must get distinct names");
+        assert_ne!(reservations[0].1.name, reservations[1].1.name);
+    }
+
+    #[test]
+    fn test_loop_devices() {
+        let output = "/dev/loop3: [2049]:1311 (/tmp/out.sqfs)\n/dev/loop12: [2049]:1311 (/tmp/out.sqfs (deleted))\n";
+        assert_eq!(loop_devices(output), ["/dev/loop3", "/dev/loop12"]);
+        assert!(loop_devices("").is_empty());
+        assert!(loop_devices("losetup: /tmp/out.sqfs: No such file or directory\n").is_empty());
+    }
+
+    #[test]
+    fn test_release_stale_mapper_closes_unmounted() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .withf(|prog, args: &[&str]| prog == "sudo" && args == ["cryptsetup", "close", "sq_stale"])
+            .times(1)
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
+
+        // A mount of some other mapper does not keep this one busy
+        let other = zero_kelvin::mounts::MountInfo {
+            mount_point: PathBuf::from("/mnt/other"),
+            backend: zero_kelvin::mounts::MountBackend::LuksMapper("sq_other".into()),
+        };
+        release_stale_mapper(&mock, &["sudo".to_string()], "sq_stale", &[other]).unwrap();
+    }
+
+    #[test]
+    fn test_release_stale_mapper_in_use() {
+        // No expectations: nothing may be closed
+        let mock = MockCommandExecutor::new();
+        let mounted = zero_kelvin::mounts::MountInfo {
+            mount_point: PathBuf::from("/mnt/secret"),
+            backend: zero_kelvin::mounts::MountBackend::LuksMapper("sq_stale".into()),
+        };
+        let err = release_stale_mapper(&mock, &["sudo".to_string()], "sq_stale", &[mounted]).unwrap_err();
+        assert!(err.to_string().contains("/mnt/secret"), "{}", err);
+    }
+
     #[test]
     fn test_open_luks_container_success_first_try() {
         let mut mock = MockCommandExecutor::new();
-        mock.expect_run_interactive()
+        mock.expect_run_and_capture_error()
             .withf(|prog, args: &[&str]| {
                 prog == "sudo" && args.contains(&"cryptsetup") && args.contains(&"open")
                     && args.contains(&"sq_test")
             })
             .times(1)
-            .returning(|_, _| Ok(std::process::ExitStatus::from_raw(0)));
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
 
         let result = open_luks_container(
             &mock,
             &["sudo".to_string()],
             "/path/to/image",
             "sq_test",
+            3,
+            false,
+            None,
         );
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "sq_test");
@@ -2315,7 +3596,7 @@ mod tests {
         let call_count = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
         let call_count_clone = call_count.clone();
 
-        mock.expect_run_interactive()
+        mock.expect_run_and_capture_error()
             .withf(|prog, args: &[&str]| {
                 prog == "sudo" && args.contains(&"cryptsetup") && args.contains(&"open")
             })
@@ -2325,10 +3606,10 @@ mod tests {
                 if count == 0 {
                     // First attempt: exit code 5 = name already exists
                     // raw status 5*256 = 1280 on Linux
-                    Ok(std::process::ExitStatus::from_raw(5 << 8))
+                    Ok((std::process::ExitStatus::from_raw(5 << 8), String::new()))
                 } else {
                     // Second attempt: success
-                    Ok(std::process::ExitStatus::from_raw(0))
+                    Ok((std::process::ExitStatus::from_raw(0), String::new()))
                 }
             });
 
@@ -2337,22 +3618,93 @@ mod tests {
             &["sudo".to_string()],
             "/path/to/image",
             "sq_test",
+            3,
+            false,
+            None,
         );
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "sq_test_2");
     }
 
+    /// Mock that reports `target` as a plain (non-LUKS) mount and answers `fuser` with nothing
+    fn umount_mock_base() -> MockCommandExecutor {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, _| program == "findmnt")
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: b"squashfuse\n".to_vec(),
+                stderr: vec![],
+            }));
+        mock.expect_run()
+            .withf(|program, _| program == "fuser")
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+        mock
+    }
+
+    #[test]
+    fn test_umount_busy_retries_then_fails_with_busy() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path().join("mnt");
+        fs::create_dir(&target).unwrap();
+
+        let mut mock = umount_mock_base();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "fusermount" && args[0] == "-u" && args.len() == 2)
+            .times(UMOUNT_RETRY_ATTEMPTS as usize)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: b"fusermount: failed to unmount: Device or resource busy".to_vec(),
+            }));
+
+        let args = Args { command: Commands::Umount { mount_point: target.clone(), lazy: false } };
+        let err = run(args, &mock).unwrap_err();
+        assert!(matches!(err, ZkError::Busy(_)), "Expected Busy, got {:?}", err);
+        assert!(err.to_string().contains("--lazy"));
+        assert!(target.exists(), "Busy mount point must not be removed");
+    }
+
+    #[test]
+    fn test_umount_lazy_uses_detach_flag() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path().join("mnt");
+        fs::create_dir(&target).unwrap();
+        let target_check = target.to_str().unwrap().to_string();
+
+        let mut mock = umount_mock_base();
+        mock.expect_run()
+            .withf(move |program, args: &[&str]| program == "fusermount" && args == ["-u", "-z", target_check.as_str()])
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+
+        let args = Args { command: Commands::Umount { mount_point: target.clone(), lazy: true } };
+        run(args, &mock).unwrap();
+        assert!(!target.exists(), "Empty mount point should be removed after unmount");
+    }
+
     #[test]
     fn test_open_luks_container_real_error_no_retry() {
         let mut mock = MockCommandExecutor::new();
-        mock.expect_run_interactive()
+        mock.expect_run_and_capture_error()
             .withf(|prog, args: &[&str]| {
                 prog == "sudo" && args.contains(&"cryptsetup") && args.contains(&"open")
             })
             .times(1) // Should only try once
             .returning(|_, _| {
-                // Exit code 2 = wrong password / no permission
-                Ok(std::process::ExitStatus::from_raw(2 << 8))
+                // Not a passphrase problem: must not be retried even with attempts left
+                Ok((
+                    std::process::ExitStatus::from_raw(4 << 8),
+                    "Device /path/to/image does not exist or access denied.".to_string(),
+                ))
             });
 
         let result = open_luks_container(
@@ -2360,9 +3712,388 @@ mod tests {
             &["sudo".to_string()],
             "/path/to/image",
             "sq_test",
+            3,
+            false,
+            None,
         );
         assert!(result.is_err());
         let err_msg = format!("{}", result.unwrap_err());
         assert!(err_msg.contains("cryptsetup open failed"));
     }
+
+    #[test]
+    fn test_mount_path_opens_luks_readonly() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .withf(|prog, args: &[&str]| {
+                prog == "sudo" && args.contains(&"open") && args.contains(&"--readonly")
+            })
+            .times(1)
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
+
+        let name = open_luks_container(&mock, &["sudo".to_string()], "/path/to/image", "sq_ro", 1, true, None)
+            .unwrap();
+        assert_eq!(name, "sq_ro");
+
+        let mount = luks_mount_command(&["sudo".to_string()], "/dev/mapper/sq_ro", Path::new("/mnt/x")).unwrap();
+        assert_eq!(
+            mount,
+            ["sudo", "mount", "-t", "squashfs", "-o", "ro", "/dev/mapper/sq_ro", "/mnt/x"]
+        );
+    }
+
+    /// `tool` run directly or through the root command (tests may run as root or not)
+    fn runs(program: &str, args: &[&str], tool: &str) -> bool {
+        program == tool || args.first() == Some(&tool)
+    }
+
+    fn exit(code: i32, stdout: &str) -> std::io::Result<Output> {
+        Ok(Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: b"Can't find a SQUASHFS superblock on /dev/mapper/x\n".to_vec(),
+        })
+    }
+
+    /// sync, udevadm settle and cryptsetup close: the mapper must be closed on every path
+    fn expect_mapper_closed(mock: &mut MockCommandExecutor) {
+        mock.expect_run().withf(|p, a: &[&str]| runs(p, a, "sync")).returning(|_, _| exit(0, ""));
+        mock.expect_run().withf(|p, a: &[&str]| runs(p, a, "udevadm")).returning(|_, _| exit(0, ""));
+        mock.expect_run()
+            .withf(|p, a: &[&str]| runs(p, a, "cryptsetup") && a.contains(&"close"))
+            .times(1)
+            .returning(|_, _| exit(0, ""));
+    }
+
+    #[test]
+    fn test_verify_luks_payload() {
+        let image = Path::new("/path/to/verify.sqfs_luks.img");
+
+        // Success: readonly open, superblock, listing, close
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .withf(|p, a: &[&str]| runs(p, a, "cryptsetup") && a.contains(&"open") && a.contains(&"--readonly"))
+            .times(1)
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
+        mock.expect_run()
+            .withf(|p, a: &[&str]| runs(p, a, "unsquashfs") && a.contains(&"-s"))
+            .times(1)
+            .returning(|_, _| exit(0, "Compression zstd\nBlock size 131072\nNumber of inodes 4\n"));
+        mock.expect_run_each_line()
+            .withf(|p, a: &[&str], _| runs(p, a, "unsquashfs") && a.contains(&"-l"))
+            .times(1)
+            .returning(|_, _, sink| {
+                for line in ["squashfs-root", "squashfs-root/list.yaml", "squashfs-root/to_restore"] {
+                    sink.line(line);
+                }
+                exit(0, "")
+            });
+        expect_mapper_closed(&mut mock);
+        let info = verify_luks_payload(image, &mock, 1).unwrap();
+        assert_eq!(info.compression.as_deref(), Some("zstd"));
+        assert_eq!(info.inodes, Some(4));
+
+        // Bad magic: the right passphrase, but no SquashFS inside
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(0), String::new())));
+        mock.expect_run()
+            .withf(|p, a: &[&str]| runs(p, a, "unsquashfs") && a.contains(&"-s"))
+            .times(1)
+            .returning(|_, _| exit(1, ""));
+        expect_mapper_closed(&mut mock);
+        let err = verify_luks_payload(image, &mock, 1).unwrap_err();
+        assert!(err.to_string().contains("not a valid SquashFS image"), "{}", err);
+
+        // Open failure: nothing to verify, nothing to close (no run() expectations)
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .times(1)
+            .returning(|_, _| Ok((std::process::ExitStatus::from_raw(1 << 8), "Device is not a LUKS device\n".to_string())));
+        let err = verify_luks_payload(image, &mock, 1).unwrap_err();
+        assert!(matches!(err, ZkError::LuksError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_open_luks_container_retries_bad_passphrase_then_succeeds() {
+        let mut mock = MockCommandExecutor::new();
+        let call_count = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let call_count_clone = call_count.clone();
+
+        mock.expect_run_and_capture_error()
+            .withf(|prog, args: &[&str]| {
+                prog == "sudo" && args.contains(&"open") && args.contains(&"sq_pass")
+                    && args.windows(2).any(|w| w == ["--tries", "1"])
+            })
+            .times(2)
+            .returning(move |_, _| {
+                if call_count_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    Ok((
+                        std::process::ExitStatus::from_raw(2 << 8),
+                        "No key available with this passphrase.\n".to_string(),
+                    ))
+                } else {
+                    Ok((std::process::ExitStatus::from_raw(0), String::new()))
+                }
+            });
+
+        let result = open_luks_container(&mock, &["sudo".to_string()], "/path/to/image", "sq_pass", 3, false, None);
+        // Same mapper name: a wrong passphrase is not a name collision
+        assert_eq!(result.unwrap(), "sq_pass");
+        assert_eq!(call_count.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_open_luks_container_bad_passphrase_gives_up_after_limit() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_and_capture_error()
+            .times(2)
+            .returning(|_, _| {
+                Ok((
+                    std::process::ExitStatus::from_raw(2 << 8),
+                    "No key available with this passphrase.\n".to_string(),
+                ))
+            });
+
+        let err = open_luks_container(&mock, &["sudo".to_string()], "/path/to/image", "sq_pass_limit", 2, false, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("incorrect passphrase"), "{}", err);
+    }
+
+    #[test]
+    fn test_open_luks_container_feeds_agent_passphrase_on_stdin() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run_with_stdin()
+            .withf(|prog, args: &[&str], input: &[u8]| {
+                prog == "sudo"
+                    && args.contains(&"open")
+                    && args.contains(&"--key-file=-")
+                    && !args.iter().any(|a| a.contains("hunter2"))
+                    && input == b"hunter2 pass"
+            })
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(std::process::Output {
+                    status: std::process::ExitStatus::from_raw(0),
+                    stdout: vec![],
+                    stderr: vec![],
+                })
+            });
+        // No run_and_capture_error expectation: cryptsetup must not prompt
+        let name = open_luks_container(
+            &mock,
+            &["sudo".to_string()],
+            "/path/to/image",
+            "sq_agent",
+            1,
+            true,
+            Some("hunter2 pass"),
+        )
+        .unwrap();
+        assert_eq!(name, "sq_agent");
+    }
+
+    #[test]
+    fn test_parse_passphrase_attempts() {
+        use clap::Parser;
+        let args = Args::parse_from(["0k-core", "mount", "img.sqfs"]);
+        assert!(matches!(
+            args.command,
+            Commands::Mount { passphrase_attempts: LUKS_PASSPHRASE_ATTEMPTS, .. }
+        ));
+        let args = Args::parse_from(["0k-core", "create", "in", "out.sqfs", "--passphrase-attempts", "5"]);
+        assert!(matches!(args.command, Commands::Create { passphrase_attempts: 5, .. }));
+        assert!(Args::try_parse_from(["0k-core", "mount", "img", "--passphrase-attempts", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_mount_list_does_not_mount() {
+        use clap::Parser;
+        // --list only reports; a manual mount point makes no sense with it
+        assert!(Args::try_parse_from(["0k-core", "mount", "img.sqfs", "/mnt/x", "--list"]).is_err());
+
+        let temp = tempfile::tempdir().unwrap();
+        let image = temp.path().join("img.sqfs");
+        fs::write(&image, b"not mounted").unwrap();
+        let args = Args::parse_from(["0k-core", "mount", image.to_str().unwrap(), "--list"]);
+
+        // No expectations: any command run (mount, squashfuse, cryptsetup) would panic
+        let mock = MockCommandExecutor::new();
+        run(args, &mock).unwrap();
+    }
+
+    #[test]
+    fn test_mount_precheck_rejects_non_squashfs() {
+        use clap::Parser;
+        let temp = tempfile::tempdir().unwrap();
+        let image = temp.path().join("img.sqfs");
+        fs::write(&image, b"not an image").unwrap();
+        let mount_point = temp.path().join("mnt");
+
+        // No squashfuse expectation: the pre-check must stop before mounting
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args[0] == "-s")
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: b"Can't find a SQUASHFS superblock on img.sqfs\n".to_vec(),
+            }));
+        let args = Args::parse_from(["0k-core", "mount", image.to_str().unwrap(), mount_point.to_str().unwrap()]);
+        let err = run(args, &mock).unwrap_err();
+        assert!(err.to_string().contains("not a valid SquashFS image"), "{}", err);
+    }
+
+    #[test]
+    fn test_ensure_mount_point_empty() {
+        let temp = tempfile::tempdir().unwrap();
+        assert!(ensure_mount_point_empty(&temp.path().join("missing")).is_ok());
+        assert!(ensure_mount_point_empty(temp.path()).is_ok());
+
+        for i in 0..12 {
+            fs::write(temp.path().join(format!("file{:02}", i)), b"x").unwrap();
+        }
+        let msg = ensure_mount_point_empty(temp.path()).unwrap_err().to_string();
+        assert!(msg.contains("12 entries"), "{}", msg);
+        assert!(msg.contains("file09") && !msg.contains("file10"), "{}", msg);
+        assert!(msg.contains("... and 2 more"), "{}", msg);
+        assert!(msg.contains("--nonempty"), "{}", msg);
+    }
+
+    #[test]
+    fn test_mount_refuses_nonempty_mount_point() {
+        use clap::Parser;
+        let temp = tempfile::tempdir().unwrap();
+        let image = temp.path().join("img.sqfs");
+        fs::write(&image, b"dummy").unwrap();
+        let mount_point = temp.path().join("mnt");
+        fs::create_dir(&mount_point).unwrap();
+        fs::write(mount_point.join("existing.txt"), b"keep").unwrap();
+        let argv = ["0k-core", "mount", image.to_str().unwrap(), mount_point.to_str().unwrap()];
+
+        // Refused before anything runs
+        let mock = MockCommandExecutor::new();
+        let err = run(Args::parse_from(argv), &mock).unwrap_err();
+        assert!(err.to_string().contains("existing.txt"));
+
+        // --nonempty: mounted, with FUSE told to allow it
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args[0] == "-s")
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: b"Compression zstd\n".to_vec(),
+                stderr: vec![],
+            }));
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "squashfuse" && args[..2] == ["-o", "nonempty"])
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+        let mut argv = argv.to_vec();
+        argv.push("--nonempty");
+        run(Args::parse_from(argv), &mock).unwrap();
+    }
+
+    #[test]
+    fn test_validate_create_input() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        let tar = temp.path().join("data.tar");
+        fs::write(&tar, b"tar").unwrap();
+        let excludes = temp.path().join("exclude.list");
+        fs::write(&excludes, b"a\n").unwrap();
+
+        assert!(validate_create_input(&dir, true, Some(&excludes)).is_ok());
+        assert!(validate_create_input(&tar, false, None).is_ok());
+        assert!(matches!(
+            validate_create_input(&temp.path().join("missing"), false, None),
+            Err(ZkError::InvalidPath(_))
+        ));
+        // Encryption and exclusions need a directory
+        assert!(validate_create_input(&tar, true, None).is_err());
+        assert!(validate_create_input(&tar, false, Some(&excludes)).is_err());
+        assert!(matches!(
+            validate_create_input(&dir, false, Some(&temp.path().join("nope.list"))),
+            Err(ZkError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_create_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let input = Path::new("/data/photos");
+
+        let file = temp.path().join("out.sqfs");
+        assert_eq!(resolve_create_output(Some(&file), input, false).unwrap(), file);
+        assert!(matches!(resolve_create_output(None, input, false), Err(ZkError::MissingTarget(_))));
+
+        let plain = resolve_create_output(Some(temp.path()), input, false).unwrap();
+        assert_eq!(plain.parent().unwrap(), temp.path());
+        let name = plain.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("photos_") && name.ends_with(".sqfs"), "{}", name);
+
+        let encrypted = resolve_create_output(Some(temp.path()), input, true).unwrap();
+        assert!(encrypted.to_str().unwrap().ends_with(".sqfs_luks.img"));
+
+        // Directory names are normalized into a shell- and /proc-friendly prefix
+        for (dir, prefix) in [("my docs (old)!", "my_docs_old_"), ("фото 🎉", "archive_")] {
+            let out = resolve_create_output(Some(temp.path()), &Path::new("/data").join(dir), false).unwrap();
+            let name = out.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with(prefix), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_check_existing_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let output = temp.path().join("out.sqfs");
+
+        let append = OverwritePolicy { overwrite_files: true, ..Default::default() };
+
+        // Nothing there
+        assert_eq!(check_existing_output(&output, &OverwritePolicy::default()).unwrap(), Action::CreateNew);
+
+        // Existing non-archive: refused whatever the flags
+        fs::write(&output, b"not an archive").unwrap();
+        assert!(check_existing_output(&output, &OverwritePolicy::default()).is_err());
+        let err = check_existing_output(&output, &append).unwrap_err();
+        assert!(err.to_string().contains("neither a SquashFS archive nor a LUKS container"), "{}", err);
+
+        // A LUKS container, told by its header magic alone (no cryptsetup needed)
+        fs::write(&output, b"LUKS\xba\xbe\x00\x02").unwrap();
+        let replace = OverwritePolicy { encrypt: true, overwrite_luks_content: true, ..Default::default() };
+        assert_eq!(check_existing_output(&output, &replace).unwrap(), Action::ReplaceLuksPayload);
+
+        // A plain archive: appended to with --overwrite-files, unless -e
+        fs::write(&output, b"hsqs").unwrap();
+        assert_eq!(check_existing_output(&output, &append).unwrap(), Action::AppendPlain);
+        let encrypted = OverwritePolicy { encrypt: true, ..append };
+        let err = check_existing_output(&output, &encrypted).unwrap_err();
+        assert!(err.to_string().contains("--overwrite-files without -e"), "{}", err);
+    }
+
+    #[test]
+    fn test_dir_size_bytes() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args| program == "du" && args == ["-sb", "/data"])
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: b"4096\t/data\n".to_vec(),
+                stderr: vec![],
+            }));
+        mock.expect_run()
+            .withf(|program, args| program == "du" && args == ["-sb", "/denied"])
+            .times(1)
+            .returning(|_, _| Ok(Output { status: std::process::ExitStatus::from_raw(256), stdout: vec![], stderr: vec![] }));
+        assert_eq!(dir_size_bytes(&mock, "/data"), 4096);
+        assert_eq!(dir_size_bytes(&mock, "/denied"), 0);
+    }
 }
@@ -2,6 +2,7 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use clap::Parser;
 use std::io;
+use zero_kelvin::utils::suggest_command;
 
 #[derive(Parser, Debug)]
 #[command(version, about = "Safely removes empty directories recursively")]
@@ -53,8 +54,9 @@ fn main() -> std::process::ExitCode {
 }
 
 /// Checks that no active mount points exist within the given path.
-/// Reads /proc/self/mountinfo (Linux-specific) to find all current mount points
-/// and verifies none of them are inside our target directory.
+/// Reads /proc/self/mountinfo (Linux-specific), or /etc/mtab where /proc is not
+/// mounted, to find all current mount points and verifies none of them are inside
+/// our target directory.
 /// This prevents catastrophic data loss if a bind mount from a crashed namespace
 /// is still active — remove_dir_all would follow the mount and delete real data.
 fn check_no_active_mounts(path: &Path) -> io::Result<()> {
@@ -63,25 +65,23 @@ fn check_no_active_mounts(path: &Path) -> io::Result<()> {
     })?;
     let target_prefix = canonical.to_string_lossy().to_string();
 
-    let mountinfo = match fs::read_to_string("/proc/self/mountinfo") {
-        Ok(content) => content,
-        Err(_) => {
-            // If /proc is unavailable (container, exotic setup), skip the check
-            // but warn the user
-            eprintln!("Warning: Cannot read /proc/self/mountinfo. Skipping mount point safety check.");
-            return Ok(());
-        }
+    let mount_points: Vec<String> = match fs::read_to_string("/proc/self/mountinfo") {
+        // mountinfo format (fields separated by spaces):
+        // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+        // Field index 4 (0-based) is the mount point.
+        Ok(content) => content.lines().filter_map(|line| line.split_whitespace().nth(4)).map(unescape_mountinfo).collect(),
+        // No /proc (container, exotic setup): the mount table from /etc/mtab
+        Err(_) => match zero_kelvin::mounts::mount_points() {
+            Ok(points) => points.iter().map(|p| p.to_string_lossy().into_owned()).collect(),
+            Err(e) => {
+                // Nothing to go on: skip the check but warn the user
+                eprintln!("Warning: {}. Skipping mount point safety check.", e);
+                return Ok(());
+            }
+        },
     };
 
-    // mountinfo format (fields separated by spaces):
-    // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
-    // Field index 4 (0-based) is the mount point.
-    for line in mountinfo.lines() {
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() < 5 {
-            continue;
-        }
-        let mount_point = unescape_mountinfo(fields[4]);
+    for mount_point in mount_points {
         // Check if this mount point is inside our target directory (or is the target itself)
         if mount_point.starts_with(&target_prefix) && mount_point.len() > target_prefix.len() {
             return Err(io::Error::new(
@@ -89,8 +89,10 @@ fn check_no_active_mounts(path: &Path) -> io::Result<()> {
                 format!(
                     "Active mount point detected inside target: '{}'. \
                      This likely means a bind mount from a previous 0k session is still active. \
-                     Please unmount it first (e.g., 'umount {}' or 'fusermount -u {}').",
-                    mount_point, mount_point, mount_point
+                     Please unmount it first, e.g.:\n  {}\n  {}",
+                    mount_point,
+                    suggest_command(&["umount".as_ref(), mount_point.as_ref()]),
+                    suggest_command(&["fusermount".as_ref(), "-u".as_ref(), mount_point.as_ref()])
                 ),
             ));
         }
@@ -1,12 +1,100 @@
-use rand::Rng;
-use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
 use std::fs;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use zero_kelvin::cancel::CancellationToken;
+use zero_kelvin::catalog;
+use zero_kelvin::check_audit::ReportTarget;
 use zero_kelvin::cli::zk::{Args, Commands};
 use zero_kelvin::engine::{self, FreezeOptions, UnfreezeOptions};
 use zero_kelvin::error::ZkError;
 use zero_kelvin::executor::RealSystem;
 use zero_kelvin::logging;
+use zero_kelvin::mounts;
+use zero_kelvin::prune;
+use zero_kelvin::remap;
+use zero_kelvin::report;
+use zero_kelvin::summary;
+use zero_kelvin::targets::{self, Target};
 use zero_kelvin::utils;
+use zero_kelvin::version;
+
+/// Set by Ctrl+C: freeze, unfreeze and check stop at their next entry and clean up as on any
+/// other error.
+static INTERRUPTED: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+/// Staging directory of the running freeze, removed on interrupt (None with --keep-staging)
+static CLEANUP_BUILD_DIR: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+/// Archive the running freeze creates, removed on interrupt (None when appending to one)
+static CLEANUP_OUTPUT: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+/// How long children get to exit on the Ctrl+C the terminal sent them too before SIGTERM
+const CHILD_STOP_GRACE: Duration = Duration::from_secs(2);
+
+fn cleanup_slot(slot: &'static OnceLock<Mutex<Option<PathBuf>>>) -> &'static Mutex<Option<PathBuf>> {
+    slot.get_or_init(|| Mutex::new(None))
+}
+
+fn register_cleanup(slot: &'static OnceLock<Mutex<Option<PathBuf>>>, path: Option<PathBuf>) {
+    if let Ok(mut guard) = cleanup_slot(slot).lock() {
+        *guard = path;
+    }
+}
+
+fn take_cleanup(slot: &'static OnceLock<Mutex<Option<PathBuf>>>) -> Option<PathBuf> {
+    cleanup_slot(slot).lock().ok().and_then(|mut guard| guard.take())
+}
+
+/// `FreezeOptions::on_staging`: the staging directory is known from here on
+fn register_build_dir(build_dir: &Path) {
+    register_cleanup(&CLEANUP_BUILD_DIR, Some(build_dir.to_path_buf()));
+}
+
+/// Waits up to `grace` for the child processes (unshare, 0k-core, mksquashfs) to exit, then
+/// sends SIGTERM to those still running. Returns how many were terminated.
+fn stop_children(grace: Duration) -> usize {
+    let me = std::process::id();
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && !utils::descendant_pids(me).is_empty() {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let remaining = utils::descendant_pids(me);
+    for pid in &remaining {
+        // SAFETY: kill(2) has no memory preconditions; a pid that exited meanwhile only fails
+        unsafe { libc::kill(*pid as libc::pid_t, libc::SIGTERM) };
+    }
+    remaining.len()
+}
+
+/// Removes what an interrupted freeze leaves: the incomplete archive it was creating and its
+/// staging directory, unless something is still mounted inside. Each is handled once.
+fn cleanup_on_interrupt() {
+    if let Some(output) = take_cleanup(&CLEANUP_OUTPUT)
+        && output.exists()
+    {
+        match fs::remove_file(&output) {
+            Ok(()) => eprintln!("Interrupted: removed the incomplete archive {}", output.display()),
+            Err(e) => eprintln!("Error removing the incomplete archive {}: {}", output.display(), e),
+        }
+    }
+    if let Some(build_dir) = take_cleanup(&CLEANUP_BUILD_DIR) {
+        if !build_dir.exists() {
+            // Already removed as the freeze unwound
+            eprintln!("Interrupted: removed the staging directory {}", build_dir.display());
+        } else if engine::has_active_mounts_inside(&build_dir) {
+            eprintln!(
+                "Warning: staging directory {} left in place: something is still mounted inside (0k gc removes it once unmounted)",
+                build_dir.display()
+            );
+        } else {
+            match fs::remove_dir_all(&build_dir) {
+                Ok(()) => eprintln!("Interrupted: removed the staging directory {}", build_dir.display()),
+                Err(e) => eprintln!("Error removing the staging directory {}: {}", build_dir.display(), e),
+            }
+        }
+    }
+}
 
 fn main() -> std::process::ExitCode {
     // Initialize tracing with file rotation (guard must be kept alive)
@@ -15,6 +103,11 @@ fn main() -> std::process::ExitCode {
     match run_app() {
         Ok(()) => std::process::ExitCode::SUCCESS,
         Err(ZkError::CliExit(code)) => std::process::ExitCode::from(code),
+        Err(e @ ZkError::Cancelled) => {
+            cleanup_on_interrupt();
+            eprintln!("Error: {}", e);
+            std::process::ExitCode::from(130)
+        }
         Err(e) => {
             if let Some(friendly) = e.friendly_message() {
                 eprintln!("Suggestion: {}", friendly);
@@ -26,6 +119,32 @@ fn main() -> std::process::ExitCode {
 }
 
 fn run_app() -> Result<(), ZkError> {
+    // The first Ctrl+C or SIGTERM cancels the running operation (child processes get a Ctrl+C
+    // too; those of a freeze that outlive the grace period are terminated); a second one quits
+    // at once, e.g. while a prompt waits for an answer. Either way an interrupted freeze is
+    // cleaned up.
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.is_cancelled() {
+            stop_children(Duration::ZERO);
+            cleanup_on_interrupt();
+            std::process::exit(130);
+        }
+        INTERRUPTED.cancel();
+        eprintln!("\nInterrupted: stopping after the current entry (interrupt again to quit now)");
+        let freezing = cleanup_slot(&CLEANUP_BUILD_DIR).lock().is_ok_and(|guard| guard.is_some());
+        if freezing {
+            // Waited for on its own thread: ctrlc runs one handler at a time, and the second
+            // signal must not wait for the grace period to end
+            std::thread::spawn(|| {
+                let stopped = stop_children(CHILD_STOP_GRACE);
+                if stopped > 0 {
+                    eprintln!("Interrupted: terminated {} child process(es)", stopped);
+                }
+            });
+        }
+    })
+    .map_err(|e| ZkError::OperationFailed(format!("Failed to set signal handler: {}", e)))?;
+
     let args_raw: Vec<String> = std::env::args().collect();
 
     // 1. No args -> Help + Exit 0
@@ -34,6 +153,9 @@ fn run_app() -> Result<(), ZkError> {
         println!();
         return Ok(());
     }
+    if version::is_json_version_request(&args_raw) {
+        return version::print_json("0k");
+    }
 
     let matches = match Args::build_command().try_get_matches() {
         Ok(m) => m,
@@ -86,21 +208,55 @@ fn run_app() -> Result<(), ZkError> {
         ZkError::CliExit(code)
     })?;
 
+    let threads = args.threads;
     match args.command {
         Commands::Freeze {
             args,
             encrypt,
             read,
+            read0,
             overwrite_files,
+            no_recovery,
             overwrite_luks_content,
             no_progress,
             vanilla_progress: _vanilla_progress,
             alfa_progress,
             compression,
             dereference,
+            dereference_target,
+            skip_broken_symlinks,
             prefix,
+            name_template,
+            log_file,
+            debug_log,
+            keep_log,
+            keep_staging,
+            staging_dir,
+            mode,
+            json_events,
+            check_open_files,
+            allow_open_files,
+            skip_unreadable,
+            no_xattrs,
+            no_space_check,
+            reserve,
+            yes,
+            mksquashfs_arg,
+            exclude,
+            no_ignore_files,
+            mem,
+            checksums,
+            auto_fallback_compression,
+            integrity_token,
+            preserve_order,
+            dry_run,
+            json,
+            delete_after,
         } => {
-            let (targets, output) = resolve_freeze_args(args, read)?;
+            if json_events {
+                zero_kelvin::events::init()?;
+            }
+            let (targets, output) = resolve_freeze_args(args, read, read0)?;
 
             // Validate compression level
             if let Some(level) = compression {
@@ -112,16 +268,27 @@ fn run_app() -> Result<(), ZkError> {
                 }
             }
 
+            // Validate archive permissions
+            let mode = mode.map(|m| utils::parse_octal_mode(&m)).transpose()?;
+
+            utils::validate_mksquashfs_args(&mksquashfs_arg)?;
+
             let executor = RealSystem;
 
             // If output is a directory, resolve to a full file path
             let output = if output.is_dir() {
-                resolve_directory_output(&output, prefix, encrypt)?
+                resolve_directory_output(&output, prefix, name_template.as_deref(), encrypt)?
             } else {
                 output
             };
 
-            let progress_mode = if no_progress {
+            let log_file = utils::resolve_log_path(log_file, debug_log, &output);
+
+            // Resolved here so the elevated rerun gets it even though sudo drops $ZK_STAGING_DIR
+            let staging_flag = staging_dir.is_some();
+            let staging_dir = utils::staging_base(staging_dir.as_deref());
+
+            let progress_mode = if no_progress || json_events {
                 engine::ProgressMode::None
             } else if alfa_progress {
                 engine::ProgressMode::Alfa
@@ -138,23 +305,121 @@ fn run_app() -> Result<(), ZkError> {
                 progress_mode,
                 compression,
                 dereference,
+                dereference_targets: dereference_target,
+                log_file,
+                keep_log,
+                keep_staging,
+                staging_dir,
+                mode,
+                check_open_files: check_open_files || allow_open_files,
+                allow_open_files,
+                skip_unreadable,
+                no_xattrs,
+                no_space_check,
+                reserve,
+                yes,
+                mksquashfs_args: mksquashfs_arg,
+                exclude,
+                no_ignore_files,
+                mem,
+                threads: Some(utils::resolve_threads(threads)),
+                checksums,
+                auto_fallback_compression,
+                skip_broken_symlinks,
+                no_recovery,
+                integrity_token,
+                preserve_order,
+                cancel: Some(INTERRUPTED.clone()),
+                progress: None,
+                on_staging: (!keep_staging).then_some(register_build_dir as fn(&Path)),
             };
 
+            // Ids follow the target order: drop duplicates and sort, so they are stable.
+            // engine::freeze does it too; done here first, the warnings say where each was given
+            let (targets, duplicates) = targets::normalize(targets, |t| options.dereferences(t), preserve_order);
+            for duplicate in &duplicates {
+                eprintln!("Warning: skipping duplicate target: {}", duplicate);
+            }
+
+            if dry_run {
+                let plan = engine::plan_freeze(&targets, &options)?;
+                if json {
+                    report::print_json(&plan, "the dry-run summary")?;
+                } else {
+                    println!("{}", plan.render());
+                }
+                return Ok(());
+            }
+
             // Log info
             // println!("Freezing {:?} to {:?}", targets, options.output);
 
-            // engine::freeze(&targets, &options, &executor)?;
-            if let Err(e) = engine::freeze(&targets, &options, &executor) {
-                if utils::is_permission_denied(&e) {
-                    if let Some(runner) = utils::check_root_or_get_runner(
+            // An archive that is there already (appended to, or its LUKS payload replaced) is
+            // never removed on interrupt
+            if !options.output.exists() {
+                register_cleanup(&CLEANUP_OUTPUT, Some(options.output.clone()));
+            }
+            let frozen = engine::freeze(&targets, &options, &executor);
+            // Kept for cleanup_on_interrupt only when the freeze stopped on Ctrl+C
+            if !matches!(frozen, Err(ZkError::Cancelled)) {
+                register_cleanup(&CLEANUP_OUTPUT, None);
+                register_cleanup(&CLEANUP_BUILD_DIR, None);
+            }
+            if let Err(e) = frozen {
+                // --skip-unreadable asked us to work with what we can read, not to escalate
+                if utils::is_permission_denied(&e)
+                    && !skip_unreadable
+                    && let Some(args) = elevated_freeze_args(
+                        &std::env::args().skip(1).collect::<Vec<_>>(),
+                        &targets,
+                        &options.output,
+                        staging_flag,
+                        options.staging_dir.as_deref(),
+                    )
+                    && let Some(runner) = utils::check_root_or_get_runner(
                         "Permission denied during freeze. Retrying with elevation...",
-                    )? {
-                        return utils::re_exec_with_runner(&runner);
-                    }
+                    )?
+                {
+                    return utils::re_exec_with_runner_custom_args(&runner, &args);
                 }
                 return Err(e);
             }
             println!("Successfully created archive: {:?}", options.output);
+            if delete_after
+                && let Err(e) = engine::delete_after_freeze(&options.output, &targets, yes, &executor)
+            {
+                // Mounting a LUKS archive needs root even when the freeze did not
+                if utils::is_permission_denied(&e)
+                    && let Some(args) = elevated_delete_after_args(&options.output, &targets, yes)
+                    && let Some(runner) = utils::check_root_or_get_runner(
+                        "Permission denied during --delete-after. Retrying with elevation...",
+                    )?
+                {
+                    return utils::re_exec_with_runner_custom_args(&runner, &args);
+                }
+                eprintln!("The archive {} was created; the originals were not deleted.", options.output.display());
+                return Err(e);
+            }
+            println!("{}", utils::archive_result_line(&options.output));
+        }
+        Commands::Version { json } => {
+            if json {
+                version::print_json("0k")?;
+            } else {
+                print!("{}", Args::build_command().render_long_version());
+            }
+        }
+        Commands::DeleteAfter { archive_path, targets, yes } => {
+            if let Err(e) = engine::delete_after_freeze(&archive_path, &targets, yes, &RealSystem) {
+                eprintln!("The archive {} was created; the originals were not deleted.", archive_path.display());
+                return Err(e);
+            }
+            println!("{}", utils::archive_result_line(&archive_path));
+        }
+        Commands::DumpSchema => {
+            let schema = serde_json::to_string_pretty(&report::schema())
+                .map_err(|e| ZkError::OperationFailed(format!("Cannot encode the JSON Schema: {}", e)))?;
+            println!("{}", schema);
         }
         Commands::Unfreeze {
             archive_path,
@@ -162,12 +427,48 @@ fn run_app() -> Result<(), ZkError> {
             skip_existing,
             force_unfreeze,
             verify,
+            follow_dest_symlinks,
+            no_times,
+            no_xattrs,
+            no_restorecon,
+            parent_mode,
+            no_manifest,
+            target,
+            resume,
+            restart,
+            allow_fs_change,
+            target_dir,
+            only,
+            remap,
+            dry_run,
+            no_progress,
+            json_events,
         } => {
+            if json_events {
+                zero_kelvin::events::init()?;
+            }
+            let parent_mode = parent_mode.map(|m| utils::parse_octal_mode(&m)).transpose()?;
             let options = UnfreezeOptions {
                 overwrite,
                 skip_existing,
                 force_unfreeze,
                 verify,
+                follow_dest_symlinks,
+                no_times,
+                no_xattrs,
+                no_restorecon,
+                parent_mode,
+                no_manifest_target: target.filter(|_| no_manifest),
+                resume,
+                restart,
+                allow_fs_change,
+                target_dir: target_dir.map(std::path::absolute).transpose()?,
+                only,
+                dry_run,
+                no_progress: no_progress || json_events,
+                remap: remap::parse_remaps(&remap)?,
+                cancel: Some(INTERRUPTED.clone()),
+                progress: None,
             };
             let executor = RealSystem;
             // engine::unfreeze(&archive_path, &options, &executor)?;
@@ -188,25 +489,193 @@ fn run_app() -> Result<(), ZkError> {
             use_cmp,
             delete,
             force_delete,
+            prune_dirs,
+            show_extra,
+            exclude,
+            quick,
+            checksums,
+            no_manifest,
+            target,
+            remap,
+            json_events,
+            json,
+            exit_zero,
+            report,
         } => {
+            if json_events {
+                zero_kelvin::events::init()?;
+            }
             let executor = RealSystem;
             let options = engine::CheckOptions {
                 use_cmp,
                 delete,
                 force_delete,
+                prune_dirs,
+                show_extra,
+                exclude,
+                quick,
+                checksums,
+                no_manifest_target: target.filter(|_| no_manifest),
+                only_targets: vec![],
+                remap: remap::parse_remaps(&remap)?,
+                report: report.map(|path| path.map_or(ReportTarget::Default, ReportTarget::Path)),
+                cancel: Some(INTERRUPTED.clone()),
+                progress: None,
             };
-            // engine::check(&archive_path, &options, &executor)?;
-            if let Err(e) = engine::check(&archive_path, &options, &executor) {
-                if utils::is_permission_denied(&e) {
-                    if let Some(runner) = utils::check_root_or_get_runner(
-                        "Permission denied during check. Retrying with elevation...",
-                    )? {
+            let report = match engine::check(&archive_path, &options, &executor) {
+                Ok(report) => report,
+                Err(e) => {
+                    if utils::is_permission_denied(&e)
+                        && let Some(runner) = utils::check_root_or_get_runner(
+                            "Permission denied during check. Retrying with elevation...",
+                        )?
+                    {
                         return utils::re_exec_with_runner(&runner);
                     }
+                    return Err(e);
                 }
-                return Err(e);
+            };
+            if json {
+                report::print_json(&report, "the check result")?;
+            } else {
+                engine::print_check_report(&report, &options);
+            }
+            if report.has_problems() && !exit_zero {
+                return Err(ZkError::OperationFailed(format!(
+                    "Check failed: {} path(s) mismatched, {} missing",
+                    report.mismatched, report.missing
+                )));
+            }
+            if !json {
+                println!("Check completed successfully.");
+            }
+        }
+        Commands::Info { archive_path, json } => {
+            let info = engine::info(&archive_path, &RealSystem)?;
+            if json {
+                report::print_json(&info, "archive info")?;
+            } else {
+                println!("{}", info.render());
+            }
+        }
+        Commands::List { archive_path, sizes, json } => {
+            let entries = match engine::list(&archive_path, sizes, &RealSystem) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    if utils::is_permission_denied(&e)
+                        && let Some(runner) = utils::check_root_or_get_runner(
+                            "Permission denied during list. Retrying with elevation...",
+                        )?
+                    {
+                        return utils::re_exec_with_runner(&runner);
+                    }
+                    return Err(e);
+                }
+            };
+            if json {
+                report::print_json(&report::ListReport { entries }, "archive entries")?;
+            } else {
+                println!("{}", engine::render_list(&entries));
+            }
+        }
+        Commands::Verify { archive_path, deep, json } => {
+            let report = match engine::verify(&archive_path, deep, &RealSystem) {
+                Ok(report) => report,
+                Err(e) => {
+                    if utils::is_permission_denied(&e)
+                        && let Some(runner) = utils::check_root_or_get_runner(
+                            "Permission denied during verify. Retrying with elevation...",
+                        )?
+                    {
+                        return utils::re_exec_with_runner(&runner);
+                    }
+                    return Err(e);
+                }
+            };
+            if json {
+                report::print_json(&report, "the verification result")?;
+            } else {
+                for error in &report.errors {
+                    println!("ERROR: {}", error);
+                }
+                println!("{}", summary::verify(&report, deep, summary::color_enabled()));
+            }
+            if !report.errors.is_empty() {
+                return Err(ZkError::OperationFailed(format!(
+                    "Archive verification failed: {} problem(s) found",
+                    report.errors.len()
+                )));
+            }
+        }
+        Commands::Diff { old_archive, new_archive, use_cmp, json } => {
+            let options = engine::DiffOptions { use_cmp };
+            let report = match engine::diff(&old_archive, &new_archive, &options, &RealSystem) {
+                Ok(report) => report,
+                Err(e) => {
+                    if utils::is_permission_denied(&e)
+                        && let Some(runner) = utils::check_root_or_get_runner(
+                            "Permission denied during diff. Retrying with elevation...",
+                        )?
+                    {
+                        return utils::re_exec_with_runner(&runner);
+                    }
+                    return Err(e);
+                }
+            };
+            if json {
+                report::print_json(&report, "archive differences")?;
+            } else {
+                if !report.changes.is_empty() {
+                    println!("{}", engine::render_diff(&report));
+                }
+                println!("{}", summary::diff(&report, summary::color_enabled()));
+            }
+        }
+        Commands::Prune { source, keep_last, keep_within, verify_newer, dry_run, yes } => {
+            let source = if source == "catalog" {
+                prune::Source::Catalog
+            } else {
+                prune::Source::Dir(PathBuf::from(source))
+            };
+            let options = prune::PruneOptions {
+                policy: prune::Policy {
+                    keep_last: keep_last as usize,
+                    keep_within,
+                },
+                verify_newer,
+                dry_run,
+                yes,
+            };
+            prune::run(&source, &options, &RealSystem, &mounts::SystemReader, &catalog::catalog_path())?;
+        }
+        Commands::Gc { dry_run, older_than, staging_dir } => {
+            let options = engine::GcOptions {
+                dry_run,
+                older_than: older_than.map(std::time::Duration::from_secs),
+                staging_dir,
+            };
+            let summary = engine::gc_staging(&options)?;
+            if summary.entries.is_empty() {
+                println!("No staging directories to clean up.");
+            }
+            for entry in &summary.entries {
+                let (label, path) = (entry.outcome.label(), entry.path.display());
+                match &entry.outcome {
+                    engine::GcOutcome::Failed(e) => println!("{} {} ({}): {}", label, path, entry.reason, e),
+                    _ => println!("{} {} ({})", label, path, entry.reason),
+                }
+            }
+            if !summary.entries.is_empty() {
+                println!("Total: {}", summary.totals());
+            }
+            let failed = summary.failed();
+            if failed > 0 {
+                return Err(ZkError::OperationFailed(format!(
+                    "Could not remove {} staging director{}",
+                    failed,
+                    if failed == 1 { "y" } else { "ies" }
+                )));
             }
-            println!("Check completed successfully.");
         }
     }
 
@@ -214,26 +683,33 @@ fn run_app() -> Result<(), ZkError> {
 }
 
 /// Resolve output directory to a full file path with auto-generated name.
-/// If `prefix` is Some, uses it directly. Otherwise, prompts the user interactively.
+/// If `prefix` is Some, uses it directly. Otherwise, prompts the user interactively
+/// (only if the template actually uses `{prefix}`).
 fn resolve_directory_output(
     dir: &Path,
     prefix: Option<String>,
+    template: Option<&str>,
     encrypt: bool,
 ) -> Result<PathBuf, ZkError> {
+    let template = template.unwrap_or(utils::DEFAULT_NAME_TEMPLATE);
+    utils::validate_name_template(template)?;
     let prefix = match prefix {
         Some(p) => p,
-        None => prompt_for_prefix()?,
+        None if template.contains("{prefix}") => prompt_for_prefix()?,
+        None => String::new(),
+    };
+    let prefix = if template.contains("{prefix}") {
+        let normalized = utils::sanitize_prefix(&prefix);
+        if normalized != prefix {
+            eprintln!("Archive name prefix normalized: {:?} -> {:?}", prefix, normalized);
+        }
+        normalized
+    } else {
+        prefix
     };
 
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| ZkError::OperationFailed(format!("Time error: {}", e)))?
-        .as_secs();
-    let rnd: u32 = rand::rng().random_range(100000..999999u32);
     let ext = if encrypt { "sqfs_luks.img" } else { "sqfs" };
-    let filename = format!("{}_{}_{}.{}", prefix, timestamp, rnd, ext);
-
-    let final_path = dir.join(filename);
+    let final_path = utils::generate_unique_path(dir, template, &prefix, ext)?;
     eprintln!("Auto-generated output filename: {}", final_path.display());
     Ok(final_path)
 }
@@ -269,13 +745,25 @@ fn prompt_for_prefix() -> Result<String, ZkError> {
 }
 
 fn resolve_freeze_args(
+    args: Vec<PathBuf>,
+    read_file: Option<PathBuf>,
+    read0_file: Option<PathBuf>,
+) -> Result<(Vec<Target>, PathBuf), ZkError> {
+    resolve_freeze_args_from(args, read_file, read0_file, &mut std::io::stdin().lock())
+}
+
+/// [`resolve_freeze_args`] with `-` (for `--read` / `--read0`) reading from `stdin`.
+fn resolve_freeze_args_from(
     mut args: Vec<PathBuf>,
     read_file: Option<PathBuf>,
-) -> Result<(Vec<PathBuf>, PathBuf), ZkError> {
+    read0_file: Option<PathBuf>,
+    stdin: &mut dyn Read,
+) -> Result<(Vec<Target>, PathBuf), ZkError> {
     // Logic:
     // Last argument is Output Path (Archive).
     // Preceding arguments are Targets.
     // If -r file provided, read lines and add to Targets.
+    // If --read0 file provided, read NUL-separated paths and add to Targets.
 
     // 1. Determine Output Path
     if args.is_empty() {
@@ -289,22 +777,47 @@ fn resolve_freeze_args(
     })?;
 
     // 2. Collect Targets
-    let mut targets = args; // The rest are targets
+    let mut targets: Vec<Target> = args // The rest are targets
+        .into_iter()
+        .enumerate()
+        .map(|(i, path)| Target::new(path, format!("argument {}", i + 1)))
+        .collect();
 
-    // 3. Read from file if provided
+    // 3. Read from file (or stdin for "-") if provided
     if let Some(path) = read_file {
-        let content = fs::read_to_string(&path).map_err(|e| ZkError::IoError(e))?;
+        let mut content = String::new();
+        if path.as_os_str() == "-" {
+            stdin.read_to_string(&mut content)?;
+        } else {
+            content = fs::read_to_string(&path)?;
+        }
 
-        for line in content.lines() {
+        let source = list_name(&path);
+        for (number, line) in content.lines().enumerate() {
             let trimmed = line.trim();
             if !trimmed.is_empty() && !trimmed.starts_with('#') {
                 // Fix: Expand tilde manually
                 let expanded = zero_kelvin::utils::expand_tilde(trimmed);
-                targets.push(expanded);
+                targets.push(Target::new(expanded, format!("line {} of {}", number + 1, source)));
             }
         }
     }
 
+    if let Some(path) = read0_file {
+        let mut content = Vec::new();
+        if path.as_os_str() == "-" {
+            stdin.read_to_end(&mut content)?;
+        } else {
+            content = fs::read(&path)?;
+        }
+
+        // Paths are taken verbatim: no trimming, comments or ~ (a name may contain any of them)
+        let source = list_name(&path);
+        for (number, raw) in content.split(|b| *b == 0).enumerate().filter(|(_, raw)| !raw.is_empty()) {
+            targets.push(Target::new(OsStr::from_bytes(raw), format!("path {} of {}", number + 1, source)));
+        }
+    }
+
     if targets.is_empty() {
         return Err(ZkError::MissingTarget(
             "No targets specified to freeze".into(),
@@ -317,6 +830,97 @@ fn resolve_freeze_args(
     Ok((targets, output_path))
 }
 
+/// Arguments of the elevated freeze rerun, or None when a path is not UTF-8 (the rerun
+/// arguments are strings). A `--read -` / `--read0 -` list has been read from stdin already,
+/// so the list options and the positional arguments give way to the resolved
+/// `-- TARGETS... ARCHIVE_PATH`; the other options are passed on as given. sudo resets the
+/// environment, so a staging base taken from `$ZK_STAGING_DIR` is passed on as
+/// `--staging-dir` (right after `freeze`).
+fn elevated_freeze_args(
+    args: &[String],
+    targets: &[PathBuf],
+    output: &Path,
+    staging_flag: bool,
+    staging_dir: Option<&Path>,
+) -> Option<Vec<String>> {
+    use clap::CommandFactory;
+    let mut command = Args::command();
+    command.build();
+    let freeze = command.find_subcommand("freeze")?;
+    let long = |name: &str| freeze.get_arguments().find(|a| a.get_long() == Some(name));
+    let short = |c: char| freeze.get_arguments().find(|a| a.get_short() == Some(c));
+    let is_list = |arg: &clap::Arg| matches!(arg.get_id().as_str(), "read" | "read0");
+
+    let at = args.iter().position(|a| a == "freeze")?;
+    let mut rerun = args[..=at].to_vec();
+    if !staging_flag && let Some(dir) = staging_dir {
+        rerun.push(format!("--staging-dir={}", dir.to_str()?));
+    }
+    let mut rest = args[at + 1..].iter();
+    while let Some(token) = rest.next() {
+        if token == "--" {
+            break;
+        }
+        if let Some(name) = token.strip_prefix("--") {
+            let (name, inline) = name.split_once('=').map_or((name, false), |(name, _)| (name, true));
+            let Some(arg) = long(name) else {
+                rerun.push(token.clone());
+                continue;
+            };
+            let value = if !inline && arg.get_action().takes_values() { rest.next() } else { None };
+            if !is_list(arg) {
+                rerun.push(token.clone());
+                rerun.extend(value.cloned());
+            }
+        } else if let Some(cluster) = token.strip_prefix('-').filter(|c| !c.is_empty()) {
+            // `-eL`, `-c3`, `-r -`: each short option on its own, a value kept with its option
+            for (i, c) in cluster.char_indices() {
+                let Some(arg) = short(c).filter(|a| a.get_action().takes_values()) else {
+                    rerun.push(format!("-{}", c));
+                    continue;
+                };
+                let attached = &cluster[i + c.len_utf8()..];
+                let option = if attached.is_empty() {
+                    [Some(format!("-{}", c)), rest.next().cloned()]
+                } else {
+                    [Some(format!("-{}{}", c, attached)), None]
+                };
+                if !is_list(arg) {
+                    rerun.extend(option.into_iter().flatten());
+                }
+                break;
+            }
+        }
+        // Positional arguments are dropped: the targets and the archive follow the `--`
+    }
+    rerun.push("--".to_string());
+    for target in targets {
+        rerun.push(target.to_str()?.to_string());
+    }
+    rerun.push(output.to_str()?.to_string());
+    Some(rerun)
+}
+
+/// Arguments of the elevated `delete-after` rerun of `freeze --delete-after`, or None when a
+/// path is not UTF-8 (the rerun arguments are strings).
+fn elevated_delete_after_args(archive: &Path, targets: &[PathBuf], yes: bool) -> Option<Vec<String>> {
+    let mut args = vec!["delete-after".to_string()];
+    if yes {
+        args.push("--yes".to_string());
+    }
+    args.push("--".to_string());
+    args.push(archive.to_str()?.to_string());
+    for target in targets {
+        args.push(target.to_str()?.to_string());
+    }
+    Some(args)
+}
+
+/// How a `--read` / `--read0` list is named in messages.
+fn list_name(path: &Path) -> String {
+    if path.as_os_str() == "-" { "stdin".to_string() } else { path.display().to_string() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -348,27 +952,87 @@ mod tests {
                 args,
                 encrypt,
                 read,
+                read0,
                 overwrite_files,
+                no_recovery,
                 overwrite_luks_content,
                 no_progress,
                 vanilla_progress,
                 alfa_progress,
                 compression,
                 dereference,
+                dereference_target,
+                skip_broken_symlinks,
                 prefix,
+                name_template,
+                log_file,
+                debug_log,
+                keep_log,
+                keep_staging,
+                staging_dir,
+                mode,
+                json_events,
+                check_open_files,
+                allow_open_files,
+                skip_unreadable,
+                no_xattrs,
+                no_space_check,
+                reserve,
+                yes,
+                mksquashfs_arg,
+                exclude,
+                no_ignore_files,
+                mem,
+                checksums,
+                auto_fallback_compression,
+                integrity_token,
+                preserve_order,
+                dry_run,
+                json,
+                delete_after,
             } => {
                 assert_eq!(args[0], PathBuf::from("/home/user/data"));
                 assert_eq!(args[1], PathBuf::from("/mnt/backup/data.sqfs"));
                 assert!(encrypt);
                 assert_eq!(read, Some(PathBuf::from("/tmp/list.txt")));
+                assert_eq!(read0, None); // not passed
                 assert!(!overwrite_files);
+                assert!(!no_recovery); // not passed
                 assert!(!overwrite_luks_content);
                 assert!(!no_progress); // not passed
                 assert!(!vanilla_progress); // not passed
                 assert!(!alfa_progress); // not passed
                 assert_eq!(compression, Some(19));
                 assert!(!dereference);
+                assert!(dereference_target.is_empty()); // not passed
+                assert!(!skip_broken_symlinks); // not passed
                 assert_eq!(prefix, None); // not passed
+                assert_eq!(name_template, None); // not passed
+                assert_eq!(log_file, None); // not passed
+                assert!(!debug_log); // not passed
+                assert!(!keep_log); // not passed
+                assert!(!keep_staging); // not passed
+                assert_eq!(staging_dir, None); // not passed
+                assert_eq!(mode, None); // not passed
+                assert!(!json_events); // not passed
+                assert!(!check_open_files); // not passed
+                assert!(!allow_open_files); // not passed
+                assert!(!skip_unreadable); // not passed
+                assert!(!no_xattrs); // not passed
+                assert!(!no_space_check); // not passed
+                assert_eq!(reserve, None); // not passed
+                assert!(!yes); // not passed
+                assert!(mksquashfs_arg.is_empty()); // not passed
+                assert!(exclude.is_empty()); // not passed
+                assert!(!no_ignore_files); // not passed
+                assert_eq!(mem, None); // not passed
+                assert!(!checksums); // not passed
+                assert!(!auto_fallback_compression); // not passed
+                assert!(!integrity_token); // not passed
+                assert!(!preserve_order); // not passed
+                assert!(!dry_run); // not passed
+                assert!(!json); // not passed
+                assert!(!delete_after); // not passed
             }
             _ => panic!("Expected Freeze command"),
         }
@@ -410,6 +1074,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_freeze_log_flags() {
+        let args = Args::parse_from([
+            "0k", "freeze", "target", "out.sqfs", "--log-file", "/tmp/pack.log", "--keep-log", "--keep-staging",
+        ]);
+        if let Commands::Freeze {
+            log_file,
+            debug_log,
+            keep_log,
+            keep_staging,
+            ..
+        } = args.command
+        {
+            assert_eq!(log_file, Some(PathBuf::from("/tmp/pack.log")));
+            assert!(!debug_log);
+            assert!(keep_log);
+            assert!(keep_staging);
+        } else {
+            panic!("Wrong command");
+        }
+    }
+
     #[test]
     fn test_parse_check_args() {
         let args = Args::parse_from(&["0k", "check", "archive.sqfs", "--use-cmp", "--delete"]);
@@ -419,16 +1105,220 @@ mod tests {
                 use_cmp,
                 delete,
                 force_delete,
+                prune_dirs,
+                show_extra,
+                exclude,
+                quick,
+                checksums,
+                no_manifest,
+                target,
+                remap,
+                json_events,
+                json,
+                exit_zero,
+                report,
             } => {
                 assert_eq!(archive_path, PathBuf::from("archive.sqfs"));
                 assert!(use_cmp);
                 assert!(delete);
                 assert!(!force_delete);
+                assert!(!prune_dirs);
+                assert!(!show_extra);
+                assert!(exclude.is_empty());
+                assert!(!quick);
+                assert!(!checksums);
+                assert!(!no_manifest);
+                assert_eq!(target, None);
+                assert!(remap.is_empty());
+                assert!(!json_events);
+                assert!(!json);
+                assert!(!exit_zero);
+                assert_eq!(report, None);
             }
             _ => panic!("Expected Check command"),
         }
     }
 
+    #[test]
+    fn test_parse_no_manifest_target() {
+        let args = Args::parse_from(["0k", "unfreeze", "raw.sqfs", "--no-manifest", "--target", "/srv/out"]);
+        if let Commands::Unfreeze { no_manifest, target, .. } = args.command {
+            assert!(no_manifest);
+            assert_eq!(target, Some(PathBuf::from("/srv/out")));
+        } else {
+            panic!("Expected Unfreeze command");
+        }
+
+        // Each flag needs the other; no manifest means nothing to verify or quick-check with
+        assert!(Args::try_parse_from(["0k", "check", "raw.sqfs", "--no-manifest"]).is_err());
+        assert!(Args::try_parse_from(["0k", "check", "raw.sqfs", "--target", "/srv/out"]).is_err());
+        assert!(Args::try_parse_from(["0k", "check", "raw.sqfs", "--no-manifest", "--target", "d", "--quick"]).is_err());
+        assert!(Args::try_parse_from(["0k", "unfreeze", "raw.sqfs", "--no-manifest", "--target", "d", "--verify"]).is_err());
+        assert!(Args::try_parse_from(["0k", "unfreeze", "raw.sqfs", "--no-manifest", "--target", "d", "--allow-fs-change"]).is_err());
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--allow-fs-change"]);
+        assert!(matches!(args.command, Commands::Unfreeze { allow_fs_change: true, .. }));
+    }
+
+    #[test]
+    fn test_parse_unfreeze_only() {
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--only", "3", "--only", "docs"]);
+        if let Commands::Unfreeze { only, .. } = args.command {
+            assert_eq!(only, ["3", "docs"]);
+        } else {
+            panic!("Expected Unfreeze command");
+        }
+        assert!(Args::try_parse_from(["0k", "unfreeze", "raw.sqfs", "--no-manifest", "--target", "d", "--only", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_remap() {
+        let args = Args::parse_from([
+            "0k", "unfreeze", "archive.sqfs", "--remap", "/home/anton=/home/antony", "--remap", "/opt=/usr/local",
+        ]);
+        if let Commands::Unfreeze { remap, .. } = args.command {
+            assert_eq!(remap, ["/home/anton=/home/antony", "/opt=/usr/local"]);
+        } else {
+            panic!("Expected Unfreeze command");
+        }
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--remap", "/home/anton=/home/antony"]);
+        assert!(matches!(args.command, Commands::Check { remap, .. } if remap.len() == 1));
+        assert!(Args::try_parse_from(["0k", "check", "raw.sqfs", "--no-manifest", "--target", "d", "--remap", "/a=/b"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_unfreeze_dry_run() {
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--dry-run", "--overwrite"]);
+        assert!(matches!(args.command, Commands::Unfreeze { dry_run: true, overwrite: true, .. }));
+        assert!(Args::try_parse_from(["0k", "unfreeze", "archive.sqfs", "--dry-run", "--resume"]).is_err());
+        assert!(Args::try_parse_from(["0k", "unfreeze", "archive.sqfs", "--dry-run", "--json-events"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_unfreeze_no_progress() {
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs"]);
+        assert!(matches!(args.command, Commands::Unfreeze { no_progress: false, .. }));
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--no-progress"]);
+        assert!(matches!(args.command, Commands::Unfreeze { no_progress: true, .. }));
+    }
+
+    #[test]
+    fn test_parse_unfreeze_target_dir() {
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--target-dir", "/mnt/restore"]);
+        if let Commands::Unfreeze { target_dir, no_manifest, .. } = args.command {
+            assert_eq!(target_dir, Some(PathBuf::from("/mnt/restore")));
+            assert!(!no_manifest);
+        } else {
+            panic!("Expected Unfreeze command");
+        }
+        assert!(
+            Args::try_parse_from(["0k", "unfreeze", "raw.sqfs", "--no-manifest", "--target", "d", "--target-dir", "e"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_check_quick_conflicts() {
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--quick"]);
+        if let Commands::Check { quick, use_cmp, .. } = args.command {
+            assert!(quick);
+            assert!(!use_cmp);
+        } else {
+            panic!("Expected Check command");
+        }
+
+        assert!(Args::try_parse_from(["0k", "check", "a.sqfs", "--quick", "--use-cmp"]).is_err());
+        assert!(Args::try_parse_from(["0k", "check", "a.sqfs", "--quick", "--delete"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_checksums_flags() {
+        let args = Args::parse_from(["0k", "freeze", "data", "out.sqfs", "--checksums"]);
+        if let Commands::Freeze { checksums, .. } = args.command {
+            assert!(checksums);
+        } else {
+            panic!("Expected Freeze command");
+        }
+
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--checksums", "--delete"]);
+        if let Commands::Check { checksums, delete, use_cmp, .. } = args.command {
+            assert!(checksums);
+            assert!(delete);
+            assert!(!use_cmp);
+        } else {
+            panic!("Expected Check command");
+        }
+
+        // Both judge files by the manifest alone; without a manifest there are no digests
+        assert!(Args::try_parse_from(["0k", "check", "a.sqfs", "--checksums", "--quick"]).is_err());
+        assert!(Args::try_parse_from(["0k", "check", "a.sqfs", "--checksums", "--no-manifest", "--target", "d"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_json_events_flag() {
+        let args = Args::parse_from(["0k", "unfreeze", "archive.sqfs", "--json-events"]);
+        if let Commands::Unfreeze { json_events, .. } = args.command {
+            assert!(json_events);
+        } else {
+            panic!("Expected Unfreeze command");
+        }
+
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--quick", "--json-events"]);
+        if let Commands::Check { json_events, quick, .. } = args.command {
+            assert!(json_events);
+            assert!(quick);
+        } else {
+            panic!("Expected Check command");
+        }
+    }
+
+    #[test]
+    fn test_parse_check_show_extra() {
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--show-extra", "--delete"]);
+        assert!(matches!(args.command, Commands::Check { show_extra: true, delete: true, .. }));
+    }
+
+    #[test]
+    fn test_parse_check_exclude() {
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--exclude", "*.log", "--exclude", "cache/"]);
+        if let Commands::Check { exclude, .. } = args.command {
+            assert_eq!(exclude, ["*.log", "cache/"]);
+        } else {
+            panic!("Expected Check command");
+        }
+    }
+
+    #[test]
+    fn test_parse_check_report() {
+        let report = |args: &[&str]| match Args::parse_from(args).command {
+            Commands::Check { archive_path, report, .. } => (archive_path, report),
+            _ => panic!("Expected Check command"),
+        };
+        // Without a value the archive stays positional and the report goes next to it
+        let (archive, path) = report(&["0k", "check", "--report", "archive.sqfs"]);
+        assert_eq!((archive, path), (PathBuf::from("archive.sqfs"), Some(None)));
+        let (_, path) = report(&["0k", "check", "archive.sqfs", "--report=/tmp/audit.yaml"]);
+        assert_eq!(path, Some(Some(PathBuf::from("/tmp/audit.yaml"))));
+    }
+
+    #[test]
+    fn test_parse_check_prune_dirs() {
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--delete", "--prune-dirs"]);
+        assert!(matches!(args.command, Commands::Check { delete: true, prune_dirs: true, .. }));
+        // A modifier of --delete only
+        assert!(Args::try_parse_from(["0k", "check", "archive.sqfs", "--prune-dirs"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_check_json() {
+        let args = Args::parse_from(["0k", "check", "archive.sqfs", "--json", "--exit-zero"]);
+        assert!(matches!(args.command, Commands::Check { json: true, exit_zero: true, json_events: false, .. }));
+        assert!(Args::try_parse_from(["0k", "check", "archive.sqfs", "--json", "--json-events"]).is_err());
+    }
+
+    fn paths(targets: Vec<Target>) -> Vec<PathBuf> {
+        targets.into_iter().map(|t| t.path).collect()
+    }
+
     #[test]
     fn test_resolve_freeze_args_basic() {
         let args = vec![
@@ -436,7 +1326,8 @@ mod tests {
             PathBuf::from("t2"),
             PathBuf::from("out.sqfs"),
         ];
-        let (targets, out) = super::resolve_freeze_args(args, None).unwrap();
+        let (targets, out) = super::resolve_freeze_args(args, None, None).unwrap();
+        let targets = paths(targets);
         assert_eq!(targets, vec![PathBuf::from("t1"), PathBuf::from("t2")]);
         assert_eq!(out, PathBuf::from("out.sqfs"));
     }
@@ -452,7 +1343,11 @@ mod tests {
         let file_path = tmp.path().to_path_buf();
         let args = vec![PathBuf::from("cli_target"), PathBuf::from("out.sqfs")];
 
-        let (targets, out) = super::resolve_freeze_args(args, Some(file_path)).unwrap();
+        let (targets, out) = super::resolve_freeze_args(args, Some(file_path.clone()), None).unwrap();
+        // Origins name the line, for the duplicate warnings
+        assert_eq!(targets[0].origin, "argument 1");
+        assert_eq!(targets[2].origin, format!("line 3 of {}", file_path.display()));
+        let targets = paths(targets);
         assert_eq!(out, PathBuf::from("out.sqfs"));
         assert_eq!(targets.len(), 3);
         assert!(targets.contains(&PathBuf::from("cli_target")));
@@ -460,13 +1355,78 @@ mod tests {
         assert!(targets.contains(&PathBuf::from("file2_from_list")));
     }
 
+    #[test]
+    fn test_resolve_freeze_args_from_stdin() {
+        let home = std::env::var("HOME").unwrap();
+        let args = vec![PathBuf::from("cli_target"), PathBuf::from("out.sqfs")];
+        let mut stdin = std::io::Cursor::new("# from find\n\nfrom_stdin\n  ~/docs  \n");
+        let (targets, out) =
+            super::resolve_freeze_args_from(args, Some(PathBuf::from("-")), None, &mut stdin).unwrap();
+        let targets = paths(targets);
+        assert_eq!(out, PathBuf::from("out.sqfs"));
+        assert_eq!(
+            targets,
+            vec![PathBuf::from("cli_target"), PathBuf::from("from_stdin"), PathBuf::from(home).join("docs")]
+        );
+    }
+
+    #[test]
+    fn test_resolve_freeze_args_read0() {
+        // Names with newlines, spaces, a leading '#' and '~' are taken as they are
+        let list = b"./a file\0./multi\nline\0# not a comment\0~/literal\0";
+        let args = vec![PathBuf::from("cli_target"), PathBuf::from("out.sqfs")];
+        let mut stdin = std::io::Cursor::new(list.to_vec());
+        let (targets, out) =
+            super::resolve_freeze_args_from(args.clone(), None, Some(PathBuf::from("-")), &mut stdin).unwrap();
+        let targets = paths(targets);
+        let expected: Vec<PathBuf> = ["cli_target", "./a file", "./multi\nline", "# not a comment", "~/literal"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(out, PathBuf::from("out.sqfs"));
+        assert_eq!(targets, expected);
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut tmp, list).unwrap();
+        let (targets, _) =
+            super::resolve_freeze_args_from(args, None, Some(tmp.path().to_path_buf()), &mut std::io::empty()).unwrap();
+        let targets = paths(targets);
+        assert_eq!(targets, expected);
+
+        // Only the archive path given and an empty list: nothing to freeze
+        let mut stdin = std::io::Cursor::new(Vec::new());
+        let args = vec![PathBuf::from("out.sqfs")];
+        let res = super::resolve_freeze_args_from(args, None, Some(PathBuf::from("-")), &mut stdin);
+        assert!(res.is_err());
+    }
+
     #[test]
     fn test_resolve_freeze_args_no_output() {
         let args = vec![];
-        let res = super::resolve_freeze_args(args, None);
+        let res = super::resolve_freeze_args(args, None, None);
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_parse_freeze_delete_after() {
+        let args = Args::parse_from(["0k", "freeze", "target", "out.sqfs", "--delete-after", "--yes"]);
+        if let Commands::Freeze { delete_after, yes, .. } = args.command {
+            assert!(delete_after);
+            assert!(yes);
+        } else {
+            panic!("Expected Freeze command");
+        }
+        assert!(Args::try_parse_from(["0k", "freeze", "target", "out.sqfs", "--delete-after", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_global_threads() {
+        assert_eq!(Args::parse_from(["0k", "--threads", "4", "freeze", "t", "out.sqfs"]).threads, Some(4));
+        assert_eq!(Args::parse_from(["0k", "freeze", "t", "out.sqfs", "--threads", "2"]).threads, Some(2));
+        assert_eq!(Args::parse_from(["0k", "check", "a.sqfs"]).threads, None);
+        assert!(Args::try_parse_from(["0k", "--threads", "0", "check", "a.sqfs"]).is_err());
+    }
+
     #[test]
     fn test_parse_freeze_with_prefix() {
         let args =
@@ -482,20 +1442,240 @@ mod tests {
     fn test_resolve_directory_output_with_prefix() {
         let dir = tempfile::tempdir().unwrap();
         let result =
-            super::resolve_directory_output(dir.path(), Some("myprefix".into()), false).unwrap();
+            super::resolve_directory_output(dir.path(), Some("myprefix".into()), None, false).unwrap();
         let filename = result.file_name().unwrap().to_str().unwrap();
         assert!(filename.starts_with("myprefix_"));
         assert!(filename.ends_with(".sqfs"));
         assert_eq!(result.parent().unwrap(), dir.path());
     }
 
+    #[test]
+    fn test_resolve_directory_output_normalizes_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let result =
+            super::resolve_directory_output(dir.path(), Some("my docs (old)!".into()), None, false).unwrap();
+        let filename = result.file_name().unwrap().to_str().unwrap();
+        assert!(filename.starts_with("my_docs_old_"), "{}", filename);
+    }
+
     #[test]
     fn test_resolve_directory_output_encrypted() {
         let dir = tempfile::tempdir().unwrap();
         let result =
-            super::resolve_directory_output(dir.path(), Some("secret".into()), true).unwrap();
+            super::resolve_directory_output(dir.path(), Some("secret".into()), None, true).unwrap();
         let filename = result.file_name().unwrap().to_str().unwrap();
         assert!(filename.starts_with("secret_"));
         assert!(filename.ends_with(".sqfs_luks.img"));
     }
+
+    #[test]
+    fn test_resolve_directory_output_with_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = super::resolve_directory_output(
+            dir.path(),
+            None, // no prompt: template does not use {prefix}
+            Some("nightly-{date}-{rand}"),
+            false,
+        )
+        .unwrap();
+        let filename = result.file_name().unwrap().to_str().unwrap();
+        assert!(filename.starts_with("nightly-"), "{}", filename);
+        assert!(filename.ends_with(".sqfs"));
+
+        let err = super::resolve_directory_output(dir.path(), Some("p".into()), Some("../{rand}"), false);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_parse_prune() {
+        let args = Args::parse_from(["0k", "prune", "/backups", "--keep-last", "3", "--keep-within", "30d", "--yes"]);
+        if let Commands::Prune { source, keep_last, keep_within, verify_newer, dry_run, yes } = args.command {
+            assert_eq!(source, "/backups");
+            assert_eq!(keep_last, 3);
+            assert_eq!(keep_within, Some(30 * 86_400));
+            assert!(!verify_newer); // not passed
+            assert!(!dry_run); // not passed
+            assert!(yes);
+        } else {
+            panic!("Expected Prune command");
+        }
+
+        assert!(Args::try_parse_from(["0k", "prune", "catalog"]).is_err(), "--keep-last is required");
+        assert!(Args::try_parse_from(["0k", "prune", "catalog", "--keep-last", "0"]).is_err());
+        assert!(Args::try_parse_from(["0k", "prune", "catalog", "--keep-last", "1", "--dry-run", "--yes"]).is_err());
+        let err = Args::try_parse_from(["0k", "prune", "catalog", "--keep-last", "1", "--keep-within", "30"]).unwrap_err();
+        assert!(err.to_string().contains("s, m, h, d, w or y"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_gc() {
+        let args = Args::parse_from(["0k", "gc", "--dry-run", "--older-than", "1h30m"]);
+        if let Commands::Gc { dry_run, older_than, .. } = args.command {
+            assert!(dry_run);
+            assert_eq!(older_than, Some(5400));
+        } else {
+            panic!("Expected Gc command");
+        }
+        assert!(Args::try_parse_from(["0k", "gc", "--older-than", "0h"]).is_err());
+        assert!(Args::try_parse_from(["0k", "gc", "--older-than", "6"]).is_err(), "a unit is required");
+    }
+
+    #[test]
+    fn test_cleanup_on_interrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.sqfs");
+        let build_dir = dir.path().join("build_1");
+        fs::write(&output, "partial").unwrap();
+        fs::create_dir_all(build_dir.join("payload")).unwrap();
+
+        register_cleanup(&CLEANUP_OUTPUT, Some(output.clone()));
+        register_build_dir(&build_dir);
+        cleanup_on_interrupt();
+        assert!(!output.exists() && !build_dir.exists());
+
+        // Handled once: a later interrupt leaves a new file of the same name alone
+        fs::write(&output, "someone else's").unwrap();
+        cleanup_on_interrupt();
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_parse_staging_dir() {
+        let args = Args::parse_from(["0k", "freeze", "target", "out.sqfs", "--staging-dir", "/big/scratch"]);
+        if let Commands::Freeze { staging_dir, .. } = args.command {
+            assert_eq!(staging_dir, Some(PathBuf::from("/big/scratch")));
+        } else {
+            panic!("Expected Freeze command");
+        }
+        let args = Args::parse_from(["0k", "gc", "--staging-dir", "/big/scratch"]);
+        if let Commands::Gc { staging_dir, .. } = args.command {
+            assert_eq!(staging_dir, Some(PathBuf::from("/big/scratch")));
+        } else {
+            panic!("Expected Gc command");
+        }
+    }
+
+    #[test]
+    fn test_elevated_freeze_args() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let targets = |list: &[&str]| list.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let base = Path::new("/big/scratch");
+        let out = Path::new("out.sqfs");
+        let rerun = |given: &[&str], listed: &[&str], staging_flag: bool, staging_dir: Option<&Path>| {
+            elevated_freeze_args(&args(given), &targets(listed), out, staging_flag, staging_dir).unwrap()
+        };
+        assert_eq!(
+            rerun(&["freeze", "target", "out.sqfs"], &["target"], false, Some(base)),
+            args(&["freeze", "--staging-dir=/big/scratch", "--", "target", "out.sqfs"])
+        );
+        assert_eq!(
+            rerun(&["--threads", "2", "freeze", "--", "-target", "out.sqfs"], &["-target"], false, Some(base)),
+            args(&["--threads", "2", "freeze", "--staging-dir=/big/scratch", "--", "-target", "out.sqfs"])
+        );
+        // Given on the command line: it is there already
+        assert_eq!(
+            rerun(&["freeze", "target", "out.sqfs", "--staging-dir", "scratch"], &["target"], true, Some(base)),
+            args(&["freeze", "--staging-dir", "scratch", "--", "target", "out.sqfs"])
+        );
+        assert_eq!(rerun(&["freeze", "t", "o"], &["t"], false, None), args(&["freeze", "--", "t", "out.sqfs"]));
+
+        // Options and their values are kept, whatever their form; the positionals are not
+        assert_eq!(
+            rerun(
+                &["freeze", "-eLc3", "t", "--exclude", "*.tmp", "--mksquashfs-arg", "-no-fragments", "-c", "5", "out.sqfs"],
+                &["t"],
+                true,
+                None
+            ),
+            args(&["freeze", "-e", "-L", "-c3", "--exclude", "*.tmp", "--mksquashfs-arg", "-no-fragments", "-c", "5", "--", "t", "out.sqfs"])
+        );
+
+        let rerun = elevated_freeze_args(&args(&["0k", "freeze", "target", "out.sqfs"]), &targets(&["target"]), out, false, Some(base));
+        if let Commands::Freeze { staging_dir, .. } = Args::parse_from(rerun.unwrap()).command {
+            assert_eq!(staging_dir, Some(PathBuf::from("/big/scratch")));
+        } else {
+            panic!("Expected Freeze command");
+        }
+
+        let non_utf8 = PathBuf::from(OsStr::from_bytes(b"bad\xff"));
+        assert!(elevated_freeze_args(&args(&["freeze", "x", "out.sqfs"]), &[non_utf8], out, true, None).is_none());
+    }
+
+    #[test]
+    fn test_elevated_freeze_args_stdin_list() {
+        let given = ["0k", "freeze", "-r", "-", "--read0=-", "-er-", "--read", "-", "out.sqfs"].map(String::from);
+        let (targets, output) =
+            resolve_freeze_args_from(vec![PathBuf::from("out.sqfs")], Some(PathBuf::from("-")), None, &mut &b"/data/a\n/data/b\n"[..])
+                .unwrap();
+        let targets: Vec<PathBuf> = targets.into_iter().map(|t| t.path).collect();
+        // stdin has been read: the rerun gets the targets themselves, not the list options
+        let rerun = elevated_freeze_args(&given, &targets, &output, true, None).unwrap();
+        assert_eq!(rerun, ["0k", "freeze", "-e", "--", "/data/a", "/data/b", "out.sqfs"]);
+        if let Commands::Freeze { args, read, read0, encrypt, .. } = Args::parse_from(rerun).command {
+            assert_eq!(args, [PathBuf::from("/data/a"), PathBuf::from("/data/b"), PathBuf::from("out.sqfs")]);
+            assert_eq!((read, read0), (None, None));
+            assert!(encrypt);
+        } else {
+            panic!("Expected Freeze command");
+        }
+    }
+
+    #[test]
+    fn test_elevated_delete_after_args() {
+        let targets = [PathBuf::from("/home/user/docs"), PathBuf::from("-odd")];
+        let rerun = elevated_delete_after_args(Path::new("out.sqfs"), &targets, true).unwrap();
+        assert_eq!(rerun, ["delete-after", "--yes", "--", "out.sqfs", "/home/user/docs", "-odd"]);
+        let parsed = Args::parse_from(std::iter::once("0k".to_string()).chain(rerun)).command;
+        if let Commands::DeleteAfter { archive_path, targets: parsed_targets, yes } = parsed {
+            assert_eq!(archive_path, PathBuf::from("out.sqfs"));
+            assert_eq!(parsed_targets, targets);
+            assert!(yes);
+        } else {
+            panic!("Expected DeleteAfter command");
+        }
+
+        assert!(!elevated_delete_after_args(Path::new("out.sqfs"), &targets, false).unwrap().contains(&"--yes".to_string()));
+        let non_utf8 = PathBuf::from(OsStr::from_bytes(b"bad\xff"));
+        assert!(elevated_delete_after_args(Path::new("out.sqfs"), &[non_utf8], false).is_none());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let args = Args::parse_from(["0k", "list", "archive.sqfs", "--sizes"]);
+        if let Commands::List { archive_path, sizes, json } = args.command {
+            assert_eq!(archive_path, PathBuf::from("archive.sqfs"));
+            assert!(sizes);
+            assert!(!json); // not passed
+        } else {
+            panic!("Expected List command");
+        }
+        assert!(Args::try_parse_from(["0k", "list"]).is_err(), "ARCHIVE_PATH is required");
+    }
+
+    #[test]
+    fn test_parse_verify() {
+        let args = Args::parse_from(["0k", "verify", "archive.sqfs", "--deep"]);
+        if let Commands::Verify { archive_path, deep, json } = args.command {
+            assert_eq!(archive_path, PathBuf::from("archive.sqfs"));
+            assert!(deep);
+            assert!(!json); // not passed
+        } else {
+            panic!("Expected Verify command");
+        }
+        assert!(Args::try_parse_from(["0k", "verify"]).is_err(), "ARCHIVE_PATH is required");
+    }
+
+    #[test]
+    fn test_parse_diff() {
+        let args = Args::try_parse_from(["0k", "diff", "may.sqfs", "june.sqfs", "--use-cmp"]).unwrap();
+        if let Commands::Diff { old_archive, new_archive, use_cmp, json } = args.command {
+            assert_eq!(old_archive, PathBuf::from("may.sqfs"));
+            assert_eq!(new_archive, PathBuf::from("june.sqfs"));
+            assert!(use_cmp);
+            assert!(!json);
+        } else {
+            panic!("Expected Diff command");
+        }
+        assert!(Args::try_parse_from(["0k", "diff", "may.sqfs"]).is_err(), "NEW_ARCHIVE is required");
+    }
 }
@@ -0,0 +1,200 @@
+//! Catalog of the archives this user created: one JSON object per line in
+//! `$XDG_DATA_HOME/zero-kelvin/catalog.jsonl` (`~/.local/share/zero-kelvin/catalog.jsonl`).
+//!
+//! `freeze` appends an entry after each successful run and, before starting, looks for an
+//! earlier archive of exactly the same targets (see [`targets_hash`]) to catch accidental
+//! re-freezes. The catalog is a convenience: failing to read or write it never fails a freeze.
+
+use crate::constants::{APP_NAME, CATALOG_FILE_NAME};
+use crate::error::ZkError;
+use crate::locks::{self, LockClass};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One archive created by `freeze`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Absolute path of the archive when it was written
+    pub archive: PathBuf,
+    /// Unix seconds
+    pub created: u64,
+    pub host: String,
+    /// Canonical targets, sorted
+    pub targets: Vec<PathBuf>,
+    /// [`targets_hash`] of `targets`
+    pub targets_hash: String,
+    /// Archive size in bytes
+    pub size: u64,
+}
+
+/// `$XDG_DATA_HOME/zero-kelvin/catalog.jsonl` (or under `~/.local/share`).
+pub fn catalog_path() -> PathBuf {
+    let data_home = std::env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| format!("{}/.local/share", std::env::var("HOME").unwrap_or_default()));
+    PathBuf::from(data_home).join(APP_NAME).join(CATALOG_FILE_NAME)
+}
+
+/// Canonical (symlinks resolved where possible), sorted and deduplicated target list.
+pub fn canonical_targets(targets: &[PathBuf]) -> Vec<PathBuf> {
+    let mut canonical: Vec<PathBuf> = targets
+        .iter()
+        .map(|t| fs::canonicalize(t).unwrap_or_else(|_| t.clone()))
+        .collect();
+    canonical.sort();
+    canonical.dedup();
+    canonical
+}
+
+/// Identifies a set of targets independently of order, duplicates and the spelling of the
+/// paths (`./docs`, `/home/me/docs/`): 64-bit FNV-1a over the canonical list.
+pub fn targets_hash(targets: &[PathBuf]) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for target in canonical_targets(targets) {
+        // NUL separates paths (it cannot occur in one)
+        for b in target.as_os_str().as_bytes().iter().chain(&[0]) {
+            hash ^= u64::from(*b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// All entries of the catalog at `path`, oldest first. A missing file is an empty catalog;
+/// lines that do not parse are skipped.
+pub fn load(path: &Path) -> Result<Vec<CatalogEntry>, ZkError> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ZkError::IoError(e)),
+    };
+    Ok(content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect())
+}
+
+/// Appends `entry` to the catalog at `path` (created with its directory if needed).
+pub fn record(path: &Path, entry: &CatalogEntry) -> Result<(), ZkError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let line = serde_json::to_string(entry)
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot serialize catalog entry: {}", e)))?;
+    let _lock = locks::lock(LockClass::Catalog, &lock_path(path))?;
+    let mut f = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per line: concurrent appends do not interleave
+    f.write_all(format!("{}\n", line).as_bytes())?;
+    Ok(())
+}
+
+/// Drops the entries of `archives` from the catalog at `path` (rewritten in place, under the
+/// catalog lock). Returns the number of entries removed; lines that do not parse are kept.
+pub fn remove(path: &Path, archives: &[PathBuf]) -> Result<usize, ZkError> {
+    let _lock = locks::lock(LockClass::Catalog, &lock_path(path))?;
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(ZkError::IoError(e)),
+    };
+    let mut kept = String::new();
+    let mut removed = 0;
+    for line in content.lines() {
+        let entry: Option<CatalogEntry> = serde_json::from_str(line).ok();
+        if entry.is_some_and(|e| archives.contains(&e.archive)) {
+            removed += 1;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if removed > 0 {
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, kept)?;
+        fs::rename(&tmp, path)?;
+    }
+    Ok(removed)
+}
+
+/// `catalog.jsonl.lock` next to the catalog.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// The newest entry for the same targets whose archive still exists.
+pub fn find_duplicate<'a>(entries: &'a [CatalogEntry], targets_hash: &str) -> Option<&'a CatalogEntry> {
+    entries
+        .iter()
+        .rev()
+        .find(|e| e.targets_hash == targets_hash && e.archive.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_hash_ignores_order_and_spelling() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&a).unwrap();
+        fs::create_dir(&b).unwrap();
+        let link = dir.path().join("link_to_a");
+        std::os::unix::fs::symlink(&a, &link).unwrap();
+
+        let hash = targets_hash(&[a.clone(), b.clone()]);
+        assert_eq!(hash.len(), 16);
+        assert_eq!(targets_hash(&[b.clone(), a.clone()]), hash);
+        assert_eq!(targets_hash(&[link, b.join("../b"), a.clone()]), hash);
+        assert_ne!(targets_hash(std::slice::from_ref(&a)), hash);
+        // ["/x", "/y"] vs ["/x/y"]-style collisions are ruled out by the separator
+        assert_ne!(
+            targets_hash(&[PathBuf::from("/nonexistent/ab")]),
+            targets_hash(&[PathBuf::from("/nonexistent/a"), PathBuf::from("/nonexistent/b")])
+        );
+    }
+
+    #[test]
+    fn test_record_load_and_find_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/catalog.jsonl");
+        assert!(load(&path).unwrap().is_empty());
+
+        let archive = dir.path().join("docs.sqfs");
+        fs::write(&archive, "x").unwrap();
+        let entry = |archive: PathBuf, hash: &str| CatalogEntry {
+            archive,
+            created: 1_700_000_000,
+            host: "host".into(),
+            targets: vec![PathBuf::from("/home/me/docs")],
+            targets_hash: hash.into(),
+            size: 1,
+        };
+        record(&path, &entry(archive.clone(), "aaaa")).unwrap();
+        record(&path, &entry(dir.path().join("deleted.sqfs"), "aaaa")).unwrap();
+        record(&path, &entry(archive.clone(), "bbbb")).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"not json\n").unwrap();
+
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        // The newer match is gone from disk, so the older one is reported
+        assert_eq!(find_duplicate(&entries, "aaaa"), Some(&entries[0]));
+        assert!(find_duplicate(&entries, "cccc").is_none());
+
+        assert_eq!(remove(&path, std::slice::from_ref(&archive)).unwrap(), 2);
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].archive, dir.path().join("deleted.sqfs"));
+        // The unparsable line is left alone
+        assert!(fs::read_to_string(&path).unwrap().contains("not json"));
+        assert_eq!(remove(&dir.path().join("missing.jsonl"), &[archive]).unwrap(), 0);
+    }
+}
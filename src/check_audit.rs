@@ -0,0 +1,195 @@
+//! `check --report`: an audit trail of one check, a YAML file next to the archive
+//! (`<archive>.check-<unixtime>.yaml` unless a path is given).
+//!
+//! It records what was checked (the archive and its manifest metadata), how (the options)
+//! and the result (every path and the totals of the [`CheckReport`]). The file is a
+//! by-product: failing to write it only warns, the check result stands.
+
+use crate::engine::CheckOptions;
+use crate::error::ZkError;
+use crate::manifest::Metadata;
+use crate::report::CheckReport;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where `check --report` writes the audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportTarget {
+    /// `--report` without a path: next to the archive ([`CheckAudit::default_path`])
+    Default,
+    /// `--report=PATH`
+    Path(PathBuf),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckAudit {
+    /// Absolute path of the archive checked
+    pub archive: PathBuf,
+    /// Unix seconds when the check finished
+    pub checked: u64,
+    /// Host the check ran on
+    pub host: String,
+    /// Metadata of the archive's manifest; absent with `--no-manifest`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<Metadata>,
+    pub options: AuditOptions,
+    pub report: CheckReport,
+}
+
+/// The [`CheckOptions`] a check ran with, as its flags name them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditOptions {
+    pub use_cmp: bool,
+    pub delete: bool,
+    pub force_delete: bool,
+    pub prune_dirs: bool,
+    pub show_extra: bool,
+    pub quick: bool,
+    pub checksums: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// `--no-manifest --target <DIR>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_manifest_target: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_targets: Vec<PathBuf>,
+    /// `--remap`, as `OLD=NEW`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remap: Vec<String>,
+}
+
+impl From<&CheckOptions> for AuditOptions {
+    fn from(options: &CheckOptions) -> Self {
+        AuditOptions {
+            use_cmp: options.use_cmp,
+            delete: options.delete,
+            force_delete: options.force_delete,
+            prune_dirs: options.prune_dirs,
+            show_extra: options.show_extra,
+            quick: options.quick,
+            checksums: options.checksums,
+            exclude: options.exclude.clone(),
+            no_manifest_target: options.no_manifest_target.clone(),
+            only_targets: options.only_targets.clone(),
+            remap: options.remap.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl CheckAudit {
+    /// The audit of a check of `archive` that just finished with `report`.
+    pub fn new(archive: &Path, manifest: Option<Metadata>, options: &CheckOptions, report: CheckReport) -> Self {
+        let checked = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        CheckAudit {
+            archive: std::path::absolute(archive).unwrap_or_else(|_| archive.to_path_buf()),
+            checked,
+            host: crate::utils::get_hostname().unwrap_or_default(),
+            manifest,
+            options: options.into(),
+            report,
+        }
+    }
+
+    /// Where `--report` without a path writes: `<archive>.check-<checked>.yaml`.
+    pub fn default_path(&self) -> PathBuf {
+        let mut path = self.archive.as_os_str().to_os_string();
+        path.push(format!(".check-{}.yaml", self.checked));
+        PathBuf::from(path)
+    }
+
+    /// The file `target` names for this audit.
+    pub fn path(&self, target: &ReportTarget) -> PathBuf {
+        match target {
+            ReportTarget::Default => self.default_path(),
+            ReportTarget::Path(path) => path.clone(),
+        }
+    }
+
+    /// Writes the audit to `path` through a temporary file, synced and then renamed over it,
+    /// so a reader never sees half a report, not even after a crash.
+    pub fn write(&self, path: &Path) -> Result<(), ZkError> {
+        use std::io::Write;
+
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let written = serde_yaml::to_string(self)
+            .map_err(ZkError::from)
+            .and_then(|text| {
+                let mut file = fs::File::create(&tmp)?;
+                file.write_all(text.as_bytes())?;
+                file.sync_all().map_err(ZkError::from)
+            })
+            .and_then(|()| fs::rename(&tmp, path).map_err(ZkError::from));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::CheckStatus;
+    use crate::report::CheckedEntry;
+
+    fn options() -> CheckOptions {
+        CheckOptions {
+            use_cmp: true,
+            delete: false,
+            force_delete: false,
+            prune_dirs: false,
+            show_extra: false,
+            exclude: vec!["*.log".to_string()],
+            quick: false,
+            checksums: false,
+            no_manifest_target: None,
+            only_targets: vec![],
+            remap: crate::remap::parse_remaps(&["/old=/new".to_string()]).unwrap(),
+            report: None,
+            cancel: None,
+            progress: None,
+        }
+    }
+
+    #[test]
+    fn test_write_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("data.sqfs");
+        let report = CheckReport {
+            files_matched: 1,
+            missing: 1,
+            entries: vec![CheckedEntry {
+                path: PathBuf::from("/home/u/gone.txt"),
+                status: CheckStatus::Missing,
+                category: None,
+                detail: None,
+            }],
+            ..Default::default()
+        };
+        let audit = CheckAudit::new(&archive, None, &options(), report.clone());
+        let path = audit.path(&ReportTarget::Default);
+        assert_eq!(path, dir.path().join(format!("data.sqfs.check-{}.yaml", audit.checked)));
+        assert_eq!(audit.path(&ReportTarget::Path("/tmp/r.yaml".into())), PathBuf::from("/tmp/r.yaml"));
+
+        audit.write(&path).unwrap();
+        let read: CheckAudit = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read.archive, archive);
+        assert_eq!(read.report, report);
+        assert_eq!(read.options.remap, ["/old=/new"]);
+        assert_eq!(read.options.exclude, ["*.log"]);
+        assert!(read.manifest.is_none());
+        // Only the report itself is left
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // An unwritable destination fails without leaving anything behind
+        let missing = dir.path().join("no/such/dir/report.yaml");
+        assert!(audit.write(&missing).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,173 @@
+//! SHA-256 digests of the regular files of each manifest entry (`freeze --checksums`).
+//!
+//! A digest map is recorded per entry (`FileEntry::sha256`): the path relative to the entry
+//! root mapped to the hex digest, with `.` for an entry that is itself a file. `check
+//! --checksums` and `unfreeze --verify` judge content against it without reading the archive
+//! twice. Archives frozen without `--checksums` (or by older versions) have no map at all.
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Key of the entry root in a digest map (an entry that is a single file)
+pub const ENTRY_ROOT_KEY: &str = ".";
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Hex SHA-256 of the file at `path`, read in fixed-size chunks.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    sha256_reader(fs::File::open(path)?)
+}
+
+/// Hex SHA-256 of everything `reader` yields, read in fixed-size chunks.
+pub fn sha256_reader(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Digest map key for `rel` (relative to the entry root); None for non-UTF-8 paths,
+/// which are not recorded.
+pub fn digest_key(rel: &Path) -> Option<String> {
+    if rel.as_os_str().is_empty() {
+        return Some(ENTRY_ROOT_KEY.to_string());
+    }
+    rel.to_str().map(str::to_string)
+}
+
+/// The path a digest map key stands for below `root`.
+pub fn key_path(root: &Path, key: &str) -> PathBuf {
+    if key == ENTRY_ROOT_KEY { root.to_path_buf() } else { root.join(key) }
+}
+
+/// Digests of every regular file at or below `root`. Paths in `skip` (and everything below
+/// them) are left out; `follow_root_link` hashes the target of a symlinked root
+/// (`freeze --dereference`), deeper symlinks are never followed.
+pub fn record(root: &Path, follow_root_link: bool, skip: &HashSet<PathBuf>) -> io::Result<BTreeMap<String, String>> {
+    let mut digests = BTreeMap::new();
+    let walker = walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .follow_root_links(follow_root_link)
+        .into_iter()
+        .filter_entry(|e| !skip.contains(e.path()));
+    for item in walker {
+        let item = item?;
+        if !item.file_type().is_file() {
+            continue;
+        }
+        let rel = item.path().strip_prefix(root).unwrap_or(Path::new(""));
+        if let Some(key) = digest_key(rel) {
+            digests.insert(key, sha256_file(item.path())?);
+        }
+    }
+    Ok(digests)
+}
+
+/// Outcome of comparing one file against its recorded digest.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Match,
+    Mismatch,
+    Missing,
+    /// The file exists but could not be read
+    Unreadable(String),
+}
+
+pub fn verify_file(path: &Path, expected: &str) -> Verdict {
+    match fs::symlink_metadata(path) {
+        Err(_) => return Verdict::Missing,
+        Ok(meta) if !meta.is_file() => return Verdict::Mismatch,
+        Ok(_) => {}
+    }
+    match sha256_file(path) {
+        Ok(digest) if digest == expected => Verdict::Match,
+        Ok(_) => Verdict::Mismatch,
+        Err(e) => Verdict::Unreadable(e.to_string()),
+    }
+}
+
+/// Checks the files of a digest map below `root`; returns the paths that are not
+/// [`Verdict::Match`] with their verdict.
+pub fn verify_tree(root: &Path, digests: &BTreeMap<String, String>) -> Vec<(PathBuf, Verdict)> {
+    digests
+        .iter()
+        .map(|(key, expected)| {
+            let path = key_path(root, key);
+            let verdict = verify_file(&path, expected);
+            (path, verdict)
+        })
+        .filter(|(_, verdict)| *verdict != Verdict::Match)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sha256("alpha\n")
+    const ALPHA: &str = "b6a98d9ce9a2d9149288fa3df42d377c3e42737afdcdaf714e33c0a100b51060";
+
+    #[test]
+    fn test_sha256_file_streams_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(
+            sha256_file(&empty).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        // Several read buffers' worth: same digest as hashing it in one go
+        let big = dir.path().join("big");
+        let data: Vec<u8> = (0..READ_BUFFER_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect();
+        fs::write(&big, &data).unwrap();
+        let expected: String = Sha256::digest(&data).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(sha256_file(&big).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_record_and_verify_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("data");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(root.join("cache")).unwrap();
+        fs::write(root.join("a.txt"), "alpha\n").unwrap();
+        fs::write(root.join("sub/b.txt"), "bravo\n").unwrap();
+        fs::write(root.join("cache/c.bin"), "cache").unwrap();
+        std::os::unix::fs::symlink("a.txt", root.join("link")).unwrap();
+
+        let skip = HashSet::from([root.join("cache")]);
+        let digests = record(&root, false, &skip).unwrap();
+        assert_eq!(digests.keys().collect::<Vec<_>>(), ["a.txt", "sub/b.txt"]);
+        assert_eq!(digests["a.txt"], ALPHA);
+        assert!(verify_tree(&root, &digests).is_empty());
+
+        // A single-file entry is keyed by "."
+        let single = record(&root.join("a.txt"), false, &HashSet::new()).unwrap();
+        assert_eq!(single.keys().collect::<Vec<_>>(), [ENTRY_ROOT_KEY]);
+        assert_eq!(key_path(&root.join("a.txt"), ENTRY_ROOT_KEY), root.join("a.txt"));
+
+        fs::write(root.join("a.txt"), "ALPHA\n").unwrap();
+        fs::remove_file(root.join("sub/b.txt")).unwrap();
+        let failures = verify_tree(&root, &digests);
+        assert_eq!(
+            failures,
+            vec![(root.join("a.txt"), Verdict::Mismatch), (root.join("sub/b.txt"), Verdict::Missing)]
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use clap::Parser;
 use std::path::PathBuf;
-use crate::constants::DEFAULT_ZSTD_COMPRESSION;
+use crate::constants::{
+    DEFAULT_ARCHIVE_MODE, DEFAULT_ZSTD_COMPRESSION, EXIT_CODE_BUSY, LUKS_PASSPHRASE_ATTEMPTS,
+    MIN_ARCHIVE_SIZE_PERCENT,
+};
 
 const BANNER: &str = r#"
 Copyleft 🄯 2026 :: GPL3
@@ -42,12 +45,71 @@ impl Args {
     Arguments:
       INPUT                 Source directory or archive file.
       OUTPUT                (Optional) Path to the resulting image.
+                            If OUTPUT is a directory, a name is generated and printed
+                            as the last stdout line: ARCHIVE: <path>
     Options:
       -e, --encrypt         Create an encrypted LUKS container (Requires root/sudo).
-      -c, --compression N   Zstd compression level (default: {1}) 0 = no compression.
+      -c, --compression N   Zstd compression level (default: {1}) 0 = no compression
+                            (directories only: tar2sqfs always compresses a repack).
+      --overwrite-files     Overwrite files inside an existing archive (append): a plain
+                            archive without -e, a LUKS container with -e.
+      --overwrite-luks-content
+                            Replace the entire content of an existing LUKS container
+                            (with -e; not together with --overwrite-files).
       --no-progress         Disable progress bar completely.
       --vanilla-progress    Use native mksquashfs progress (explicit, also default).
       --alfa-progress       Use experimental custom progress bar (not fixed in encryption mode, yet; for testing).
+      --log-file <PATH>     Tee full mksquashfs/tar2sqfs output (timestamped) into PATH.
+      --debug-log           Same as --log-file <OUTPUT>.log.
+      --keep-log            Keep the log file even if packing succeeds.
+      --mode <OCTAL>        Permissions of a newly created archive (default: {3:o}).
+                            Encrypted containers are created by root (via sudo) and
+                            stay root-owned; the mode applies to that owner.
+      --passphrase-attempts N
+                            LUKS passphrase attempts before giving up (default: {4}).
+                            Only retried when stdin is a terminal. Without one (systemd
+                            units, timers) the passphrase is asked via systemd-ask-password
+                            or pinentry: passphrase_agent in ~/.config/0k/config.yaml.
+      --exclude-file <PATH> Leave out the paths listed in PATH (relative to INPUT,
+                            one per line; passed to mksquashfs -ef). Directory input only.
+      --no-xattrs           Do not store extended attributes (SELinux labels, ACLs);
+                            they are stored by default.
+      --reserve <SIZE|PERCENT>
+                            Space to leave free on the destination when allocating a
+                            LUKS container, e.g. 2G or 5% (default: reserve in
+                            ~/.config/0k/config.yaml, else 1 GiB or 2%, whichever is larger).
+      --no-space-check      Pack even if the destination has less free space than a
+                            well-compressed archive ({5}% of the input; uncompressed: all
+                            of it) or, encrypted, than the LUKS container to allocate.
+      --mksquashfs-arg <ARG>
+                            Append ARG to the mksquashfs command line (repeatable, one word
+                            each: --mksquashfs-arg=-nopad). Unsupported, at your own risk.
+                            Paths, whitespace and -e/-ef are refused; directory input only.
+                            The final command is logged with RUST_LOG=info.
+      --mem <SIZE>          Memory mksquashfs may use (-mem), e.g. 512M. Default: 25% of
+                            the available memory on machines with less than 4 GiB RAM,
+                            else the mksquashfs default. Directory input only.
+      --threads N           Threads for mksquashfs (-processors) or tar2sqfs (--num-jobs).
+                            Default: threads in ~/.config/0k/config.yaml, else the number
+                            of physical cores.
+      --auto-fallback-compression
+                            Encrypted: compress with gzip if this kernel was built without
+                            zstd SquashFS (CONFIG_SQUASHFS_ZSTD) and could not mount the
+                            container; otherwise only warn. Checked once per boot.
+      --verify              Read the result back: SquashFS superblock and top directory
+                            level. Encrypted containers are reopened read-only after the
+                            trim (the passphrase is asked again).
+      --no-recovery         When appending to a plain archive (--overwrite-files),
+                            mksquashfs keeps a recovery file (squashfs_recovery_<name>_<pid>
+                            in $HOME) to undo a failed append with mksquashfs -recover;
+                            0k-core removes it on success and names it on failure. This
+                            passes -no-recovery instead, for space-constrained setups.
+      --integrity-token     Encrypted: after packing, store the SHA-256 of the SquashFS
+                            payload (and a random archive id) in the LUKS2 header as a
+                            token of type 0k-meta (cryptsetup token import). A LUKS1
+                            header has no tokens: <OUTPUT>.sha256.json is written instead.
+                            Without it, appending or replacing the payload removes the
+                            digest recorded for the previous content.
 
     Supported Input Formats (repacked on-the-fly via pipe):
       - Directory: Standard behavior
@@ -63,16 +125,42 @@ impl Args {
 
   mount <IMAGE> [MOUNT_POINT]
     Mount a SquashFS image as a directory.
+    Encrypted (LUKS) images are opened and mounted read-only.
     Arguments:
       IMAGE                 Path to the SquashFS image file.
       MOUNT_POINT           (Optional) Manual mount point.
                             Generated if omitted (prefix_timestamp_random).
+    Options:
+      --passphrase-attempts N
+                            LUKS passphrase attempts before giving up (default: {4}).
+                            Without a terminal the passphrase agent is asked (see create).
+      --list                Do not mount; print where IMAGE is already mounted, one
+                            MOUNT_POINT<TAB>BACKEND line each (squashfuse, luks:<mapper>, loop).
+      --nonempty            Mount over a MOUNT_POINT that is not empty. Without it such a
+                            mount point is refused and its first entries are listed.
+    An image that is already mounted is reported (and mounted again).
 
-  umount <TARGET>
+  umount <TARGET> [OPTIONS]
     Unmounts a directory or all instances of an image.
+    Busy mounts are retried a few times; processes holding them are listed.
     Arguments:
       TARGET                Mount point directory OR path to the image file.
-", BANNER, DEFAULT_ZSTD_COMPRESSION))
+    Options:
+      -l, --lazy            Detach now, clean up once no longer busy (umount -l / fusermount -z).
+    Exit code {2} means the mount was still busy after all retries.
+
+  version [OPTIONS]
+    Print the version (same as --version).
+    Options:
+      --json                One JSON object: version, git hash, enabled cargo features and
+                            the tools/kernel features found on this system, including the
+                            cryptsetup version and the optional luksFormat flags it takes
+                            (older cryptsetup: such flags are dropped with a warning).
+                            Also: --version --json.
+
+Sizes (SIZE) are bytes or a number with a unit, case-insensitive and possibly fractional:
+K, M, G, T, P or KiB..PiB are powers of 1024, KB..PB powers of 1000 (1.5G, 500MB).
+", BANNER, DEFAULT_ZSTD_COMPRESSION, EXIT_CODE_BUSY, DEFAULT_ARCHIVE_MODE, LUKS_PASSPHRASE_ATTEMPTS, MIN_ARCHIVE_SIZE_PERCENT))
     }
 }
 
@@ -115,6 +203,75 @@ pub enum Commands {
         /// Replace ENTIRE content of LUKS container (Requires LUKS output)
         #[arg(long)]
         overwrite_luks_content: bool,
+
+        /// Tee the full output of the packing commands into this file (timestamped per line)
+        #[arg(long, value_name = "PATH")]
+        log_file: Option<PathBuf>,
+
+        /// Write the packing log to `<OUTPUT>.log` (ignored if --log-file is given)
+        #[arg(long)]
+        debug_log: bool,
+
+        /// Keep the packing log even if packing succeeds (it is removed by default)
+        #[arg(long)]
+        keep_log: bool,
+
+        /// Octal permissions of a newly created archive (existing archives keep theirs)
+        #[arg(long, value_name = "OCTAL")]
+        mode: Option<String>,
+
+        /// Number of LUKS passphrase attempts (retried only when stdin is a terminal)
+        #[arg(long, value_name = "N", default_value_t = LUKS_PASSPHRASE_ATTEMPTS,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        passphrase_attempts: u32,
+
+        /// File listing paths (relative to INPUT, one per line) to leave out of the archive
+        #[arg(long, value_name = "PATH")]
+        exclude_file: Option<PathBuf>,
+
+        /// Do not store extended attributes (SELinux labels, POSIX ACLs, capabilities)
+        #[arg(long)]
+        no_xattrs: bool,
+
+        /// Space to leave free on the destination: a size (2G) or a percentage (5%)
+        #[arg(long, value_name = "SIZE|PERCENT", value_parser = crate::units::size_or_percent_arg)]
+        reserve: Option<String>,
+
+        /// Pack even if the destination looks too small for the archive
+        #[arg(long)]
+        no_space_check: bool,
+
+        /// Extra mksquashfs argument, appended verbatim (repeatable; unsupported, at your own risk)
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+        mksquashfs_arg: Vec<String>,
+
+        /// Memory mksquashfs may use (-mem), e.g. 512M; default: 25% of available memory
+        /// on machines with less than 4 GiB, else the mksquashfs default
+        #[arg(long, value_name = "SIZE", value_parser = crate::units::size_arg)]
+        mem: Option<u64>,
+
+        /// Threads for mksquashfs (-processors) or tar2sqfs (--num-jobs); default: `threads`
+        /// in the config file, else the number of physical cores
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        threads: Option<u32>,
+
+        /// Encrypted: compress with gzip instead of zstd if the kernel was built without zstd
+        /// SquashFS (it could not mount the container)
+        #[arg(long)]
+        auto_fallback_compression: bool,
+
+        /// Verify the result: read back the SquashFS superblock (encrypted: reopen the
+        /// container read-only, after the trim) and walk the top directory level
+        #[arg(long)]
+        verify: bool,
+
+        /// Appending to a plain archive: no mksquashfs recovery file (-no-recovery)
+        #[arg(long)]
+        no_recovery: bool,
+
+        /// Encrypted: store the SHA-256 of the SquashFS payload as a LUKS2 token (0k-meta)
+        #[arg(long, requires = "encrypt")]
+        integrity_token: bool,
     },
     /// Mount a SquashFS archive to a directory (using squashfuse)
     Mount {
@@ -124,11 +281,45 @@ pub enum Commands {
         /// Optional: Manual mount point. If omitted, a directory is created in the current working directory.
         #[arg(value_name = "MOUNT_POINT")]
         mount_point: Option<PathBuf>,
+
+        /// Number of LUKS passphrase attempts (retried only when stdin is a terminal)
+        #[arg(long, value_name = "N", default_value_t = LUKS_PASSPHRASE_ATTEMPTS,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        passphrase_attempts: u32,
+
+        /// Do not mount: print where IMAGE is already mounted (MOUNT_POINT<TAB>BACKEND per line)
+        #[arg(long, conflicts_with = "mount_point")]
+        list: bool,
+
+        /// Mount over a MOUNT_POINT that already contains files (they are hidden until unmount)
+        #[arg(long)]
+        nonempty: bool,
+    },
+    /// Print the version, or a JSON report of version and capabilities
+    Version {
+        /// Print a JSON object with build info and detected capabilities
+        #[arg(long)]
+        json: bool,
     },
     /// Unmount a previously mounted SquashFS image (using fusermount -u)
     Umount {
         /// Target mount point directory OR path to the source image file
         #[arg(value_name = "TARGET")]
         mount_point: PathBuf,
+
+        /// Lazy unmount: detach immediately, clean up once the mount is no longer busy
+        #[arg(short, long)]
+        lazy: bool,
+    },
+    /// Debugging aid: open a LUKS image read-only, check the SquashFS inside, close it
+    #[command(hide = true)]
+    VerifyMapper {
+        #[arg(value_name = "IMAGE")]
+        image: PathBuf,
+
+        /// 0: check the superblock only. N: also read the whole tree, logging the entries
+        /// found down to N directory levels
+        #[arg(long, default_value_t = 1)]
+        depth: u32,
     },
 }
@@ -1,2 +1,57 @@
 pub mod zk;
 pub mod core;
+
+#[cfg(test)]
+mod tests {
+    /// Splits the hand-written after_help into one section per subcommand
+    /// (a section starts at a line "  <name> ..." naming a subcommand).
+    fn help_section(help: &str, name: &str) -> Option<String> {
+        let mut section: Option<String> = None;
+        for line in help.lines() {
+            let heading = line.strip_prefix("  ").filter(|rest| !rest.starts_with(' '));
+            if let Some(rest) = heading {
+                let first = rest.split_whitespace().next().unwrap_or("");
+                if section.is_some() && first != name {
+                    break;
+                }
+                if first == name {
+                    section = Some(String::new());
+                    continue;
+                }
+            }
+            if let Some(text) = section.as_mut() {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        section
+    }
+
+    /// Every visible long flag of every subcommand must be described in the detailed help,
+    /// so the hand-written text (and the man pages built from it) cannot drift from the parser.
+    fn assert_help_covers_flags(cmd: clap::Command) {
+        let help = cmd.get_after_help().expect("after_help is set").to_string();
+        for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+            let name = sub.get_name();
+            let section = help_section(&help, name).unwrap_or_else(|| panic!("no help section for {}", name));
+            let missing: Vec<String> = sub
+                .get_arguments()
+                .filter(|arg| !arg.is_hide_set())
+                .filter_map(|arg| arg.get_long())
+                .map(|long| format!("--{}", long))
+                .filter(|flag| !section.split(|c: char| !(c.is_alphanumeric() || c == '-')).any(|w| w == flag))
+                .collect();
+            assert!(missing.is_empty(), "{}: flags missing from detailed help: {:?}", name, missing);
+        }
+    }
+
+    #[test]
+    fn test_zk_help_covers_all_flags() {
+        assert_help_covers_flags(super::zk::Args::build_command());
+    }
+
+    #[test]
+    fn test_core_help_covers_all_flags() {
+        assert_help_covers_flags(super::core::Args::build_command());
+    }
+}
@@ -1,6 +1,6 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-use crate::constants::DEFAULT_ZSTD_COMPRESSION;
+use crate::constants::{DEFAULT_ZSTD_COMPRESSION, MIN_ARCHIVE_SIZE_PERCENT};
 
 const BANNER: &str = concat!(
     r#"
@@ -22,6 +22,10 @@ github.com/Antony-hash512/Zero-Kelvin
 pub struct Args {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Threads for the whole pipeline (default: `threads` in the config file, else physical cores)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
 }
 
 impl Args {
@@ -38,12 +42,106 @@ impl Args {
       ARCHIVE_PATH          Destination .sqfs archive path.
     Options:
       -e, --encrypt         Encrypt the archive using LUKS (via 0k-core).
-      -r, --read <FILE>     Read list of targets from a file.
+      -r, --read <FILE>     Read list of targets from a file, one per line (`-` = stdin).
+          --read0 <FILE>    Like --read, but NUL-separated paths, as printed by
+                            `find -print0` (`-` = stdin).
+                            Targets named twice (also through a symlinked directory) are
+                            frozen once, with a warning. The rest are sorted by path before
+                            they get their manifest ids, so the same set of targets gets the
+                            same ids however it is listed.
+          --preserve-order  Number the targets in the order given instead of sorting them.
       -c, --compression N   Zstd compression level (default: {1}) 0 = no compression.
+      -L, --dereference     Store the content of symlinked files instead of the links.
+          --dereference-target <PATH>
+                            Like -L for the target PATH only (repeatable); the other
+                            symlinked targets stay links. check compares its referent.
+          --skip-broken-symlinks
+                            A dangling symlink cannot be dereferenced: such targets are
+                            left out (listed in the manifest) instead of failing the
+                            freeze. Dangling links archived as links are only counted.
+          --overwrite-files Append to an existing archive (its manifest is extended).
+          --no-recovery     With --overwrite-files: no mksquashfs recovery file (kept in
+                            $HOME while a plain append runs, to undo a failed one).
+          --overwrite-luks-content
+                            Replace the entire content of an existing LUKS container.
           --no-progress     Disable progress bar.
+          --vanilla-progress
+                            Use native mksquashfs progress (explicit, also default).
+          --alfa-progress   Use experimental custom progress bar (for testing).
+          --log-file <PATH> Tee full mksquashfs/tar2sqfs output (timestamped) into PATH.
+          --debug-log       Same as --log-file <ARCHIVE_PATH>.log.
+          --keep-log        Keep the log file even if freezing succeeds.
+          --keep-staging    Debugging: keep the staging directory (build_* in
+                            $TMPDIR/0k-cache-<uid>, with the stubs, manifest and freeze.sh)
+                            after the freeze, whether it succeeds or fails. By default it is
+                            removed either way; the next 0k gc or freeze removes a kept one.
+          --staging-dir <DIR>
+                            Stage in DIR/0k-cache-<uid> instead of $TMPDIR/0k-cache-<uid>,
+                            e.g. on a big disk when /tmp is a small tmpfs (default:
+                            $ZK_STAGING_DIR, if set). DIR must exist; the 0k-cache-<uid>
+                            directory gets the same checks (no symlink, owned by you, 0700).
+          --mode <OCTAL>    Permissions of a newly created archive (default: 600).
+          --json-events     Print one JSON event per line on stdout (no other stdout output).
+          --check-open-files
+                            List processes writing to files under TARGETS (torn snapshot risk)
+                            and ask before continuing; refuse when not interactive.
+          --allow-open-files
+                            Like --check-open-files, but continue without asking.
+          --skip-unreadable Leave out files and directories you cannot read instead of
+                            asking for elevation; they are listed in the archive manifest.
+          --no-xattrs       Do not store extended attributes (SELinux labels, ACLs).
+          --no-space-check  Freeze without checking the free space at ARCHIVE_PATH. Needed:
+                            {2}% of the targets' uncompressed size (all of it when
+                            encrypted or with -c 0), plus --reserve on a target's filesystem.
+      -y, --yes             Do not ask when the exact same targets were frozen before
+                            (per the catalog, ~/.local/share/zero-kelvin/catalog.jsonl),
+                            nor before --delete-after deletes the originals.
+          --reserve <SIZE|PERCENT>
+                            Space to leave free on the destination, e.g. 2G or 5%
+                            (default: reserve in ~/.config/0k/config.yaml, else
+                            1 GiB or 2% of the filesystem, whichever is larger).
+          --exclude <GLOB>  Leave out paths matching GLOB below each target (repeatable).
+                            No '/': matches a name at any depth (node_modules, *.tmp);
+                            with '/': the path from the target root (build/cache);
+                            trailing '/': directories only (target/). '**' spans directories.
+                            Excluded paths are listed in the manifest; check skips them.
+          --dry-run         Check the targets and print what would be archived (file count,
+                            size, output, compression, encryption); write nothing.
+          --json            With --dry-run: print the summary as one JSON object.
+          --delete-after    Once the archive is written, mount it, compare it with the
+                            targets byte by byte (check --use-cmp) and, only if every entry
+                            matches, delete the originals (check --delete). Asks first
+                            unless --yes; refused when not interactive without --yes.
+          --mem <SIZE>      Memory mksquashfs may use, e.g. 512M (passed on to 0k-core;
+                            default: 25% of available memory below 4 GiB RAM).
+          --no-ignore-files Do not honor .0kignore files (same syntax as --exclude, relative
+                            to their directory; '!' re-includes, leading '/' anchors).
+          --checksums       Record the SHA-256 of every file in the manifest (reads all data
+                            once more); used by check --checksums and unfreeze --verify.
+          --auto-fallback-compression
+                            With -e: compress with gzip if this kernel was built without
+                            zstd SquashFS and could not mount the container (passed on to
+                            0k-core; otherwise it only warns).
+          --integrity-token With -e: store the SHA-256 of the SquashFS inside the container
+                            as a LUKS2 token (type 0k-meta, with a random archive id), so
+                            the digest cannot get separated from the archive; LUKS1
+                            containers get a sidecar <ARCHIVE>.sha256.json instead. Shown
+                            by info, checked by verify. Appending or replacing the content
+                            without it removes the digest of the previous content.
+          --mksquashfs-arg <ARG>
+                            Passed on to 0k-core create: append ARG to the mksquashfs
+                            command line (repeatable, e.g. --mksquashfs-arg=-nopad).
+                            Unsupported, at your own risk; paths, whitespace and -e/-ef
+                            are refused.
           --prefix <NAME>   Prefix for auto-generated filename
                             (when ARCHIVE_PATH is a directory).
                             If omitted, you will be prompted interactively.
+                            Characters other than A-Z a-z 0-9 . _ - become '_'.
+          --name-template <TEMPLATE>
+                            Auto-generated filename template (default: {{prefix}}_{{time}}_{{rand}}).
+                            Placeholders: {{prefix}} {{date}} (UTC YYYYMMDD-HHMMSS) {{time}}
+                            (unix seconds) {{host}} {{rand}}; other characters: A-Z a-z 0-9 . _ -
+    On success the last stdout line is ARCHIVE: <path> (stable, for scripts).
 
   unfreeze <ARCHIVE_PATH> [OPTIONS]
     Restore data from a frozen archive to its original locations.
@@ -51,9 +149,56 @@ impl Args {
       ARCHIVE_PATH          Path to the .sqfs archive to restore.
     Options:
       --overwrite           Overwrite existing files.
-      --skip-existing       Skip files that already exist.
-      --force-unfreeze      Force unfreeze even if hostname mismatches.
-      --verify              Verify archive integrity before restoring.
+      --skip-existing       Skip files that already exist (not with --overwrite).
+      --force-unfreeze      Force unfreeze even if hostname mismatches. Otherwise a
+                            mismatch is confirmed on a terminal and refused elsewhere.
+      --verify              Read every file in the archive before restoring anything and
+                            stop if one is missing or corrupt; for archives frozen with
+                            --checksums, also verify the restored files afterwards.
+      --follow-dest-symlinks
+                            Restore through symlinked parent directories whose target
+                            is owned by you (default: refuse any symlink in the path).
+      --no-times            Do not restore timestamps (restored data gets the current time).
+      --no-xattrs           Do not restore ACLs and extended attributes. By default they
+                            are restored (rsync -A -X) when the archive carries any.
+      --no-restorecon       After a restore as root with SELinux enforcing, restored paths
+                            are relabeled with restorecon -R; this skips that step.
+      --parent-mode <OCTAL> Mode for missing parent directories created on the way.
+                            Default: the archived directory's mode if the archive has it,
+                            else 700 inside home directories and 755 elsewhere.
+      --no-manifest --target <DIR>
+                            Archive without list.yaml (e.g. made by plain mksquashfs):
+                            copy its whole tree into DIR.
+      --resume              Continue an unfreeze that stopped partway (disk full, network
+                            home unmounted): entries it restored are skipped if they still
+                            exist. Progress is journaled in $TMPDIR/0k-cache-<uid>/restore-state.
+      --restart             Start over, discarding that progress. Without --resume or
+                            --restart, an unfreeze that stopped partway is not redone.
+      --allow-fs-change     Restore even where the destination's nearest existing parent
+                            is on another filesystem than the one it was frozen from
+                            (e.g. an autofs /home that has not mounted). Otherwise this
+                            is confirmed on a terminal and refused elsewhere.
+      --target-dir <DIR>    Restore below DIR instead of the original locations:
+                            /home/user/docs goes to DIR/home/user/docs. Missing parents
+                            (DIR included) are created; the hostname and filesystem
+                            checks are skipped.
+      --only <ID|NAME>      Restore only this entry (repeatable): its id, or its name (the
+                            last component of its path), as 0k list shows them. Other
+                            options apply as in a full restore of just these entries.
+      --remap <OLD=NEW>     Restore entries recorded under OLD under NEW instead (repeatable;
+                            the longest matching OLD wins), e.g. after a home directory was
+                            renamed: /home/anton=/home/antony. Only whole path components
+                            match; a remap that matches no entry is a warning.
+      --dry-run             Mount the archive, run the checks a restore makes, and print
+                            each entry's source, destination, size, whether the
+                            destination exists and whether elevation would be needed.
+                            Nothing is restored. Exits non-zero if a destination exists
+                            and neither --overwrite nor --skip-existing is given, or is
+                            on another filesystem than when frozen (see
+                            --allow-fs-change).
+      --no-progress         Disable the overall progress bar shown on a terminal; rsync
+                            then prints its own progress for each entry.
+      --json-events         Print one JSON event per line on stdout (no other stdout output).
 
   check <ARCHIVE_PATH> [OPTIONS]
     Verify archive integrity against the live system.
@@ -62,19 +207,160 @@ impl Args {
     Options:
       --use-cmp             Verify file content (byte-by-byte) in addition to size/mtime.
       --delete              Delete local files if they match the archive (Destructive!).
+                            A file replaced or modified between its comparison and its
+                            deletion is kept (CHANGED DURING RUN).
       -D, --force-delete    Modifier for --delete: also delete files newer than archive.
                             (Useful for cleaning up already restored/unfrozen files).
+      --prune-dirs          Modifier for --delete, which removes the empty directories the
+                            archive has too (never one it could not restore): also remove
+                            the root of a directory entry left empty by that.
+                            Directories that still hold anything are never touched.
+      --show-extra          Also list paths inside frozen directories that exist live but not
+                            in the archive (EXTRA), e.g. files created after the freeze. They
+                            are never deleted, not even with --delete.
+      --exclude <GLOB>      Leave paths matching GLOB out of the check (repeatable), e.g.
+                            logs, lock files or caches that always differ. Same syntax as
+                            freeze --exclude, relative to each frozen directory; an excluded
+                            directory is skipped whole. Excluded paths are not compared,
+                            not listed as EXTRA and never deleted; the summary counts them.
+      --quick               Fast sanity check: compare files by size/mtime recorded in the
+                            manifest (MATCH / LIKELY-CHANGED / MISSING), no content reads.
+      --checksums           Verify file content against the SHA-256 recorded by freeze
+                            --checksums (no archive content reads); with --delete, a matching
+                            digest counts like --use-cmp.
+      --no-manifest --target <DIR>
+                            Archive without list.yaml: compare its whole tree against DIR
+                            (with --delete, DIR itself is kept).
+      --remap <OLD=NEW>     Check entries recorded under OLD against NEW instead (repeatable),
+                            as unfreeze --remap restores them.
+      --json-events         Print one JSON event per line on stdout (no other stdout output).
+      --json                One JSON object: the totals, indexed_paths, notes and entries, a
+                            list of path, status, category and detail for each path checked.
+      --exit-zero           Exit with status 0 even if paths mismatched or are missing
+                            (by default any MISMATCH or MISSING makes check fail).
+      --report[=PATH]       Also write an audit trail of the check as YAML: the archive, its
+                            manifest metadata, the options, every path and the totals. To
+                            PATH, else next to the archive as <ARCHIVE_PATH>.check-<unixtime>.yaml.
+                            Written atomically; failing to write it only warns.
+
+  info <ARCHIVE_PATH> [OPTIONS]
+    Show compression and SquashFS details of an archive (from unsquashfs -s), the LUKS
+    header of an encrypted one (from cryptsetup luksDump), and when and where it was
+    frozen and its uncompressed size (from list.yaml and unsquashfs -lls). A plain archive
+    is not mounted. An encrypted one is opened, which needs root; without it only the
+    header is shown.
+    Arguments:
+      ARCHIVE_PATH          Path to the .sqfs archive.
+    Options:
+      --json                One JSON object: path, size, encrypted, compression,
+                            compression_level, block_size, fs_size, inodes, created,
+                            luks {{luks_version, uuid, cipher}}, contents {{date, host,
+                            privilege_mode, entries, uncompressed_bytes}},
+                            contents_unavailable (why contents is missing) and
+                            payload_digest (if frozen with --integrity-token).
+
+  list <ARCHIVE_PATH> [OPTIONS]
+    List the entries of an archive (from its list.yaml): id, type and restore path.
+    Encrypted archives need root to mount, like check.
+    Arguments:
+      ARCHIVE_PATH          Path to the .sqfs archive.
+    Options:
+      --sizes               Also show the size of each entry inside the archive.
+      --json                One JSON object: entries, a list of id, type, path (and size).
+
+  verify <ARCHIVE_PATH> [OPTIONS]
+    Check that an archive itself is intact, e.g. after copying it elsewhere; live files
+    are not looked at. Its list.yaml must parse and validate, and every entry it lists
+    must be in the image. Exits non-zero if anything is wrong. Encrypted archives need
+    root to mount, like check.
+    Arguments:
+      ARCHIVE_PATH          Path to the .sqfs archive.
+    Options:
+      --deep                Also read every file back, so damaged blocks surface as I/O
+                            errors; files frozen with --checksums are also compared with
+                            their recorded SHA-256.
+      --json                One JSON object: entries, entries_verified, files_read,
+                            bytes_read, checksums_verified, payload_digest (where the
+                            stored payload SHA-256 came from, if checked) and errors.
+
+  diff <OLD_ARCHIVE> <NEW_ARCHIVE> [OPTIONS]
+    Compare two archives (e.g. monthly refreezes of the same directories) by restore
+    path: ADDED, REMOVED and MODIFIED lines, then a summary. Both are mounted at once;
+    an encrypted one needs root, like check.
+    Arguments:
+      OLD_ARCHIVE           The older .sqfs archive.
+      NEW_ARCHIVE           The newer .sqfs archive.
+    Options:
+      --use-cmp             Also compare files of equal size byte by byte.
+      --json                One JSON object: added, removed, modified, unchanged and
+                            changes, a list of change, path (and detail).
+
+  prune <DIR|catalog> [OPTIONS]
+    Delete old archives, keeping the newest of each series (archives named
+    <prefix>_<time>_<rand> share a series by prefix). Mounted archives, and those a
+    running freeze or unfreeze has locked, are never deleted.
+    Arguments:
+      DIR|catalog           Directory holding the archives, or `catalog` for every
+                            archive recorded in the catalog.
+    Options:
+      --keep-last <N>       Keep the newest N archives of each series (at least 1).
+      --keep-within <DURATION>
+                            Also keep archives younger than DURATION (e.g. 12h, 30d, 8w, 1y).
+      --verify-newer        Only prune a series whose newest kept archive passes `0k info`.
+      --dry-run             Print the plan without deleting anything.
+      --yes                 Actually delete (required unless --dry-run). Sidecar files
+                            (<archive>.log, <archive>.trim-journal) go too, and the catalog
+                            is updated.
+
+  gc [OPTIONS]
+    Remove the staging directories (build_*) that crashed freezes left in
+    $TMPDIR/0k-cache-<uid>; every freeze also does this first. A directory whose lock is
+    held by a running freeze is always kept; one whose lock is free is removed; one without
+    a lock file is removed once older than 24 hours. Prints each directory as REMOVED,
+    SKIPPED (locked), with the PID and command line of the freeze holding it, SKIPPED
+    (lock error) when its lock cannot be taken, SKIPPED (no lock), SKIPPED (mounted) or
+    FAILED, and why, then the totals.
+    Exits non-zero if any removal failed.
+    Options:
+      --dry-run             Only print what would be removed.
+      --older-than <DURATION>
+                            Age limit for directories without a lock file (default 24h).
+      --staging-dir <DIR>   Clean up DIR/0k-cache-<uid> (where freeze --staging-dir stages)
+                            instead of $TMPDIR/0k-cache-<uid> (default: $ZK_STAGING_DIR, if set).
+
+  version [OPTIONS]
+    Print the version (same as --version).
+    Options:
+      --json                One JSON object: version, git hash, enabled cargo features and
+                            the tools/kernel features found on this system, including the
+                            cryptsetup version and the optional luksFormat flags it takes
+                            (older cryptsetup: such flags are dropped with a warning).
+                            Also: --version --json.
+
+Global Options:
+  --threads N               Threads for the whole pipeline: mksquashfs -processors and
+                            tar2sqfs --num-jobs (via 0k-core). Default: threads in
+                            ~/.config/0k/config.yaml, else the number of physical cores.
+                            Shown by freeze --dry-run and in the freeze JSON report.
+
+Every JSON object printed (--json, --json-events) starts with schema_version; fields are
+only ever added, and a removal or rename bumps it.
+
+Sizes (SIZE) are bytes or a number with a unit, case-insensitive and possibly fractional:
+K, M, G, T, P or KiB..PiB are powers of 1024, KB..PB powers of 1000 (1.5G, 500MB).
+Durations (DURATION) are numbers with a unit s, m, h, d, w or y (365 days): 90m, 1h30m, 1.5d.
 
 Full help for a specific command can be obtained via:
   zero-kelvin <command> --help
   0k help <command>
 ",
-            BANNER, DEFAULT_ZSTD_COMPRESSION
+            BANNER, DEFAULT_ZSTD_COMPRESSION, MIN_ARCHIVE_SIZE_PERCENT
         ))
     }
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)] // parsed once per run; freeze simply has many options
 pub enum Commands {
     /// Freeze data into a SquashFS archive
     #[command(
@@ -97,14 +383,23 @@ pub enum Commands {
         #[arg(short, long)]
         encrypt: bool,
 
-        /// Read the list of target paths from a file
+        /// Read the list of target paths from a file, one per line (`-` for stdin)
         #[arg(short, long, value_name = "FILE")]
         read: Option<PathBuf>,
 
-        /// Overwrite files inside existing archive (Applies to both Plain and LUKS)
+        /// Read NUL-separated target paths from a file, e.g. `find -print0` output (`-` for stdin)
+        #[arg(long, value_name = "FILE", conflicts_with = "read")]
+        read0: Option<PathBuf>,
+
+        /// Append to an existing archive; its manifest is extended with the new targets
+        /// (Applies to both Plain and LUKS)
         #[arg(long)]
         overwrite_files: bool,
 
+        /// With --overwrite-files on a plain archive: no mksquashfs recovery file
+        #[arg(long, requires = "overwrite_files")]
+        no_recovery: bool,
+
         /// Replace ENTIRE content of LUKS container (Requires LUKS output)
         #[arg(long)]
         overwrite_luks_content: bool,
@@ -129,10 +424,125 @@ pub enum Commands {
         #[arg(short = 'L', long)]
         dereference: bool,
 
+        /// Dereference only this target (repeatable); other symlinked targets stay links
+        #[arg(long, value_name = "PATH")]
+        dereference_target: Vec<PathBuf>,
+
+        /// Leave out dereferenced targets that are dangling symlinks (recorded in the manifest)
+        /// instead of refusing to freeze
+        #[arg(long)]
+        skip_broken_symlinks: bool,
+
         /// Prefix for auto-generated filename (when ARCHIVE_PATH is a directory).
         /// Skips the interactive prompt.
         #[arg(long, value_name = "NAME")]
         prefix: Option<String>,
+
+        /// Template for the auto-generated filename (placeholders: {prefix}, {date}, {time}, {host}, {rand})
+        #[arg(long, value_name = "TEMPLATE")]
+        name_template: Option<String>,
+
+        /// Tee the full output of the packing commands into this file (timestamped per line)
+        #[arg(long, value_name = "PATH")]
+        log_file: Option<PathBuf>,
+
+        /// Write the packing log to `<ARCHIVE_PATH>.log` (ignored if --log-file is given)
+        #[arg(long)]
+        debug_log: bool,
+
+        /// Keep the packing log even if freezing succeeds (it is removed by default)
+        #[arg(long)]
+        keep_log: bool,
+
+        /// Debugging: keep the staging directory (stubs, manifest, freeze.sh) whether freezing
+        /// succeeds or fails; removed by the next 0k gc or freeze
+        #[arg(long)]
+        keep_staging: bool,
+
+        /// Stage in DIR/0k-cache-<uid> instead of $TMPDIR (default: $ZK_STAGING_DIR, if set)
+        #[arg(long, value_name = "DIR")]
+        staging_dir: Option<PathBuf>,
+
+        /// Octal permissions of a newly created archive (default: 600)
+        #[arg(long, value_name = "OCTAL")]
+        mode: Option<String>,
+
+        /// Emit a line-delimited JSON event stream on stdout instead of human-readable output
+        #[arg(long)]
+        json_events: bool,
+
+        /// Warn about files under the targets that running processes have open for writing
+        /// (asks before continuing; refuses when not interactive)
+        #[arg(long)]
+        check_open_files: bool,
+
+        /// With --check-open-files: freeze anyway without asking (implies --check-open-files)
+        #[arg(long)]
+        allow_open_files: bool,
+
+        /// Leave out unreadable files/directories (recorded in the manifest) instead of elevating
+        #[arg(long)]
+        skip_unreadable: bool,
+
+        /// Do not store extended attributes (SELinux labels, POSIX ACLs, capabilities)
+        #[arg(long)]
+        no_xattrs: bool,
+
+        /// Freeze even if the destination looks too small for the archive
+        #[arg(long)]
+        no_space_check: bool,
+
+        /// Space to leave free on the destination: a size (2G) or a percentage (5%)
+        #[arg(long, value_name = "SIZE|PERCENT", value_parser = crate::units::size_or_percent_arg)]
+        reserve: Option<String>,
+
+        /// Do not ask before freezing targets that the catalog already has an archive of
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Extra mksquashfs argument, passed on to 0k-core create (repeatable; at your own risk)
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+        mksquashfs_arg: Vec<String>,
+
+        /// Leave out paths matching GLOB, relative to each target (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Do not honor .0kignore files found inside the targets
+        #[arg(long)]
+        no_ignore_files: bool,
+
+        /// Memory mksquashfs may use, e.g. 512M (default: 25% of available memory below 4 GiB RAM)
+        #[arg(long, value_name = "SIZE", value_parser = crate::units::size_arg)]
+        mem: Option<u64>,
+
+        /// Record the SHA-256 of every regular file in the manifest (slower)
+        #[arg(long)]
+        checksums: bool,
+
+        /// With --encrypt: compress with gzip if the kernel cannot mount zstd SquashFS
+        #[arg(long, requires = "encrypt")]
+        auto_fallback_compression: bool,
+
+        /// With --encrypt: store the SHA-256 of the SquashFS payload in the LUKS header
+        #[arg(long, requires = "encrypt")]
+        integrity_token: bool,
+
+        /// Number the targets in the order given instead of sorting them by path
+        #[arg(long)]
+        preserve_order: bool,
+
+        /// Validate the targets and print what would be archived, without freezing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Print the --dry-run summary as JSON
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+
+        /// After freezing, verify the archive byte by byte and delete the originals if all match
+        #[arg(long, conflicts_with = "dry_run")]
+        delete_after: bool,
     },
     /// Unfreeze (restore) data from a SquashFS archive
     Unfreeze {
@@ -145,16 +555,106 @@ pub enum Commands {
         overwrite: bool,
 
         /// Skip existing files (conflicts)
-        #[arg(long)]
+        #[arg(long, conflicts_with = "overwrite")]
         skip_existing: bool,
 
         /// Skip hostname mismatch check (non-interactive mode)
         #[arg(long)]
         force_unfreeze: bool,
         
-        /// Verify archive integrity before restoring (pre-flight check)
+        /// Read every archived file before restoring (pre-flight check), and verify the
+        /// restored files against the manifest checksums if the archive has them
         #[arg(long)]
         verify: bool,
+
+        /// Allow restoring beneath symlinked directories (e.g. ~/Documents -> /data/docs)
+        /// if the link target is owned by you
+        #[arg(long)]
+        follow_dest_symlinks: bool,
+
+        /// Do not restore timestamps (restored files get the current time)
+        #[arg(long)]
+        no_times: bool,
+
+        /// Do not restore POSIX ACLs and extended attributes (no rsync -A -X)
+        #[arg(long)]
+        no_xattrs: bool,
+
+        /// Do not relabel restored paths with restorecon after a root restore under SELinux
+        #[arg(long)]
+        no_restorecon: bool,
+
+        /// Octal mode for missing parent directories that have to be created
+        #[arg(long, value_name = "OCTAL")]
+        parent_mode: Option<String>,
+
+        /// Archive has no manifest (e.g. made by plain mksquashfs): restore its whole tree into --target
+        #[arg(long, requires = "target", conflicts_with = "verify")]
+        no_manifest: bool,
+
+        /// Destination directory for --no-manifest
+        #[arg(long, value_name = "DIR", requires = "no_manifest")]
+        target: Option<PathBuf>,
+
+        /// Continue an unfreeze that stopped partway, skipping the entries it restored
+        #[arg(long, conflicts_with_all = ["restart", "no_manifest"])]
+        resume: bool,
+
+        /// Start over, ignoring the progress of an unfreeze that stopped partway
+        #[arg(long, conflicts_with = "no_manifest")]
+        restart: bool,
+
+        /// Restore even where the destination now lies on another filesystem than when frozen
+        #[arg(long, conflicts_with = "no_manifest")]
+        allow_fs_change: bool,
+
+        /// Restore below DIR instead of the original locations (/home/u/docs -> DIR/home/u/docs)
+        #[arg(long, value_name = "DIR", conflicts_with = "no_manifest")]
+        target_dir: Option<PathBuf>,
+
+        /// Restore only this entry, by manifest id or name (repeatable; see `0k list`)
+        #[arg(long, value_name = "ID|NAME", conflicts_with = "no_manifest")]
+        only: Vec<String>,
+
+        /// Restore entries recorded under OLD under NEW instead (repeatable; longest OLD wins)
+        #[arg(long, value_name = "OLD=NEW", conflicts_with = "no_manifest")]
+        remap: Vec<String>,
+
+        /// Print what would be restored where, without restoring; fails if anything conflicts
+        #[arg(long, conflicts_with_all = ["no_manifest", "resume", "restart", "json_events"])]
+        dry_run: bool,
+
+        /// Disable the overall progress bar (rsync prints its own progress instead)
+        #[arg(long)]
+        no_progress: bool,
+
+        /// Emit a line-delimited JSON event stream on stdout instead of human-readable output
+        #[arg(long)]
+        json_events: bool,
+    },
+    /// Print the version, or a JSON report of version and capabilities
+    Version {
+        /// Print a JSON object with build info and detected capabilities
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the JSON Schema of every JSON output (for consumers of the --json flags)
+    #[command(hide = true)]
+    DumpSchema,
+    /// The --delete-after pass of freeze, rerun elevated when the archive needs root to mount
+    #[command(hide = true)]
+    DeleteAfter {
+        /// The archive just frozen
+        #[arg(value_name = "ARCHIVE_PATH")]
+        archive_path: PathBuf,
+
+        /// The frozen targets, deleted if they all match the archive
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<PathBuf>,
+
+        /// Do not ask before deleting
+        #[arg(short, long)]
+        yes: bool,
     },
     /// Check integrity of an archive against the original files
     Check {
@@ -175,5 +675,156 @@ pub enum Commands {
         /// files that were already restored (unfrozen) as they often have newer mtime.
         #[arg(short = 'D', long, requires = "delete")]
         force_delete: bool,
+
+        /// Also remove the root of a directory entry once --delete has pruned the empty
+        /// directories in it and nothing is left. Directories that still hold anything are
+        /// never touched
+        #[arg(long, requires = "delete")]
+        prune_dirs: bool,
+
+        /// Also list live paths inside frozen directories that the archive does not have
+        /// (EXTRA), e.g. files created after the freeze. Never deleted by --delete
+        #[arg(long)]
+        show_extra: bool,
+
+        /// Leave paths matching GLOB out of the check, relative to each frozen directory
+        /// (repeatable; same syntax as freeze --exclude). Never deleted by --delete
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Quick check: compare regular files by the size/mtime recorded in the manifest,
+        /// without reading archive contents
+        #[arg(long, conflicts_with_all = ["use_cmp", "delete"])]
+        quick: bool,
+
+        /// Compare regular files by the SHA-256 recorded at freeze time (freeze --checksums),
+        /// without reading archive contents
+        #[arg(long, conflicts_with = "quick")]
+        checksums: bool,
+
+        /// Archive has no manifest (e.g. made by plain mksquashfs): compare its whole tree against --target
+        #[arg(long, requires = "target", conflicts_with_all = ["quick", "checksums"])]
+        no_manifest: bool,
+
+        /// Directory that corresponds to the archive root, for --no-manifest
+        #[arg(long, value_name = "DIR", requires = "no_manifest")]
+        target: Option<PathBuf>,
+
+        /// Check entries recorded under OLD against NEW instead (repeatable; longest OLD wins)
+        #[arg(long, value_name = "OLD=NEW", conflicts_with = "no_manifest")]
+        remap: Vec<String>,
+
+        /// Emit a line-delimited JSON event stream on stdout instead of human-readable output
+        #[arg(long)]
+        json_events: bool,
+
+        /// Print the result as one JSON object (every path checked, and the totals)
+        #[arg(long, conflicts_with = "json_events")]
+        json: bool,
+
+        /// Exit with status 0 even if paths mismatched or are missing
+        #[arg(long)]
+        exit_zero: bool,
+
+        /// Also write the options, manifest metadata, every path and the totals to a YAML
+        /// file: PATH, or <ARCHIVE_PATH>.check-<unixtime>.yaml without one
+        #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+        report: Option<Option<PathBuf>>,
+    },
+    /// Show compression, encryption and manifest details of an archive
+    Info {
+        /// Path to the SquashFS archive
+        #[arg(value_name = "ARCHIVE_PATH")]
+        archive_path: PathBuf,
+
+        /// Print the details as one JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the entries of an archive and where they restore to
+    List {
+        /// Path to the SquashFS archive
+        #[arg(value_name = "ARCHIVE_PATH")]
+        archive_path: PathBuf,
+
+        /// Also show the size of each entry inside the archive
+        #[arg(long)]
+        sizes: bool,
+
+        /// Print the entries as one JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that an archive itself is intact, without looking at live files
+    Verify {
+        /// Path to the SquashFS archive
+        #[arg(value_name = "ARCHIVE_PATH")]
+        archive_path: PathBuf,
+
+        /// Also read every file back (and compare recorded checksums)
+        #[arg(long)]
+        deep: bool,
+
+        /// Print the result as one JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare two archives: paths added, removed and modified since the older one
+    Diff {
+        /// The older SquashFS archive
+        #[arg(value_name = "OLD_ARCHIVE")]
+        old_archive: PathBuf,
+
+        /// The newer SquashFS archive
+        #[arg(value_name = "NEW_ARCHIVE")]
+        new_archive: PathBuf,
+
+        /// Also compare files of equal size byte by byte
+        #[arg(long)]
+        use_cmp: bool,
+
+        /// Print the differences as one JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete old archives by a retention policy
+    Prune {
+        /// Directory holding the archives, or `catalog` for the archives recorded in the catalog
+        #[arg(value_name = "DIR|catalog")]
+        source: String,
+
+        /// Keep the newest N archives of each series
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        keep_last: u32,
+
+        /// Also keep archives younger than this (e.g. 12h, 30d, 8w, 1y)
+        #[arg(long, value_name = "DURATION", value_parser = crate::units::duration_arg)]
+        keep_within: Option<u64>,
+
+        /// Only prune a series whose newest kept archive passes the `0k info` checks
+        #[arg(long)]
+        verify_newer: bool,
+
+        /// Print the plan without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Delete without further confirmation (required unless --dry-run)
+        #[arg(long, conflicts_with = "dry_run")]
+        yes: bool,
+    },
+    /// Remove staging directories left behind by crashed freezes
+    Gc {
+        /// Print what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Remove staging directories without a lock file once older than this (e.g. 12h; default 24h)
+        #[arg(long, value_name = "DURATION", value_parser = crate::units::duration_arg)]
+        older_than: Option<u64>,
+
+        /// Clean up DIR/0k-cache-<uid>, as staged in by freeze --staging-dir (default: $ZK_STAGING_DIR, if set)
+        #[arg(long, value_name = "DIR")]
+        staging_dir: Option<PathBuf>,
     },
 }
@@ -0,0 +1,63 @@
+//! Optional user settings from `$XDG_CONFIG_HOME/0k/config.yaml` (`~/.config/0k/config.yaml`).
+//!
+//! ```yaml
+//! reserve: 5%      # space left free on the destination (size like 2G, or a percentage)
+//! passphrase_agent: auto   # LUKS passphrase without a terminal: auto, systemd-ask-password,
+//!                          # pinentry or tty (see crate::passphrase)
+//! pinentry: pinentry-gnome3  # pinentry program for passphrase_agent (default: pinentry)
+//! threads: 4      # threads for mksquashfs and tar2sqfs (default: physical cores; --threads)
+//! ```
+//!
+//! A missing file means defaults; an unreadable or invalid one is reported and ignored.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Keys of the config file (all optional).
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Space to leave free on the destination (see [`crate::space::Reserve`])
+    #[serde(default)]
+    pub reserve: Option<String>,
+    /// How LUKS passphrases are asked when stdin is not a terminal (see [`crate::passphrase`])
+    #[serde(default)]
+    pub passphrase_agent: Option<String>,
+    /// Pinentry program used by the `pinentry` agent
+    #[serde(default)]
+    pub pinentry: Option<String>,
+    /// Threads the pipeline may use when `--threads` is not given
+    #[serde(default)]
+    pub threads: Option<u32>,
+}
+
+/// `$XDG_CONFIG_HOME/0k` (or `~/.config/0k`): where 0k looks for its config files.
+pub fn config_dir() -> PathBuf {
+    let base = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| format!("{}/.config", std::env::var("HOME").unwrap_or_default()));
+    PathBuf::from(base).join("0k")
+}
+
+/// Path of the settings file.
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.yaml")
+}
+
+impl Config {
+    /// Reads the settings file; defaults if it does not exist or cannot be used.
+    pub fn load() -> Config {
+        let path = config_path();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("Warning: invalid YAML in {:?}: {}", path, e);
+                Config::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                eprintln!("Warning: cannot read config {:?}: {}", path, e);
+                Config::default()
+            }
+        }
+    }
+}
@@ -12,6 +12,23 @@ pub const LUKS_HEADER_SIZE: u64 = 32 * 1024 * 1024; // 32MB for LUKS2 header
 /// Safety buffer size in bytes to avoid truncation
 pub const LUKS_SAFETY_BUFFER: u64 = 128 * 1024 * 1024; // 128MB safety buffer to avoid truncation
 
+/// LUKS container overhead (% of the input size) for uncompressed payloads (`-c 0`): the
+/// image is the input plus SquashFS metadata, so no compression worst case is budgeted
+pub const LUKS_UNCOMPRESSED_OVERHEAD_PERCENT: u32 = 10;
+
+/// Default free-space reserve on the destination: this many bytes...
+pub const DEFAULT_RESERVE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// ...or this percentage of the filesystem, whichever is larger
+pub const DEFAULT_RESERVE_PERCENT: f64 = 2.0;
+
+/// Smallest archive the free-space pre-flight assumes, in percent of the input: most data
+/// compresses less than this, so only destinations that clearly cannot hold it are refused
+pub const MIN_ARCHIVE_SIZE_PERCENT: u64 = 25;
+
+/// Default permission bits for created archives (owner read/write only)
+pub const DEFAULT_ARCHIVE_MODE: u32 = 0o600;
+
 /// Whitelist of allowed privilege escalation commands.
 /// Only these binaries are accepted via ROOT_CMD env var or config file.
 pub const ALLOWED_ROOT_CMDS: &[&str] = &["sudo", "doas", "sudo-rs", "run0", "pkexec", "please"];
@@ -19,11 +36,53 @@ pub const ALLOWED_ROOT_CMDS: &[&str] = &["sudo", "doas", "sudo-rs", "run0", "pke
 /// Prefix for LUKS mapper device names in /dev/mapper/
 pub const LUKS_MAPPER_PREFIX: &str = "zrklv";
 
+/// Default number of LUKS passphrase attempts when opening a container interactively
+pub const LUKS_PASSPHRASE_ATTEMPTS: u32 = 3;
+
+/// Maximum number of image basename characters kept in a LUKS mapper name
+pub const MAPPER_BASENAME_MAX_LEN: usize = 64;
+
+/// Maximum length of the `{prefix}` in auto-generated archive names
+pub const NAME_PREFIX_MAX_LEN: usize = 64;
+
 /// Maximum number of processes to scan in /proc during umount (DoS protection)
 pub const PROC_SCAN_LIMIT: usize = 10000;
 
+/// Entries of a non-empty mount point listed when `mount` refuses it
+pub const MOUNT_POINT_LISTING_LIMIT: usize = 10;
+
+/// Archive entries inspected when deciding whether to restore extended attributes
+pub const XATTR_PROBE_LIMIT: usize = 10000;
+
+/// Number of unmount attempts while the target is busy (EBUSY)
+pub const UMOUNT_RETRY_ATTEMPTS: u32 = 4;
+
+/// Initial delay between busy unmount attempts in milliseconds (doubled after each attempt)
+pub const UMOUNT_RETRY_DELAY_MS: u64 = 500;
+
+/// Process exit code when a mount is still busy after all retries (matches errno EBUSY)
+pub const EXIT_CODE_BUSY: u8 = 16;
+
 /// Maximum size of manifest file (list.yaml) in bytes (10MB, YAML-bomb protection)
 pub const MANIFEST_MAX_SIZE: u64 = 10 * 1024 * 1024;
 
 /// Directory for application logs under XDG_STATE_HOME
 pub const LOG_DIR_NAME: &str = "logs";
+
+/// Archive catalog under XDG_DATA_HOME/<APP_NAME>
+pub const CATALOG_FILE_NAME: &str = "catalog.jsonl";
+
+/// Maximum attempts to find a free auto-generated file or mount point name
+pub const NAME_GENERATION_ATTEMPTS: u32 = 16;
+
+/// Environment variable naming the base directory of freeze staging (like `--staging-dir`)
+pub const STAGING_DIR_ENV: &str = "ZK_STAGING_DIR";
+
+/// Per-directory exclusion patterns honored by freeze (like .gitignore)
+pub const IGNORE_FILE_NAME: &str = ".0kignore";
+
+/// Below this much RAM, mksquashfs is capped to a share of the available memory (`--mem` default)
+pub const SMALL_MACHINE_MEM_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Lower bound for the automatic `mksquashfs -mem` cap
+pub const MKSQUASHFS_MIN_MEM_BYTES: u64 = 64 * 1024 * 1024;
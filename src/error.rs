@@ -30,6 +30,26 @@ pub enum ZkError {
     #[error("Missing target: {0}")]
     MissingTarget(String),
 
+    /// Mount point is still in use after all unmount retries (exit code EXIT_CODE_BUSY).
+    #[error("Target is busy: {0}")]
+    Busy(String),
+
+    /// External tools that are not installed (see `utils::check_dependencies`).
+    #[error("Missing required tools: {}", .0.iter().map(|d| d.program()).collect::<Vec<_>>().join(", "))]
+    MissingDependencies(Vec<crate::utils::Dependency>),
+
+    /// The archive manifest is of a newer format than this build reads (see `manifest::MANIFEST_VERSION`).
+    #[error("Unsupported manifest version {0} (this build reads versions up to {max})", max = crate::manifest::MANIFEST_VERSION)]
+    UnsupportedManifestVersion(u32),
+
+    /// /proc is not mounted (a minimal chroot or container); says what cannot be done without it.
+    #[error("/proc is not available: {0}")]
+    ProcUnavailable(String),
+
+    /// Stopped through a [`crate::cancel::CancellationToken`] (e.g. Ctrl+C).
+    #[error("Operation cancelled")]
+    Cancelled,
+
     /// CLI argument parsing resulted in an error that was already printed.
     /// Carries the desired process exit code (e.g. 2 for invalid subcommand).
     #[error("")]
@@ -56,6 +76,16 @@ impl ZkError {
                 }
                 None
             },
+            ZkError::MissingDependencies(missing) => {
+                let tools: Vec<String> = missing
+                    .iter()
+                    .map(|d| format!("{} (package: {})", d.program(), d.package()))
+                    .collect();
+                Some(format!("Install with your distribution's package manager: {}", tools.join(", ")))
+            }
+            ZkError::UnsupportedManifestVersion(_) => {
+                Some("The archive was made by a newer version of 0k; upgrade to check or unfreeze it.".to_string())
+            }
             _ => None,
         }
     }
@@ -0,0 +1,255 @@
+//! Line-delimited JSON event stream (`--json-events`) for machine consumers.
+//!
+//! When enabled, every event is written as one JSON object per line to the
+//! original stdout, and everything else that would go to stdout (our own
+//! messages and those of child processes) is sent to /dev/null.
+//! Warnings and errors still go to stderr.
+//!
+//! Schema (`event` is the discriminator; see [`crate::report`] for the versioning rules):
+//!
+//! ```text
+//! {"schema_version":1,"event":"phase","name":"staging|packing|verifying|mounting|restoring|checking"}
+//! {"schema_version":1,"event":"entry_frozen","id":1,"path":"/home/user/docs"}
+//! {"schema_version":1,"event":"entry_restored","id":3,"path":"/home/user/docs","bytes":123}
+//! {"schema_version":1,"event":"entry_skipped","id":3,"path":"/home/user/docs"}
+//! {"schema_version":1,"event":"entry_checked","path":"/home/user/docs/a.txt","status":"match"}
+//! {"schema_version":1,"event":"done","report":{"operation":"freeze|unfreeze|check", ...}}
+//! ```
+//!
+//! `status` is one of `match`, `mismatch`, `missing`, `skipped`, `deleted`, `likely_changed`,
+//! `changed_during_run`, `error`, `extra`.
+//! The `report` fields are those of [`FreezeReport`], [`UnfreezeReport`] and [`CheckReport`].
+//! `open_files` (freeze) is only present when `--check-open-files` found writers:
+//! `[{"pid":1234,"command":"firefox","path":"/home/user/.mozilla/.../places.sqlite"}]`.
+//!
+//! Programs embedding the crate get the same events in-process through a [`ProgressSink`]
+//! passed in the operation's options (`progress`, like `cancel`); stdout is left alone.
+
+use crate::error::ZkError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Saved original stdout (events go here) and the stdout fd we redirected.
+static EVENT_SINK: OnceLock<Mutex<std::fs::File>> = OnceLock::new();
+
+thread_local! {
+    /// Sink of the operation running on this thread (see [`scope_sink`]).
+    static PROGRESS_SINK: RefCell<Option<Arc<dyn ProgressSink>>> = const { RefCell::new(None) };
+}
+
+/// Receives every event of the stream, on the thread running the operation.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+pub use crate::report::{CheckReport, FreezeReport, OpenFile, Report, UnfreezeReport};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Phase { name: String },
+    EntryFrozen { id: u32, path: String },
+    EntryRestored { id: u32, path: String, bytes: u64 },
+    EntrySkipped { id: u32, path: String },
+    EntryChecked { path: String, status: CheckStatus },
+    Done { report: Report },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Match,
+    Mismatch,
+    Missing,
+    Skipped,
+    Deleted,
+    LikelyChanged,
+    ChangedDuringRun,
+    /// Could not be checked or deleted (see the entry's detail)
+    Error,
+    /// Inside a frozen directory but not in the archive (`check --show-extra`)
+    Extra,
+}
+
+impl std::fmt::Display for CheckStatus {
+    /// The label `check` prints in front of each path.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            CheckStatus::Match => "MATCH",
+            CheckStatus::Mismatch => "MISMATCH",
+            CheckStatus::Missing => "MISSING",
+            CheckStatus::Skipped => "SKIPPED",
+            CheckStatus::Deleted => "DELETED",
+            CheckStatus::LikelyChanged => "LIKELY-CHANGED",
+            CheckStatus::ChangedDuringRun => "CHANGED DURING RUN",
+            CheckStatus::Error => "ERROR",
+            CheckStatus::Extra => "EXTRA",
+        };
+        f.write_str(label)
+    }
+}
+
+/// Enables the event stream: keeps a handle to the real stdout for events and
+/// points fd 1 at /dev/null so no other output mixes into the stream.
+pub fn init() -> Result<(), ZkError> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    if EVENT_SINK.get().is_some() {
+        return Ok(());
+    }
+    std::io::stdout().flush()?;
+
+    // SAFETY: dup/dup2 on valid descriptors; the duplicated fd is owned by the File below.
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if saved < 0 {
+        return Err(ZkError::IoError(std::io::Error::last_os_error()));
+    }
+    let sink = unsafe { std::fs::File::from_raw_fd(saved) };
+
+    let devnull = std::fs::OpenOptions::new().write(true).open("/dev/null")?;
+    if unsafe { libc::dup2(devnull.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return Err(ZkError::IoError(std::io::Error::last_os_error()));
+    }
+
+    let _ = EVENT_SINK.set(Mutex::new(sink));
+    Ok(())
+}
+
+/// Puts the original stdout back on fd 1 (e.g. before re-executing ourselves via sudo,
+/// so the elevated child can set up its own event stream).
+pub fn release_stdout() {
+    use std::os::fd::AsRawFd;
+    if let Some(sink) = EVENT_SINK.get()
+        && let Ok(f) = sink.lock()
+    {
+        // SAFETY: both descriptors are valid for the lifetime of the process.
+        unsafe { libc::dup2(f.as_raw_fd(), libc::STDOUT_FILENO) };
+    }
+}
+
+/// Puts back the sink that was in place before [`scope_sink`] when dropped.
+pub(crate) struct SinkScope(Option<Arc<dyn ProgressSink>>);
+
+impl Drop for SinkScope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        PROGRESS_SINK.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Sends the events of the operation running on this thread to `sink` (the `progress` option)
+/// until the returned scope is dropped. Like `--json-events`, a sink makes the operation
+/// non-interactive: no prompts and no progress bars.
+pub(crate) fn scope_sink(sink: Option<&Arc<dyn ProgressSink>>) -> SinkScope {
+    SinkScope(PROGRESS_SINK.with(|current| current.replace(sink.cloned())))
+}
+
+fn progress_sink() -> Option<Arc<dyn ProgressSink>> {
+    PROGRESS_SINK.with(|current| current.borrow().clone())
+}
+
+/// True if `--json-events` is active or a [`ProgressSink`] is set.
+pub fn enabled() -> bool {
+    EVENT_SINK.get().is_some() || progress_sink().is_some()
+}
+
+/// Writes one event line and hands the event to the progress sink (no-op unless either is set).
+pub fn emit(event: &Event) {
+    if let Some(sink) = progress_sink() {
+        sink.event(event);
+    }
+    if let Some(sink) = EVENT_SINK.get()
+        && let Ok(mut f) = sink.lock()
+    {
+        let _ = writeln!(f, "{}", to_json(&crate::report::Versioned::new(event)));
+        let _ = f.flush();
+    }
+}
+
+/// Serializes an event as a single-line JSON object.
+pub fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Event {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_event_json_shape() {
+        let ev = Event::EntryRestored { id: 3, path: "/home/u/a b".into(), bytes: 123 };
+        assert_eq!(
+            to_json(&ev),
+            r#"{"event":"entry_restored","id":3,"path":"/home/u/a b","bytes":123}"#
+        );
+        assert_eq!(
+            to_json(&Event::Phase { name: "packing".into() }),
+            r#"{"event":"phase","name":"packing"}"#
+        );
+    }
+
+    #[test]
+    fn test_event_stream_round_trip() {
+        let events = vec![
+            Event::Phase { name: "checking".into() },
+            Event::EntryChecked { path: "/tmp/x\"y\n".into(), status: CheckStatus::LikelyChanged },
+            Event::EntrySkipped { id: 2, path: "/tmp/skip".into() },
+            Event::Done {
+                report: Report::Check(CheckReport { files_matched: 4, missing: 1, ..Default::default() }),
+            },
+            Event::Done {
+                report: Report::Freeze(FreezeReport {
+                    archive: "/tmp/a.sqfs".into(),
+                    entries: 2,
+                    bytes: 4096,
+                    open_files: vec![OpenFile { pid: 42, command: "sqlite3".into(), path: "/tmp/db".into() }],
+                    threads: Some(4),
+                }),
+            },
+        ];
+        let stream: Vec<String> = events.iter().map(to_json).collect();
+        for line in &stream {
+            assert!(!line.contains('\n'), "Event must be a single line: {}", line);
+        }
+        let parsed: Vec<Event> = stream.iter().map(|l| parse(l)).collect();
+        assert_eq!(parsed, events);
+    }
+
+    #[test]
+    fn test_sink_is_scoped_to_the_operation() {
+        struct Collect(Mutex<Vec<Event>>);
+        impl ProgressSink for Collect {
+            fn event(&self, event: &Event) {
+                self.0.lock().unwrap().push(event.clone());
+            }
+        }
+        let sink = Arc::new(Collect(Mutex::new(Vec::new())));
+        let phase = Event::Phase { name: "checking".into() };
+        {
+            let as_sink: Arc<dyn ProgressSink> = sink.clone();
+            let _scope = scope_sink(Some(&as_sink));
+            assert!(enabled());
+            emit(&phase);
+            // Another thread runs another operation: not this one's sink
+            std::thread::spawn(|| emit(&Event::Phase { name: "packing".into() })).join().unwrap();
+        }
+        emit(&phase);
+        assert_eq!(*sink.0.lock().unwrap(), vec![phase]);
+    }
+
+    #[test]
+    fn test_done_report_is_tagged_with_operation() {
+        let ev = Event::Done { report: Report::Unfreeze(UnfreezeReport { restored: 2, skipped: 1, bytes: 10 }) };
+        assert_eq!(
+            to_json(&ev),
+            r#"{"event":"done","report":{"operation":"unfreeze","restored":2,"skipped":1,"bytes":10}}"#
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! `freeze --exclude <GLOB>`: paths below the targets that are left out of the archive.
+//!
+//! Globs are matched relative to each freeze target, in the spirit of `.gitignore`:
+//!
+//! - a pattern without `/` matches a name at any depth (`node_modules`, `*.tmp`);
+//! - a pattern with `/` matches the whole path from the target root (`build/cache`, `src/**/*.o`);
+//! - a trailing `/` restricts the pattern to directories (`target/`).
+//!
+//! `*` and `?` do not cross `/`, `**` does, `[...]` is a character class. Matching directories
+//! are not descended into; the matches are resolved up front so they can be excluded from the
+//! image (mksquashfs `-ef`) and recorded in the manifest.
+//!
+//! A `.0kignore` file in any directory below a target (the target included) adds patterns
+//! in the same syntax, relative to the directory holding it. One pattern per line; blank
+//! lines and `#` comments are skipped, a leading `/` anchors a pattern to that directory and
+//! a leading `!` re-includes what an earlier pattern excluded. Precedence, as in `.gitignore`:
+//!
+//! 1. `--exclude` always wins (an ignore file cannot re-include what the command line excludes);
+//! 2. among ignore files, the last matching line wins, deeper files coming after shallower ones;
+//! 3. nothing below an excluded directory can be re-included (it is not looked at).
+//!
+//! `check --exclude` takes the same globs, relative to the live root of each directory entry,
+//! to leave volatile paths out of the comparison (see [`excludes_path`]).
+
+use crate::constants::IGNORE_FILE_NAME;
+use crate::error::ZkError;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct ExcludePattern {
+    /// As given on the command line
+    pub glob: String,
+    regex: Regex,
+    /// Matches the path relative to the target root instead of the file name
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl ExcludePattern {
+    /// A `--exclude` glob.
+    pub fn parse(glob: &str) -> Result<Self, ZkError> {
+        if glob.trim_end_matches('/').starts_with('/') {
+            return Err(ZkError::OperationFailed(format!(
+                "Invalid --exclude '{}': patterns are relative to each target, drop the leading '/'",
+                glob
+            )));
+        }
+        Self::compile(glob, glob, false)
+            .map_err(|why| ZkError::OperationFailed(format!("Invalid --exclude '{}': {}", glob, why)))
+    }
+
+    /// Compiles `glob`; `force_anchored` for `.0kignore` lines with a leading `/`.
+    fn compile(glob: &str, shown: &str, force_anchored: bool) -> Result<Self, String> {
+        let dir_only = glob.ends_with('/');
+        let body = glob.trim_end_matches('/');
+        let body = body.strip_prefix("./").unwrap_or(body);
+        if body.is_empty() {
+            return Err("empty pattern".to_string());
+        }
+        let anchored = force_anchored || body.contains('/');
+        let regex = Regex::new(&format!("^{}$", glob_to_regex(body)?)).map_err(|e| e.to_string())?;
+        Ok(ExcludePattern { glob: shown.to_string(), regex, anchored, dir_only })
+    }
+
+    /// Does the pattern match `rel` (relative to the target root)?
+    pub fn matches(&self, rel: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let subject = if self.anchored {
+            rel.to_string_lossy()
+        } else {
+            match rel.file_name() {
+                Some(name) => name.to_string_lossy(),
+                None => return false,
+            }
+        };
+        self.regex.is_match(&subject)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directory at all
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    out.push_str("(?:.*/)?");
+                } else {
+                    out.push_str(".*");
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                let mut class = String::from("[");
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    class.push('^');
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('\\') => class.push_str("\\\\"),
+                        Some(ch) => class.push(ch),
+                        None => return Err("unclosed '['".to_string()),
+                    }
+                }
+                class.push(']');
+                out.push_str(&class);
+            }
+            other => out.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    Ok(out)
+}
+
+/// One line of a `.0kignore` file.
+#[derive(Debug)]
+struct IgnoreRule {
+    pattern: ExcludePattern,
+    /// `!pattern`: re-include
+    negated: bool,
+}
+
+/// The rules of one `.0kignore` file, with the directory they are relative to.
+#[derive(Debug)]
+struct IgnoreFile {
+    /// Directory holding the file, relative to the target root
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+/// Parses `.0kignore` content. Invalid lines are reported as warnings and skipped, so a typo
+/// deep in a tree does not fail the whole freeze.
+fn parse_ignore_file(content: &str, source: &Path) -> Vec<IgnoreRule> {
+    let mut rules = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, glob) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (anchored, glob) = match glob.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        match ExcludePattern::compile(glob, line, anchored) {
+            Ok(pattern) => rules.push(IgnoreRule { pattern, negated }),
+            Err(why) => eprintln!(
+                "Warning: {}:{}: ignoring invalid pattern '{}': {}",
+                source.display(),
+                number + 1,
+                line,
+                why
+            ),
+        }
+    }
+    rules
+}
+
+/// Reads `dir/.0kignore` if there is one.
+fn load_ignore_file(dir: &Path, base: &Path) -> Option<IgnoreFile> {
+    let source = dir.join(IGNORE_FILE_NAME);
+    if fs_is_symlink(&source) {
+        return None;
+    }
+    let content = std::fs::read_to_string(&source).ok()?;
+    Some(IgnoreFile { base: base.to_path_buf(), rules: parse_ignore_file(&content, &source) })
+}
+
+/// Verdict of the ignore files in effect for `rel` (ancestors first): the last matching rule.
+fn ignored_by(files: &[IgnoreFile], rel: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in files {
+        let Ok(local) = rel.strip_prefix(&file.base) else { continue };
+        for rule in &file.rules {
+            if rule.pattern.matches(local, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// Paths below `root` matched by any pattern (matching directories are not descended into),
+/// and for each pattern whether it matched anything. With `ignore_files`, `.0kignore` files
+/// found along the way are honored too (see the module docs for precedence). A non-directory
+/// root has nothing below it.
+pub fn find_excluded(root: &Path, patterns: &[ExcludePattern], ignore_files: bool) -> (Vec<PathBuf>, Vec<bool>) {
+    let mut excluded = Vec::new();
+    let mut used = vec![false; patterns.len()];
+    if !root.is_dir() || fs_is_symlink(root) {
+        return (excluded, used);
+    }
+    // Ignore files of the directories on the way from root to the current entry
+    let mut active: Vec<IgnoreFile> = Vec::new();
+    if ignore_files {
+        active.extend(load_ignore_file(root, Path::new("")));
+    }
+    let mut walker = walkdir::WalkDir::new(root).min_depth(1).into_iter();
+    while let Some(item) = walker.next() {
+        let Ok(item) = item else { continue };
+        let Ok(rel) = item.path().strip_prefix(root) else { continue };
+        // Depth-first: leaving a directory retires its ignore file
+        while active.last().is_some_and(|f| !f.base.as_os_str().is_empty() && !rel.starts_with(&f.base)) {
+            active.pop();
+        }
+        let is_dir = item.file_type().is_dir();
+        let hit = patterns.iter().position(|p| p.matches(rel, is_dir));
+        if let Some(i) = hit {
+            used[i] = true;
+        }
+        if hit.is_some() || ignored_by(&active, rel, is_dir) {
+            excluded.push(item.path().to_path_buf());
+            if is_dir {
+                walker.skip_current_dir();
+            }
+        } else if is_dir && ignore_files {
+            active.extend(load_ignore_file(item.path(), rel));
+        }
+    }
+    (excluded, used)
+}
+
+/// Whether `rel` or a directory above it matches one of `patterns`: for walks that visit
+/// children before their directory and so cannot skip an excluded subtree.
+pub fn excludes_path(patterns: &[ExcludePattern], rel: &Path, is_dir: bool) -> bool {
+    rel.ancestors()
+        .take_while(|a| !a.as_os_str().is_empty())
+        .enumerate()
+        .any(|(i, a)| patterns.iter().any(|p| p.matches(a, i > 0 || is_dir)))
+}
+
+fn fs_is_symlink(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn pattern(glob: &str) -> ExcludePattern {
+        ExcludePattern::parse(glob).unwrap()
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        assert!(pattern("node_modules").matches(Path::new("web/node_modules"), true));
+        assert!(pattern("*.tmp").matches(Path::new("a/b/x.tmp"), false));
+        assert!(!pattern("*.tmp").matches(Path::new("a/x.tmp/keep"), false));
+        assert!(pattern("target/").matches(Path::new("target"), true));
+        assert!(!pattern("target/").matches(Path::new("target"), false));
+        assert!(pattern("build/cache").matches(Path::new("build/cache"), true));
+        assert!(!pattern("build/cache").matches(Path::new("x/build/cache"), true));
+        assert!(pattern("src/**/*.o").matches(Path::new("src/a/b/m.o"), false));
+        assert!(pattern("src/**/*.o").matches(Path::new("src/m.o"), false));
+        assert!(pattern("file[0-9].log").matches(Path::new("file7.log"), false));
+        assert!(pattern("a+b(1).txt").matches(Path::new("a+b(1).txt"), false));
+        assert!(ExcludePattern::parse("/abs").is_err());
+        assert!(ExcludePattern::parse("bad[").is_err());
+        assert!(ExcludePattern::parse("").is_err());
+    }
+
+    #[test]
+    fn test_excludes_path() {
+        let patterns = [pattern("*.log"), pattern("cache/")];
+        assert!(excludes_path(&patterns, Path::new("a/b/run.log"), false));
+        assert!(excludes_path(&patterns, Path::new("app/cache"), true));
+        // Below an excluded directory, whatever the name
+        assert!(excludes_path(&patterns, Path::new("app/cache/data/blob"), false));
+        // `cache/` is for directories: a file of that name stays
+        assert!(!excludes_path(&patterns, Path::new("app/cache"), false));
+        assert!(!excludes_path(&patterns, Path::new("app/run.log.d/keep"), false));
+        assert!(!excludes_path(&patterns, Path::new(""), true));
+    }
+
+    #[test]
+    fn test_find_excluded() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("app/node_modules/dep")).unwrap();
+        fs::write(root.join("app/node_modules/dep/index.js"), "x").unwrap();
+        fs::create_dir_all(root.join(".cache")).unwrap();
+        fs::write(root.join("app/main.js"), "x").unwrap();
+
+        let patterns = [pattern("node_modules"), pattern(".cache"), pattern("target/")];
+        let (mut excluded, used) = find_excluded(root, &patterns, false);
+        excluded.sort();
+        assert_eq!(excluded, vec![root.join(".cache"), root.join("app/node_modules")]);
+        assert_eq!(used, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_ignore_files_precedence() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        for dir in ["logs", "src/gen", "web/dist", "web/assets"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["a.log", "keep.log", "logs/x.txt", "src/gen/out.rs", "src/main.rs", "web/dist/app.js",
+            "web/assets/logo.png", "web/debug.log", "web/important.log", "secret.key"]
+        {
+            fs::write(root.join(file), "x").unwrap();
+        }
+        // Root rules; '!' re-includes, '/' anchors to the directory of the file
+        fs::write(root.join(".0kignore"), "# build output\n*.log\n!keep.log\n/logs/\ngen/\n!secret.key\nbad[\n").unwrap();
+        // Nested file: relative to web/, and later (deeper) than the root rules
+        fs::write(root.join("web/.0kignore"), "dist/\n!important.log\n").unwrap();
+
+        let collect = |patterns: &[ExcludePattern], ignore_files: bool| {
+            let (excluded, _) = find_excluded(root, patterns, ignore_files);
+            let mut rel: Vec<String> =
+                excluded.iter().map(|p| p.strip_prefix(root).unwrap().display().to_string()).collect();
+            rel.sort();
+            rel
+        };
+
+        assert_eq!(
+            collect(&[], true),
+            vec!["a.log", "logs", "src/gen", "web/debug.log", "web/dist"]
+        );
+        // --exclude wins over a re-include in an ignore file
+        assert_eq!(
+            collect(&[pattern("keep.log"), pattern("secret.key")], true),
+            vec!["a.log", "keep.log", "logs", "secret.key", "src/gen", "web/debug.log", "web/dist"]
+        );
+        // --no-ignore-files
+        assert_eq!(collect(&[pattern("*.png")], false), vec!["web/assets/logo.png"]);
+    }
+}
@@ -1,12 +1,24 @@
 use indicatif::ProgressBar;
 use regex::Regex;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use std::process::{Command, Output, Stdio};
 use std::time::Duration;
 use std::thread;
 use std::fs;
 
+/// Receives the stdout lines of [`CommandExecutor::run_each_line`]. Closures taking a line
+/// are sinks (mockall cannot mock `FnMut` arguments directly).
+pub trait LineSink {
+    fn line(&mut self, line: &str);
+}
+
+impl<F: FnMut(&str)> LineSink for F {
+    fn line(&mut self, line: &str) {
+        self(line)
+    }
+}
+
 /// Abstraction for running system commands.
 #[cfg_attr(any(test, feature = "testing"), mockall::automock)]
 pub trait CommandExecutor {
@@ -42,6 +54,181 @@ pub trait CommandExecutor {
         args: &[&'a str],
         progress_bar: &ProgressBar,
     ) -> std::io::Result<Output>;
+
+    /// Runs a command that reports its progress like rsync `--info=progress2`, parsing its
+    /// stdout (see [`parse_transfer_progress`]) to set the bar to `base` plus the bytes
+    /// transferred so far. Stdin and stderr are inherited; stdout is not shown.
+    #[allow(clippy::needless_lifetimes)] // mockall needs the lifetime named
+    fn run_with_transfer_progress<'a>(
+        &self,
+        program: &str,
+        args: &[&'a str],
+        progress_bar: &ProgressBar,
+        base: u64,
+    ) -> std::io::Result<std::process::ExitStatus>;
+
+    /// Runs a command while teeing its stdout/stderr to the terminal AND to `log_file`.
+    /// Every line written to the log is prefixed with a timestamp; the file is opened in
+    /// append mode so several packing steps can share one log.
+    /// Captured stderr is returned in `Output` for friendly error messages.
+    #[allow(clippy::needless_lifetimes)] // mockall needs the lifetime named
+    fn run_with_log<'a>(
+        &self,
+        program: &str,
+        args: &[&'a str],
+        log_file: &Path,
+    ) -> std::io::Result<Output>;
+
+    /// Runs a command with `input` written to its stdin (then closed), capturing stdout and
+    /// stderr. Used to hand secrets to a program without putting them on its command line.
+    #[allow(clippy::needless_lifetimes)] // mockall needs the lifetime named
+    fn run_with_stdin<'a>(&self, program: &str, args: &[&'a str], input: &[u8]) -> std::io::Result<Output>;
+
+    /// Runs a command and hands each line of its stdout to `on_line` as it arrives, so long
+    /// listings are never held in memory. Stderr is captured for error messages; the
+    /// returned `Output` has an empty stdout.
+    #[allow(clippy::needless_lifetimes)] // mockall needs the lifetime named
+    fn run_each_line<'a>(&self, program: &str, args: &[&'a str], on_line: &mut dyn LineSink) -> std::io::Result<Output>;
+
+    /// Runs `first | second` without a shell: `first[0]` with arguments `first[1..]` writes
+    /// into an OS pipe read by `second[0]`. Stderr of both (and stdout of `second`) is captured;
+    /// with `log_file` it is also teed to the terminal and the log, like `run_with_log`.
+    /// With `progress`, the bar follows the size of `progress_file` while the pipeline runs.
+    /// The status is that of `second` if it failed, else that of `first` (like `pipefail`).
+    fn run_piped<'a>(
+        &self,
+        first: &[&'a str],
+        second: &[&'a str],
+        log_file: Option<&'a Path>,
+        progress: Option<&'a ProgressBar>,
+        progress_file: &Path,
+    ) -> std::io::Result<Output>;
+}
+
+/// Runs a command up to `attempts` times with exponential backoff starting at `initial_delay`.
+/// A failed attempt is retried only while `should_retry` returns true for its output;
+/// `before_retry` is called with the attempt number before each sleep (e.g. to print diagnostics).
+/// Returns the output of the last attempt.
+pub fn run_with_retry<E: CommandExecutor + ?Sized>(
+    executor: &E,
+    program: &str,
+    args: &[&str],
+    attempts: u32,
+    initial_delay: Duration,
+    should_retry: impl Fn(&Output) -> bool,
+    mut before_retry: impl FnMut(u32),
+) -> std::io::Result<Output> {
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+    loop {
+        let output = executor.run(program, args)?;
+        if output.status.success() || attempt >= attempts || !should_retry(&output) {
+            return Ok(output);
+        }
+        before_retry(attempt);
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Timestamp prefix for log lines: `[<unix seconds>.<millis>]`.
+fn log_timestamp() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("[{}.{:03}]", now.as_secs(), now.subsec_millis())
+}
+
+/// Copies everything from `reader` to `passthrough`, and writes each line (split on
+/// `\n` and `\r`, so progress bars don't collapse into one giant line) to the shared log.
+/// Returns all bytes read so the caller can keep e.g. stderr for error messages.
+fn tee_to_log(
+    mut reader: impl std::io::Read,
+    mut passthrough: impl std::io::Write,
+    log: &std::sync::Mutex<fs::File>,
+    stream: &str,
+) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut captured = Vec::new();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buffer = [0; 4096];
+
+    let flush_line = |line: &[u8]| {
+        if line.is_empty() {
+            return;
+        }
+        if let Ok(mut f) = log.lock() {
+            let _ = writeln!(
+                f,
+                "{} {}: {}",
+                log_timestamp(),
+                stream,
+                String::from_utf8_lossy(line)
+            );
+        }
+    };
+
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                let chunk = &buffer[..n];
+                let _ = passthrough.write_all(chunk);
+                let _ = passthrough.flush();
+                captured.extend_from_slice(chunk);
+
+                for &b in chunk {
+                    if b == b'\n' || b == b'\r' {
+                        flush_line(&pending);
+                        pending.clear();
+                    } else {
+                        pending.push(b);
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    flush_line(&pending);
+    captured
+}
+
+/// Drains a child's stream on a thread: teed to `passthrough` and the log when there is
+/// one, otherwise only collected.
+fn drain_stream<R, W>(
+    reader: R,
+    passthrough: W,
+    log: Option<std::sync::Arc<std::sync::Mutex<fs::File>>>,
+    stream: &'static str,
+) -> thread::JoinHandle<Vec<u8>>
+where
+    R: std::io::Read + Send + 'static,
+    W: std::io::Write + Send + 'static,
+{
+    thread::spawn(move || match log {
+        Some(log) => tee_to_log(reader, passthrough, &log, stream),
+        None => {
+            let mut captured = Vec::new();
+            let _ = BufReader::new(reader).read_to_end(&mut captured);
+            captured
+        }
+    })
+}
+
+/// Bytes transferred so far from one rsync `--info=progress2` line such as
+/// `  1,234,567  45%  10.00MB/s    0:00:01 (xfr#3, to-chk=0/4)`; None for any other line.
+pub fn parse_transfer_progress(line: &str) -> Option<u64> {
+    let mut fields = line.split_whitespace();
+    let bytes = fields.next()?;
+    if !fields.next()?.ends_with('%') || !bytes.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    // Thousands separators depend on the locale (1,234,567 or 1.234.567)
+    let digits: String = bytes.chars().filter(|c| !matches!(c, ',' | '.' | '\'')).collect();
+    digits.parse().ok()
 }
 
 /// Real system executor using std::process::Command.
@@ -119,6 +306,50 @@ impl CommandExecutor for RealSystem {
         Ok((status, captured_string))
     }
 
+    fn run_with_stdin(&self, program: &str, args: &[&str], input: &[u8]) -> std::io::Result<Output> {
+        use std::io::Write;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::other(format!("Failed to execute command: {} {:?}: {}", program, args, e)))?;
+        let mut stdin = child.stdin.take()
+            .ok_or_else(|| std::io::Error::other("Failed to open stdin pipe"))?;
+        // A program that exits without reading everything closes the pipe: not our error
+        match stdin.write_all(input) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+        drop(stdin);
+        child.wait_with_output()
+    }
+
+    fn run_each_line(&self, program: &str, args: &[&str], on_line: &mut dyn LineSink) -> std::io::Result<Output> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::other(format!("Failed to execute command: {} {:?}: {}", program, args, e)))?;
+        let stdout = child.stdout.take().ok_or_else(|| std::io::Error::other("Failed to capture stdout"))?;
+        let mut stderr = child.stderr.take().ok_or_else(|| std::io::Error::other("Failed to capture stderr"))?;
+        // Drained alongside, so a chatty stderr cannot block the program while stdout is read
+        let stderr_reader = thread::spawn(move || {
+            let mut captured = Vec::new();
+            let _ = stderr.read_to_end(&mut captured);
+            captured
+        });
+        for line in BufReader::new(stdout).split(b'\n') {
+            on_line.line(&String::from_utf8_lossy(&line?));
+        }
+        let status = child.wait()?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+        Ok(Output { status, stdout: Vec::new(), stderr })
+    }
+
     fn run_with_file_progress<'a>(
         &self,
         program: &str,
@@ -216,9 +447,187 @@ impl CommandExecutor for RealSystem {
         if output.status.success() {
             progress_bar.set_position(100);
         }
-        
+
         Ok(output)
     }
+
+    fn run_with_transfer_progress(
+        &self,
+        program: &str,
+        args: &[&str],
+        progress_bar: &ProgressBar,
+        base: u64,
+    ) -> std::io::Result<std::process::ExitStatus> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::other(format!("Failed to spawn command: {} {:?}: {}", program, args, e)))?;
+        let stdout = child.stdout.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stdout"))?;
+
+        // rsync rewrites its progress line with \r, so both \r and \n end a line
+        let mut reader = BufReader::new(stdout);
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while reader.read(&mut byte)? == 1 {
+            if matches!(byte[0], b'\r' | b'\n') {
+                if let Some(bytes) = parse_transfer_progress(&String::from_utf8_lossy(&line)) {
+                    progress_bar.set_position(base + bytes);
+                }
+                line.clear();
+            } else {
+                line.push(byte[0]);
+            }
+        }
+        child.wait()
+    }
+
+    fn run_with_log(
+        &self,
+        program: &str,
+        args: &[&str],
+        log_file: &Path,
+    ) -> std::io::Result<Output> {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to open log file {:?}: {}", log_file, e)))?;
+        writeln!(log, "{} $ {} {}", log_timestamp(), program, args.join(" "))?;
+        let log = Arc::new(Mutex::new(log));
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::other(format!("Failed to spawn command: {} {:?}: {}", program, args, e)))?;
+
+        let stdout_pipe = child.stdout.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stdout"))?;
+        let stderr_pipe = child.stderr.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stderr pipe"))?;
+
+        // Both streams are drained concurrently, otherwise a chatty stderr could fill
+        // its pipe and block the child while we wait on stdout.
+        let log_out = Arc::clone(&log);
+        let t_out = thread::spawn(move || tee_to_log(stdout_pipe, std::io::stdout(), &log_out, "stdout"));
+        let log_err = Arc::clone(&log);
+        let t_err = thread::spawn(move || tee_to_log(stderr_pipe, std::io::stderr(), &log_err, "stderr"));
+
+        let status = child.wait()?;
+        let stdout = t_out.join().unwrap_or_default();
+        let stderr = t_err.join().unwrap_or_default();
+
+        if let Ok(mut f) = log.lock() {
+            let _ = writeln!(f, "{} exit status: {}", log_timestamp(), status);
+        }
+
+        Ok(Output { status, stdout, stderr })
+    }
+
+    fn run_piped<'a>(
+        &self,
+        first: &[&'a str],
+        second: &[&'a str],
+        log_file: Option<&'a Path>,
+        progress: Option<&'a ProgressBar>,
+        progress_file: &Path,
+    ) -> std::io::Result<Output> {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+
+        let (Some((first_prog, first_args)), Some((second_prog, second_args))) =
+            (first.split_first(), second.split_first())
+        else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty command in pipeline"));
+        };
+
+        let log = match log_file {
+            Some(path) => {
+                let mut f = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to open log file {:?}: {}", path, e)))?;
+                writeln!(f, "{} $ {} | {}", log_timestamp(), first.join(" "), second.join(" "))?;
+                Some(Arc::new(Mutex::new(f)))
+            }
+            None => None,
+        };
+
+        let mut producer = Command::new(first_prog)
+            .args(first_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::other(format!("Failed to spawn command: {} {:?}: {}", first_prog, first_args, e)))?;
+        let pipe = producer.stdout.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stdout"))?;
+
+        let mut consumer = match Command::new(second_prog)
+            .args(second_args)
+            .stdin(Stdio::from(pipe))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = producer.kill();
+                let _ = producer.wait();
+                return Err(std::io::Error::other(format!(
+                    "Failed to spawn command: {} {:?}: {}",
+                    second_prog, second_args, e
+                )));
+            }
+        };
+
+        let first_err = producer.stderr.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stderr pipe"))?;
+        let second_out = consumer.stdout.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stdout"))?;
+        let second_err = consumer.stderr.take()
+            .ok_or_else(|| std::io::Error::other("Failed to capture stderr pipe"))?;
+        let t_first_err = drain_stream(first_err, std::io::stderr(), log.clone(), "stderr");
+        let t_second_out = drain_stream(second_out, std::io::stdout(), log.clone(), "stdout");
+        let t_second_err = drain_stream(second_err, std::io::stderr(), log.clone(), "stderr");
+
+        let second_status = loop {
+            if let Some(status) = consumer.try_wait()? {
+                break status;
+            }
+            if let (Some(pb), Ok(meta)) = (progress, fs::metadata(progress_file)) {
+                pb.set_position(meta.len());
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        if let (Some(pb), Ok(meta)) = (progress, fs::metadata(progress_file)) {
+            pb.set_position(meta.len());
+        }
+        // If the consumer died early the producer gets SIGPIPE; its status is reported only
+        // when the consumer itself succeeded (e.g. a truncated or corrupt input)
+        let first_status = producer.wait()?;
+        let status = if second_status.success() { first_status } else { second_status };
+
+        let stdout = t_second_out.join().unwrap_or_default();
+        let mut stderr = t_first_err.join().unwrap_or_default();
+        stderr.extend(t_second_err.join().unwrap_or_default());
+
+        if let Some(log) = &log
+            && let Ok(mut f) = log.lock()
+        {
+            let _ = writeln!(f, "{} exit status: {} | {}", log_timestamp(), first_status, second_status);
+        }
+
+        Ok(Output { status, stdout, stderr })
+    }
 }
 
 #[cfg(test)]
@@ -250,6 +659,59 @@ mod tests {
         assert_eq!(res.stdout, b"OK");
     }
 
+    #[test]
+    fn test_run_with_retry_stops_on_success() {
+        let mut mock = MockCommandExecutor::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_run()
+            .times(2)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: b"Device or resource busy".to_vec(),
+            }));
+        mock.expect_run()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            }));
+
+        let mut retries = Vec::new();
+        let res = run_with_retry(&mock, "umount", &["/mnt"], 5, Duration::ZERO, |_| true, |n| retries.push(n)).unwrap();
+        assert!(res.status.success());
+        assert_eq!(retries, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_run_with_retry_gives_up() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .times(3)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: b"busy".to_vec(),
+            }));
+        let res = run_with_retry(&mock, "umount", &["/mnt"], 3, Duration::ZERO, |_| true, |_| {}).unwrap();
+        assert!(!res.status.success());
+
+        // Non-retryable failure returns immediately
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .times(1)
+            .returning(|_, _| Ok(Output {
+                status: std::process::ExitStatus::from_raw(256),
+                stdout: vec![],
+                stderr: b"not mounted".to_vec(),
+            }));
+        let res = run_with_retry(&mock, "umount", &["/mnt"], 3, Duration::ZERO, |_| false, |_| {}).unwrap();
+        assert!(!res.status.success());
+    }
+
     #[test]
     #[should_panic]
     fn test_mock_system_wrong_args() {
@@ -269,4 +731,138 @@ mod tests {
         // Should panic because args don't match (expected -la, got -l)
         let _ = mock.run("ls", &["-l"]);
     }
+
+    #[test]
+    fn test_run_with_log_tees_both_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("pack.log");
+
+        let output = RealSystem
+            .run_with_log("sh", &["-c", "echo packed; echo 'Unrecognised xattr' >&2; exit 3"], &log_path)
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "packed\n");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Unrecognised xattr"));
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.contains("$ sh -c"));
+        assert!(log.contains("stdout: packed"));
+        assert!(log.contains("stderr: Unrecognised xattr"));
+        assert!(log.contains("exit status"));
+        // Every line carries a timestamp prefix
+        assert!(log.lines().all(|l| l.starts_with('[')));
+    }
+
+    #[test]
+    fn test_run_with_stdin() {
+        let output = RealSystem.run_with_stdin("sh", &["-c", "tr a-z A-Z; echo err >&2"], b"secret").unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"SECRET");
+        assert_eq!(output.stderr, b"err\n");
+        // The input is not needed by the program: no error for the closed pipe
+        let output = RealSystem.run_with_stdin("true", &[], &[b'x'; 1 << 20]).unwrap();
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_run_each_line() {
+        let mut lines = Vec::new();
+        let mut collect = |line: &str| lines.push(line.to_string());
+        // More stderr than a pipe buffer holds must not stall the program
+        let script = "printf 'a\\nb c\\n\\nlast'; head -c 200000 /dev/zero >&2; exit 3";
+        let output = RealSystem.run_each_line("sh", &["-c", script], &mut collect).unwrap();
+        assert_eq!(lines, ["a", "b c", "", "last"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.stderr.len(), 200_000);
+    }
+
+    #[test]
+    fn test_run_piped_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.txt");
+        let log_path = dir.path().join("pack.log");
+        let out_str = out.to_str().unwrap();
+
+        // Arguments reach the programs verbatim: no shell, no quoting
+        let output = RealSystem
+            .run_piped(
+                &["printf", "%s", "it's $HOME"],
+                &["sh", "-c", "cat > \"$1\"", "sh", out_str],
+                Some(&log_path),
+                None,
+                &out,
+            )
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&out).unwrap(), "it's $HOME");
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.contains("$ printf %s it's $HOME | sh -c"), "{}", log);
+    }
+
+    #[test]
+    fn test_run_piped_first_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        // The consumer succeeds, but a failing producer (corrupt input) fails the pipeline
+        let output = RealSystem
+            .run_piped(&["sh", "-c", "echo 'gzip: corrupt' >&2; exit 2"], &["cat"], None, None, dir.path())
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("gzip: corrupt"));
+    }
+
+    #[test]
+    fn test_run_piped_second_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        // The consumer's status wins, even though the producer then dies of SIGPIPE
+        let output = RealSystem
+            .run_piped(&["yes"], &["sh", "-c", "echo 'tar2sqfs: bad header' >&2; exit 5"], None, None, dir.path())
+            .unwrap();
+        assert_eq!(output.status.code(), Some(5));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("tar2sqfs: bad header"));
+
+        let err = RealSystem.run_piped(&["true"], &[], None, None, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_parse_transfer_progress() {
+        assert_eq!(
+            parse_transfer_progress("      1,234,567  45%   10.00MB/s    0:00:01 (xfr#3, to-chk=0/4)"),
+            Some(1_234_567)
+        );
+        assert_eq!(parse_transfer_progress("  1.234.567 100%  1,00MB/s  0:00:01"), Some(1_234_567));
+        assert_eq!(parse_transfer_progress("              0   0%    0.00kB/s    0:00:00"), Some(0));
+        assert_eq!(parse_transfer_progress("sending incremental file list"), None);
+        assert_eq!(parse_transfer_progress("docs/a 50% done"), None);
+        assert_eq!(parse_transfer_progress(""), None);
+    }
+
+    #[test]
+    fn test_run_with_transfer_progress() {
+        let pb = ProgressBar::hidden();
+        let script = r"printf '  1,000  10%%  1kB/s 0:00:01\r  5,000  50%%  1kB/s 0:00:01\r  9,000 100%%\n'";
+        let status = RealSystem.run_with_transfer_progress("sh", &["-c", script], &pb, 100).unwrap();
+        assert!(status.success());
+        assert_eq!(pb.position(), 9_100);
+    }
+
+    #[test]
+    fn test_tee_to_log_splits_carriage_returns() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("progress.log");
+        let log = std::sync::Mutex::new(fs::File::create(&log_path).unwrap());
+
+        let input: &[u8] = b"[==  ] 10%\r[=== ] 50%\r[====] 100%\n";
+        let mut sink = Vec::new();
+        let captured = tee_to_log(input, &mut sink, &log, "stdout");
+        drop(log);
+
+        assert_eq!(captured, input);
+        assert_eq!(sink, input);
+        let content = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.lines().last().unwrap().ends_with("stdout: [====] 100%"));
+    }
 }
@@ -0,0 +1,594 @@
+//! C ABI for embedding Zero Kelvin without shelling out (`--features ffi`).
+//!
+//! Every operation takes its options as one UTF-8 JSON object and, on success, hands back
+//! the same versioned report `--json-events` ends with (see [`crate::report`]):
+//!
+//! ```text
+//! int zk_freeze(const char *options_json, char **report_json);
+//! int zk_check(const char *options_json, char **report_json);
+//! int zk_unfreeze(const char *options_json, char **report_json);
+//! void zk_free_report(char *report_json);
+//! const char *zk_last_error_message(void);
+//! ```
+//!
+//! Ownership: the caller keeps `options_json`; `*report_json` belongs to the caller and is
+//! released with `zk_free_report` (never `free`). On error the return value is one of the
+//! `ZK_ERR_*` codes, `*report_json` is set to NULL, and `zk_last_error_message` describes the
+//! error. That string belongs to the library and stays valid on the calling thread until its
+//! next `zk_*` call.
+//!
+//! Option keys are the fields of [`FreezeRequest`], [`CheckRequest`] and [`UnfreezeRequest`];
+//! unknown keys are rejected, and so are keys the CLI does not take together. Like the report
+//! fields, keys are only ever added. Confirmations work as in the CLI, so a service should
+//! pass the keys that skip them (such as `yes`). Progress and summaries still go to the
+//! process's stdout and stderr.
+//!
+//! The library is not part of the default build: `cargo rustc --release --lib --features ffi
+//! --crate-type cdylib` makes `libzero_kelvin.so`. Its header, `include/zero_kelvin.h`, is
+//! written by hand and also documents the option keys; keep it in step with this file.
+
+use crate::check_audit::ReportTarget;
+use crate::engine::{self, CheckOptions, FreezeOptions, ProgressMode, UnfreezeOptions};
+use crate::error::ZkError;
+use crate::executor::RealSystem;
+use crate::remap::{self, PathRemap};
+use crate::report::{Report, Versioned};
+use crate::utils;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::path::PathBuf;
+
+/// The operation succeeded and `*report_json` holds its report.
+pub const ZK_OK: c_int = 0;
+/// A NULL pointer, options that are not UTF-8, or JSON that does not match the options.
+pub const ZK_ERR_INVALID_ARGUMENT: c_int = 1;
+/// The operation failed; see `zk_last_error_message`.
+pub const ZK_ERR_FAILED: c_int = 2;
+/// The operation needs root (or read access the caller lacks); rerun it elevated.
+pub const ZK_ERR_PERMISSION_DENIED: c_int = 3;
+/// Tools the operation runs (mksquashfs, squashfuse, ...) are not installed.
+pub const ZK_ERR_MISSING_DEPENDENCIES: c_int = 4;
+/// A bug: the library panicked. The message is the panic's.
+pub const ZK_ERR_PANIC: c_int = 5;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Options of `zk_freeze`; the keys mirror the `0k freeze` flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FreezeRequest {
+    pub targets: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub encrypt: bool,
+    pub overwrite_files: bool,
+    pub overwrite_luks_content: bool,
+    pub compression: Option<u32>,
+    pub dereference: bool,
+    pub exclude: Vec<String>,
+    pub no_ignore_files: bool,
+    pub skip_unreadable: bool,
+    pub no_xattrs: bool,
+    pub no_space_check: bool,
+    pub reserve: Option<String>,
+    pub yes: bool,
+    pub checksums: bool,
+    pub mode: Option<u32>,
+    pub threads: Option<u32>,
+    pub integrity_token: bool,
+    pub preserve_order: bool,
+}
+
+/// Options of `zk_check`; the keys mirror the `0k check` flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckRequest {
+    pub archive: PathBuf,
+    pub use_cmp: bool,
+    pub delete: bool,
+    pub force_delete: bool,
+    pub prune_dirs: bool,
+    pub show_extra: bool,
+    pub exclude: Vec<String>,
+    pub quick: bool,
+    pub checksums: bool,
+    /// `--no-manifest --target <DIR>`
+    pub no_manifest_target: Option<PathBuf>,
+    /// `--remap`, as `"OLD=NEW"` strings
+    pub remap: Vec<String>,
+    /// `--report`: `true` writes next to the archive, a path writes there
+    pub report: Option<ReportRequest>,
+}
+
+/// The `report` key of [`CheckRequest`]: `true` (or `false`, no report) or a path.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ReportRequest {
+    Default(bool),
+    Path(PathBuf),
+}
+
+impl ReportRequest {
+    fn target(self) -> Option<ReportTarget> {
+        match self {
+            ReportRequest::Default(true) => Some(ReportTarget::Default),
+            ReportRequest::Default(false) => None,
+            ReportRequest::Path(path) => Some(ReportTarget::Path(path)),
+        }
+    }
+}
+
+/// Options of `zk_unfreeze`; the keys mirror the `0k unfreeze` flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnfreezeRequest {
+    pub archive: PathBuf,
+    pub overwrite: bool,
+    pub skip_existing: bool,
+    pub force_unfreeze: bool,
+    pub verify: bool,
+    pub follow_dest_symlinks: bool,
+    pub no_times: bool,
+    pub no_xattrs: bool,
+    pub no_restorecon: bool,
+    pub parent_mode: Option<u32>,
+    /// `--no-manifest --target <DIR>`
+    pub no_manifest_target: Option<PathBuf>,
+    pub resume: bool,
+    pub restart: bool,
+    pub allow_fs_change: bool,
+    pub target_dir: Option<PathBuf>,
+    pub only: Vec<String>,
+    /// `--remap`, as `"OLD=NEW"` strings
+    pub remap: Vec<String>,
+    pub dry_run: bool,
+}
+
+impl FreezeRequest {
+    fn validate(&self) -> Result<(), FfiError> {
+        requires(("integrity_token", self.integrity_token), ("encrypt", self.encrypt))
+    }
+}
+
+impl CheckRequest {
+    fn validate(&self) -> Result<(), FfiError> {
+        let no_manifest = ("no_manifest_target", self.no_manifest_target.is_some());
+        requires(("force_delete", self.force_delete), ("delete", self.delete))?;
+        requires(("prune_dirs", self.prune_dirs), ("delete", self.delete))?;
+        conflict(("quick", self.quick), ("use_cmp", self.use_cmp))?;
+        conflict(("quick", self.quick), ("delete", self.delete))?;
+        conflict(("checksums", self.checksums), ("quick", self.quick))?;
+        conflict(no_manifest, ("quick", self.quick))?;
+        conflict(no_manifest, ("checksums", self.checksums))?;
+        conflict(no_manifest, ("remap", !self.remap.is_empty()))?;
+        if matches!(&self.report, Some(ReportRequest::Path(path)) if path.as_os_str().is_empty()) {
+            return Err(FfiError::Invalid("\"report\" is an empty path (true writes next to the archive)".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl UnfreezeRequest {
+    fn validate(&self) -> Result<(), FfiError> {
+        let no_manifest = ("no_manifest_target", self.no_manifest_target.is_some());
+        conflict(("overwrite", self.overwrite), ("skip_existing", self.skip_existing))?;
+        conflict(("resume", self.resume), ("restart", self.restart))?;
+        conflict(("dry_run", self.dry_run), ("resume", self.resume))?;
+        conflict(("dry_run", self.dry_run), ("restart", self.restart))?;
+        for other in [
+            ("verify", self.verify),
+            ("resume", self.resume),
+            ("restart", self.restart),
+            ("allow_fs_change", self.allow_fs_change),
+            ("target_dir", self.target_dir.is_some()),
+            ("only", !self.only.is_empty()),
+            ("remap", !self.remap.is_empty()),
+            ("dry_run", self.dry_run),
+        ] {
+            conflict(no_manifest, other)?;
+        }
+        Ok(())
+    }
+}
+
+/// Two keys given together that the CLI refuses together (clap `conflicts_with`).
+fn conflict(a: (&str, bool), b: (&str, bool)) -> Result<(), FfiError> {
+    if a.1 && b.1 {
+        return Err(FfiError::Invalid(format!("\"{}\" cannot be used with \"{}\"", a.0, b.0)));
+    }
+    Ok(())
+}
+
+/// A key given without the one it modifies (clap `requires`).
+fn requires(key: (&str, bool), needed: (&str, bool)) -> Result<(), FfiError> {
+    if key.1 && !needed.1 {
+        return Err(FfiError::Invalid(format!("\"{}\" needs \"{}\"", key.0, needed.0)));
+    }
+    Ok(())
+}
+
+/// Why a `zk_*` call failed, before it is turned into a return code.
+enum FfiError {
+    Invalid(String),
+    Zk(ZkError),
+}
+
+impl From<ZkError> for FfiError {
+    fn from(e: ZkError) -> Self {
+        FfiError::Zk(e)
+    }
+}
+
+impl FfiError {
+    fn code(&self) -> c_int {
+        match self {
+            FfiError::Invalid(_) => ZK_ERR_INVALID_ARGUMENT,
+            FfiError::Zk(ZkError::MissingDependencies(_)) => ZK_ERR_MISSING_DEPENDENCIES,
+            FfiError::Zk(e) if utils::is_permission_denied(e) => ZK_ERR_PERMISSION_DENIED,
+            FfiError::Zk(_) => ZK_ERR_FAILED,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            FfiError::Invalid(msg) => msg.clone(),
+            FfiError::Zk(e) => e.friendly_message().unwrap_or_else(|| e.to_string()),
+        }
+    }
+}
+
+fn set_last_error(message: Option<String>) {
+    // A message with a NUL in it would be cut short by C; drop the NULs instead
+    let message = message.map(|m| CString::new(m.replace('\0', "")).unwrap_or_default());
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Parses `options_json` into `T`, or explains what is wrong with it.
+///
+/// # Safety
+/// `options_json` is NULL or a NUL-terminated string.
+unsafe fn parse_options<T: for<'de> Deserialize<'de>>(options_json: *const c_char) -> Result<T, FfiError> {
+    if options_json.is_null() {
+        return Err(FfiError::Invalid("options_json is NULL".to_string()));
+    }
+    // SAFETY: non-NULL and NUL-terminated per the caller's contract
+    let json = unsafe { CStr::from_ptr(options_json) }
+        .to_str()
+        .map_err(|e| FfiError::Invalid(format!("options_json is not UTF-8: {}", e)))?;
+    serde_json::from_str(json).map_err(|e| FfiError::Invalid(format!("Invalid options: {}", e)))
+}
+
+/// Runs `op` on the parsed options and stores its report in `*report_json`; the shared
+/// body of every `zk_*` operation. Panics become `ZK_ERR_PANIC` instead of crossing the ABI.
+///
+/// # Safety
+/// As for the `zk_*` functions.
+unsafe fn run<T: for<'de> Deserialize<'de>>(
+    options_json: *const c_char,
+    report_json: *mut *mut c_char,
+    op: impl FnOnce(T) -> Result<Report, FfiError>,
+) -> c_int {
+    set_last_error(None);
+    if report_json.is_null() {
+        set_last_error(Some("report_json is NULL".to_string()));
+        return ZK_ERR_INVALID_ARGUMENT;
+    }
+    // SAFETY: non-NULL and writable per the caller's contract
+    unsafe { *report_json = std::ptr::null_mut() };
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        // SAFETY: per the caller's contract
+        let options = unsafe { parse_options(options_json) }?;
+        let report = op(options)?;
+        let json = serde_json::to_string(&Versioned::new(report))
+            .map_err(|e| ZkError::OperationFailed(format!("Cannot encode the report: {}", e)))?;
+        CString::new(json).map_err(|e| FfiError::Zk(ZkError::OperationFailed(e.to_string())))
+    }));
+
+    match result {
+        Ok(Ok(json)) => {
+            // SAFETY: checked above
+            unsafe { *report_json = json.into_raw() };
+            ZK_OK
+        }
+        Ok(Err(e)) => {
+            set_last_error(Some(e.message()));
+            e.code()
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(Some(format!("Internal error: {}", message)));
+            ZK_ERR_PANIC
+        }
+    }
+}
+
+fn freeze(request: FreezeRequest) -> Result<Report, FfiError> {
+    if request.targets.is_empty() || request.output.as_os_str().is_empty() {
+        return Err(FfiError::Invalid("freeze needs \"targets\" and \"output\"".to_string()));
+    }
+    request.validate()?;
+    let options = FreezeOptions {
+        encrypt: request.encrypt,
+        output: std::path::absolute(&request.output).map_err(ZkError::from)?,
+        overwrite_files: request.overwrite_files,
+        overwrite_luks_content: request.overwrite_luks_content,
+        progress_mode: ProgressMode::None,
+        compression: request.compression,
+        dereference: request.dereference,
+        dereference_targets: vec![],
+        log_file: None,
+        keep_log: false,
+        keep_staging: false,
+        staging_dir: None,
+        on_staging: None,
+        mode: request.mode,
+        check_open_files: false,
+        allow_open_files: false,
+        skip_unreadable: request.skip_unreadable,
+        no_xattrs: request.no_xattrs,
+        no_space_check: request.no_space_check,
+        reserve: request.reserve,
+        yes: request.yes,
+        mksquashfs_args: vec![],
+        exclude: request.exclude,
+        no_ignore_files: request.no_ignore_files,
+        mem: None,
+        threads: Some(utils::resolve_threads(request.threads)),
+        checksums: request.checksums,
+        auto_fallback_compression: false,
+        skip_broken_symlinks: false,
+        no_recovery: false,
+        integrity_token: request.integrity_token,
+        preserve_order: request.preserve_order,
+        cancel: None,
+        progress: None,
+    };
+    let targets = request
+        .targets
+        .iter()
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ZkError::from)?;
+    Ok(Report::Freeze(engine::freeze(&targets, &options, &RealSystem)?))
+}
+
+fn check(request: CheckRequest) -> Result<Report, FfiError> {
+    request.validate()?;
+    let options = CheckOptions {
+        use_cmp: request.use_cmp,
+        delete: request.delete,
+        force_delete: request.force_delete,
+        prune_dirs: request.prune_dirs,
+        show_extra: request.show_extra,
+        exclude: request.exclude,
+        quick: request.quick,
+        checksums: request.checksums,
+        no_manifest_target: request.no_manifest_target,
+        only_targets: vec![],
+        remap: remaps(&request.remap)?,
+        report: request.report.and_then(ReportRequest::target),
+        cancel: None,
+        progress: None,
+    };
+    Ok(Report::Check(engine::check(&request.archive, &options, &RealSystem)?))
+}
+
+fn unfreeze(request: UnfreezeRequest) -> Result<Report, FfiError> {
+    request.validate()?;
+    let options = UnfreezeOptions {
+        overwrite: request.overwrite,
+        skip_existing: request.skip_existing,
+        force_unfreeze: request.force_unfreeze,
+        verify: request.verify,
+        follow_dest_symlinks: request.follow_dest_symlinks,
+        no_times: request.no_times,
+        no_xattrs: request.no_xattrs,
+        no_restorecon: request.no_restorecon,
+        parent_mode: request.parent_mode,
+        no_manifest_target: request.no_manifest_target,
+        resume: request.resume,
+        restart: request.restart,
+        allow_fs_change: request.allow_fs_change,
+        target_dir: request.target_dir.map(std::path::absolute).transpose().map_err(ZkError::from)?,
+        only: request.only,
+        dry_run: request.dry_run,
+        no_progress: true,
+        remap: remaps(&request.remap)?,
+        cancel: None,
+        progress: None,
+    };
+    Ok(Report::Unfreeze(engine::unfreeze(&request.archive, &options, &RealSystem)?))
+}
+
+/// The `remap` key of a request, each value checked like `--remap`.
+fn remaps(raw: &[String]) -> Result<Vec<PathRemap>, FfiError> {
+    raw.iter().map(|r| remap::remap_arg(r).map_err(FfiError::Invalid)).collect()
+}
+
+/// Freezes `targets` into `output` (see [`FreezeRequest`]).
+///
+/// # Safety
+/// `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zk_freeze(options_json: *const c_char, report_json: *mut *mut c_char) -> c_int {
+    // SAFETY: forwarded from the caller
+    unsafe { run(options_json, report_json, freeze) }
+}
+
+/// Compares `archive` with the live files (see [`CheckRequest`]).
+///
+/// # Safety
+/// `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zk_check(options_json: *const c_char, report_json: *mut *mut c_char) -> c_int {
+    // SAFETY: forwarded from the caller
+    unsafe { run(options_json, report_json, check) }
+}
+
+/// Restores `archive` (see [`UnfreezeRequest`]).
+///
+/// # Safety
+/// `options_json` is NULL or a NUL-terminated string; `report_json` is NULL or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zk_unfreeze(options_json: *const c_char, report_json: *mut *mut c_char) -> c_int {
+    // SAFETY: forwarded from the caller
+    unsafe { run(options_json, report_json, unfreeze) }
+}
+
+/// Releases a report returned by `zk_freeze`, `zk_check` or `zk_unfreeze`. NULL is ignored.
+///
+/// # Safety
+/// `report_json` is NULL or a report from this library that was not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zk_free_report(report_json: *mut c_char) {
+    if !report_json.is_null() {
+        // SAFETY: created by CString::into_raw in `run`
+        drop(unsafe { CString::from_raw(report_json) });
+    }
+}
+
+/// The error of the last failed `zk_*` call on this thread, or NULL after a success.
+#[unsafe(no_mangle)]
+pub extern "C" fn zk_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |m| m.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls `f` like a C caller would; returns the code, the report and the error message.
+    fn call(
+        f: unsafe extern "C" fn(*const c_char, *mut *mut c_char) -> c_int,
+        json: &str,
+    ) -> (c_int, Option<String>, Option<String>) {
+        let json = CString::new(json).unwrap();
+        let mut report: *mut c_char = std::ptr::dangling_mut();
+        let code = unsafe { f(json.as_ptr(), &mut report) };
+        let report_text = (!report.is_null()).then(|| unsafe { CStr::from_ptr(report) }.to_string_lossy().into_owned());
+        unsafe { zk_free_report(report) };
+        let error = zk_last_error_message();
+        let error = (!error.is_null()).then(|| unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned());
+        (code, report_text, error)
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let (code, report, error) = call(zk_check, "{not json");
+        assert_eq!(code, ZK_ERR_INVALID_ARGUMENT);
+        assert_eq!(report, None);
+        assert!(error.unwrap().starts_with("Invalid options"));
+
+        let (code, _, error) = call(zk_unfreeze, r#"{"archive": "/tmp/a.sqfs", "overwirte": true}"#);
+        assert_eq!(code, ZK_ERR_INVALID_ARGUMENT);
+        assert!(error.unwrap().contains("unknown field `overwirte`"));
+
+        let mut report: *mut c_char = std::ptr::null_mut();
+        assert_eq!(unsafe { zk_freeze(std::ptr::null(), &mut report) }, ZK_ERR_INVALID_ARGUMENT);
+        assert!(report.is_null());
+        let json = CString::new("{}").unwrap();
+        assert_eq!(unsafe { zk_check(json.as_ptr(), std::ptr::null_mut()) }, ZK_ERR_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let (code, report, error) = call(zk_freeze, r#"{"output": "/tmp/a.sqfs"}"#);
+        assert_eq!(code, ZK_ERR_INVALID_ARGUMENT);
+        assert_eq!(report, None);
+        assert!(error.unwrap().contains("\"targets\""));
+
+        let failed = FfiError::Zk(ZkError::OperationFailed("Failed to mount archive".into()));
+        assert_eq!(failed.code(), ZK_ERR_FAILED);
+        let missing = FfiError::Zk(ZkError::MissingDependencies(vec![utils::Dependency::Rsync]));
+        assert_eq!(missing.code(), ZK_ERR_MISSING_DEPENDENCIES);
+        let denied = FfiError::Zk(ZkError::IoError(std::io::Error::from(std::io::ErrorKind::PermissionDenied)));
+        assert_eq!(denied.code(), ZK_ERR_PERMISSION_DENIED);
+    }
+
+    #[test]
+    fn test_conflicting_options() {
+        let refused = [
+            (zk_unfreeze as unsafe extern "C" fn(_, _) -> _, r#"{"overwrite": true, "skip_existing": true}"#),
+            (zk_unfreeze, r#"{"resume": true, "restart": true}"#),
+            (zk_unfreeze, r#"{"dry_run": true, "resume": true}"#),
+            (zk_unfreeze, r#"{"no_manifest_target": "/tmp/t", "only": ["1"]}"#),
+            (zk_check, r#"{"prune_dirs": true}"#),
+            (zk_check, r#"{"quick": true, "delete": true}"#),
+            (zk_freeze, r#"{"targets": ["/tmp/a"], "output": "/tmp/a.sqfs", "integrity_token": true}"#),
+        ];
+        for (f, json) in refused {
+            let (code, report, error) = call(f, json);
+            assert_eq!(code, ZK_ERR_INVALID_ARGUMENT, "{}", json);
+            assert_eq!(report, None);
+            let error = error.unwrap();
+            assert!(error.contains("cannot be used with") || error.contains("needs"), "{}", error);
+        }
+        let (_, _, error) = call(zk_check, r#"{"prune_dirs": true}"#);
+        assert_eq!(error.unwrap(), r#""prune_dirs" needs "delete""#);
+    }
+
+    #[test]
+    fn test_check_report_key() {
+        let target = |json: &str| {
+            let request: CheckRequest = serde_json::from_str(json).unwrap();
+            request.validate().map(|()| request.report.and_then(ReportRequest::target)).ok()
+        };
+        assert_eq!(target("{}"), Some(None));
+        assert_eq!(target(r#"{"report": false}"#), Some(None));
+        assert_eq!(target(r#"{"report": true}"#), Some(Some(ReportTarget::Default)));
+        assert_eq!(target(r#"{"report": "/tmp/r.yaml"}"#), Some(Some(ReportTarget::Path("/tmp/r.yaml".into()))));
+        assert_eq!(target(r#"{"report": ""}"#), None);
+        assert!(serde_json::from_str::<CheckRequest>(r#"{"report": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_header_declares_the_abi() {
+        let header = include_str!("../include/zero_kelvin.h");
+        for function in ["zk_freeze(", "zk_check(", "zk_unfreeze(", "zk_free_report(", "zk_last_error_message("] {
+            assert!(header.contains(function), "{} is missing from the header", function);
+        }
+        for (name, value) in [
+            ("ZK_OK", ZK_OK),
+            ("ZK_ERR_INVALID_ARGUMENT", ZK_ERR_INVALID_ARGUMENT),
+            ("ZK_ERR_FAILED", ZK_ERR_FAILED),
+            ("ZK_ERR_PERMISSION_DENIED", ZK_ERR_PERMISSION_DENIED),
+            ("ZK_ERR_MISSING_DEPENDENCIES", ZK_ERR_MISSING_DEPENDENCIES),
+            ("ZK_ERR_PANIC", ZK_ERR_PANIC),
+        ] {
+            assert!(header.contains(&format!("#define {} {}\n", name, value)), "{} is missing from the header", name);
+        }
+    }
+
+    #[test]
+    fn test_report_and_panic() {
+        let mut report: *mut c_char = std::ptr::null_mut();
+        let json = CString::new(r#"{"archive": "/tmp/a.sqfs"}"#).unwrap();
+        let code = unsafe {
+            run(json.as_ptr(), &mut report, |request: CheckRequest| {
+                assert_eq!(request.archive, PathBuf::from("/tmp/a.sqfs"));
+                Ok(Report::Check(crate::report::CheckReport { files_matched: 3, ..Default::default() }))
+            })
+        };
+        assert_eq!(code, ZK_OK);
+        assert!(zk_last_error_message().is_null());
+        let text = unsafe { CStr::from_ptr(report) }.to_str().unwrap().to_string();
+        unsafe { zk_free_report(report) };
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["schema_version"], crate::report::SCHEMA_VERSION);
+        assert_eq!(value["operation"], "check");
+        assert_eq!(value["files_matched"], 3);
+
+        let code = unsafe { run(json.as_ptr(), &mut report, |_: CheckRequest| -> Result<Report, FfiError> { panic!("boom") }) };
+        assert_eq!(code, ZK_ERR_PANIC);
+        assert!(report.is_null());
+        let error = unsafe { CStr::from_ptr(zk_last_error_message()) }.to_str().unwrap();
+        assert_eq!(error, "Internal error: boom");
+    }
+}
@@ -0,0 +1,112 @@
+//! Whether the running kernel can mount zstd-compressed SquashFS.
+//!
+//! Encrypted containers are mounted by the kernel (plain archives go through squashfuse), and
+//! a kernel built without `CONFIG_SQUASHFS_ZSTD` refuses their filesystem only at mount time,
+//! long after packing. Mainline SquashFS exposes no feature list under `/sys/fs`, so the answer
+//! comes from the kernel build config: `/boot/config-<release>`, else `/proc/config.gz`.
+//! It cannot change until the next boot, so `0k-core create` caches it per boot (keyed by
+//! `/proc/sys/kernel/random/boot_id`) in the 0k temp directory.
+
+use crate::executor::CommandExecutor;
+use std::fs;
+use std::path::Path;
+
+const CACHE_FILE: &str = "kernel-squashfs-zstd";
+
+/// What a kernel config says about zstd SquashFS; None if it does not mention SquashFS.
+///
+/// Kernels older than 4.14 have no `CONFIG_SQUASHFS_ZSTD` at all: SquashFS without it
+/// means no zstd.
+pub fn zstd_from_config(config: &str) -> Option<bool> {
+    let mut squashfs = None;
+    for line in config.lines().map(str::trim) {
+        match line {
+            "CONFIG_SQUASHFS_ZSTD=y" => return Some(true),
+            "# CONFIG_SQUASHFS_ZSTD is not set" => return Some(false),
+            "CONFIG_SQUASHFS=y" | "CONFIG_SQUASHFS=m" => squashfs = Some(false),
+            "# CONFIG_SQUASHFS is not set" => return Some(false),
+            _ => {}
+        }
+    }
+    squashfs
+}
+
+/// Reads the config of the running kernel; None if it is not available.
+pub fn probe(executor: &impl CommandExecutor) -> Option<bool> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    if let Ok(config) = fs::read_to_string(format!("/boot/config-{}", release.trim())) {
+        return zstd_from_config(&config);
+    }
+    if !Path::new("/proc/config.gz").exists() {
+        return None;
+    }
+    let output = executor.run("zcat", &["/proc/config.gz"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    zstd_from_config(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// [`probe`], cached until the next boot.
+pub fn zstd_supported(executor: &impl CommandExecutor) -> Option<bool> {
+    let Ok(boot_id) = fs::read_to_string("/proc/sys/kernel/random/boot_id") else {
+        return probe(executor);
+    };
+    let Ok(dir) = crate::utils::get_0k_temp_dir(None) else {
+        return probe(executor);
+    };
+    cached(&dir.join(CACHE_FILE), boot_id.trim(), || probe(executor))
+}
+
+/// The answer stored in `cache` for `boot_id`, else `probe()` (stored for the next call).
+fn cached(cache: &Path, boot_id: &str, probe: impl FnOnce() -> Option<bool>) -> Option<bool> {
+    if let Ok(text) = fs::read_to_string(cache)
+        && let Some((id, answer)) = text.trim().split_once(' ')
+        && id == boot_id
+    {
+        match answer {
+            "yes" => return Some(true),
+            "no" => return Some(false),
+            "unknown" => return None,
+            _ => {}
+        }
+    }
+    let answer = probe();
+    let word = match answer {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    };
+    if let Err(e) = fs::write(cache, format!("{} {}\n", boot_id, word)) {
+        log::debug!("Cannot cache the kernel zstd probe in {}: {}", cache.display(), e);
+    }
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zstd_from_config() {
+        assert_eq!(zstd_from_config("CONFIG_SQUASHFS=y\nCONFIG_SQUASHFS_XZ=y\nCONFIG_SQUASHFS_ZSTD=y\n"), Some(true));
+        assert_eq!(zstd_from_config("CONFIG_SQUASHFS=m\n# CONFIG_SQUASHFS_ZSTD is not set\n"), Some(false));
+        // Before 4.14 the option does not exist
+        assert_eq!(zstd_from_config("CONFIG_SQUASHFS=m\nCONFIG_SQUASHFS_XZ=y\n"), Some(false));
+        assert_eq!(zstd_from_config("# CONFIG_SQUASHFS is not set\n"), Some(false));
+        assert_eq!(zstd_from_config("CONFIG_EXT4_FS=y\n"), None);
+    }
+
+    #[test]
+    fn test_cached_per_boot() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join(CACHE_FILE);
+        assert_eq!(cached(&cache, "boot-1", || Some(false)), Some(false));
+        // Same boot: the stored answer, no new probe
+        assert_eq!(cached(&cache, "boot-1", || panic!("probed again")), Some(false));
+        // Next boot: probed again
+        assert_eq!(cached(&cache, "boot-2", || None), None);
+        assert_eq!(cached(&cache, "boot-2", || panic!("probed again")), None);
+        assert_eq!(fs::read_to_string(&cache).unwrap(), "boot-2 unknown\n");
+    }
+}
@@ -1,8 +1,36 @@
+pub mod cancel;
+pub mod catalog;
+pub mod check_audit;
+pub mod checksums;
 pub mod cli;
+pub mod config;
 pub mod constants;
 pub mod engine;
 pub mod error;
+pub mod events;
+pub mod exclude;
 pub mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod kernel_squashfs;
+pub mod locks;
 pub mod logging;
+pub mod luks;
+pub mod luks_token;
 pub mod manifest;
+pub mod mounts;
+pub mod overwrite;
+pub mod passphrase;
+pub mod prune;
+pub mod readonly;
+pub mod remap;
+pub mod report;
+pub mod restore_state;
+pub mod space;
+pub mod squashfs_info;
+pub mod summary;
+pub mod targets;
+pub mod trim_journal;
+pub mod units;
 pub mod utils;
+pub mod version;
@@ -0,0 +1,275 @@
+//! All `flock` acquisition goes through here, so every lock is taken in one global order.
+//!
+//! Lock classes, in the order they must be acquired (see [`LockClass`]):
+//!
+//! 1. `Catalog` — the archive catalog (`catalog.jsonl.lock`);
+//! 2. `Output`  — an archive path being written;
+//! 3. `Archive` — an existing archive being read or modified;
+//! 4. `Staging` — a freeze staging directory (`build_*/.lock`);
+//! 5. `Mapper`  — a LUKS mapper name reservation.
+//!
+//! A blocking [`lock`] may only be taken while every lock the thread already holds belongs to
+//! an earlier class; debug builds assert this with a per-thread stack of held classes, so a
+//! path that could deadlock against a concurrent operation fails loudly in tests instead.
+//! [`try_lock`] never waits and therefore may be taken in any order (the attempt itself cannot
+//! deadlock), but the lock it returns counts as held from then on.
+//!
+//! Each holder writes its PID and command line into the lock file, so a failed try-lock can
+//! name the process in the way ([`try_lock_or_busy`], staging GC).
+
+use crate::error::ZkError;
+use fs2::FileExt;
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Kinds of locks, declared in acquisition order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockClass {
+    Catalog,
+    Output,
+    Archive,
+    Staging,
+    Mapper,
+}
+
+thread_local! {
+    /// Classes of the locks held by this thread, in acquisition order
+    static HELD: RefCell<Vec<LockClass>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An acquired lock; released (and forgotten by the ordering check) on drop.
+#[derive(Debug)]
+pub struct LockGuard {
+    class: LockClass,
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl LockGuard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The locked file (e.g. to compare its inode with the path after locking)
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(pos) = held.iter().rposition(|c| *c == self.class) {
+                held.remove(pos);
+            }
+        });
+        // The flock itself goes away when the file is closed
+    }
+}
+
+/// Err describes the violation when a blocking lock of class `next` may not be taken while
+/// the classes in `held` are held.
+pub fn check_order(held: &[LockClass], next: LockClass) -> Result<(), String> {
+    match held.iter().find(|c| **c >= next) {
+        Some(c) => Err(format!(
+            "lock order violation: blocking on a {:?} lock while holding a {:?} lock (order: Catalog, Output, Archive, Staging, Mapper)",
+            next, c
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Takes an exclusive lock on `path` (created if missing), waiting for other holders.
+pub fn lock(class: LockClass, path: &Path) -> Result<LockGuard, ZkError> {
+    if cfg!(debug_assertions) {
+        HELD.with(|held| {
+            if let Err(violation) = check_order(&held.borrow(), class) {
+                panic!("{} ({})", violation, path.display());
+            }
+        });
+    }
+    let file = open_lock_file(path)?;
+    file.lock_exclusive()
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot lock {}: {}", path.display(), e)))?;
+    Ok(acquired(class, path, file))
+}
+
+/// Takes an exclusive lock on `path` if nobody holds it; `Ok(None)` if someone does.
+pub fn try_lock(class: LockClass, path: &Path) -> Result<Option<LockGuard>, ZkError> {
+    let file = open_lock_file(path)?;
+    if file.try_lock_exclusive().is_err() {
+        return Ok(None);
+    }
+    Ok(Some(acquired(class, path, file)))
+}
+
+/// [`try_lock`], with a [`ZkError::Busy`] naming the holder when the lock is taken.
+pub fn try_lock_or_busy(class: LockClass, path: &Path, what: &str) -> Result<LockGuard, ZkError> {
+    try_lock(class, path)?.ok_or_else(|| ZkError::Busy(format!("{} is in use ({})", what, held_by(path))))
+}
+
+/// Lock file of the archive at `archive`: `<archive>.lock` next to it, taken as `Output` while
+/// freeze writes the archive and as `Archive` while unfreeze reads it or prune deletes it.
+pub fn archive_lock_path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// [`try_lock_or_busy`] on the `Archive` lock of an archive that is only read. None when the
+/// lock file cannot be created (read-only media, a directory of another user): that must not
+/// keep anyone from reading the archive.
+pub fn try_lock_archive_for_reading(archive: &Path) -> Result<Option<LockGuard>, ZkError> {
+    let path = archive_lock_path(archive);
+    match try_lock(LockClass::Archive, &path) {
+        Ok(Some(lock)) => Ok(Some(lock)),
+        Ok(None) => Err(ZkError::Busy(format!("{} is in use ({})", archive.display(), held_by(&path)))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Longest command line [`held_by`] shows
+const HOLDER_COMMAND_SHOWN: usize = 80;
+
+/// "held by PID N (0k freeze ...)" from the lock file contents, without the command line for
+/// lock files written before it was recorded, or a generic description for those written
+/// before PIDs were.
+pub fn held_by(path: &Path) -> String {
+    match (holder_pid(path), holder_command(path)) {
+        (Some(pid), Some(command)) if command.chars().count() > HOLDER_COMMAND_SHOWN => {
+            let shown: String = command.chars().take(HOLDER_COMMAND_SHOWN - 1).collect();
+            format!("held by PID {} ({}…)", pid, shown)
+        }
+        (Some(pid), Some(command)) => format!("held by PID {} ({})", pid, command),
+        (Some(pid), None) => format!("held by PID {}", pid),
+        (None, _) => "held by another process".to_string(),
+    }
+}
+
+/// PID recorded in the lock file by its current (or last) holder (the first line).
+pub fn holder_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.lines().next()?.trim().parse().ok()
+}
+
+/// Command line of the current (or last) holder (the second line).
+pub fn holder_command(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let command = content.lines().nth(1)?.trim();
+    (!command.is_empty()).then(|| command.to_string())
+}
+
+/// This process as the lock file records it: the program name without its directory, then
+/// the arguments.
+fn command_line() -> String {
+    let mut args = std::env::args_os().map(|a| a.to_string_lossy().into_owned());
+    let program = args.next().map(|p| match p.rsplit_once('/') {
+        Some((_, name)) => name.to_string(),
+        None => p,
+    });
+    program.into_iter().chain(args).collect::<Vec<_>>().join(" ").replace('\n', " ")
+}
+
+fn open_lock_file(path: &Path) -> Result<fs::File, ZkError> {
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot open lock file {}: {}", path.display(), e)))
+}
+
+/// Records the new holder (PID in the file, class on the thread's stack).
+fn acquired(class: LockClass, path: &Path, mut file: fs::File) -> LockGuard {
+    // Best effort: PID and command line are only used for diagnostics
+    let _ = file.set_len(0).and_then(|_| writeln!(file, "{}\n{}", std::process::id(), command_line()));
+    HELD.with(|held| held.borrow_mut().push(class));
+    LockGuard { class, path: path.to_path_buf(), file }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_order() {
+        use LockClass::*;
+        assert!(check_order(&[], Staging).is_ok());
+        assert!(check_order(&[Catalog, Output], Staging).is_ok());
+        assert!(check_order(&[Staging], Catalog).is_err());
+        assert!(check_order(&[Output], Output).is_err());
+        assert!(check_order(&[Catalog, Mapper], Archive).is_err());
+    }
+
+    #[test]
+    fn test_ordered_locks_and_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = lock(LockClass::Catalog, &dir.path().join("catalog.lock")).unwrap();
+        let staging = lock(LockClass::Staging, &dir.path().join("staging.lock")).unwrap();
+        drop(staging);
+        drop(catalog);
+        // Everything released: any class may be taken again
+        let _output = lock(LockClass::Output, &dir.path().join("output.lock")).unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lock order violation")]
+    fn test_out_of_order_lock_panics_in_debug() {
+        let dir = tempfile::tempdir().unwrap();
+        let _staging = lock(LockClass::Staging, &dir.path().join("staging.lock")).unwrap();
+        let _catalog = lock(LockClass::Catalog, &dir.path().join("catalog.lock"));
+    }
+
+    #[test]
+    fn test_try_lock_reports_holder_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.lock");
+        let _held = try_lock(LockClass::Output, &path).unwrap().unwrap();
+        assert_eq!(holder_pid(&path), Some(std::process::id()));
+
+        // flock is per open file description: a second open in this process is refused too
+        assert!(try_lock(LockClass::Output, &path).unwrap().is_none());
+        let err = try_lock_or_busy(LockClass::Output, &path, "the archive").unwrap_err();
+        assert!(err.to_string().contains(&format!("held by PID {}", std::process::id())));
+    }
+
+    #[test]
+    fn test_try_lock_archive_for_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("data.sqfs");
+        assert_eq!(archive_lock_path(&archive), dir.path().join("data.sqfs.lock"));
+
+        let held = try_lock_archive_for_reading(&archive).unwrap();
+        assert!(held.is_some());
+        assert!(matches!(try_lock_archive_for_reading(&archive), Err(ZkError::Busy(_))));
+        drop(held);
+        // No lock file possible: read anyway
+        assert!(try_lock_archive_for_reading(&dir.path().join("missing/data.sqfs")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_held_by_names_the_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".lock");
+        let _held = try_lock(LockClass::Staging, &path).unwrap().unwrap();
+        // The test binary, without its directory
+        let command = holder_command(&path).unwrap();
+        assert!(!command.split(' ').next().unwrap().contains('/'), "{}", command);
+        assert!(held_by(&path).starts_with(&format!("held by PID {} (", std::process::id())));
+
+        // Written before command lines were recorded, or before PIDs were
+        fs::write(dir.path().join("old.lock"), "1234").unwrap();
+        assert_eq!(held_by(&dir.path().join("old.lock")), "held by PID 1234");
+        fs::write(dir.path().join("older.lock"), "").unwrap();
+        assert_eq!(held_by(&dir.path().join("older.lock")), "held by another process");
+
+        let long = format!("4321\n0k freeze {}\n", "/data".repeat(40));
+        fs::write(dir.path().join("long.lock"), long).unwrap();
+        let shown = held_by(&dir.path().join("long.lock"));
+        assert!(shown.starts_with("held by PID 4321 (0k freeze /data") && shown.ends_with("…)"), "{}", shown);
+    }
+}
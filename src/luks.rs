@@ -0,0 +1,203 @@
+//! cryptsetup version probe and the optional `luksFormat` flags it allows.
+//!
+//! Old cryptsetup releases (Debian oldstable ships 2.0) reject flags newer ones take for
+//! granted. `cryptsetup --version` is run once per create; each optional flag has a minimum
+//! version in [`OPTIONAL_FLAGS`], and is dropped with a warning on an older (or unknown)
+//! cryptsetup instead of failing the whole create.
+
+use crate::executor::CommandExecutor;
+use std::fmt;
+
+/// A cryptsetup release, as `cryptsetup --version` prints it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CryptsetupVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl CryptsetupVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        CryptsetupVersion { major, minor, patch }
+    }
+
+    /// Parses `cryptsetup 2.6.1 flags: UDEV BLKID ...` or `cryptsetup 2.7.0-rc1`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let word = output.split_whitespace().find(|w| w.starts_with(|c: char| c.is_ascii_digit()))?;
+        let numeric = word.split(|c: char| !(c.is_ascii_digit() || c == '.')).next()?;
+        let mut parts = numeric.split('.').map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some(CryptsetupVersion::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for CryptsetupVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Optional `luksFormat` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionalFlag {
+    /// `--label`: LUKS2 only. LUKS2 is the default format from 2.1.0 on, but a build can still
+    /// default to LUKS1, so the flag comes with `--type luks2`
+    Label,
+}
+
+/// Oldest cryptsetup that takes each optional flag.
+pub const OPTIONAL_FLAGS: [(OptionalFlag, &str, CryptsetupVersion); 1] =
+    [(OptionalFlag::Label, "--label", CryptsetupVersion::new(2, 1, 0))];
+
+impl OptionalFlag {
+    fn entry(self) -> &'static (OptionalFlag, &'static str, CryptsetupVersion) {
+        OPTIONAL_FLAGS.iter().find(|(flag, _, _)| *flag == self).expect("every flag is in OPTIONAL_FLAGS")
+    }
+
+    pub fn name(self) -> &'static str {
+        self.entry().1
+    }
+
+    pub fn min_version(self) -> CryptsetupVersion {
+        self.entry().2
+    }
+
+    /// Flags `luksFormat` needs for this one to be valid, pushed before it.
+    fn requires(self) -> &'static [&'static str] {
+        match self {
+            OptionalFlag::Label => &["--type", "luks2"],
+        }
+    }
+
+    /// Whether `version` takes the flag; an unknown version takes none.
+    pub fn supported_by(self, version: Option<CryptsetupVersion>) -> bool {
+        version.is_some_and(|v| v >= self.min_version())
+    }
+}
+
+/// The optional flags `version` takes, by name (`0k version --json`).
+pub fn enabled_flags(version: Option<CryptsetupVersion>) -> Vec<String> {
+    OPTIONAL_FLAGS
+        .iter()
+        .filter(|(flag, _, _)| flag.supported_by(version))
+        .map(|(_, name, _)| name.to_string())
+        .collect()
+}
+
+/// Appends `flag` with `args` to `cmd` if `version` takes it; otherwise returns the warning
+/// to print instead.
+pub fn push_optional(
+    cmd: &mut Vec<String>,
+    version: Option<CryptsetupVersion>,
+    flag: OptionalFlag,
+    args: &[String],
+) -> Option<String> {
+    if flag.supported_by(version) {
+        cmd.extend(flag.requires().iter().map(|a| a.to_string()));
+        cmd.push(flag.name().to_string());
+        cmd.extend(args.iter().cloned());
+        return None;
+    }
+    Some(match version {
+        Some(v) => format!(
+            "Warning: cryptsetup {} is older than {}, which {} needs; creating the container without it.",
+            v,
+            flag.min_version(),
+            flag.name()
+        ),
+        None => format!(
+            "Warning: cannot determine the cryptsetup version; creating the container without {}.",
+            flag.name()
+        ),
+    })
+}
+
+/// Runs `cryptsetup --version`; None if it fails or prints something unexpected.
+pub fn probe(executor: &impl CommandExecutor) -> Option<CryptsetupVersion> {
+    let output = executor.run("cryptsetup", &["--version"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    CryptsetupVersion::parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// LUKS2 labels hold at most 47 bytes: `name`, cut at a character boundary.
+pub fn label_for(name: &str) -> String {
+    let mut end = name.len().min(47);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockCommandExecutor;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_parse_version() {
+        let v = CryptsetupVersion::parse;
+        assert_eq!(v("cryptsetup 2.0.6\n"), Some(CryptsetupVersion::new(2, 0, 6)));
+        assert_eq!(
+            v("cryptsetup 2.6.1 flags: UDEV BLKID KEYRING FIPS KERNEL_CAPI PWQUALITY"),
+            Some(CryptsetupVersion::new(2, 6, 1))
+        );
+        assert_eq!(v("cryptsetup 2.7.0-rc1"), Some(CryptsetupVersion::new(2, 7, 0)));
+        assert_eq!(v("cryptsetup 1.7"), Some(CryptsetupVersion::new(1, 7, 0)));
+        assert_eq!(v("cryptsetup"), None);
+        assert_eq!(v("cryptsetup version unknown"), None);
+        assert_eq!(v(""), None);
+        assert_eq!(CryptsetupVersion::new(2, 10, 0).to_string(), "2.10.0");
+        assert!(CryptsetupVersion::new(2, 10, 0) > CryptsetupVersion::new(2, 9, 9));
+    }
+
+    #[test]
+    fn test_optional_flags_gated_by_version() {
+        let old = Some(CryptsetupVersion::new(2, 0, 6));
+        let new = Some(CryptsetupVersion::new(2, 1, 0));
+        assert!(!OptionalFlag::Label.supported_by(old));
+        assert!(OptionalFlag::Label.supported_by(new));
+        assert!(!OptionalFlag::Label.supported_by(None));
+        assert_eq!(enabled_flags(new), vec!["--label"]);
+        assert!(enabled_flags(old).is_empty());
+
+        let label = vec!["data.sqfs".to_string()];
+        let mut cmd = vec!["luksFormat".to_string()];
+        assert_eq!(push_optional(&mut cmd, new, OptionalFlag::Label, &label), None);
+        assert_eq!(cmd, ["luksFormat", "--type", "luks2", "--label", "data.sqfs"]);
+
+        let mut cmd = vec!["luksFormat".to_string()];
+        let warning = push_optional(&mut cmd, old, OptionalFlag::Label, &label).unwrap();
+        assert_eq!(cmd, ["luksFormat"]);
+        assert!(warning.contains("2.0.6 is older than 2.1.0") && warning.contains("--label"), "{}", warning);
+        let warning = push_optional(&mut cmd, None, OptionalFlag::Label, &label).unwrap();
+        assert!(warning.contains("cannot determine"), "{}", warning);
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args == ["--version"])
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: std::process::ExitStatus::from_raw(0),
+                    stdout: b"cryptsetup 2.0.6\n".to_vec(),
+                    stderr: vec![],
+                })
+            });
+        assert_eq!(probe(&mock), Some(CryptsetupVersion::new(2, 0, 6)));
+    }
+
+    #[test]
+    fn test_label_for() {
+        assert_eq!(label_for("data.sqfs"), "data.sqfs");
+        assert_eq!(label_for(&"a".repeat(60)).len(), 47);
+        // 'é' is two bytes: never cut in the middle
+        assert_eq!(label_for(&format!("{}é", "a".repeat(46))), "a".repeat(46));
+    }
+}
@@ -0,0 +1,434 @@
+//! Digest of the SquashFS inside an encrypted archive, kept in its LUKS2 header
+//! (`freeze --integrity-token`).
+//!
+//! A digest next to the container can get separated from it; a LUKS2 token travels with the
+//! header. After `0k-core create` packs a container, the SHA-256 of the SquashFS payload (its
+//! `bytes_used`, read through the open mapper) is stored as a token of type `0k-meta` with
+//! `cryptsetup token import`. LUKS1 headers have no tokens: the same JSON object then goes to
+//! the sidecar file `<archive>.sha256.json`. `0k info` shows the digest and `0k verify`
+//! recomputes it, reading the token back with `cryptsetup token export` and falling back to
+//! the sidecar. A later append or payload replacement without `--integrity-token` removes
+//! both, so verify never checks the new payload against the old digest.
+
+use crate::error::ZkError;
+use crate::executor::CommandExecutor;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+
+/// `type` of the token in the LUKS2 header
+pub const TOKEN_TYPE: &str = "0k-meta";
+
+/// Appended to the archive path for the sidecar file
+pub const SIDECAR_SUFFIX: &str = ".sha256.json";
+
+/// SquashFS superblock: magic `hsqs` at 0, `bytes_used` (u64, little endian) at 40
+const SQUASHFS_MAGIC: &[u8; 4] = b"hsqs";
+const BYTES_USED_OFFSET: usize = 40;
+
+/// The token as stored (and as written to the sidecar).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetaToken {
+    #[serde(rename = "type")]
+    pub token_type: String,
+    /// Required in every LUKS2 token; this one unlocks nothing
+    #[serde(default)]
+    pub keyslots: Vec<String>,
+    /// Hex SHA-256 of the SquashFS payload (not of the container file)
+    pub payload_sha256: String,
+    /// Random id given to the archive when the token was first stored
+    pub archive_id: String,
+}
+
+impl MetaToken {
+    pub fn new(payload_sha256: String, archive_id: String) -> Self {
+        MetaToken { token_type: TOKEN_TYPE.to_string(), keyslots: vec![], payload_sha256, archive_id }
+    }
+}
+
+/// Where a payload digest was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestSource {
+    LuksToken,
+    Sidecar,
+}
+
+impl fmt::Display for DigestSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestSource::LuksToken => write!(f, "LUKS token"),
+            DigestSource::Sidecar => write!(f, "sidecar {}", SIDECAR_SUFFIX),
+        }
+    }
+}
+
+/// A recorded payload digest, as `0k info` and `0k verify` report it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct PayloadDigest {
+    pub payload_sha256: String,
+    pub archive_id: String,
+    pub source: DigestSource,
+}
+
+/// A fresh random archive id (128 bits, hex).
+pub fn new_archive_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// `bytes_used` of a SquashFS superblock; None if `superblock` is not one.
+fn bytes_used(superblock: &[u8]) -> Option<u64> {
+    if !superblock.starts_with(SQUASHFS_MAGIC) {
+        return None;
+    }
+    let field = superblock.get(BYTES_USED_OFFSET..BYTES_USED_OFFSET + 8)?;
+    Some(u64::from_le_bytes(field.try_into().ok()?))
+}
+
+/// Hex SHA-256 of the SquashFS at the start of `device` (an opened mapper), up to the
+/// `bytes_used` its superblock records: the padding after it is not part of the payload.
+pub fn payload_sha256(device: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(device)?;
+    let mut superblock = [0u8; BYTES_USED_OFFSET + 8];
+    file.read_exact(&mut superblock)?;
+    let len = bytes_used(&superblock).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} holds no SquashFS superblock", device.display()))
+    })?;
+    file.rewind()?;
+    crate::checksums::sha256_reader(file.take(len))
+}
+
+/// `<archive>.sha256.json`
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_os_string();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Id of the `0k-meta` token in `cryptsetup luksDump` output (its `Tokens:` section lists
+/// `  <id>: <type>`); None if there is none.
+pub fn token_id(dump: &str) -> Option<u32> {
+    let mut in_tokens = false;
+    for line in dump.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            in_tokens = line.trim_end() == "Tokens:";
+            continue;
+        }
+        if in_tokens
+            && let Some((id, kind)) = line.trim().split_once(':')
+            && kind.trim() == TOKEN_TYPE
+        {
+            return id.parse().ok();
+        }
+    }
+    None
+}
+
+/// `program` and arguments of `cryptsetup args...`, prefixed with `root_cmd` (sudo, ...).
+fn cryptsetup(root_cmd: &[String], args: &[&str]) -> (String, Vec<String>) {
+    let mut full: Vec<String> = root_cmd.to_vec();
+    full.push("cryptsetup".to_string());
+    full.extend(args.iter().map(|a| a.to_string()));
+    let program = full.remove(0);
+    (program, full)
+}
+
+fn failure(what: &str, output: &std::process::Output) -> ZkError {
+    ZkError::LuksError(format!("{} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim()))
+}
+
+/// Stores `token` for `image`: as a LUKS2 token (replacing an earlier `0k-meta` one), or in
+/// the sidecar if the header is LUKS1. Returns where it went.
+pub fn store<E: CommandExecutor + ?Sized>(
+    executor: &E,
+    root_cmd: &[String],
+    image: &Path,
+    token: &MetaToken,
+) -> Result<DigestSource, ZkError> {
+    let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+    let json = serde_json::to_string(token)
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot encode the {} token: {}", TOKEN_TYPE, e)))?;
+
+    let (program, args) = cryptsetup(root_cmd, &["luksDump", image_str]);
+    let dump = executor.run(&program, &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+    if !dump.status.success() {
+        return Err(failure("cryptsetup luksDump", &dump));
+    }
+    let dump = String::from_utf8_lossy(&dump.stdout);
+    if crate::squashfs_info::LuksHeader::parse(&dump).luks_version == Some(1) {
+        fs::write(sidecar_path(image), format!("{}\n", json))?;
+        return Ok(DigestSource::Sidecar);
+    }
+
+    if let Some(id) = token_id(&dump) {
+        remove_token(executor, root_cmd, image_str, id)?;
+    }
+    let (program, args) = cryptsetup(root_cmd, &["token", "import", "--json-file", "-", image_str]);
+    let output = executor.run_with_stdin(&program, &args.iter().map(String::as_str).collect::<Vec<_>>(), json.as_bytes())?;
+    if !output.status.success() {
+        return Err(failure("cryptsetup token import", &output));
+    }
+    Ok(DigestSource::LuksToken)
+}
+
+fn remove_token<E: CommandExecutor + ?Sized>(executor: &E, root_cmd: &[String], image: &str, id: u32) -> Result<(), ZkError> {
+    let id = id.to_string();
+    let (program, args) = cryptsetup(root_cmd, &["token", "remove", "--token-id", &id, image]);
+    let output = executor.run(&program, &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+    if !output.status.success() {
+        return Err(failure("cryptsetup token remove", &output));
+    }
+    Ok(())
+}
+
+/// Removes the digest recorded for `image` (its `0k-meta` token and its sidecar), once the
+/// payload it describes was replaced. Returns whether there was one.
+pub fn remove<E: CommandExecutor + ?Sized>(executor: &E, root_cmd: &[String], image: &Path) -> Result<bool, ZkError> {
+    let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+    let mut removed = match fs::remove_file(sidecar_path(image)) {
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e.into()),
+    };
+    let (program, args) = cryptsetup(root_cmd, &["luksDump", image_str]);
+    let dump = executor.run(&program, &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+    if !dump.status.success() {
+        return Err(failure("cryptsetup luksDump", &dump));
+    }
+    if let Some(id) = token_id(&String::from_utf8_lossy(&dump.stdout)) {
+        remove_token(executor, root_cmd, image_str, id)?;
+        removed = true;
+    }
+    Ok(removed)
+}
+
+/// The `0k-meta` token in the header of `image`; None if there is none or the header cannot
+/// be read (LUKS1 has no tokens).
+pub fn export<E: CommandExecutor + ?Sized>(executor: &E, image: &Path) -> Result<Option<MetaToken>, ZkError> {
+    let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+    let dump = executor.run("cryptsetup", &["luksDump", image_str])?;
+    if !dump.status.success() {
+        return Ok(None);
+    }
+    let Some(id) = token_id(&String::from_utf8_lossy(&dump.stdout)) else {
+        return Ok(None);
+    };
+    let id = id.to_string();
+    let output = executor.run("cryptsetup", &["token", "export", "--token-id", &id, image_str])?;
+    if !output.status.success() {
+        return Err(failure("cryptsetup token export", &output));
+    }
+    serde_json::from_slice(&output.stdout)
+        .map(Some)
+        .map_err(|e| ZkError::OperationFailed(format!("Invalid {} token in {}: {}", TOKEN_TYPE, image.display(), e)))
+}
+
+/// The payload digest recorded for `image`: its LUKS token, else its sidecar; None if
+/// neither exists.
+pub fn read<E: CommandExecutor + ?Sized>(executor: &E, image: &Path) -> Result<Option<PayloadDigest>, ZkError> {
+    let (token, source) = match export(executor, image)? {
+        Some(token) => (token, DigestSource::LuksToken),
+        None => {
+            let sidecar = sidecar_path(image);
+            let text = match fs::read_to_string(&sidecar) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let token = serde_json::from_str(&text)
+                .map_err(|e| ZkError::OperationFailed(format!("Invalid sidecar {}: {}", sidecar.display(), e)))?;
+            (token, DigestSource::Sidecar)
+        }
+    };
+    let MetaToken { payload_sha256, archive_id, .. } = token;
+    Ok(Some(PayloadDigest { payload_sha256, archive_id, source }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockCommandExecutor;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::{Arc, Mutex};
+
+    const LUKS2_DUMP: &str = "\
+LUKS header information
+Version:       \t2
+UUID:          \t5b1e2c7e-8f0a-4a7e-9d55-3c1f2a0b9e11
+
+Data segments:
+  0: crypt
+\toffset: 16777216 [bytes]
+
+Keyslots:
+  0: luks2
+\tKey:        512 bits
+";
+
+    fn output(code: i32, stdout: &str) -> std::io::Result<std::process::Output> {
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: b"token error".to_vec(),
+        })
+    }
+
+    /// luksDump of `LUKS2_DUMP` with a `Tokens:` section listing `tokens`
+    fn dump_with(tokens: &[(u32, &str)]) -> String {
+        let mut dump = LUKS2_DUMP.to_string();
+        if !tokens.is_empty() {
+            dump.push_str("Tokens:\n");
+            for (id, kind) in tokens {
+                dump.push_str(&format!("  {}: {}\n\tKeyslot:    0\n", id, kind));
+            }
+        }
+        dump.push_str("Digests:\n  0: pbkdf2\n");
+        dump
+    }
+
+    #[test]
+    fn test_token_id() {
+        assert_eq!(token_id(&dump_with(&[])), None);
+        assert_eq!(token_id(&dump_with(&[(0, "systemd-tpm2"), (3, "0k-meta")])), Some(3));
+        // Keyslot types are not tokens
+        assert_eq!(token_id("Keyslots:\n  0: 0k-meta\n"), None);
+    }
+
+    #[test]
+    fn test_payload_sha256_covers_bytes_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("mapper");
+        let mut payload = vec![0u8; 100];
+        payload[..4].copy_from_slice(SQUASHFS_MAGIC);
+        payload[BYTES_USED_OFFSET..BYTES_USED_OFFSET + 8].copy_from_slice(&100u64.to_le_bytes());
+        let mut padded = payload.clone();
+        padded.extend([0xffu8; 4000]);
+        fs::write(&device, &padded).unwrap();
+        let plain = dir.path().join("payload");
+        fs::write(&plain, &payload).unwrap();
+
+        assert_eq!(payload_sha256(&device).unwrap(), crate::checksums::sha256_file(&plain).unwrap());
+
+        fs::write(&device, vec![0u8; 100]).unwrap();
+        assert_eq!(payload_sha256(&device).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_store_and_read_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("data.sqfs_luks.img");
+        let token = MetaToken::new("ab".repeat(32), new_archive_id());
+
+        // What `token import` received stands in for the header: luksDump and `token export`
+        // answer from it
+        let imported: Arc<Mutex<Option<Vec<u8>>>> = Arc::default();
+        let mut mock = MockCommandExecutor::new();
+        let stored = imported.clone();
+        mock.expect_run()
+            .withf(|_, args: &[&str]| args.contains(&"luksDump"))
+            .returning(move |_, _| {
+                let tokens: &[(u32, &str)] = if stored.lock().unwrap().is_some() { &[(1, TOKEN_TYPE)] } else { &[] };
+                output(0, &dump_with(tokens))
+            });
+        let stored = imported.clone();
+        mock.expect_run_with_stdin()
+            .withf(|program, args: &[&str], _| program == "sudo" && args[..4] == ["cryptsetup", "token", "import", "--json-file"])
+            .times(1)
+            .returning(move |_, _, input| {
+                *stored.lock().unwrap() = Some(input.to_vec());
+                output(0, "")
+            });
+        let stored = imported.clone();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[..4] == ["token", "export", "--token-id", "1"])
+            .returning(move |_, _| output(0, &String::from_utf8(stored.lock().unwrap().clone().unwrap()).unwrap()));
+
+        assert_eq!(read(&mock, &image).unwrap(), None);
+        let source = store(&mock, &["sudo".to_string()], &image, &token).unwrap();
+        assert_eq!(source, DigestSource::LuksToken);
+        let json: serde_json::Value = serde_json::from_slice(&imported.lock().unwrap().clone().unwrap()).unwrap();
+        assert_eq!(json["type"], "0k-meta");
+        assert_eq!(json["keyslots"], serde_json::json!([]));
+
+        let digest = read(&mock, &image).unwrap().unwrap();
+        assert_eq!(digest.payload_sha256, token.payload_sha256);
+        assert_eq!(digest.archive_id, token.archive_id);
+        assert_eq!(digest.source, DigestSource::LuksToken);
+        assert!(!sidecar_path(&image).exists());
+    }
+
+    #[test]
+    fn test_store_replaces_earlier_token() {
+        let image = Path::new("/srv/data.sqfs_luks.img");
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[0] == "luksDump")
+            .returning(|_, _| output(0, &dump_with(&[(0, TOKEN_TYPE)])));
+        let mut seq = mockall::Sequence::new();
+        mock.expect_run()
+            .withf(|_, args: &[&str]| args == ["token", "remove", "--token-id", "0", "/srv/data.sqfs_luks.img"])
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| output(0, ""));
+        mock.expect_run_with_stdin()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| output(1, ""));
+        let e = store(&mock, &[], image, &MetaToken::new("0".repeat(64), "id".into())).unwrap_err();
+        assert!(e.to_string().contains("token import failed: token error"), "{}", e);
+    }
+
+    #[test]
+    fn test_luks1_uses_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("old.sqfs_luks.img");
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[0] == "luksDump")
+            .returning(|_, _| output(0, "LUKS header information\nVersion:       \t1\n"));
+        // No token commands at all
+        mock.expect_run_with_stdin().never();
+
+        let token = MetaToken::new("cd".repeat(32), "0123".into());
+        assert_eq!(store(&mock, &[], &image, &token).unwrap(), DigestSource::Sidecar);
+        let digest = read(&mock, &image).unwrap().unwrap();
+        assert_eq!((digest.payload_sha256, digest.source), (token.payload_sha256, DigestSource::Sidecar));
+
+        assert!(sidecar_path(&image).to_str().unwrap().ends_with("old.sqfs_luks.img.sha256.json"));
+
+        fs::write(sidecar_path(&image), "not json").unwrap();
+        assert!(read(&mock, &image).unwrap_err().to_string().contains("Invalid sidecar"));
+
+        assert!(remove(&mock, &[], &image).unwrap());
+        assert!(!sidecar_path(&image).exists());
+        assert!(!remove(&mock, &[], &image).unwrap());
+    }
+
+    #[test]
+    fn test_remove_token() {
+        let image = Path::new("/srv/data.sqfs_luks.img");
+        let mut mock = MockCommandExecutor::new();
+        let removed = Arc::new(Mutex::new(false));
+        let dumped = removed.clone();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "sudo" && args[..2] == ["cryptsetup", "luksDump"])
+            .returning(move |_, _| {
+                let tokens: &[(u32, &str)] = if *dumped.lock().unwrap() { &[] } else { &[(2, TOKEN_TYPE)] };
+                output(0, &dump_with(tokens))
+            });
+        let flag = removed.clone();
+        mock.expect_run()
+            .withf(|_, args: &[&str]| args == ["cryptsetup", "token", "remove", "--token-id", "2", "/srv/data.sqfs_luks.img"])
+            .times(1)
+            .returning(move |_, _| {
+                *flag.lock().unwrap() = true;
+                output(0, "")
+            });
+
+        let root_cmd = ["sudo".to_string()];
+        assert!(remove(&mock, &root_cmd, image).unwrap());
+        assert!(!remove(&mock, &root_cmd, image).unwrap());
+    }
+}
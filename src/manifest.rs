@@ -1,25 +1,48 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use serde::de::Error as SerdeError; // Import trait for .custom()
 use crate::error::ZkError;
 use std::fs;
+use std::os::unix::fs::MetadataExt;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Manifest format written by this version: 1 is every archive from before the field existed
+/// (legacy `original_path` entries as well as `name`/`restore_path` ones), 2 adds it.
+pub const MANIFEST_VERSION: u32 = 2;
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// `metadata.version` alone, read by [`Manifest::parse`] before the rest
+#[derive(Deserialize)]
+struct VersionPeek {
+    metadata: Option<VersionOnly>,
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    #[serde(default = "legacy_version")]
+    version: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum EntryType {
+    #[default]
     File,
     Directory,
     Symlink,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum PrivilegeMode {
     User,
     Root,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileEntry {
     pub id: u32,
 
@@ -29,10 +52,48 @@ pub struct FileEntry {
     // New format
     pub name: Option<String>,
     pub restore_path: Option<String>,
+    /// Name at the restore location when `--remap` renamed the entry itself; `name` still
+    /// names its copy in the archive. Never written to the manifest
+    #[serde(skip)]
+    pub restore_name: Option<String>,
 
     // Legacy format
     #[serde(skip_serializing_if = "Option::is_none")]
     pub original_path: Option<String>,
+
+    // Recorded at freeze time; absent in older archives
+    /// Size in bytes (regular files only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Modification time in Unix seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Permission bits (`st_mode & 0o7777`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Owner, restored by a root `unfreeze`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Mount point and filesystem type of the directory the entry was frozen from;
+    /// `unfreeze` will not write onto another filesystem unless told to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mount: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_fs: Option<String>,
+    /// SHA-256 of each regular file, keyed by its path below the entry (`.` for the entry
+    /// itself); only recorded by `freeze --checksums`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sha256: BTreeMap<String, String>,
+    /// Whether the archived copy is expected to be empty: an empty file or directory, or a
+    /// directory whose contents were all left out. Not recorded for symlinks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub empty: Option<bool>,
+    /// The target was a symlink frozen as what it points to (`-L`, `--dereference-target`);
+    /// check judges its referent
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dereferenced: bool,
 }
 
 impl FileEntry {
@@ -51,6 +112,8 @@ impl FileEntry {
             fs::symlink_metadata(&abs_path).map_err(ZkError::IoError)?
         };
 
+        let dereferenced = follow_links && fs::symlink_metadata(&abs_path).is_ok_and(|m| m.is_symlink());
+
         let entry_type = if metadata.is_symlink() {
             EntryType::Symlink
         } else if metadata.is_dir() {
@@ -77,15 +140,42 @@ impl FileEntry {
             )))?
             .to_string();
 
+        let size = if entry_type == EntryType::File { Some(metadata.len()) } else { None };
+        let source = abs_path.parent().and_then(|p| fs::canonicalize(p).ok()).and_then(|p| crate::mounts::mount_of(&p));
+        let (source_mount, source_fs) = match source {
+            Some((mount_point, fs_type)) => (mount_point.to_str().map(str::to_string), Some(fs_type)),
+            None => (None, None),
+        };
+
         Ok(FileEntry {
             id,
             entry_type,
             name: Some(name),
             restore_path: Some(restore_path),
+            restore_name: None,
             original_path: None,
+            size,
+            mtime: Some(metadata.mtime()),
+            mode: Some(metadata.mode() & 0o7777),
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            source_mount,
+            source_fs,
+            sha256: BTreeMap::new(),
+            empty: is_empty(&abs_path, &metadata),
+            dereferenced,
         })
     }
 
+    /// Where this entry is restored to (`restore_path/name`, or the legacy `original_path`)
+    pub fn destination(&self) -> Option<std::path::PathBuf> {
+        match (&self.restore_path, &self.name, &self.original_path) {
+            (Some(parent), Some(name), _) => Some(Path::new(parent).join(self.restore_name.as_ref().unwrap_or(name))),
+            (_, _, Some(orig)) => Some(orig.into()),
+            _ => None,
+        }
+    }
+
     pub fn validate(&self) -> Result<(), ZkError> {
         if let Some(name) = &self.name {
             if name == ".." || name == "." || name.contains('/') || name.contains('\0') {
@@ -111,13 +201,25 @@ impl FileEntry {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metadata {
+    /// Format version (see [`MANIFEST_VERSION`]); absent means 1
+    #[serde(default = "legacy_version")]
+    pub version: u32,
     pub date: String,
     pub host: String,
     // Optional for backward compatibility with legacy archives
     #[serde(skip_serializing_if = "Option::is_none")]
     pub privilege_mode: Option<PrivilegeMode>,
+    /// Live paths left out by `freeze --skip-unreadable` (absent from the archive on purpose)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_unreadable: Vec<String>,
+    /// Live paths left out by `freeze --exclude` (absent from the archive on purpose)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<String>,
+    /// Dangling symlink targets left out by `freeze --skip-broken-symlinks` (nothing to dereference)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_broken_symlinks: Vec<String>,
 }
 
 impl Metadata {
@@ -131,14 +233,18 @@ impl Metadata {
             .unwrap_or_else(|| "Unknown Date".to_string());
 
         Metadata {
+            version: MANIFEST_VERSION,
             date: date_str,
             host,
             privilege_mode: Some(privilege_mode),
+            skipped_unreadable: Vec::new(),
+            excluded: Vec::new(),
+            skipped_broken_symlinks: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
     pub metadata: Metadata,
     pub files: Vec<FileEntry>,
@@ -152,6 +258,43 @@ impl Manifest {
         }
     }
 
+    /// Reads and validates `list.yaml`. Refuses oversized files (YAML bombs) and versions
+    /// newer than [`MANIFEST_VERSION`], whose fields this build could misread.
+    pub fn load(path: &Path) -> Result<Self, ZkError> {
+        use std::io::Read;
+        let f = fs::File::open(path)?;
+        let size = f.metadata().map(|m| m.len()).unwrap_or(0);
+        if size > crate::constants::MANIFEST_MAX_SIZE {
+            return Err(ZkError::ManifestError(serde_yaml::Error::custom(format!(
+                "Manifest file too large ({} bytes). Maximum allowed: {} bytes",
+                size,
+                crate::constants::MANIFEST_MAX_SIZE
+            ))));
+        }
+        let mut yaml = String::new();
+        f.take(crate::constants::MANIFEST_MAX_SIZE).read_to_string(&mut yaml)?;
+        Self::parse(&yaml)
+    }
+
+    /// [`Manifest::load`] for a manifest read some other way (its size already checked).
+    pub fn parse(yaml: &str) -> Result<Self, ZkError> {
+        // The version first: a newer format may not parse into this build's types at all
+        if let Ok(VersionPeek { metadata: Some(VersionOnly { version }) }) = serde_yaml::from_str(yaml)
+            && version > MANIFEST_VERSION
+        {
+            return Err(ZkError::UnsupportedManifestVersion(version));
+        }
+        let manifest: Manifest = serde_yaml::from_str(yaml)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Writes the manifest to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), ZkError> {
+        serde_yaml::to_writer(fs::File::create(path)?, self)?;
+        Ok(())
+    }
+
     pub fn validate(&self) -> Result<(), ZkError> {
         use serde::de::Error;
         for entry in &self.files {
@@ -161,6 +304,33 @@ impl Manifest {
     }
 }
 
+/// Whether the file or directory at `path` (described by `metadata`) has no content: a
+/// zero-length file or a directory without children. None for anything else, or if the
+/// directory cannot be listed.
+pub fn is_empty(path: &Path, metadata: &fs::Metadata) -> Option<bool> {
+    if metadata.is_file() {
+        Some(metadata.len() == 0)
+    } else if metadata.is_dir() {
+        fs::read_dir(path).ok().map(|mut children| children.next().is_none())
+    } else {
+        None
+    }
+}
+
+/// Test entries: everything not given is unknown, as in an archive from before the field.
+#[cfg(test)]
+impl FileEntry {
+    /// A file entry `restore_path/name`
+    pub(crate) fn named(id: u32, name: &str, restore_path: &str) -> Self {
+        FileEntry { id, name: Some(name.into()), restore_path: Some(restore_path.into()), ..Default::default() }
+    }
+
+    /// A legacy file entry recorded by its `original_path`
+    pub(crate) fn legacy(id: u32, original_path: &str) -> Self {
+        FileEntry { id, original_path: Some(original_path.into()), ..Default::default() }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,6 +352,7 @@ files:
         
         // Ensure privilege_mode is None for legacy
         assert_eq!(manifest.metadata.privilege_mode, None);
+        assert_eq!(manifest.metadata.version, 1);
 
         if let Some(path) = &manifest.files[0].original_path {
             assert_eq!(path, "/home/user/data");
@@ -207,9 +378,66 @@ files:
 "#;
         let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
         assert_eq!(manifest.metadata.privilege_mode, Some(PrivilegeMode::User));
+        assert_eq!(manifest.metadata.version, 1);
         assert_eq!(manifest.files[0].id, 2);
         assert_eq!(manifest.files[0].name.as_ref().unwrap(), "docs");
         assert_eq!(manifest.files[0].entry_type, EntryType::File);
+        // Archives from before size/mtime were recorded still parse
+        assert_eq!(manifest.files[0].size, None);
+        assert_eq!(manifest.files[0].mtime, None);
+        assert_eq!((manifest.files[0].mode, manifest.files[0].uid, manifest.files[0].gid), (None, None, None));
+        assert!(manifest.files[0].sha256.is_empty());
+        assert_eq!(manifest.files[0].empty, None);
+        assert!(manifest.metadata.skipped_unreadable.is_empty());
+    }
+
+    #[test]
+    fn test_load_manifest_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("list.yaml");
+        let with_version = |version: &str| {
+            format!(
+                "metadata:\n{}  date: \"Tue Jan 27 08:09:58 PM +04 2026\"\n  host: \"katana\"\n\
+                 files:\n  - id: 1\n    name: \"docs\"\n    restore_path: \"/home/user\"\n    type: directory\n",
+                version
+            )
+        };
+
+        // No version: 1
+        fs::write(&path, with_version("")).unwrap();
+        assert_eq!(Manifest::load(&path).unwrap().metadata.version, 1);
+
+        // Written by freeze: the current version, read back as is
+        let manifest = Manifest::new(Metadata::new("katana".into(), PrivilegeMode::User), vec![]);
+        manifest.save(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("version: 2"));
+        assert_eq!(Manifest::load(&path).unwrap().metadata.version, MANIFEST_VERSION);
+
+        // From a newer 0k: refused rather than misread
+        fs::write(&path, with_version("  version: 3\n")).unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert!(matches!(err, ZkError::UnsupportedManifestVersion(3)), "{:?}", err);
+        assert!(err.to_string().contains("up to 2"), "{}", err);
+
+        // Even when its entries no longer parse as this build's
+        fs::write(&path, with_version("  version: 3\n").replace("type: directory", "type: [snapshot]")).unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert!(matches!(err, ZkError::UnsupportedManifestVersion(3)), "{:?}", err);
+        fs::write(&path, with_version("  version: 2\n").replace("type: directory", "type: [snapshot]")).unwrap();
+        assert!(matches!(Manifest::load(&path).unwrap_err(), ZkError::ManifestError(_)));
+    }
+
+    #[test]
+    fn test_skipped_unreadable_round_trip() {
+        let mut metadata = Metadata::new("katana".into(), PrivilegeMode::User);
+        let yaml = serde_yaml::to_string(&metadata).unwrap();
+        // Not written at all unless something was skipped
+        assert!(!yaml.contains("skipped_unreadable"));
+
+        metadata.skipped_unreadable = vec!["/home/user/docs/private".into()];
+        let yaml = serde_yaml::to_string(&metadata).unwrap();
+        let parsed: Metadata = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.skipped_unreadable, vec!["/home/user/docs/private".to_string()]);
     }
 
     #[test]
@@ -239,6 +467,103 @@ files: []
         assert_eq!(entry.restore_path.unwrap(), temp.path().to_string_lossy());
     }
 
+    #[test]
+    fn test_file_entry_records_size_and_mtime() {
+        let temp = tempfile::tempdir().unwrap();
+        let file_path = temp.path().join("data.bin");
+        std::fs::write(&file_path, b"12345").unwrap();
+        let expected_mtime = std::fs::metadata(&file_path).unwrap().mtime();
+
+        let entry = FileEntry::from_path(1, &file_path, false).unwrap();
+        assert_eq!(entry.size, Some(5));
+        assert_eq!(entry.mtime, Some(expected_mtime));
+
+        // Round-trip through YAML
+        let yaml = serde_yaml::to_string(&entry).unwrap();
+        let parsed: FileEntry = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.size, Some(5));
+        assert_eq!(parsed.mtime, Some(expected_mtime));
+
+        // No checksums unless freeze --checksums recorded them
+        assert!(!yaml.contains("sha256"));
+
+        // Directories have no size
+        let dir_entry = FileEntry::from_path(2, temp.path(), false).unwrap();
+        assert_eq!(dir_entry.size, None);
+        assert!(dir_entry.mtime.is_some());
+    }
+
+    #[test]
+    fn test_file_entry_records_mode_and_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let file_path = temp.path().join("script.sh");
+        std::fs::write(&file_path, b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&file_path, std::fs::Permissions::from_mode(0o4750)).unwrap();
+        let meta = std::fs::metadata(&file_path).unwrap();
+
+        let entry = FileEntry::from_path(1, &file_path, false).unwrap();
+        assert_eq!(entry.mode, Some(0o4750));
+        assert_eq!(entry.uid, Some(meta.uid()));
+        assert_eq!(entry.gid, Some(meta.gid()));
+        let source = crate::mounts::mount_of(&std::fs::canonicalize(temp.path()).unwrap());
+        assert_eq!(entry.source_mount, source.map(|(mount_point, _)| mount_point.display().to_string()));
+
+        let yaml = serde_yaml::to_string(&entry).unwrap();
+        let parsed: FileEntry = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!((parsed.mode, parsed.uid, parsed.gid), (entry.mode, entry.uid, entry.gid));
+    }
+
+    #[test]
+    fn test_file_entry_records_dereferenced_links() {
+        let temp = tempfile::tempdir().unwrap();
+        let file_path = temp.path().join("data.bin");
+        std::fs::write(&file_path, b"12345").unwrap();
+        let link = temp.path().join("link");
+        std::os::unix::fs::symlink(&file_path, &link).unwrap();
+
+        let followed = FileEntry::from_path(1, &link, true).unwrap();
+        assert_eq!(followed.entry_type, EntryType::File);
+        assert!(followed.dereferenced);
+        assert!(serde_yaml::to_string(&followed).unwrap().contains("dereferenced: true"));
+
+        let kept = FileEntry::from_path(2, &link, false).unwrap();
+        assert_eq!(kept.entry_type, EntryType::Symlink);
+        assert!(!kept.dereferenced);
+        assert!(!serde_yaml::to_string(&kept).unwrap().contains("dereferenced"));
+        // Following a regular file changes nothing
+        assert!(!FileEntry::from_path(3, &file_path, true).unwrap().dereferenced);
+    }
+
+    #[test]
+    fn test_file_entry_records_emptiness() {
+        let temp = tempfile::tempdir().unwrap();
+        let file_path = temp.path().join("data.bin");
+        std::fs::write(&file_path, b"12345").unwrap();
+        let empty_file = temp.path().join("empty.bin");
+        std::fs::File::create(&empty_file).unwrap();
+        let empty_dir = temp.path().join("empty");
+        std::fs::create_dir(&empty_dir).unwrap();
+        std::os::unix::fs::symlink(&file_path, temp.path().join("link")).unwrap();
+
+        assert_eq!(FileEntry::from_path(1, &file_path, false).unwrap().empty, Some(false));
+        assert_eq!(FileEntry::from_path(2, &empty_file, false).unwrap().empty, Some(true));
+        assert_eq!(FileEntry::from_path(3, &empty_dir, false).unwrap().empty, Some(true));
+        assert_eq!(FileEntry::from_path(4, temp.path(), false).unwrap().empty, Some(false));
+        assert_eq!(FileEntry::from_path(5, &temp.path().join("link"), false).unwrap().empty, None);
+    }
+
+    #[test]
+    fn test_sha256_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut entry = FileEntry::from_path(1, temp.path(), false).unwrap();
+        entry.sha256.insert("docs/a.txt".into(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into());
+
+        let yaml = serde_yaml::to_string(&entry).unwrap();
+        let parsed: FileEntry = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.sha256, entry.sha256);
+    }
+
     #[test]
     fn test_file_entry_from_dir() {
         let temp = tempfile::tempdir().unwrap();
@@ -257,60 +582,54 @@ files: []
         // Valid case
         let entry = FileEntry {
             id: 1,
-            entry_type: EntryType::File,
             name: Some("valid.txt".to_string()),
             restore_path: Some("/home/user".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(entry.validate().is_ok());
 
         // Invalid: .. in name
         let bad_name = FileEntry {
             id: 2,
-            entry_type: EntryType::File,
             name: Some("../bad.txt".to_string()),
             restore_path: Some("/home".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(bad_name.validate().is_err());
 
         // Valid: name with consecutive dots (not path traversal)
         let dots_name = FileEntry {
             id: 10,
-            entry_type: EntryType::File,
             name: Some("backup..2024.tar".to_string()),
             restore_path: Some("/home/user".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(dots_name.validate().is_ok(), "Names with consecutive dots should be valid");
 
         // Invalid: name is exactly ".."
         let dot_dot_name = FileEntry {
             id: 11,
-            entry_type: EntryType::File,
             name: Some("..".to_string()),
             restore_path: Some("/home/user".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(dot_dot_name.validate().is_err(), "Name '..' should be rejected");
 
         // Invalid: name is exactly "."
         let dot_name = FileEntry {
             id: 12,
-            entry_type: EntryType::File,
             name: Some(".".to_string()),
             restore_path: Some("/home/user".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(dot_name.validate().is_err(), "Name '.' should be rejected");
 
         // Invalid: .. in restore_path
         let bad_path = FileEntry {
             id: 3,
-            entry_type: EntryType::File,
             name: Some("ok.txt".to_string()),
             restore_path: Some("/home/../etc".to_string()),
-            original_path: None,
+            ..Default::default()
         };
         assert!(bad_path.validate().is_err());
     }
@@ -319,10 +638,9 @@ files: []
     fn test_manifest_validation() {
         let entry_ok = FileEntry {
             id: 1,
-            entry_type: EntryType::File,
             name: Some("ok".to_string()),
             restore_path: Some("/ok".to_string()),
-            original_path: None,
+            ..Default::default()
         };
 
         let manifest_ok = Manifest::new(
@@ -333,10 +651,9 @@ files: []
 
         let entry_bad = FileEntry {
             id: 2,
-            entry_type: EntryType::File,
             name: Some("../bad".to_string()),
             restore_path: Some("/ok".to_string()),
-            original_path: None,
+            ..Default::default()
         };
 
         let manifest_bad = Manifest::new(
@@ -0,0 +1,512 @@
+//! Discovery of the places an archive image is currently mounted.
+//!
+//! An image can be mounted three ways, all of which are found by [`find_mounts_for_image`]:
+//!
+//! - `squashfuse IMAGE MOUNTPOINT` (plain archives, `0k-core mount` without root);
+//! - a LUKS container: loop device -> dm-crypt mapper (`/dev/mapper/zrklv...`) -> mount;
+//! - a kernel loop mount of a plain image (`mount -o loop IMAGE MOUNTPOINT`).
+//!
+//! Only world-readable sources are used (`/proc/<pid>/cmdline`, `/proc/mounts`, `/sys/block`),
+//! so no root is needed to ask.
+//!
+//! Where /proc is not mounted (minimal containers, early boot) the mount table comes from
+//! `/etc/mtab` instead, but squashfuse processes cannot be found at all: [`find_mounts_for_image`]
+//! warns and returns what it could see, [`try_find_mounts_for_image`] fails with
+//! [`ZkError::ProcUnavailable`].
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use zero_kelvin::mounts::{self, MountBackend};
+//!
+//! let image = Path::new("/backups/docs.sqfs");
+//! if !mounts::is_mounted(image) {
+//!     println!("not mounted");
+//! }
+//! for m in mounts::find_mounts_for_image(image) {
+//!     match m.backend {
+//!         MountBackend::LuksMapper(mapper) => println!("{} (LUKS, {})", m.mount_point.display(), mapper),
+//!         _ => println!("{}", m.mount_point.display()),
+//!     }
+//! }
+//! ```
+//!
+//! Tests (and other callers that want to look at a snapshot) can pass their own [`ProcReader`]
+//! to [`find_mounts_for_image_with`].
+
+use crate::constants::PROC_SCAN_LIMIT;
+use crate::error::ZkError;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// How an image is mounted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountBackend {
+    /// A `squashfuse` process serves the mount
+    Squashfuse,
+    /// Mounted from a device-mapper target (the mapper name) on top of a loop device
+    LuksMapper(String),
+    /// Mounted straight from a loop device
+    KernelLoop,
+}
+
+impl std::fmt::Display for MountBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MountBackend::Squashfuse => write!(f, "squashfuse"),
+            MountBackend::LuksMapper(name) => write!(f, "luks:{}", name),
+            MountBackend::KernelLoop => write!(f, "loop"),
+        }
+    }
+}
+
+/// One place an image is mounted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub mount_point: PathBuf,
+    pub backend: MountBackend,
+}
+
+/// Read access to `/proc` and `/sys`, so discovery can run against fixture content.
+pub trait ProcReader {
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String>;
+    /// Names of the entries of a directory (unsorted)
+    fn list_dir(&self, path: &Path) -> std::io::Result<Vec<String>>;
+    /// `(source, mount point)` of every mount in the mount table; only asked where
+    /// `/proc/mounts` cannot be read
+    fn mntent(&self) -> std::io::Result<Vec<(String, String)>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Reads the live system.
+pub struct SystemReader;
+
+impl ProcReader for SystemReader {
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> std::io::Result<Vec<String>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    fn mntent(&self) -> std::io::Result<Vec<(String, String)>> {
+        read_mntent(Path::new("/etc/mtab"))
+    }
+}
+
+/// `(source, mount point)` of every entry of a mount table in fstab(5) format, read with
+/// getmntent(3) (which also undoes the `\040` escapes).
+pub fn read_mntent(path: &Path) -> std::io::Result<Vec<(String, String)>> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    // SAFETY: both arguments are NUL-terminated strings; the stream is closed below
+    let stream = unsafe { libc::setmntent(path.as_ptr(), c"r".as_ptr()) };
+    if stream.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut entries = Vec::new();
+    loop {
+        // SAFETY: the stream is open; the entry is only valid until the next call, so it is
+        // copied out right away
+        let entry = unsafe { libc::getmntent(stream) };
+        if entry.is_null() {
+            break;
+        }
+        let (source, dir) = unsafe { (CStr::from_ptr((*entry).mnt_fsname), CStr::from_ptr((*entry).mnt_dir)) };
+        entries.push((source.to_string_lossy().into_owned(), dir.to_string_lossy().into_owned()));
+    }
+    // SAFETY: opened by setmntent above and not used afterwards
+    unsafe { libc::endmntent(stream) };
+    Ok(entries)
+}
+
+/// All current mounts of `image` on this system (empty if it is not mounted).
+pub fn find_mounts_for_image(image: &Path) -> Vec<MountInfo> {
+    find_mounts_for_image_with(&SystemReader, image)
+}
+
+/// True if `image` is mounted anywhere.
+pub fn is_mounted(image: &Path) -> bool {
+    !find_mounts_for_image(image).is_empty()
+}
+
+/// [`find_mounts_for_image`] against the given reader. Where /proc is not available this
+/// warns on stderr and returns the mounts that could still be found.
+pub fn find_mounts_for_image_with<R: ProcReader>(reader: &R, image: &Path) -> Vec<MountInfo> {
+    let (found, problem) = discover(reader, image);
+    if let Some(e) = problem {
+        eprintln!("Warning: {}", e);
+    }
+    found
+}
+
+/// All current mounts of `image`, or [`ZkError::ProcUnavailable`] if some kinds of mount
+/// cannot be looked for on this system (an empty list then would not mean "not mounted").
+pub fn try_find_mounts_for_image(image: &Path) -> Result<Vec<MountInfo>, ZkError> {
+    try_find_mounts_for_image_with(&SystemReader, image)
+}
+
+/// [`try_find_mounts_for_image`] against the given reader.
+pub fn try_find_mounts_for_image_with<R: ProcReader>(reader: &R, image: &Path) -> Result<Vec<MountInfo>, ZkError> {
+    match discover(reader, image) {
+        (_, Some(e)) => Err(e),
+        (found, None) => Ok(found),
+    }
+}
+
+/// Every mount point on the system: `/proc/self/mountinfo`, or the mount table where /proc
+/// is not mounted.
+pub fn mount_points() -> Result<Vec<PathBuf>, ZkError> {
+    mount_points_with(&SystemReader)
+}
+
+/// [`mount_points`] against the given reader.
+pub fn mount_points_with<R: ProcReader>(reader: &R) -> Result<Vec<PathBuf>, ZkError> {
+    if let Ok(mountinfo) = reader.read_to_string(Path::new("/proc/self/mountinfo")) {
+        // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw: field 4 is the mount point
+        return Ok(mountinfo
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(4))
+            .map(|field| PathBuf::from(crate::utils::unescape_mountinfo_octal(field)))
+            .collect());
+    }
+    match mount_table(reader) {
+        Ok(table) => Ok(table.into_iter().map(|(_, dir)| PathBuf::from(dir)).collect()),
+        Err(e) => Err(ZkError::ProcUnavailable(format!(
+            "cannot read /proc/self/mountinfo or /etc/mtab ({}), so active mounts cannot be listed",
+            e
+        ))),
+    }
+}
+
+/// `(source, mount point)` from `/proc/mounts`, else from the reader's mount table.
+fn mount_table<R: ProcReader>(reader: &R) -> std::io::Result<Vec<(String, String)>> {
+    let Ok(mounts) = reader.read_to_string(Path::new("/proc/mounts")) else {
+        return reader.mntent();
+    };
+    Ok(mounts
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let source = crate::utils::unescape_mountinfo_octal(parts.next()?);
+            Some((source, crate::utils::unescape_mountinfo_octal(parts.next()?)))
+        })
+        .collect())
+}
+
+/// The mounts of `image` that could be found, and what could not be looked at.
+fn discover<R: ProcReader>(reader: &R, image: &Path) -> (Vec<MountInfo>, Option<ZkError>) {
+    let image = fs::canonicalize(image).unwrap_or_else(|_| image.to_path_buf());
+    let mut problems = Vec::new();
+    let mut found = find_squashfuse_mounts(reader, &image).unwrap_or_else(|e| {
+        problems.push(e);
+        Vec::new()
+    });
+
+    let table = mount_table(reader).unwrap_or_else(|e| {
+        problems.push(format!(
+            "cannot read /proc/mounts or /etc/mtab ({}), so LUKS and loop mounts cannot be found",
+            e
+        ));
+        Vec::new()
+    });
+    for (source, mount_point) in table {
+        let mount_point = PathBuf::from(mount_point);
+        let backend = if let Some(loop_name) = source.strip_prefix("/dev/").filter(|n| n.starts_with("loop")) {
+            loop_backs_image(reader, loop_name, &image).then_some(MountBackend::KernelLoop)
+        } else if let Some(mapper) = source.strip_prefix("/dev/mapper/") {
+            dm_device_for_mapper(reader, mapper)
+                .filter(|dm| dm_backs_image(reader, dm, &image))
+                .map(|_| MountBackend::LuksMapper(mapper.to_string()))
+        } else {
+            None
+        };
+
+        if let Some(backend) = backend {
+            found.push(MountInfo { mount_point, backend });
+        }
+    }
+    let problem = (!problems.is_empty()).then(|| ZkError::ProcUnavailable(problems.join("; ")));
+    (found, problem)
+}
+
+/// Mount point and filesystem type (`ext4`, `autofs`, ...) of the mount `path` (canonical) is
+/// on. Unlike st_dev, both stay the same across reboots and machines.
+pub fn mount_of(path: &Path) -> Option<(PathBuf, String)> {
+    mount_of_with(&SystemReader, path)
+}
+
+/// [`mount_of`] against the given reader: the deepest mount point of `/proc/self/mountinfo`
+/// above `path` (the last one, if mounts are stacked there), and the type after the ` - `
+/// separator.
+pub fn mount_of_with<R: ProcReader>(reader: &R, path: &Path) -> Option<(PathBuf, String)> {
+    let mountinfo = reader.read_to_string(Path::new("/proc/self/mountinfo")).ok()?;
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (fields, rest) = line.split_once(" - ")?;
+            let mount_point = PathBuf::from(crate::utils::unescape_mountinfo_octal(fields.split_whitespace().nth(4)?));
+            let fs_type = rest.split_whitespace().next()?;
+            path.starts_with(&mount_point).then(|| (mount_point, fs_type.to_string()))
+        })
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+}
+
+/// squashfuse [options] IMAGE MOUNTPOINT: the argument after the image is the mount point.
+/// Fails (with the reason) if /proc cannot be listed.
+fn find_squashfuse_mounts<R: ProcReader>(reader: &R, image: &Path) -> Result<Vec<MountInfo>, String> {
+    let mut found = Vec::new();
+    let pids = reader
+        .list_dir(Path::new("/proc"))
+        .map_err(|e| format!("cannot list /proc ({}), so squashfuse mounts cannot be found", e))?;
+
+    let mut scan_count = 0;
+    for pid in pids.iter().filter(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())) {
+        // DoS protection: limit number of processes scanned
+        scan_count += 1;
+        if scan_count > PROC_SCAN_LIMIT {
+            eprintln!("Warning: /proc scan limit ({}) reached, some mounts may not be found", PROC_SCAN_LIMIT);
+            break;
+        }
+        let Ok(cmdline) = reader.read_to_string(&Path::new("/proc").join(pid).join("cmdline")) else {
+            continue;
+        };
+        let args: Vec<&str> = cmdline.split('\0').collect();
+        if !args[0].contains("squashfuse") {
+            continue;
+        }
+        for (i, arg) in args.iter().enumerate().skip(1) {
+            if arg.is_empty() || arg.starts_with('-') || !same_file(Path::new(arg), image) {
+                continue;
+            }
+            if let Some(mount_point) = args.get(i + 1).filter(|m| !m.is_empty() && !m.starts_with('-')) {
+                found.push(MountInfo {
+                    mount_point: PathBuf::from(mount_point),
+                    backend: MountBackend::Squashfuse,
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Compares by canonical path (relative paths, symlinks), falling back to the plain path
+/// when the candidate cannot be resolved.
+fn same_file(candidate: &Path, image: &Path) -> bool {
+    match fs::canonicalize(candidate) {
+        Ok(c) => c == image,
+        Err(_) => candidate == image,
+    }
+}
+
+/// /sys/block/loopN/loop/backing_file names the file behind a loop device.
+fn loop_backs_image<R: ProcReader>(reader: &R, loop_name: &str, image: &Path) -> bool {
+    let path = Path::new("/sys/block").join(loop_name).join("loop/backing_file");
+    match reader.read_to_string(&path) {
+        Ok(backing) => {
+            let backing = backing.trim_end_matches('\n');
+            same_file(Path::new(backing.trim_end_matches(" (deleted)")), image)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Finds the dm-N device whose /sys/block/dm-N/dm/name is `mapper`.
+fn dm_device_for_mapper<R: ProcReader>(reader: &R, mapper: &str) -> Option<String> {
+    reader
+        .list_dir(Path::new("/sys/block"))
+        .ok()?
+        .into_iter()
+        .filter(|dev| dev.starts_with("dm-"))
+        .find(|dev| {
+            reader
+                .read_to_string(&Path::new("/sys/block").join(dev).join("dm/name"))
+                .is_ok_and(|name| name.trim() == mapper)
+        })
+}
+
+/// True if one of the devices under the mapper (/sys/block/dm-N/slaves) is a loop over `image`.
+fn dm_backs_image<R: ProcReader>(reader: &R, dm: &str, image: &Path) -> bool {
+    reader
+        .list_dir(&Path::new("/sys/block").join(dm).join("slaves"))
+        .unwrap_or_default()
+        .iter()
+        .any(|slave| slave.starts_with("loop") && loop_backs_image(reader, slave, image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Fixture /proc and /sys: file contents by path; directories are implied by the paths.
+    #[derive(Default)]
+    struct FixtureReader {
+        files: BTreeMap<PathBuf, String>,
+    }
+
+    impl FixtureReader {
+        fn with(mut self, path: &str, content: &str) -> Self {
+            self.files.insert(PathBuf::from(path), content.to_string());
+            self
+        }
+    }
+
+    impl ProcReader for FixtureReader {
+        fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+            self.files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+        }
+
+        fn list_dir(&self, path: &Path) -> std::io::Result<Vec<String>> {
+            let mut names: Vec<String> = self
+                .files
+                .keys()
+                .filter_map(|p| p.strip_prefix(path).ok())
+                .filter_map(|rel| rel.components().next())
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            names.dedup();
+            if names.is_empty() {
+                return Err(std::io::ErrorKind::NotFound.into());
+            }
+            Ok(names)
+        }
+
+        /// A fixture "/etc/mtab", as getmntent would return it
+        fn mntent(&self) -> std::io::Result<Vec<(String, String)>> {
+            Ok(self
+                .read_to_string(Path::new("/etc/mtab"))?
+                .lines()
+                .filter_map(|line| {
+                    let mut parts = line.split_whitespace();
+                    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+                })
+                .collect())
+        }
+    }
+
+    const IMAGE: &str = "/nonexistent/backups/docs.sqfs";
+
+    #[test]
+    fn test_mount_of() {
+        let reader = FixtureReader::default().with(
+            "/proc/self/mountinfo",
+            "22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n\
+             48 22 0:44 / /home rw,relatime shared:24 - autofs systemd-1 rw,fd=47,direct\n\
+             51 48 0:52 / /home rw,relatime shared:30 - btrfs /dev/sda1 rw\n\
+             60 22 0:60 / /mnt/my\\040disk rw - vfat /dev/sdb1 rw\n",
+        );
+        let mount = |path: &str| mount_of_with(&reader, Path::new(path)).map(|(m, t)| (m.display().to_string(), t));
+        assert_eq!(mount("/etc/fstab"), Some(("/".to_string(), "ext4".to_string())));
+        // Stacked on /home: the last one is what is seen there
+        assert_eq!(mount("/home/user"), Some(("/home".to_string(), "btrfs".to_string())));
+        assert_eq!(mount("/homework"), Some(("/".to_string(), "ext4".to_string())));
+        assert_eq!(mount("/mnt/my disk/x"), Some(("/mnt/my disk".to_string(), "vfat".to_string())));
+        assert_eq!(mount_of_with(&FixtureReader::default(), Path::new("/etc")), None);
+    }
+
+    #[test]
+    fn test_squashfuse_mount() {
+        let reader = FixtureReader::default()
+            .with("/proc/100/cmdline", &format!("squashfuse\0-o\0nonempty\0{}\0/mnt/docs\0", IMAGE))
+            .with("/proc/200/cmdline", "squashfuse\0/nonexistent/other.sqfs\0/mnt/other\0")
+            .with("/proc/self/cmdline", &format!("squashfuse\0{}\0/mnt/ignored\0", IMAGE));
+
+        let found = find_mounts_for_image_with(&reader, Path::new(IMAGE));
+        assert_eq!(
+            found,
+            vec![MountInfo { mount_point: PathBuf::from("/mnt/docs"), backend: MountBackend::Squashfuse }]
+        );
+    }
+
+    #[test]
+    fn test_luks_mapper_mount() {
+        let reader = FixtureReader::default()
+            .with("/proc/mounts", "/dev/mapper/zrklvdocs_1a2b3c4d /mnt/secret\\040docs squashfs ro 0 0\n")
+            .with("/sys/block/dm-0/dm/name", "other\n")
+            .with("/sys/block/dm-3/dm/name", "zrklvdocs_1a2b3c4d\n")
+            .with("/sys/block/dm-3/slaves/loop7/dev", "7:7\n")
+            .with("/sys/block/loop7/loop/backing_file", &format!("{}\n", IMAGE));
+
+        let found = find_mounts_for_image_with(&reader, Path::new(IMAGE));
+        assert_eq!(
+            found,
+            vec![MountInfo {
+                mount_point: PathBuf::from("/mnt/secret docs"),
+                backend: MountBackend::LuksMapper("zrklvdocs_1a2b3c4d".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_kernel_loop_mount_and_unrelated() {
+        let reader = FixtureReader::default()
+            .with(
+                "/proc/mounts",
+                "/dev/sda1 / ext4 rw 0 0\n/dev/loop2 /mnt/other squashfs ro 0 0\n/dev/loop3 /mnt/docs squashfs ro 0 0\n",
+            )
+            .with("/sys/block/loop2/loop/backing_file", "/nonexistent/other.sqfs\n")
+            .with("/sys/block/loop3/loop/backing_file", &format!("{} (deleted)\n", IMAGE));
+
+        let found = find_mounts_for_image_with(&reader, Path::new(IMAGE));
+        assert_eq!(
+            found,
+            vec![MountInfo { mount_point: PathBuf::from("/mnt/docs"), backend: MountBackend::KernelLoop }]
+        );
+        assert!(find_mounts_for_image_with(&reader, Path::new("/nonexistent/third.sqfs")).is_empty());
+    }
+
+    #[test]
+    fn test_without_proc() {
+        // Only /sys and a static mount table: loop mounts are still found, squashfuse ones cannot be
+        let reader = FixtureReader::default()
+            .with("/etc/mtab", "/dev/sda1 / ext4 rw 0 0\n/dev/loop3 /mnt/docs squashfs ro 0 0\n")
+            .with("/sys/block/loop3/loop/backing_file", &format!("{}\n", IMAGE));
+        let loop_mount = MountInfo { mount_point: PathBuf::from("/mnt/docs"), backend: MountBackend::KernelLoop };
+
+        assert_eq!(find_mounts_for_image_with(&reader, Path::new(IMAGE)), vec![loop_mount]);
+        match try_find_mounts_for_image_with(&reader, Path::new(IMAGE)) {
+            Err(ZkError::ProcUnavailable(msg)) => assert!(msg.contains("squashfuse mounts cannot be found"), "{}", msg),
+            other => panic!("expected ProcUnavailable, got {:?}", other),
+        }
+        assert_eq!(mount_points_with(&reader).unwrap(), vec![PathBuf::from("/"), PathBuf::from("/mnt/docs")]);
+
+        // Nothing at all
+        let empty = FixtureReader::default();
+        assert!(matches!(mount_points_with(&empty), Err(ZkError::ProcUnavailable(_))));
+        match try_find_mounts_for_image_with(&empty, Path::new(IMAGE)) {
+            Err(ZkError::ProcUnavailable(msg)) => assert!(msg.contains("LUKS and loop mounts"), "{}", msg),
+            other => panic!("expected ProcUnavailable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mount_points_from_mountinfo() {
+        let reader = FixtureReader::default().with(
+            "/proc/self/mountinfo",
+            "22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n\
+             90 22 0:50 / /tmp/0k\\040cache rw - fuse.squashfuse squashfuse ro\n",
+        );
+        assert_eq!(mount_points_with(&reader).unwrap(), vec![PathBuf::from("/"), PathBuf::from("/tmp/0k cache")]);
+    }
+
+    #[test]
+    fn test_read_mntent() {
+        let dir = tempfile::tempdir().unwrap();
+        let mtab = dir.path().join("mtab");
+        fs::write(&mtab, "/dev/sda1 / ext4 rw 0 0\n# comment\n/dev/loop3 /mnt/my\\040docs squashfs ro 0 0\n").unwrap();
+        assert_eq!(
+            read_mntent(&mtab).unwrap(),
+            vec![("/dev/sda1".into(), "/".into()), ("/dev/loop3".into(), "/mnt/my docs".into())]
+        );
+        assert!(read_mntent(&dir.path().join("missing")).is_err());
+    }
+}
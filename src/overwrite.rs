@@ -0,0 +1,197 @@
+//! What `0k-core create` does when its output already exists.
+//!
+//! The answer depends on whether the output exists, what it is (plain SquashFS, LUKS
+//! container, anything else) and three flags (`-e`, `--overwrite-files`,
+//! `--overwrite-luks-content`). [`OverwritePolicy::resolve`] turns them into one [`Action`]
+//! before anything is written; the plain and encrypted packing flows only act on it.
+
+use crate::error::ZkError;
+use std::path::Path;
+
+/// What an existing output is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    /// A plain SquashFS archive
+    Plain,
+    Luks,
+    /// Anything else: never written to
+    Other,
+}
+
+impl OutputKind {
+    /// Probes the existing file at `path` (the LUKS header magic, then the SquashFS magic).
+    pub fn detect(path: &Path) -> Self {
+        if crate::utils::is_luks_image(path) {
+            OutputKind::Luks
+        } else if matches!(crate::utils::get_file_type(path), Ok(crate::utils::ArchiveType::Squashfs)) {
+            OutputKind::Plain
+        } else {
+            OutputKind::Other
+        }
+    }
+}
+
+/// The `create` flags that decide what happens to an existing output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverwritePolicy {
+    /// `-e/--encrypt`
+    pub encrypt: bool,
+    /// `--overwrite-files`: append to the existing archive
+    pub overwrite_files: bool,
+    /// `--overwrite-luks-content`: replace the payload of the existing LUKS container
+    pub overwrite_luks_content: bool,
+}
+
+/// What `create` does with its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing there: a new archive (the overwrite flags do not matter)
+    CreateNew,
+    /// mksquashfs appends to the plain archive
+    AppendPlain,
+    /// The container is opened and mksquashfs appends to its payload
+    AppendLuks,
+    /// The container is opened and its payload is packed anew (`-noappend`)
+    ReplaceLuksPayload,
+    Refuse(Refusal),
+}
+
+/// Why an existing output is left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Neither overwrite flag was given
+    Exists(OutputKind),
+    /// Neither a SquashFS archive nor a LUKS container
+    NotAnArchive,
+    /// `--overwrite-luks-content` on a plain archive
+    ContentNeedsLuks,
+    /// `-e` with `--overwrite-files` on a plain archive
+    PlainWithEncrypt,
+    /// An overwrite flag on a LUKS container without `-e`
+    LuksWithoutEncrypt,
+    /// Both overwrite flags on a LUKS container: append or replace?
+    BothFlags,
+}
+
+impl OverwritePolicy {
+    /// The action for an output that `exists` as `kind` (ignored when it does not exist).
+    pub fn resolve(&self, exists: bool, kind: OutputKind) -> Action {
+        if !exists {
+            return Action::CreateNew;
+        }
+        let refuse = Action::Refuse;
+        match kind {
+            OutputKind::Other => refuse(Refusal::NotAnArchive),
+            OutputKind::Plain if self.overwrite_luks_content => refuse(Refusal::ContentNeedsLuks),
+            OutputKind::Plain if !self.overwrite_files => refuse(Refusal::Exists(kind)),
+            OutputKind::Plain if self.encrypt => refuse(Refusal::PlainWithEncrypt),
+            OutputKind::Plain => Action::AppendPlain,
+            OutputKind::Luks => match (self.overwrite_files, self.overwrite_luks_content) {
+                (false, false) => refuse(Refusal::Exists(kind)),
+                _ if !self.encrypt => refuse(Refusal::LuksWithoutEncrypt),
+                (true, true) => refuse(Refusal::BothFlags),
+                (true, false) => Action::AppendLuks,
+                (false, true) => Action::ReplaceLuksPayload,
+            },
+        }
+    }
+}
+
+impl Refusal {
+    /// The error `create` fails with for `output`: what is there and which flags would make
+    /// the operation legal.
+    pub fn error(&self, output: &Path) -> ZkError {
+        let output = output.display();
+        let message = match self {
+            Refusal::Exists(OutputKind::Luks) => format!(
+                "Output {} exists and is a LUKS container.\nUse -e --overwrite-files to append inside it, \
+                 or -e --overwrite-luks-content to replace its payload.",
+                output
+            ),
+            Refusal::Exists(_) => format!(
+                "Output {} exists and is a plain SquashFS archive.\nUse --overwrite-files (without -e) to append to it.",
+                output
+            ),
+            Refusal::NotAnArchive => format!(
+                "Output {} exists but is neither a SquashFS archive nor a LUKS container; no flag updates it.\n\
+                 Remove it or choose another output.",
+                output
+            ),
+            Refusal::ContentNeedsLuks => {
+                return ZkError::LuksError(format!(
+                    "--overwrite-luks-content requires a LUKS container, but {} is a plain SquashFS archive.\n\
+                     Use --overwrite-files (without -e) to append to it.",
+                    output
+                ));
+            }
+            Refusal::PlainWithEncrypt => format!(
+                "Output {} is a plain SquashFS archive and cannot be appended to encrypted.\n\
+                 Use --overwrite-files without -e to append to it unencrypted.",
+                output
+            ),
+            Refusal::LuksWithoutEncrypt => format!(
+                "Output {} is a LUKS container: add -e/--encrypt to update it.",
+                output
+            ),
+            Refusal::BothFlags => format!(
+                "--overwrite-files appends inside {} and --overwrite-luks-content replaces its payload: use only one of them.",
+                output
+            ),
+        };
+        ZkError::OperationFailed(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_truth_table() {
+        use Action::*;
+        use OutputKind::*;
+        let refuse = |r| Refuse(r);
+        // (kind, encrypt, overwrite_files, overwrite_luks_content) -> action, for an existing output
+        let cases = [
+            (Plain, false, false, false, refuse(Refusal::Exists(Plain))),
+            (Plain, false, true, false, AppendPlain),
+            (Plain, false, false, true, refuse(Refusal::ContentNeedsLuks)),
+            (Plain, false, true, true, refuse(Refusal::ContentNeedsLuks)),
+            (Plain, true, false, false, refuse(Refusal::Exists(Plain))),
+            (Plain, true, true, false, refuse(Refusal::PlainWithEncrypt)),
+            (Plain, true, false, true, refuse(Refusal::ContentNeedsLuks)),
+            (Plain, true, true, true, refuse(Refusal::ContentNeedsLuks)),
+            (Luks, false, false, false, refuse(Refusal::Exists(Luks))),
+            (Luks, false, true, false, refuse(Refusal::LuksWithoutEncrypt)),
+            (Luks, false, false, true, refuse(Refusal::LuksWithoutEncrypt)),
+            (Luks, false, true, true, refuse(Refusal::LuksWithoutEncrypt)),
+            (Luks, true, false, false, refuse(Refusal::Exists(Luks))),
+            (Luks, true, true, false, AppendLuks),
+            (Luks, true, false, true, ReplaceLuksPayload),
+            (Luks, true, true, true, refuse(Refusal::BothFlags)),
+        ];
+        for (kind, encrypt, overwrite_files, overwrite_luks_content, expected) in cases {
+            let policy = OverwritePolicy { encrypt, overwrite_files, overwrite_luks_content };
+            assert_eq!(policy.resolve(true, kind), expected, "{:?} {:?}", kind, policy);
+            // Nothing there: always a new archive
+            assert_eq!(policy.resolve(false, kind), CreateNew);
+            assert_eq!(policy.resolve(false, Other), CreateNew);
+            // Anything else is never written to
+            assert_eq!(policy.resolve(true, Other), refuse(Refusal::NotAnArchive));
+        }
+    }
+
+    #[test]
+    fn test_refusals_name_the_flags() {
+        let output = Path::new("/backup/a.sqfs");
+        let message = |r: Refusal| r.error(output).to_string();
+        assert!(message(Refusal::Exists(OutputKind::Plain)).contains("--overwrite-files (without -e)"));
+        let luks = message(Refusal::Exists(OutputKind::Luks));
+        assert!(luks.contains("-e --overwrite-files") && luks.contains("-e --overwrite-luks-content"), "{}", luks);
+        assert!(message(Refusal::LuksWithoutEncrypt).contains("add -e/--encrypt"));
+        assert!(message(Refusal::PlainWithEncrypt).contains("--overwrite-files without -e"));
+        assert!(message(Refusal::NotAnArchive).contains("no flag updates it"));
+        assert!(message(Refusal::BothFlags).contains("only one of them"));
+        assert!(matches!(Refusal::ContentNeedsLuks.error(output), ZkError::LuksError(_)));
+    }
+}
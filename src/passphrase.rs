@@ -0,0 +1,237 @@
+//! LUKS passphrases when there is no terminal for cryptsetup to prompt on (a systemd service,
+//! a timer): the passphrase is asked through an agent and fed to cryptsetup on stdin
+//! (`--key-file=-`), never on its command line.
+//!
+//! The agent is chosen by `passphrase_agent` in the config file (see [`crate::config`]):
+//! `auto` (default: `systemd-ask-password` if installed, else the `pinentry` program if
+//! installed), `systemd-ask-password`, `pinentry`, or `tty` (never use an agent). With a
+//! terminal on stdin cryptsetup always prompts itself.
+
+use crate::config::Config;
+use crate::error::ZkError;
+use crate::executor::CommandExecutor;
+
+const SYSTEMD_ASK_PASSWORD: &str = "systemd-ask-password";
+const DEFAULT_PINENTRY: &str = "pinentry";
+
+/// How a passphrase is obtained.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Agent {
+    /// cryptsetup prompts on the terminal (or reads its stdin) as usual
+    Tty,
+    /// `systemd-ask-password`: works under systemd with or without a terminal
+    SystemdAskPassword,
+    /// A pinentry program (Assuan protocol on stdin/stdout)
+    Pinentry(String),
+}
+
+impl Agent {
+    /// The agent to use. With a terminal on stdin this is always [`Agent::Tty`].
+    pub fn select(config: &Config, stdin_is_terminal: bool) -> Result<Agent, ZkError> {
+        Self::select_with(config, stdin_is_terminal, |program| which::which(program).is_ok())
+    }
+
+    fn select_with(
+        config: &Config,
+        stdin_is_terminal: bool,
+        installed: impl Fn(&str) -> bool,
+    ) -> Result<Agent, ZkError> {
+        if stdin_is_terminal {
+            return Ok(Agent::Tty);
+        }
+        let pinentry = config.pinentry.clone().unwrap_or_else(|| DEFAULT_PINENTRY.to_string());
+        match config.passphrase_agent.as_deref().unwrap_or("auto") {
+            "auto" if installed(SYSTEMD_ASK_PASSWORD) => Ok(Agent::SystemdAskPassword),
+            "auto" if installed(&pinentry) => Ok(Agent::Pinentry(pinentry)),
+            "auto" | "tty" => Ok(Agent::Tty),
+            "systemd-ask-password" => Ok(Agent::SystemdAskPassword),
+            "pinentry" => Ok(Agent::Pinentry(pinentry)),
+            other => Err(ZkError::OperationFailed(format!(
+                "Unknown passphrase_agent '{}' in {} (expected auto, systemd-ask-password, pinentry or tty)",
+                other,
+                crate::config::config_path().display()
+            ))),
+        }
+    }
+
+    /// Asks for the passphrase of an existing container. None for [`Agent::Tty`].
+    pub fn ask(&self, executor: &impl CommandExecutor, description: &str) -> Result<Option<String>, ZkError> {
+        match self {
+            Agent::Tty => Ok(None),
+            Agent::SystemdAskPassword => ask_systemd(executor, description).map(Some),
+            Agent::Pinentry(program) => ask_pinentry(executor, program, description).map(Some),
+        }
+    }
+
+    /// Asks for a new passphrase twice and requires both answers to match.
+    pub fn ask_new(&self, executor: &impl CommandExecutor, description: &str) -> Result<Option<String>, ZkError> {
+        let Some(first) = self.ask(executor, description)? else {
+            return Ok(None);
+        };
+        let second = self.ask(executor, &format!("Verify: {}", description))?;
+        if second.as_deref() != Some(first.as_str()) {
+            return Err(ZkError::LuksError("Passphrases do not match".to_string()));
+        }
+        Ok(Some(first))
+    }
+}
+
+fn ask_systemd(executor: &impl CommandExecutor, description: &str) -> Result<String, ZkError> {
+    let output = executor.run(SYSTEMD_ASK_PASSWORD, &["--id=0k", description])?;
+    if !output.status.success() {
+        return Err(ZkError::LuksError(format!(
+            "{} failed: {}",
+            SYSTEMD_ASK_PASSWORD,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| ZkError::LuksError(format!("{} returned a non-UTF-8 passphrase", SYSTEMD_ASK_PASSWORD)))?;
+    non_empty(stdout.strip_suffix('\n').unwrap_or(&stdout).to_string())
+}
+
+fn ask_pinentry(executor: &impl CommandExecutor, program: &str, description: &str) -> Result<String, ZkError> {
+    let script = format!(
+        "SETTITLE 0k\nSETDESC {}\nSETPROMPT Passphrase:\nGETPIN\nBYE\n",
+        assuan_escape(description)
+    );
+    let output = executor.run_with_stdin(program, &[], script.as_bytes())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in stdout.lines() {
+        if let Some(data) = line.strip_prefix("D ") {
+            return non_empty(assuan_unescape(data)?);
+        }
+        if let Some(err) = line.strip_prefix("ERR ") {
+            return Err(ZkError::LuksError(format!("{}: {}", program, err)));
+        }
+    }
+    Err(ZkError::LuksError(format!("{} returned no passphrase", program)))
+}
+
+fn non_empty(passphrase: String) -> Result<String, ZkError> {
+    if passphrase.is_empty() {
+        return Err(ZkError::LuksError("Empty passphrase".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Percent-escapes what cannot appear literally in an Assuan command line.
+fn assuan_escape(text: &str) -> String {
+    text.replace('%', "%25").replace('\n', "%0A").replace('\r', "%0D")
+}
+
+fn assuan_unescape(data: &str) -> Result<String, ZkError> {
+    let invalid = || ZkError::LuksError("Malformed passphrase data from pinentry".to_string());
+    let bytes = data.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = data.get(i + 1..i + 3).ok_or_else(invalid)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::RealSystem;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Writes an executable shell script standing in for an agent.
+    fn fake_agent(dir: &std::path::Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn config(agent: Option<&str>, pinentry: Option<&str>) -> Config {
+        Config {
+            passphrase_agent: agent.map(String::from),
+            pinentry: pinentry.map(String::from),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_select_agent() {
+        let none = |_: &str| false;
+        let all = |_: &str| true;
+        let only_pinentry = |p: &str| p == "pinentry-curses";
+        // A terminal always means cryptsetup prompts itself
+        assert_eq!(Agent::select_with(&config(Some("pinentry"), None), true, all).unwrap(), Agent::Tty);
+        assert_eq!(Agent::select_with(&config(None, None), false, all).unwrap(), Agent::SystemdAskPassword);
+        assert_eq!(
+            Agent::select_with(&config(None, Some("pinentry-curses")), false, only_pinentry).unwrap(),
+            Agent::Pinentry("pinentry-curses".into())
+        );
+        assert_eq!(Agent::select_with(&config(Some("auto"), None), false, none).unwrap(), Agent::Tty);
+        assert_eq!(Agent::select_with(&config(Some("tty"), None), false, all).unwrap(), Agent::Tty);
+        assert_eq!(
+            Agent::select_with(&config(Some("pinentry"), None), false, none).unwrap(),
+            Agent::Pinentry("pinentry".into())
+        );
+        assert!(Agent::select_with(&config(Some("kwallet"), None), false, all).is_err());
+    }
+
+    #[test]
+    fn test_pinentry_agent_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("commands");
+        // Answers GETPIN with an escaped passphrase and records what it was sent
+        let program = fake_agent(
+            dir.path(),
+            "pinentry",
+            &format!(
+                "echo 'OK Pleased to meet you'\nwhile read -r cmd rest; do\n  echo \"$cmd $rest\" >> '{}'\n  \
+                 case \"$cmd\" in GETPIN) echo 'D s3cr%25t pass'; echo OK ;; BYE) echo 'OK closing'; exit 0 ;; *) echo OK ;; esac\ndone\n",
+                log.display()
+            ),
+        );
+        let agent = Agent::Pinentry(program);
+        let passphrase = agent.ask(&RealSystem, "Passphrase for\n/b/x.sqfs_luks.img").unwrap();
+        assert_eq!(passphrase.as_deref(), Some("s3cr%t pass"));
+        let sent = std::fs::read_to_string(&log).unwrap();
+        assert!(sent.contains("SETDESC Passphrase for%0A/b/x.sqfs_luks.img"), "{}", sent);
+
+        let cancelled = fake_agent(dir.path(), "cancelled", "echo OK\necho 'ERR 83886179 Operation cancelled'\n");
+        let err = Agent::Pinentry(cancelled).ask(&RealSystem, "x").unwrap_err();
+        assert!(err.to_string().contains("Operation cancelled"), "{}", err);
+    }
+
+    #[test]
+    fn test_ask_new_requires_matching_answers() {
+        use crate::executor::MockCommandExecutor;
+        use std::os::unix::process::ExitStatusExt;
+        let answers = std::sync::Mutex::new(vec!["first\n", "second\n", "same\n", "same\n"]);
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|prog, args: &[&str]| prog == SYSTEMD_ASK_PASSWORD && args[0] == "--id=0k")
+            .times(4)
+            .returning(move |_, _| {
+                Ok(std::process::Output {
+                    status: std::process::ExitStatus::from_raw(0),
+                    stdout: answers.lock().unwrap().remove(0).as_bytes().to_vec(),
+                    stderr: vec![],
+                })
+            });
+        let agent = Agent::SystemdAskPassword;
+        assert!(agent.ask_new(&mock, "New passphrase").is_err());
+        assert_eq!(agent.ask_new(&mock, "New passphrase").unwrap().as_deref(), Some("same"));
+        assert_eq!(Agent::Tty.ask_new(&mock, "New passphrase").unwrap(), None);
+    }
+
+    #[test]
+    fn test_assuan_unescape() {
+        assert_eq!(assuan_unescape("a%0Ab%25").unwrap(), "a\nb%");
+        assert!(assuan_unescape("a%0").is_err());
+        assert!(assuan_unescape("a%zz").is_err());
+    }
+}
@@ -0,0 +1,519 @@
+//! `0k prune`: deletes old archives by a retention policy.
+//!
+//! Archives are grouped into series by prefix: the `{prefix}` of a name made by the default
+//! template (`<prefix>_<unix time>_<random>.sqfs`, see [`crate::utils::DEFAULT_NAME_TEMPLATE`]),
+//! or, for catalog entries named otherwise, the targets they were frozen from. In each series
+//! the newest `--keep-last` archives and those younger than `--keep-within` are kept; the
+//! others are deleted together with their sidecar files and dropped from the catalog.
+//! A mounted archive is never deleted, nor one a freeze or unfreeze holds the lock of.
+
+use crate::catalog;
+use crate::error::ZkError;
+use crate::executor::CommandExecutor;
+use crate::locks::{self, LockClass};
+use crate::mounts::{self, ProcReader};
+use crate::squashfs_info::ArchiveInfo;
+use crate::utils;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the archives `freeze` writes (plain and LUKS)
+const ARCHIVE_EXTENSIONS: [&str; 2] = [".sqfs_luks.img", ".sqfs"];
+
+/// Files kept next to an archive: the packing log (`--debug-log`), the LUKS trim journal
+/// and the payload digest of a LUKS1 archive (`--integrity-token`)
+const SIDECAR_SUFFIXES: [&str; 3] = [".log", ".trim-journal", crate::luks_token::SIDECAR_SUFFIX];
+
+/// Where the archives to prune are listed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// The archive files directly inside a directory (named by the default template)
+    Dir(PathBuf),
+    /// Every archive of the catalog that still exists
+    Catalog,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub path: PathBuf,
+    /// Prefix, or `targets:<hash>` for a catalog entry without a generated name
+    pub series: String,
+    /// Unix seconds
+    pub created: u64,
+    pub size: u64,
+}
+
+/// Which archives of a series survive. An archive kept by either rule is kept.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// The newest N archives (at least 1, so every series keeps its newest archive)
+    pub keep_last: usize,
+    /// Archives created less than this many seconds ago
+    pub keep_within: Option<u64>,
+}
+
+/// What the policy does with one series; both lists newest first.
+#[derive(Debug)]
+pub struct SeriesPlan {
+    pub series: String,
+    pub keep: Vec<Archive>,
+    pub delete: Vec<Archive>,
+}
+
+pub struct PruneOptions {
+    pub policy: Policy,
+    /// Only delete from a series whose newest archive passes the `0k info` checks
+    pub verify_newer: bool,
+    /// Print the plan and stop
+    pub dry_run: bool,
+    /// Needed to delete anything
+    pub yes: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PruneReport {
+    pub deleted: usize,
+    pub freed: u64,
+    /// Left in place: mounted, or their series did not verify
+    pub skipped: usize,
+}
+
+/// `(prefix, unix time)` of a file name made by the default template, e.g.
+/// `nightly_1700000000_123456.sqfs`; None for any other name.
+pub fn parse_generated_name(file_name: &str) -> Option<(String, u64)> {
+    let stem = ARCHIVE_EXTENSIONS.iter().find_map(|ext| file_name.strip_suffix(ext))?;
+    let mut parts = stem.rsplitn(3, '_');
+    let (rand, time, prefix) = (parts.next()?, parts.next()?, parts.next()?);
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if prefix.is_empty() || rand.len() != 6 || !digits(rand) || !digits(time) {
+        return None;
+    }
+    Some((prefix.to_string(), time.parse().ok()?))
+}
+
+/// The archives of `source`, and how many files were passed over (not named by the default
+/// template, or catalog entries whose archive is gone).
+pub fn discover(source: &Source, catalog_path: &Path) -> Result<(Vec<Archive>, usize), ZkError> {
+    let mut archives = Vec::new();
+    let mut passed_over = 0;
+    match source {
+        Source::Dir(dir) => {
+            for item in fs::read_dir(dir)? {
+                let item = item?;
+                let name = item.file_name().to_string_lossy().into_owned();
+                if !ARCHIVE_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+                    continue;
+                }
+                // file_type() does not follow symlinks: only real archive files are deleted
+                let meta = item.metadata()?;
+                match parse_generated_name(&name) {
+                    Some((prefix, created)) if item.file_type()?.is_file() => archives.push(Archive {
+                        path: item.path(),
+                        series: prefix,
+                        created,
+                        size: meta.len(),
+                    }),
+                    _ => passed_over += 1,
+                }
+            }
+        }
+        Source::Catalog => {
+            // Newest record per archive (an appended archive is recorded once per freeze)
+            let mut newest: BTreeMap<PathBuf, catalog::CatalogEntry> = BTreeMap::new();
+            for entry in catalog::load(catalog_path)? {
+                match newest.get(&entry.archive) {
+                    Some(seen) if seen.created > entry.created => {}
+                    _ => {
+                        newest.insert(entry.archive.clone(), entry);
+                    }
+                }
+            }
+            for (path, entry) in newest {
+                let Ok(meta) = fs::symlink_metadata(&path).map(|m| (m.is_file(), m.len())) else {
+                    passed_over += 1;
+                    continue;
+                };
+                if !meta.0 {
+                    passed_over += 1;
+                    continue;
+                }
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                let series = match parse_generated_name(&name) {
+                    Some((prefix, _)) => prefix,
+                    None => format!("targets:{}", entry.targets_hash),
+                };
+                archives.push(Archive { path, series, created: entry.created, size: meta.1 });
+            }
+        }
+    }
+    Ok((archives, passed_over))
+}
+
+/// Applies `policy` to every series of `archives` at time `now` (unix seconds).
+pub fn plan(archives: Vec<Archive>, policy: Policy, now: u64) -> Vec<SeriesPlan> {
+    let mut series: BTreeMap<String, Vec<Archive>> = BTreeMap::new();
+    for archive in archives {
+        series.entry(archive.series.clone()).or_default().push(archive);
+    }
+    series
+        .into_iter()
+        .map(|(name, mut members)| {
+            // Newest first; the path breaks ties so the plan is stable
+            members.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.path.cmp(&a.path)));
+            let (mut keep, mut delete) = (Vec::new(), Vec::new());
+            for (i, archive) in members.into_iter().enumerate() {
+                let recent = policy.keep_within.is_some_and(|within| now.saturating_sub(archive.created) < within);
+                if i < policy.keep_last || recent {
+                    keep.push(archive);
+                } else {
+                    delete.push(archive);
+                }
+            }
+            SeriesPlan { series: name, keep, delete }
+        })
+        .collect()
+}
+
+/// Lists, plans and (with `yes`) deletes. Mounts are looked up through `reader`; the
+/// catalog at `catalog_path` is the source for [`Source::Catalog`] and is updated either way.
+pub fn run<E: CommandExecutor, R: ProcReader>(
+    source: &Source,
+    options: &PruneOptions,
+    executor: &E,
+    reader: &R,
+    catalog_path: &Path,
+) -> Result<PruneReport, ZkError> {
+    let (archives, passed_over) = discover(source, catalog_path)?;
+    if passed_over > 0 {
+        println!(
+            "Note: {} file(s) or catalog entries left alone (not named <prefix>_<time>_<rand>, or already gone).",
+            passed_over
+        );
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| ZkError::OperationFailed(format!("Time error: {}", e)))?
+        .as_secs();
+    let plans = plan(archives, options.policy, now);
+    print_plan(&plans);
+
+    let candidates: Vec<&Archive> = plans.iter().flat_map(|p| &p.delete).collect();
+    let total: u64 = candidates.iter().map(|a| a.size).sum();
+    if candidates.is_empty() {
+        println!("Nothing to prune.");
+        return Ok(PruneReport::default());
+    }
+    if options.dry_run {
+        println!("Would delete {} archive(s), {}.", candidates.len(), utils::format_size(total));
+        return Ok(PruneReport::default());
+    }
+    if !options.yes {
+        return Err(ZkError::OperationFailed(format!(
+            "Not deleting {} archive(s) ({}) without --yes (--dry-run only prints the plan)",
+            candidates.len(),
+            utils::format_size(total)
+        )));
+    }
+
+    let mut report = PruneReport::default();
+    let mut deleted = Vec::new();
+    let mut failed = 0;
+    for series in &plans {
+        if series.delete.is_empty() {
+            continue;
+        }
+        if options.verify_newer
+            && let Some(newest) = series.keep.first()
+            && let Err(e) = ArchiveInfo::read(&newest.path, executor)
+        {
+            println!("SKIPPED (series {}): newest archive does not verify: {}", series.series, e);
+            report.skipped += series.delete.len();
+            continue;
+        }
+        for archive in &series.delete {
+            // Held until the archive and its sidecars are gone: a freeze appending to it or
+            // an unfreeze restoring from it holds it too
+            let lock_path = locks::archive_lock_path(&archive.path);
+            let _lock = match locks::try_lock_or_busy(LockClass::Archive, &lock_path, "the archive") {
+                Ok(lock) => lock,
+                Err(e) => {
+                    let why = match e {
+                        ZkError::Busy(why) => why,
+                        e => format!("cannot lock it: {}", e),
+                    };
+                    println!("SKIPPED ({}): {}", why, archive.path.display());
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let mounted = match mounts::try_find_mounts_for_image_with(reader, &archive.path) {
+                Ok(mounted) => mounted,
+                Err(e) => {
+                    println!("SKIPPED (cannot tell whether it is mounted: {}): {}", e, archive.path.display());
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            if let Some(mount) = mounted.first() {
+                println!("SKIPPED (mounted at {}): {}", mount.mount_point.display(), archive.path.display());
+                report.skipped += 1;
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&archive.path) {
+                println!("ERROR: cannot delete {}: {}", archive.path.display(), e);
+                failed += 1;
+                continue;
+            }
+            for suffix in SIDECAR_SUFFIXES {
+                let mut sidecar = archive.path.as_os_str().to_os_string();
+                sidecar.push(suffix);
+                match fs::remove_file(&sidecar) {
+                    Ok(()) => println!("DELETED (sidecar): {}", Path::new(&sidecar).display()),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => eprintln!("Warning: cannot delete {}: {}", Path::new(&sidecar).display(), e),
+                }
+            }
+            // Nothing left to guard
+            let _ = fs::remove_file(&lock_path);
+            println!("DELETED: {}", archive.path.display());
+            report.deleted += 1;
+            report.freed += archive.size;
+            deleted.push(archive.path.clone());
+        }
+    }
+
+    // The catalog is a convenience: failing to update it does not fail the prune
+    if let Err(e) = catalog::remove(catalog_path, &deleted) {
+        eprintln!("Warning: could not update the catalog {}: {}", catalog_path.display(), e);
+    }
+    println!(
+        "Deleted {} archive(s), freed {}; skipped {}.",
+        report.deleted,
+        utils::format_size(report.freed),
+        report.skipped
+    );
+    if failed > 0 {
+        return Err(ZkError::OperationFailed(format!("{} archive(s) could not be deleted", failed)));
+    }
+    Ok(report)
+}
+
+fn print_plan(plans: &[SeriesPlan]) {
+    for series in plans {
+        println!(
+            "Series {}: {} archive(s), keeping {}, deleting {}",
+            series.series,
+            series.keep.len() + series.delete.len(),
+            series.keep.len(),
+            series.delete.len()
+        );
+        let rows = series.keep.iter().map(|a| ("keep  ", a)).chain(series.delete.iter().map(|a| ("delete", a)));
+        for (action, archive) in rows {
+            println!(
+                "  {}  {}  ({}, {})",
+                action,
+                archive.path.display(),
+                utils::format_utc_date(archive.created),
+                utils::format_size(archive.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockCommandExecutor;
+    use std::collections::HashMap;
+
+    /// `/proc` with a single squashfuse process serving `image`
+    struct SquashfuseOf(HashMap<PathBuf, String>);
+
+    impl SquashfuseOf {
+        fn new(image: &Path) -> Self {
+            let cmdline = format!("squashfuse\0{}\0/mnt/busy\0", image.display());
+            SquashfuseOf(HashMap::from([
+                (PathBuf::from("/proc/4242/cmdline"), cmdline),
+                (PathBuf::from("/proc/mounts"), String::new()),
+            ]))
+        }
+    }
+
+    impl ProcReader for SquashfuseOf {
+        fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+            self.0.get(path).cloned().ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+        }
+
+        fn list_dir(&self, path: &Path) -> std::io::Result<Vec<String>> {
+            if path == Path::new("/proc") { Ok(vec!["4242".into()]) } else { Ok(Vec::new()) }
+        }
+    }
+
+    fn archive(series: &str, created: u64) -> Archive {
+        Archive {
+            path: PathBuf::from(format!("/b/{}_{}_123456.sqfs", series, created)),
+            series: series.into(),
+            created,
+            size: 10,
+        }
+    }
+
+    #[test]
+    fn test_parse_generated_name() {
+        assert_eq!(parse_generated_name("nightly_1700000000_123456.sqfs"), Some(("nightly".into(), 1_700_000_000)));
+        assert_eq!(
+            parse_generated_name("home_docs_1700000000_654321.sqfs_luks.img"),
+            Some(("home_docs".into(), 1_700_000_000))
+        );
+        assert_eq!(parse_generated_name("nightly_20260101-000000_123456.sqfs"), None);
+        assert_eq!(parse_generated_name("nightly_1700000000_12345.sqfs"), None);
+        assert_eq!(parse_generated_name("_1700000000_123456.sqfs"), None);
+        assert_eq!(parse_generated_name("nightly_1700000000_123456.tar"), None);
+        assert_eq!(parse_generated_name("docs.sqfs"), None);
+    }
+
+    #[test]
+    fn test_plan_keeps_last_and_recent_per_series() {
+        let now = 1_700_100_000;
+        let archives = vec![
+            archive("nightly", now - 50 * 86_400),
+            archive("nightly", now - 3 * 86_400),
+            archive("nightly", now - 86_400),
+            archive("nightly", now - 10 * 86_400),
+            archive("weekly", now - 100 * 86_400),
+        ];
+        let plans = plan(archives.clone(), Policy { keep_last: 2, keep_within: None }, now);
+        assert_eq!(plans.len(), 2);
+        let created = |list: &[Archive]| list.iter().map(|a| (now - a.created) / 86_400).collect::<Vec<_>>();
+        assert_eq!(plans[0].series, "nightly");
+        assert_eq!(created(&plans[0].keep), [1, 3]);
+        assert_eq!(created(&plans[0].delete), [10, 50]);
+        // A series with fewer archives than keep_last is left alone
+        assert_eq!((plans[1].keep.len(), plans[1].delete.len()), (1, 0));
+
+        let plans = plan(archives, Policy { keep_last: 1, keep_within: Some(14 * 86_400) }, now);
+        assert_eq!(created(&plans[0].keep), [1, 3, 10]);
+        assert_eq!(created(&plans[0].delete), [50]);
+    }
+
+    #[test]
+    fn test_run_deletes_candidates_sidecars_and_catalog_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let name = |created: u64| format!("nightly_{}_123456.sqfs", created);
+        let (newest, middle, oldest) = (now - 60, now - 86_400, now - 2 * 86_400);
+        for created in [newest, middle, oldest] {
+            fs::write(dir.path().join(name(created)), "sqsh").unwrap();
+        }
+        fs::write(dir.path().join(format!("{}.log", name(oldest))), "log").unwrap();
+        fs::write(dir.path().join("notes.sqfs"), "other").unwrap();
+
+        let catalog_path = dir.path().join("catalog.jsonl");
+        for created in [oldest, newest] {
+            let entry = catalog::CatalogEntry {
+                archive: dir.path().join(name(created)),
+                created,
+                host: "host".into(),
+                targets: vec![PathBuf::from("/data")],
+                targets_hash: "aaaa".into(),
+                size: 4,
+            };
+            catalog::record(&catalog_path, &entry).unwrap();
+        }
+
+        let source = Source::Dir(dir.path().to_path_buf());
+        let mut options = PruneOptions {
+            policy: Policy { keep_last: 1, keep_within: None },
+            verify_newer: false,
+            dry_run: true,
+            yes: false,
+        };
+        let nothing_mounted = SquashfuseOf(HashMap::new());
+        let executor = MockCommandExecutor::new();
+
+        // Dry run and a run without --yes delete nothing
+        run(&source, &options, &executor, &nothing_mounted, &catalog_path).unwrap();
+        options.dry_run = false;
+        assert!(run(&source, &options, &executor, &nothing_mounted, &catalog_path).is_err());
+        assert!(dir.path().join(name(oldest)).exists());
+
+        // The middle archive is mounted: kept
+        options.yes = true;
+        let mounted = SquashfuseOf::new(&dir.path().join(name(middle)));
+        let report = run(&source, &options, &executor, &mounted, &catalog_path).unwrap();
+        assert_eq!((report.deleted, report.freed, report.skipped), (1, 4, 1));
+        assert!(!dir.path().join(name(oldest)).exists());
+        assert!(!dir.path().join(format!("{}.log", name(oldest))).exists());
+        assert!(dir.path().join(name(middle)).exists());
+        assert!(dir.path().join(name(newest)).exists());
+        assert!(dir.path().join("notes.sqfs").exists(), "names not made by the template are left alone");
+        let entries = catalog::load(&catalog_path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].created, newest);
+    }
+
+    #[test]
+    fn test_run_skips_locked_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = |created: u64| format!("nightly_{}_123456.sqfs", created);
+        for created in [1_700_000_000u64, 1_700_000_100, 1_700_000_200] {
+            fs::write(dir.path().join(name(created)), "sqsh").unwrap();
+        }
+        let options = PruneOptions {
+            policy: Policy { keep_last: 1, keep_within: None },
+            verify_newer: false,
+            dry_run: false,
+            yes: true,
+        };
+        // A freeze appending to the oldest archive
+        let busy = dir.path().join(name(1_700_000_000));
+        let held = locks::try_lock(LockClass::Output, &locks::archive_lock_path(&busy)).unwrap().unwrap();
+
+        // On a thread of its own, like the prune of another process (locks held by this
+        // thread would otherwise count against its catalog lock in the order check)
+        let source = Source::Dir(dir.path().to_path_buf());
+        let catalog_path = dir.path().join("catalog.jsonl");
+        let report = std::thread::spawn(move || {
+            let nothing_mounted = SquashfuseOf(HashMap::from([(PathBuf::from("/proc/mounts"), String::new())]));
+            run(&source, &options, &MockCommandExecutor::new(), &nothing_mounted, &catalog_path)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        assert_eq!((report.deleted, report.skipped), (1, 1));
+        assert!(busy.exists());
+        let deleted = dir.path().join(name(1_700_000_100));
+        assert!(!deleted.exists());
+        assert!(!locks::archive_lock_path(&deleted).exists(), "the lock file goes with the archive");
+        drop(held);
+    }
+
+    #[test]
+    fn test_run_verify_newer_spares_series_with_a_bad_newest_archive() {
+        use std::os::unix::process::ExitStatusExt;
+        let dir = tempfile::tempdir().unwrap();
+        for created in [1_700_000_000u64, 1_700_000_100] {
+            fs::write(dir.path().join(format!("nightly_{}_123456.sqfs", created)), "junk").unwrap();
+        }
+        let catalog_path = dir.path().join("catalog.jsonl");
+        let options = PruneOptions {
+            policy: Policy { keep_last: 1, keep_within: None },
+            verify_newer: true,
+            dry_run: false,
+            yes: true,
+        };
+        let mut executor = MockCommandExecutor::new();
+        executor.expect_run().returning(|_, _| {
+            Ok(std::process::Output {
+                status: std::process::ExitStatus::from_raw(1 << 8),
+                stdout: vec![],
+                stderr: b"not a squashfs".to_vec(),
+            })
+        });
+        let source = Source::Dir(dir.path().to_path_buf());
+        let report = run(&source, &options, &executor, &SquashfuseOf(HashMap::new()), &catalog_path).unwrap();
+        assert_eq!((report.deleted, report.skipped), (0, 1));
+        assert!(dir.path().join("nightly_1700000000_123456.sqfs").exists());
+    }
+}
@@ -0,0 +1,120 @@
+//! Read-only filesystems, detected before long operations instead of at their end.
+//!
+//! An archive on a read-only USB drive can still be unfrozen and checked, but everything that
+//! writes must know up front: the essential write of an operation (archive destination, restore
+//! destination, `check --delete` targets) fails early with a precise message, optional writes
+//! (catalog update, packing log) are skipped with a single notice.
+//!
+//! The probe is a trait so the decisions can be tested without read-only mounts.
+
+use crate::error::ZkError;
+use std::path::Path;
+
+/// Tells whether the filesystem holding an (existing) path is mounted read-only.
+pub trait FsProbe {
+    fn is_read_only(&self, path: &Path) -> std::io::Result<bool>;
+}
+
+/// Asks the kernel (statvfs `ST_RDONLY`, see [`crate::space::statvfs`]).
+pub struct Statvfs;
+
+impl FsProbe for Statvfs {
+    fn is_read_only(&self, path: &Path) -> std::io::Result<bool> {
+        let stat = crate::space::statvfs(path)?;
+        #[allow(clippy::unnecessary_cast)]
+        Ok(has_rdonly_flag(stat.f_flag as u64))
+    }
+}
+
+/// True if statvfs `f_flag` carries `ST_RDONLY`.
+pub fn has_rdonly_flag(f_flag: u64) -> bool {
+    #[allow(clippy::unnecessary_cast)]
+    let rdonly = libc::ST_RDONLY as u64;
+    f_flag & rdonly != 0
+}
+
+/// True if `path` (or, when it does not exist yet, its closest existing ancestor) is on a
+/// read-only filesystem. Unknown counts as writable: the write itself reports the problem.
+pub fn is_read_only<P: FsProbe>(probe: &P, path: &Path) -> bool {
+    crate::utils::existing_ancestor(path).is_some_and(|p| probe.is_read_only(p).unwrap_or(false))
+}
+
+/// Fails with "`action` PATH: ... read-only filesystem" when `path` cannot be written.
+pub fn ensure_writable<P: FsProbe>(probe: &P, path: &Path, action: &str) -> Result<(), ZkError> {
+    if is_read_only(probe, path) {
+        return Err(ZkError::OperationFailed(format!(
+            "{} {}: it is on a read-only filesystem. Remount it read-write or choose another location.",
+            action,
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Names of the optional writes (`(name, path)`) that land on a read-only filesystem,
+/// to be skipped.
+pub fn skipped_writes<'a, P: FsProbe>(probe: &P, writes: &[(&'a str, &Path)]) -> Vec<&'a str> {
+    writes
+        .iter()
+        .filter(|(_, path)| is_read_only(probe, path))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The single notice for skipped optional writes (None if nothing is skipped).
+pub fn skip_notice(skipped: &[&str]) -> Option<String> {
+    (!skipped.is_empty()).then(|| format!("Notice: read-only filesystem, skipping {}.", skipped.join(" and ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Everything under the listed mount points is read-only.
+    struct FakeProbe(Vec<PathBuf>);
+
+    impl FsProbe for FakeProbe {
+        fn is_read_only(&self, path: &Path) -> std::io::Result<bool> {
+            Ok(self.0.iter().any(|m| path.starts_with(m)))
+        }
+    }
+
+    #[test]
+    fn test_has_rdonly_flag() {
+        #[allow(clippy::unnecessary_cast)]
+        let rdonly = libc::ST_RDONLY as u64;
+        assert!(has_rdonly_flag(rdonly));
+        #[allow(clippy::unnecessary_cast)]
+        let nosuid = libc::ST_NOSUID as u64;
+        assert!(has_rdonly_flag(rdonly | nosuid));
+        assert!(!has_rdonly_flag(nosuid));
+        assert!(!has_rdonly_flag(0));
+    }
+
+    #[test]
+    fn test_statvfs_probe_on_writable_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!Statvfs.is_read_only(dir.path()).unwrap());
+        // Not yet existing paths are judged by their closest existing ancestor
+        assert!(!is_read_only(&Statvfs, &dir.path().join("new/archive.sqfs")));
+    }
+
+    #[test]
+    fn test_ensure_writable_and_skips() {
+        let media = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let probe = FakeProbe(vec![media.path().to_path_buf()]);
+
+        let err = ensure_writable(&probe, &media.path().join("docs"), "Cannot restore").unwrap_err();
+        assert!(err.to_string().contains("read-only filesystem"));
+        assert!(ensure_writable(&probe, &home.path().join("docs"), "Cannot restore").is_ok());
+
+        let catalog = home.path().join("catalog.jsonl");
+        let log = media.path().join("out.sqfs.log");
+        let skipped = skipped_writes(&probe, &[("the catalog update", &catalog), ("the packing log", &log)]);
+        assert_eq!(skipped, vec!["the packing log"]);
+        assert_eq!(skip_notice(&skipped).unwrap(), "Notice: read-only filesystem, skipping the packing log.");
+        assert_eq!(skip_notice(&[]), None);
+    }
+}
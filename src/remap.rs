@@ -0,0 +1,163 @@
+//! `--remap OLD=NEW` (`unfreeze`, `check`): entries recorded under OLD are taken to live under
+//! NEW instead, e.g. after a home directory was renamed.
+//!
+//! The whole destination of an entry is rewritten, so `/home/anton=/home/antony` moves both
+//! `/home/anton/docs` and an entry frozen as `/home/anton` itself. The entry's archived name is
+//! kept apart: it still names the entry's copy inside the archive.
+
+use crate::error::ZkError;
+use crate::manifest::FileEntry;
+use std::path::{Component, Path, PathBuf};
+
+/// One `OLD=NEW` prefix rewrite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRemap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl std::fmt::Display for PathRemap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.from.display(), self.to.display())
+    }
+}
+
+/// Parses one `--remap OLD=NEW`: two absolute paths without `..`.
+pub fn remap_arg(raw: &str) -> Result<PathRemap, String> {
+    let Some((from, to)) = raw.split_once('=') else {
+        return Err(format!("Invalid remap '{}': expected OLD=NEW", raw));
+    };
+    for side in [from, to] {
+        let path = Path::new(side);
+        if !path.is_absolute() {
+            return Err(format!("Invalid remap '{}': '{}' is not an absolute path", raw, side));
+        }
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(format!("Invalid remap '{}': '{}' contains '..'", raw, side));
+        }
+    }
+    Ok(PathRemap { from: PathBuf::from(from), to: PathBuf::from(to) })
+}
+
+/// Parses the `--remap` values of a command.
+pub fn parse_remaps(raw: &[String]) -> Result<Vec<PathRemap>, ZkError> {
+    raw.iter().map(|r| remap_arg(r).map_err(ZkError::OperationFailed)).collect()
+}
+
+/// `path` rewritten by the remap with the longest matching OLD, and that remap's index; None
+/// if no remap applies. Prefixes match whole components: `/home/an` is no prefix of
+/// `/home/anton`.
+pub fn remap_path(remaps: &[PathRemap], path: &Path) -> Option<(usize, PathBuf)> {
+    let (index, remap, rest) = remaps
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| path.strip_prefix(&r.from).ok().map(|rest| (i, r, rest)))
+        .max_by_key(|(_, r, _)| r.from.components().count())?;
+    let remapped = if rest.as_os_str().is_empty() { remap.to.clone() } else { remap.to.join(rest) };
+    Some((index, remapped))
+}
+
+/// Rewrites the destination (`restore_path/name`, or a legacy `original_path`) of every entry
+/// of `files`. A remapped legacy entry takes the new format, its archived name in `name`. Returns
+/// the ids of the rewritten entries and, for each remap, how many entries it rewrote.
+pub fn apply(remaps: &[PathRemap], files: &mut [FileEntry]) -> (Vec<u32>, Vec<usize>) {
+    let mut remapped = Vec::new();
+    let mut counts = vec![0; remaps.len()];
+    for entry in files.iter_mut() {
+        let Some(dest) = entry.destination() else { continue };
+        let archived = match &entry.name {
+            Some(name) => name.clone(),
+            None => match dest.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            },
+        };
+        let Some((i, new_dest)) = remap_path(remaps, &dest) else { continue };
+        let (Some(parent), Some(name)) = (new_dest.parent(), new_dest.file_name()) else { continue };
+        let name = name.to_string_lossy().into_owned();
+        entry.restore_path = Some(parent.to_string_lossy().into_owned());
+        entry.restore_name = (name != archived).then_some(name);
+        entry.name = Some(archived);
+        entry.original_path = None;
+        counts[i] += 1;
+        remapped.push(entry.id);
+    }
+    (remapped, counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remaps(raw: &[&str]) -> Vec<PathRemap> {
+        raw.iter().map(|r| remap_arg(r).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_remap_arg() {
+        assert_eq!(
+            remap_arg("/home/anton=/home/antony"),
+            Ok(PathRemap { from: "/home/anton".into(), to: "/home/antony".into() })
+        );
+        assert!(remap_arg("/home/anton").unwrap_err().contains("expected OLD=NEW"));
+        assert!(remap_arg("home/anton=/home/antony").unwrap_err().contains("not an absolute path"));
+        assert!(remap_arg("/home/anton=").unwrap_err().contains("not an absolute path"));
+        assert!(remap_arg("/home/anton=/home/../etc").unwrap_err().contains("'..'"));
+    }
+
+    #[test]
+    fn test_remap_path_longest_prefix() {
+        let remaps = remaps(&["/home/anton=/home/antony", "/home/anton/work=/srv/work", "/home=/mnt/home"]);
+        let remap = |p: &str| remap_path(&remaps, Path::new(p)).map(|(i, p)| (i, p.display().to_string()));
+
+        assert_eq!(remap("/home/anton/docs"), Some((0, "/home/antony/docs".into())));
+        assert_eq!(remap("/home/anton"), Some((0, "/home/antony".into())));
+        assert_eq!(remap("/home/anton/work/src"), Some((1, "/srv/work/src".into())));
+        assert_eq!(remap("/home/anna"), Some((2, "/mnt/home/anna".into())));
+        // Whole components only
+        assert_eq!(remap("/home/antony/docs"), Some((2, "/mnt/home/antony/docs".into())));
+        assert_eq!(remap("/etc"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let remaps = remaps(&["/home/anton=/home/antony", "/home/anton/work=/srv/work", "/opt=/usr/local"]);
+        let mut files = vec![
+            FileEntry::named(1, "docs", "/home/anton"),
+            FileEntry::named(2, "zk", "/home/anton/work/projects"),
+            FileEntry::legacy(3, "/home/anton/.bashrc"),
+            FileEntry::named(4, "anton", "/home"),
+            FileEntry::named(5, "fstab", "/etc"),
+        ];
+        let (remapped, counts) = apply(&remaps, &mut files);
+
+        assert_eq!(remapped, vec![1, 2, 3, 4]);
+        assert_eq!(counts, vec![3, 1, 0]);
+        assert_eq!(files[0].destination(), Some("/home/antony/docs".into()));
+        assert_eq!(files[0].restore_name, None);
+        assert_eq!(files[1].destination(), Some("/srv/work/projects/zk".into()));
+        // The legacy entry takes the new format
+        assert_eq!(files[2].destination(), Some("/home/antony/.bashrc".into()));
+        assert_eq!(files[2].name.as_deref(), Some(".bashrc"));
+        assert_eq!(files[2].original_path, None);
+        // The unrelated entry is untouched
+        assert_eq!(files[4].destination(), Some("/etc/fstab".into()));
+    }
+
+    #[test]
+    fn test_apply_entry_root() {
+        let remaps = remaps(&["/home/anton=/home/antony", "/srv/old.conf=/etc/new.conf"]);
+        let mut files = vec![FileEntry::named(1, "anton", "/home"), FileEntry::legacy(2, "/srv/old.conf")];
+        let (remapped, counts) = apply(&remaps, &mut files);
+
+        assert_eq!(remapped, vec![1, 2]);
+        assert_eq!(counts, vec![1, 1]);
+        assert_eq!(files[0].destination(), Some("/home/antony".into()));
+        assert_eq!(files[0].restore_path.as_deref(), Some("/home"));
+        // The archive still holds the entry under its frozen name
+        assert_eq!(files[0].name.as_deref(), Some("anton"));
+        assert_eq!(files[0].restore_name.as_deref(), Some("antony"));
+        assert_eq!(files[1].destination(), Some("/etc/new.conf".into()));
+        assert_eq!(files[1].name.as_deref(), Some("old.conf"));
+    }
+}
@@ -0,0 +1,317 @@
+//! JSON outputs for machine consumers, and their schema.
+//!
+//! Every top-level JSON object `0k` and `0k-core` print carries `schema_version`:
+//! `freeze --dry-run --json`, each `--json-events` line, `info --json`, `list --json`,
+//! `check --json`, `diff --json`, `verify --json` and `version --json`. Fields are only ever added; removing
+//! or renaming one bumps [`SCHEMA_VERSION`]. The JSON Schema of all of them is printed by the
+//! hidden `0k dump-schema` subcommand, and golden fixtures in `tests/fixtures/json/` pin the
+//! current shape.
+
+use crate::error::ZkError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Version of the JSON output format.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A top-level JSON object: `schema_version` followed by the fields of `body`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(body: T) -> Self {
+        Versioned { schema_version: SCHEMA_VERSION, body }
+    }
+}
+
+/// Prints `body` as one versioned JSON line on stdout; `what` names it in the error.
+pub fn print_json<T: Serialize>(body: &T, what: &str) -> Result<(), ZkError> {
+    let line = serde_json::to_string(&Versioned::new(body))
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot encode {}: {}", what, e)))?;
+    println!("{}", line);
+    Ok(())
+}
+
+/// Final report of an operation (the `done` event).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Report {
+    Freeze(FreezeReport),
+    Unfreeze(UnfreezeReport),
+    Check(CheckReport),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FreezeReport {
+    pub archive: String,
+    pub entries: u32,
+    pub bytes: u64,
+    /// Files found open for writing by `--check-open-files` (frozen anyway)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub open_files: Vec<OpenFile>,
+    /// Threads mksquashfs was given (`--threads`, config, or physical cores)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OpenFile {
+    pub pid: u32,
+    pub command: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UnfreezeReport {
+    pub restored: u32,
+    pub skipped: u32,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CheckReport {
+    pub files_matched: u32,
+    pub dirs_matched: u32,
+    pub links_matched: u32,
+    pub files_deleted: u32,
+    pub dirs_deleted: u32,
+    pub links_deleted: u32,
+    pub mismatched: u32,
+    pub missing: u32,
+    pub skipped: u32,
+    pub likely_changed: u32,
+    /// Size of the files removed by `--delete` (st_size)
+    #[serde(default)]
+    pub reclaimed_bytes: u64,
+    /// Disk space freed by `--delete` (st_blocks * 512; hard links count once the last is gone)
+    #[serde(default)]
+    pub reclaimed_disk_bytes: u64,
+    /// Like `reclaimed_bytes`, for the files kept as SKIPPED (Newer)
+    #[serde(default)]
+    pub skipped_bytes: u64,
+    #[serde(default)]
+    pub skipped_disk_bytes: u64,
+    /// Files `--delete` kept because they were replaced or modified between their comparison
+    /// and their deletion (CHANGED DURING RUN)
+    #[serde(default)]
+    pub changed_during_run: u32,
+    /// Empty directories `--delete` removed that were not matched against the archive
+    /// (see `--prune-dirs`)
+    #[serde(default)]
+    pub dirs_pruned: u32,
+    /// Live paths inside a frozen directory that the archive does not have (`--show-extra`);
+    /// a directory counts once, not its contents
+    #[serde(default)]
+    pub extra: u32,
+    /// Archive paths left out by `check --exclude`; a directory counts once, not its contents
+    #[serde(default)]
+    pub excluded: u32,
+    /// Manifest entries (or, with `--no-manifest`, archive paths) looked at
+    #[serde(default)]
+    pub indexed_paths: u32,
+    /// What the archive left out at freeze time and so was not checked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// One row per path checked, in the order they were; left out of the `done` event, which
+    /// has an `entry_checked` event for each instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<CheckedEntry>,
+}
+
+impl CheckReport {
+    /// True if any path mismatched or is missing.
+    pub fn has_problems(&self) -> bool {
+        self.mismatched > 0 || self.missing > 0
+    }
+}
+
+/// One path `check` looked at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CheckedEntry {
+    pub path: PathBuf,
+    pub status: crate::events::CheckStatus,
+    /// What was compared, or why the path was judged as it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<CheckCategory>,
+    /// E.g. both sizes of a size mismatch, or why a deletion failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckCategory {
+    /// A directory (matched, deleted, or failed to delete)
+    Dir,
+    /// File type differs (e.g. a file became a directory)
+    Type,
+    Size,
+    /// Modification time differs (`--quick`)
+    Mtime,
+    /// Bytes differ (`--use-cmp`)
+    Content,
+    /// SHA-256 differs from the one recorded at freeze time (`--checksums`)
+    Checksum,
+    LinkTarget,
+    /// Live copy is newer than the archive; kept by `--delete`
+    Newer,
+    /// Empty in the archive although it was not when frozen
+    Incomplete,
+    /// Entry missing from the archive itself
+    Corrupted,
+}
+
+impl std::fmt::Display for CheckCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            CheckCategory::Dir => "Dir",
+            CheckCategory::Type => "Type",
+            CheckCategory::Size => "Size",
+            CheckCategory::Mtime => "Mtime",
+            CheckCategory::Content => "Content",
+            CheckCategory::Checksum => "Checksum",
+            CheckCategory::LinkTarget => "Link Target",
+            CheckCategory::Newer => "Newer",
+            CheckCategory::Incomplete => "Incomplete",
+            CheckCategory::Corrupted => "Corrupted",
+        };
+        f.write_str(label)
+    }
+}
+
+/// What `0k list --json` prints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ListReport {
+    pub entries: Vec<ListEntry>,
+}
+
+/// One row of `0k list`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ListEntry {
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub entry_type: crate::manifest::EntryType,
+    /// Where `unfreeze` restores the entry
+    pub path: PathBuf,
+    /// Apparent size of the entry inside the archive (`--sizes` only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// What `0k diff --json` prints: how the newer archive differs from the older one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DiffReport {
+    pub added: u32,
+    pub removed: u32,
+    pub modified: u32,
+    pub unchanged: u32,
+    pub changes: Vec<DiffChange>,
+}
+
+/// One path that differs between the two archives, by where it restores to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DiffChange {
+    pub change: ChangeKind,
+    pub path: PathBuf,
+    /// What differs in a modified path: `type`, `link target`, `size <old> -> <new>` or `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// What `0k verify --json` prints.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VerifyReport {
+    /// Manifest entries whose copy is present (and, with `--deep`, read back without errors)
+    pub entries_verified: u32,
+    pub entries: u32,
+    /// Regular files read back (`--deep` only)
+    pub files_read: u32,
+    pub bytes_read: u64,
+    /// Files checked against the SHA-256 recorded by `freeze --checksums` (`--deep` only)
+    pub checksums_verified: u32,
+    /// Where the payload SHA-256 the archive was checked against came from (encrypted archives
+    /// frozen with `--integrity-token`); a mismatch is one of `errors`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_digest: Option<crate::luks_token::DigestSource>,
+    /// One line per problem found; verification failed unless empty
+    pub errors: Vec<String>,
+}
+
+/// JSON Schema of every output, keyed by output name (`0k dump-schema`).
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "outputs": {
+            "check": schemars::schema_for!(Versioned<CheckReport>),
+            "diff": schemars::schema_for!(Versioned<DiffReport>),
+            "event": schemars::schema_for!(Versioned<crate::events::Event>),
+            "freeze_plan": schemars::schema_for!(Versioned<crate::engine::FreezePlan>),
+            "info": schemars::schema_for!(Versioned<crate::squashfs_info::ArchiveInfo>),
+            "list": schemars::schema_for!(Versioned<ListReport>),
+            "verify": schemars::schema_for!(Versioned<VerifyReport>),
+            "version": schemars::schema_for!(Versioned<crate::version::VersionReport>),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    /// Parses `fixture` as a `T` and checks that it prints back unchanged: a removed or
+    /// renamed field fails here (and needs a [`SCHEMA_VERSION`] bump and new fixtures).
+    fn assert_golden<T: Serialize + DeserializeOwned>(name: &str, fixture: &str) {
+        let expected: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        let parsed: Versioned<T> = serde_json::from_str(fixture)
+            .unwrap_or_else(|e| panic!("{} no longer parses: {}", name, e));
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION, "{}", name);
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), expected, "{}", name);
+    }
+
+    #[test]
+    fn test_golden_outputs() {
+        for line in include_str!("../tests/fixtures/json/events.jsonl").lines() {
+            assert_golden::<crate::events::Event>("events.jsonl", line);
+        }
+        assert_golden::<CheckReport>("check.json", include_str!("../tests/fixtures/json/check.json"));
+        assert_golden::<DiffReport>("diff.json", include_str!("../tests/fixtures/json/diff.json"));
+        assert_golden::<crate::engine::FreezePlan>(
+            "freeze_plan.json",
+            include_str!("../tests/fixtures/json/freeze_plan.json"),
+        );
+        assert_golden::<crate::squashfs_info::ArchiveInfo>("info.json", include_str!("../tests/fixtures/json/info.json"));
+        assert_golden::<ListReport>("list.json", include_str!("../tests/fixtures/json/list.json"));
+        assert_golden::<VerifyReport>("verify.json", include_str!("../tests/fixtures/json/verify.json"));
+        assert_golden::<crate::version::VersionReport>(
+            "version.json",
+            include_str!("../tests/fixtures/json/version.json"),
+        );
+    }
+
+    #[test]
+    fn test_versioned_json_shape() {
+        let line = serde_json::to_string(&Versioned::new(UnfreezeReport { restored: 2, skipped: 1, bytes: 10 })).unwrap();
+        assert_eq!(line, r#"{"schema_version":1,"restored":2,"skipped":1,"bytes":10}"#);
+
+        let schema = schema();
+        assert_eq!(schema["schema_version"], SCHEMA_VERSION);
+        for output in ["check", "diff", "event", "freeze_plan", "info", "list", "verify", "version"] {
+            let properties = &schema["outputs"][output]["properties"];
+            assert!(properties["schema_version"].is_object(), "{}: {}", output, schema["outputs"][output]);
+        }
+    }
+}
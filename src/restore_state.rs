@@ -0,0 +1,137 @@
+//! Progress journal of `unfreeze`, for `--resume` after a partial failure (disk full, a
+//! network home gone away).
+//!
+//! `<0k-cache>/restore-state/<archive name>-<path hash>.restore-state.yaml` lists the ids of
+//! the manifest entries restored so far, plus the entry being copied when the run died. It
+//! also records the archive's size and mtime: a journal of a since replaced archive is stale
+//! and ignored. The journal is removed once every entry is restored.
+
+use crate::error::ZkError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RestoreState {
+    /// Size and mtime of the archive the journal belongs to
+    pub archive_size: u64,
+    pub archive_mtime: i64,
+    /// Ids of the entries restored completely
+    #[serde(default)]
+    pub completed: BTreeSet<u32>,
+    /// Entry being copied when the journal was last written (possibly half restored)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_progress: Option<u32>,
+}
+
+/// The journal of one archive: where it lives and what it holds.
+#[derive(Debug)]
+pub struct Journal {
+    pub path: PathBuf,
+    pub state: RestoreState,
+}
+
+impl Journal {
+    /// The journal of `archive` (empty if there is none, or if it is stale or unreadable).
+    pub fn open(archive: &Path) -> Result<Journal, ZkError> {
+        let dir = crate::utils::get_0k_temp_dir(None)?.join("restore-state");
+        match fs::create_dir(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(ZkError::IoError(e)),
+        }
+        Self::open_in(&dir, archive)
+    }
+
+    fn open_in(dir: &Path, archive: &Path) -> Result<Journal, ZkError> {
+        let canonical = fs::canonicalize(archive)?;
+        let name = canonical.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let path = dir.join(format!(
+            "{}-{}.restore-state.yaml",
+            name,
+            crate::utils::short_path_hash(&canonical)
+        ));
+        let meta = fs::metadata(&canonical)?;
+        let fresh = RestoreState { archive_size: meta.len(), archive_mtime: meta.mtime(), ..Default::default() };
+        let state = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_yaml::from_str::<RestoreState>(&text).ok())
+            .filter(|s| (s.archive_size, s.archive_mtime) == (fresh.archive_size, fresh.archive_mtime))
+            .unwrap_or(fresh);
+        Ok(Journal { path, state })
+    }
+
+    /// Whether an earlier run left anything to resume.
+    pub fn has_progress(&self) -> bool {
+        !self.state.completed.is_empty() || self.state.in_progress.is_some()
+    }
+
+    /// Forgets earlier progress (`--restart`, or a run that does not resume).
+    pub fn reset(&mut self) -> Result<(), ZkError> {
+        self.state.completed.clear();
+        self.state.in_progress = None;
+        self.remove()
+    }
+
+    /// Records that entry `id` is being copied.
+    pub fn start(&mut self, id: u32) -> Result<(), ZkError> {
+        self.state.in_progress = Some(id);
+        self.write()
+    }
+
+    /// Records that entry `id` is restored.
+    pub fn complete(&mut self, id: u32) -> Result<(), ZkError> {
+        self.state.completed.insert(id);
+        self.state.in_progress = None;
+        self.write()
+    }
+
+    /// Removes the journal file (all entries restored).
+    pub fn remove(&self) -> Result<(), ZkError> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ZkError::IoError(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the file atomically, so a crash never leaves half a journal.
+    fn write(&self) -> Result<(), ZkError> {
+        let mut tmp = self.path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_yaml::to_string(&self.state)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_progress_and_staleness() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("data.sqfs");
+        fs::write(&archive, "image").unwrap();
+
+        let mut journal = Journal::open_in(dir.path(), &archive).unwrap();
+        assert!(!journal.has_progress());
+        journal.start(1).unwrap();
+        journal.complete(1).unwrap();
+        journal.start(2).unwrap();
+
+        let reopened = Journal::open_in(dir.path(), &archive).unwrap();
+        assert_eq!(reopened.state.completed, BTreeSet::from([1]));
+        assert_eq!(reopened.state.in_progress, Some(2));
+
+        // The archive was replaced since: the journal no longer applies
+        fs::write(&archive, "another image").unwrap();
+        assert!(!Journal::open_in(dir.path(), &archive).unwrap().has_progress());
+
+        journal.remove().unwrap();
+        assert!(!journal.path.exists());
+        journal.remove().unwrap();
+    }
+}
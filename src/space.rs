@@ -0,0 +1,252 @@
+//! Free space on the destination and the reserve kept free there.
+//!
+//! Pre-flight checks (freeze onto a target's filesystem, LUKS container allocation in
+//! `0k-core create`) compare what they need with the free space minus the reserve. The reserve
+//! comes from `--reserve`, else the `reserve` key of the config file (see [`crate::config`]),
+//! else it is 1 GiB or 2% of the filesystem, whichever is larger.
+//!
+//! Every other archive ([`ensure_archive_fits`]) only has to fit its smallest plausible
+//! compressed size into the free space, so that `mksquashfs` does not run out of room halfway.
+//! The space comes from a [`SpaceProbe`], so these decisions can be tested without full disks.
+
+use crate::constants::{DEFAULT_RESERVE_BYTES, DEFAULT_RESERVE_PERCENT, MIN_ARCHIVE_SIZE_PERCENT};
+use crate::error::ZkError;
+use crate::utils::format_size;
+use std::path::Path;
+
+/// Space on one filesystem, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsSpace {
+    /// Free for unprivileged users (statvfs f_bavail)
+    pub available: u64,
+    /// Size of the filesystem
+    pub total: u64,
+}
+
+/// statvfs(3) of the filesystem holding `path`.
+pub fn statvfs(path: &Path) -> std::io::Result<libc::statvfs> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path is NUL-terminated and stat points to writable memory of the right size
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it filled the struct
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// Space on the filesystem holding `path`.
+pub fn filesystem_space(path: &Path) -> Result<FsSpace, ZkError> {
+    let stat = statvfs(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::InvalidInput => ZkError::InvalidPath(path.to_path_buf()),
+        _ => ZkError::IoError(e),
+    })?;
+    // Field widths differ between targets (u32 on some 32-bit ones)
+    #[allow(clippy::unnecessary_cast)]
+    let (available, total, block) = (stat.f_bavail as u64, stat.f_blocks as u64, stat.f_frsize as u64);
+    Ok(FsSpace { available: available.saturating_mul(block), total: total.saturating_mul(block) })
+}
+
+/// Tells how much space the filesystem holding an (existing) path has.
+pub trait SpaceProbe {
+    fn space(&self, path: &Path) -> Result<FsSpace, ZkError>;
+}
+
+/// Asks the kernel ([`filesystem_space`]).
+pub struct StatvfsSpace;
+
+impl SpaceProbe for StatvfsSpace {
+    fn space(&self, path: &Path) -> Result<FsSpace, ZkError> {
+        filesystem_space(path)
+    }
+}
+
+/// The same space on every filesystem (tests).
+#[cfg(any(test, feature = "testing"))]
+pub struct FakeSpace(pub FsSpace);
+
+#[cfg(any(test, feature = "testing"))]
+impl SpaceProbe for FakeSpace {
+    fn space(&self, _path: &Path) -> Result<FsSpace, ZkError> {
+        Ok(self.0)
+    }
+}
+
+/// Space to leave free on the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Reserve {
+    /// 1 GiB or 2% of the filesystem, whichever is larger
+    #[default]
+    Default,
+    Bytes(u64),
+    /// Percent of the filesystem size
+    Percent(f64),
+}
+
+impl Reserve {
+    /// Parses `SIZE` (`512M`, `1.5GiB`, `0`) or `PERCENT` (`5%`).
+    pub fn parse(s: &str) -> Result<Self, ZkError> {
+        match crate::units::parse_percent(s) {
+            Some(percent) => percent.map(Reserve::Percent).map_err(ZkError::OperationFailed),
+            None => crate::utils::parse_size(s).map(Reserve::Bytes),
+        }
+    }
+
+    /// `--reserve` if given, else the config file's `reserve`, else the default.
+    pub fn resolve(flag: Option<&str>) -> Result<Self, ZkError> {
+        match flag {
+            Some(value) => Reserve::parse(value),
+            None => match crate::config::Config::load().reserve {
+                Some(value) => Reserve::parse(&value).map_err(|e| {
+                    ZkError::OperationFailed(format!("{} (reserve in {})", e, crate::config::config_path().display()))
+                }),
+                None => Ok(Reserve::Default),
+            },
+        }
+    }
+
+    /// Reserved bytes on a filesystem of `total` bytes.
+    pub fn bytes(&self, total: u64) -> u64 {
+        let percent_of = |pct: f64| (total as f64 * pct / 100.0) as u64;
+        match *self {
+            Reserve::Default => DEFAULT_RESERVE_BYTES.max(percent_of(DEFAULT_RESERVE_PERCENT)),
+            Reserve::Bytes(bytes) => bytes,
+            Reserve::Percent(pct) => percent_of(pct),
+        }
+    }
+}
+
+/// Refuses when `needed` bytes for `what` do not fit into the free space minus the reserve.
+pub fn ensure_fits(needed: u64, space: FsSpace, reserve: &Reserve, what: &str) -> Result<(), ZkError> {
+    let reserved = reserve.bytes(space.total);
+    let usable = space.available.saturating_sub(reserved);
+    if needed <= usable {
+        return Ok(());
+    }
+    Err(ZkError::OperationFailed(format!(
+        "Not enough free space for {}: needs {}, but only {} is available after the reserve \
+         ({} free, {} reserved; see --reserve).",
+        what,
+        format_size(needed),
+        format_size(usable),
+        format_size(space.available),
+        format_size(reserved)
+    )))
+}
+
+/// Apparent size of the regular files at or below `path` (symlinks are not followed).
+pub fn tree_bytes(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Smallest plausible archive of `input_bytes` at zstd `compression` (0 = stored as is).
+pub fn estimated_archive_size(input_bytes: u64, compression: u32) -> u64 {
+    let percent = if compression == 0 { 100 } else { MIN_ARCHIVE_SIZE_PERCENT };
+    input_bytes.saturating_mul(percent) / 100
+}
+
+/// Pre-flight check before packing: refuses when the filesystem that will hold `output`
+/// has less free space than `needed` (see [`estimated_archive_size`]). The reserve is not
+/// applied here; this only catches destinations that clearly cannot hold the archive.
+pub fn ensure_archive_fits<P: SpaceProbe>(probe: &P, output: &Path, needed: u64) -> Result<(), ZkError> {
+    let Some(dest_dir) = crate::utils::existing_ancestor(output) else {
+        return Ok(());
+    };
+    let available = probe.space(dest_dir)?.available;
+    if needed <= available {
+        return Ok(());
+    }
+    Err(ZkError::OperationFailed(format!(
+        "Not enough free space for {}: the archive needs at least {} ({} bytes), but {} has only {} \
+         ({} bytes) free. Free some space or choose another destination; pass --no-space-check \
+         if the data compresses well enough to fit.",
+        output.display(),
+        format_size(needed),
+        needed,
+        dest_dir.display(),
+        format_size(available),
+        available
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    #[test]
+    fn test_parse_reserve() {
+        assert_eq!(Reserve::parse("2G").unwrap(), Reserve::Bytes(2 * GIB));
+        assert_eq!(Reserve::parse("1.5GiB").unwrap(), Reserve::Bytes(3 * GIB / 2));
+        assert!(Reserve::parse("12X").is_err());
+
+        assert_eq!(Reserve::parse("5%").unwrap(), Reserve::Percent(5.0));
+        assert_eq!(Reserve::parse("0").unwrap(), Reserve::Bytes(0));
+        assert!(Reserve::parse("150%").is_err());
+    }
+
+    #[test]
+    fn test_reserve_bytes() {
+        // Default: 1 GiB on small filesystems, 2% on large ones
+        assert_eq!(Reserve::Default.bytes(10 * GIB), GIB);
+        assert_eq!(Reserve::Default.bytes(1000 * GIB), 20 * GIB);
+        assert_eq!(Reserve::Percent(10.0).bytes(50 * GIB), 5 * GIB);
+        assert_eq!(Reserve::Bytes(42).bytes(50 * GIB), 42);
+    }
+
+    #[test]
+    fn test_ensure_fits() {
+        let space = FsSpace { available: 10 * GIB, total: 100 * GIB };
+        assert!(ensure_fits(8 * GIB, space, &Reserve::Default, "x").is_ok());
+        assert!(ensure_fits(10 * GIB, space, &Reserve::Bytes(0), "x").is_ok());
+
+        let err = ensure_fits(9 * GIB + 1, space, &Reserve::Default, "the container").unwrap_err().to_string();
+        assert!(err.contains("the container"), "{}", err);
+        // Raw free space, reserve and what is left after it are all named
+        assert!(err.contains("10.0 GiB free") && err.contains("2.0 GiB reserved"), "{}", err);
+        assert!(err.contains("only 8.0 GiB is available"), "{}", err);
+    }
+
+    #[test]
+    fn test_estimated_archive_size() {
+        assert_eq!(estimated_archive_size(100 * GIB, 19), 25 * GIB);
+        // Stored as is: no compression to count on
+        assert_eq!(estimated_archive_size(100 * GIB, 0), 100 * GIB);
+        assert_eq!(estimated_archive_size(u64::MAX, 0), u64::MAX / 100);
+    }
+
+    #[test]
+    fn test_ensure_archive_fits() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("new/data.sqfs");
+        let probe = FakeSpace(FsSpace { available: 10 * GIB, total: 100 * GIB });
+
+        assert!(ensure_archive_fits(&probe, &output, 10 * GIB).is_ok());
+        let err = ensure_archive_fits(&probe, &output, 10 * GIB + 1).unwrap_err().to_string();
+        // Destination, required and available space are all named
+        assert!(err.contains(&output.display().to_string()), "{}", err);
+        assert!(err.contains(&format!("{} bytes", 10 * GIB + 1)), "{}", err);
+        assert!(err.contains(&format!("{} has only 10.0 GiB ({} bytes) free", dir.path().display(), 10 * GIB)), "{}", err);
+        assert!(err.contains("--no-space-check"), "{}", err);
+    }
+
+    #[test]
+    fn test_tree_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a"), vec![0u8; 100]).unwrap();
+        std::fs::write(dir.path().join("sub/b"), vec![0u8; 23]).unwrap();
+        std::os::unix::fs::symlink("a", dir.path().join("link")).unwrap();
+        assert_eq!(tree_bytes(dir.path()), 123);
+        assert_eq!(tree_bytes(&dir.path().join("a")), 100);
+    }
+}
@@ -0,0 +1,621 @@
+//! Superblock details of a SquashFS image, parsed from `unsquashfs -s`.
+//!
+//! Used by `0k info` (with the LUKS header from `cryptsetup luksDump`), by the `0k-core mount`
+//! pre-checks, by `0k-core create --verify` and by the LUKS trim step. The parser is line based
+//! and tolerant: squashfs-tools 4.5 prints the filesystem size as `Filesystem size N bytes
+//! (...)`, 4.6 may print it in Kbytes with the byte count on the next, indented line; unknown
+//! lines are ignored and missing values stay `None`.
+//!
+//! [`SizeIndex`] holds the file sizes of a whole image from one `unsquashfs -lls` run, for the
+//! progress totals of `check` and `unfreeze` and the contents `0k info` reports: a stat per
+//! file through squashfuse is slow. [`cat`] reads one file (the manifest) without a mount.
+
+use crate::error::ZkError;
+use crate::executor::CommandExecutor;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SquashfsInfo {
+    /// Compressor name (`gzip`, `xz`, `zstd`, ...)
+    pub compression: Option<String>,
+    /// `compression-level` option of the compressor, when it has one
+    pub compression_level: Option<u32>,
+    pub block_size: Option<u64>,
+    /// Bytes used by the filesystem (not the size of the file holding it)
+    pub fs_size: Option<u64>,
+    pub inodes: Option<u64>,
+    /// "Creation or last append time", as printed (local time of the reading machine)
+    pub created: Option<String>,
+}
+
+impl SquashfsInfo {
+    /// Parses `unsquashfs -s` output; None if it holds no superblock details at all.
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut info = SquashfsInfo::default();
+        let mut size_continues = false;
+        for line in output.lines() {
+            let trimmed = line.trim();
+            // 4.6: "Filesystem size 3.54 Kbytes (0.00 Mbytes)" + "\t3627 bytes"
+            if size_continues {
+                size_continues = false;
+                if line.starts_with(char::is_whitespace)
+                    && let Some(bytes) = trimmed.strip_suffix(" bytes").and_then(|n| n.parse().ok())
+                {
+                    info.fs_size = Some(bytes);
+                    continue;
+                }
+            }
+            if let Some(rest) = trimmed.strip_prefix("Filesystem size ") {
+                let mut words = rest.split_whitespace();
+                match (words.next().and_then(|n| n.parse().ok()), words.next()) {
+                    (Some(bytes), Some("bytes")) => info.fs_size = Some(bytes),
+                    _ => size_continues = true,
+                }
+            } else if let Some(rest) = trimmed.strip_prefix("Creation or last append time ") {
+                info.created = Some(rest.trim().to_string());
+            } else if let Some(rest) = trimmed.strip_prefix("Compression ") {
+                info.compression = Some(rest.trim().to_string());
+            } else if let Some(rest) = trimmed.strip_prefix("compression-level ") {
+                info.compression_level = rest.trim().parse().ok();
+            } else if let Some(rest) = trimmed.strip_prefix("Block size ") {
+                info.block_size = rest.trim().parse().ok();
+            } else if let Some(rest) = trimmed.strip_prefix("Number of inodes ") {
+                info.inodes = rest.trim().parse().ok();
+            }
+        }
+        (info != SquashfsInfo::default()).then_some(info)
+    }
+
+    /// One line for logs and notices: "zstd (level 19), 128.0 KiB blocks, 7 inodes, 4.0 KiB".
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(compression) = &self.compression {
+            match self.compression_level {
+                Some(level) => parts.push(format!("{} (level {})", compression, level)),
+                None => parts.push(compression.clone()),
+            }
+        }
+        if let Some(block_size) = self.block_size {
+            parts.push(format!("{} blocks", crate::utils::format_size(block_size)));
+        }
+        if let Some(inodes) = self.inodes {
+            parts.push(format!("{} inodes", inodes));
+        }
+        if let Some(fs_size) = self.fs_size {
+            parts.push(crate::utils::format_size(fs_size));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Runs `unsquashfs -s` on `image` (a file or a mapper device) and parses the result.
+pub fn read(image: &Path, executor: &impl CommandExecutor) -> Result<SquashfsInfo, ZkError> {
+    let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+    let output = executor.run("unsquashfs", &["-s", image_str])?;
+    from_output(image, &output)
+}
+
+/// Interprets the result of `unsquashfs -s image` (run by the caller, e.g. through sudo).
+pub fn from_output(image: &Path, output: &std::process::Output) -> Result<SquashfsInfo, ZkError> {
+    if !output.status.success() {
+        return Err(ZkError::OperationFailed(format!(
+            "{} is not a valid SquashFS image: {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    SquashfsInfo::parse(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        ZkError::OperationFailed(format!("Cannot read the SquashFS superblock of {}", image.display()))
+    })
+}
+
+/// Apparent sizes of the regular files of an image, by path inside it (`to_restore/1/a.txt`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeIndex {
+    files: BTreeMap<PathBuf, u64>,
+}
+
+impl SizeIndex {
+    /// Parses `unsquashfs -lls` output. Only regular files are kept; the header lines and
+    /// anything else that is not a listing line are ignored.
+    pub fn parse(listing: &str) -> Self {
+        let mut files = BTreeMap::new();
+        for line in listing.lines().filter(|l| l.starts_with('-')) {
+            // `-rw-r--r-- user/group 6 2026-01-01 00:00 squashfs-root/to_restore/1/a.txt`
+            let Some(start) = line.find(" squashfs-root/") else { continue };
+            let Some(size) = line.split_whitespace().nth(2).and_then(|s| s.parse::<u64>().ok()) else {
+                continue;
+            };
+            files.insert(PathBuf::from(&line[start + " squashfs-root/".len()..]), size);
+        }
+        SizeIndex { files }
+    }
+
+    /// Runs `unsquashfs -lls` on `image` (a plain archive or a mapper device) and parses the
+    /// result.
+    pub fn read(image: &Path, executor: &impl CommandExecutor) -> Result<Self, ZkError> {
+        let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+        let output = executor.run("unsquashfs", &["-lls", image_str])?;
+        if !output.status.success() {
+            return Err(ZkError::OperationFailed(format!(
+                "Cannot list {}: {}",
+                image.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Bytes of `path` inside the image: the file itself, or every file below a directory
+    /// (the whole image for an empty path).
+    pub fn bytes(&self, path: &Path) -> u64 {
+        self.files.range(path.to_path_buf()..).take_while(|(p, _)| p.starts_with(path)).map(|(_, size)| size).sum()
+    }
+
+    /// Every regular file with its size, in path order.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, u64)> {
+        self.files.iter().map(|(path, size)| (path.as_path(), *size))
+    }
+}
+
+/// Contents of the file `path` inside `image` (`unsquashfs -cat`, squashfs-tools 4.5+).
+pub fn cat(image: &Path, path: &Path, executor: &impl CommandExecutor) -> Result<Vec<u8>, ZkError> {
+    let image_str = image.to_str().ok_or_else(|| ZkError::InvalidPath(image.to_path_buf()))?;
+    let path_str = path.to_str().ok_or_else(|| ZkError::InvalidPath(path.to_path_buf()))?;
+    let output = executor.run("unsquashfs", &["-cat", image_str, path_str])?;
+    if !output.status.success() {
+        return Err(ZkError::OperationFailed(format!(
+            "Cannot read {} from {}: {}",
+            path.display(),
+            image.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Header of a LUKS container, parsed from `cryptsetup luksDump` (readable without root).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct LuksHeader {
+    /// LUKS format version (1 or 2)
+    pub luks_version: Option<u32>,
+    pub uuid: Option<String>,
+    /// Data cipher, e.g. `aes-xts-plain64`
+    pub cipher: Option<String>,
+}
+
+impl LuksHeader {
+    /// Parses `cryptsetup luksDump` output of LUKS1 (`Cipher name` + `Cipher mode`) or LUKS2
+    /// (`cipher:` of the data segment; keyslot ciphers are capitalized and ignored).
+    pub fn parse(output: &str) -> Self {
+        let mut header = LuksHeader::default();
+        let (mut name, mut mode) = (None, None);
+        for line in output.lines().map(str::trim) {
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let value = value.trim().to_string();
+            match key {
+                "Version" => header.luks_version = value.parse().ok(),
+                "UUID" => header.uuid = Some(value),
+                "Cipher name" => name = Some(value),
+                "Cipher mode" => mode = Some(value),
+                "cipher" if header.cipher.is_none() => header.cipher = Some(value),
+                _ => {}
+            }
+        }
+        if let (Some(name), Some(mode)) = (name, mode) {
+            header.cipher = Some(format!("{}-{}", name, mode));
+        }
+        header
+    }
+}
+
+/// What the manifest of an archive says about it (needs a mount).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ContentsInfo {
+    /// When the archive was frozen, as recorded in list.yaml
+    pub date: String,
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privilege_mode: Option<crate::manifest::PrivilegeMode>,
+    pub entries: u32,
+    /// Apparent size of the archived entries
+    pub uncompressed_bytes: u64,
+}
+
+/// What `0k info` reports about an archive file.
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ArchiveInfo {
+    pub path: std::path::PathBuf,
+    /// Size of the archive file
+    pub size: u64,
+    /// LUKS container: the SquashFS inside cannot be read without opening it
+    pub encrypted: bool,
+    #[serde(flatten)]
+    pub squashfs: Option<SquashfsInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub luks: Option<LuksHeader>,
+    /// Payload digest of an encrypted archive frozen with `--integrity-token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_digest: Option<crate::luks_token::PayloadDigest>,
+    /// From list.yaml, if the archive could be mounted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents: Option<ContentsInfo>,
+    /// Why `contents` is missing (e.g. a LUKS archive needs root to mount)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents_unavailable: Option<String>,
+}
+
+/// Runs `cryptsetup luksDump` on a LUKS container; None if it fails.
+pub fn read_luks_header(image: &Path, executor: &impl CommandExecutor) -> Option<LuksHeader> {
+    let output = executor.run("cryptsetup", &["luksDump", image.to_str()?]).ok()?;
+    output.status.success().then(|| LuksHeader::parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+impl ArchiveInfo {
+    /// What the file itself tells (no mount): the SquashFS superblock, or the LUKS header.
+    pub fn read(path: &Path, executor: &impl CommandExecutor) -> Result<Self, ZkError> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| ZkError::OperationFailed(format!("Cannot read {}: {}", path.display(), e)))?
+            .len();
+        let encrypted = crate::utils::is_luks_image(path);
+        let (squashfs, luks) = if encrypted {
+            (None, read_luks_header(path, executor))
+        } else {
+            (Some(read(path, executor)?), None)
+        };
+        let payload_digest = if encrypted {
+            crate::luks_token::read(executor, path).unwrap_or_else(|e| {
+                eprintln!("Warning: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+        Ok(ArchiveInfo {
+            path: path.to_path_buf(),
+            size,
+            encrypted,
+            squashfs,
+            luks,
+            payload_digest,
+            contents: None,
+            contents_unavailable: None,
+        })
+    }
+
+    /// Human-readable report, one "Key: value" line per known field.
+    pub fn render(&self) -> String {
+        let mut lines = vec![
+            format!("Archive:      {}", self.path.display()),
+            format!("Size:         {}", crate::utils::format_size(self.size)),
+        ];
+        if self.encrypted {
+            let luks = self.luks.clone().unwrap_or_default();
+            let mut details = vec![match luks.luks_version {
+                Some(version) => format!("LUKS{}", version),
+                None => "LUKS".to_string(),
+            }];
+            details.extend(luks.cipher);
+            lines.push(format!("Encrypted:    yes ({})", details.join(", ")));
+            if let Some(uuid) = luks.uuid {
+                lines.push(format!("LUKS UUID:    {}", uuid));
+            }
+            if let Some(digest) = &self.payload_digest {
+                lines.push(format!("Payload:      sha256 {} ({})", digest.payload_sha256, digest.source));
+                lines.push(format!("Archive ID:   {}", digest.archive_id));
+            }
+        }
+        if let Some(info) = &self.squashfs {
+            info.render_into(&mut lines);
+        }
+        if let Some(contents) = &self.contents {
+            lines.push(format!("Frozen:       {} on {}", contents.date, contents.host));
+            if let Some(mode) = &contents.privilege_mode {
+                let mode = match mode {
+                    crate::manifest::PrivilegeMode::User => "user",
+                    crate::manifest::PrivilegeMode::Root => "root",
+                };
+                lines.push(format!("Privilege:    {}", mode));
+            }
+            lines.push(format!("Entries:      {}", contents.entries));
+            let compressed = self.squashfs.as_ref().and_then(|s| s.fs_size).unwrap_or(self.size);
+            let mut uncompressed = format!("Uncompressed: {}", crate::utils::format_size(contents.uncompressed_bytes));
+            if contents.uncompressed_bytes > 0 {
+                let ratio = compressed as f64 * 100.0 / contents.uncompressed_bytes as f64;
+                uncompressed.push_str(&format!(" (archive is {:.1}% of it)", ratio));
+            }
+            lines.push(uncompressed);
+        }
+        if let Some(reason) = &self.contents_unavailable {
+            lines.push(format!("Contents:     unavailable ({})", reason));
+        }
+        lines.join("\n")
+    }
+}
+
+impl SquashfsInfo {
+    /// The superblock lines of [`ArchiveInfo::render`].
+    fn render_into(&self, lines: &mut Vec<String>) {
+        if let Some(compression) = &self.compression {
+            match self.compression_level {
+                Some(level) => lines.push(format!("Compression:  {} (level {})", compression, level)),
+                None => lines.push(format!("Compression:  {}", compression)),
+            }
+        }
+        if let Some(block_size) = self.block_size {
+            lines.push(format!("Block size:   {}", crate::utils::format_size(block_size)));
+        }
+        if let Some(fs_size) = self.fs_size {
+            lines.push(format!("Filesystem:   {} ({} bytes)", crate::utils::format_size(fs_size), fs_size));
+        }
+        if let Some(inodes) = self.inodes {
+            lines.push(format!("Inodes:       {}", inodes));
+        }
+        if let Some(created) = &self.created {
+            lines.push(format!("Created:      {}", created));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockCommandExecutor;
+
+    /// `unsquashfs -s` from squashfs-tools 4.5.1
+    const OUTPUT_4_5: &str = "\
+Found a valid SQUASHFS 4:0 superblock on data.sqfs.
+Creation or last append time Sat Mar  4 18:02:11 2023
+Filesystem size 4096 bytes (4.00 Kbytes / 0.00 Mbytes)
+Compression zstd
+\tcompression-level 19
+Block size 131072
+Filesystem is exportable via NFS
+Inodes are compressed
+Data is compressed
+Uids/Gids (Id table) are compressed
+Fragments are compressed
+Always-use-fragments option is not specified
+Xattrs are compressed
+Duplicates are removed
+Number of fragments 1
+Number of inodes 7
+Number of ids 1
+Number of xattr ids 0
+";
+
+    /// `unsquashfs -s` from squashfs-tools 4.6.1
+    const OUTPUT_4_6: &str = "\
+Found a valid SQUASHFS 4:0 superblock on data.sqfs.
+Creation or last append time Tue Jan 16 09:41:27 2024
+Filesystem size 3.54 Kbytes (0.00 Mbytes)
+\t3627 bytes
+Compression xz
+Block size 1048576
+Filesystem is exportable via NFS
+Inodes are compressed
+Data is compressed
+Uids/Gids (Id table) are compressed
+Fragments are compressed
+Always-use-fragments option is not specified
+Xattrs are compressed
+Duplicates are removed
+Number of fragments 1
+Number of inodes 12
+Number of ids (unique uids + gids) 1
+Number of xattr ids 0
+";
+
+    #[test]
+    fn test_parse_squashfs_tools_4_5() {
+        let info = SquashfsInfo::parse(OUTPUT_4_5).unwrap();
+        assert_eq!(info.compression.as_deref(), Some("zstd"));
+        assert_eq!(info.compression_level, Some(19));
+        assert_eq!(info.block_size, Some(131072));
+        assert_eq!(info.fs_size, Some(4096));
+        assert_eq!(info.inodes, Some(7));
+        assert_eq!(info.created.as_deref(), Some("Sat Mar  4 18:02:11 2023"));
+    }
+
+    #[test]
+    fn test_parse_squashfs_tools_4_6() {
+        let info = SquashfsInfo::parse(OUTPUT_4_6).unwrap();
+        assert_eq!(info.compression.as_deref(), Some("xz"));
+        assert_eq!(info.compression_level, None);
+        assert_eq!(info.block_size, Some(1048576));
+        assert_eq!(info.fs_size, Some(3627));
+        assert_eq!(info.inodes, Some(12));
+        assert_eq!(info.created.as_deref(), Some("Tue Jan 16 09:41:27 2024"));
+    }
+
+    /// `cryptsetup luksDump` of a LUKS2 container (cryptsetup 2.6, trimmed)
+    const LUKS2_DUMP: &str = "\
+LUKS header information
+Version:       \t2
+Epoch:         \t3
+Metadata area: \t16384 [bytes]
+UUID:          \t5b1e2c7e-8f0a-4a7e-9d55-3c1f2a0b9e11
+Label:         \t(no label)
+
+Data segments:
+  0: crypt
+\toffset: 16777216 [bytes]
+\tlength: (whole device)
+\tcipher: aes-xts-plain64
+\tsector: 4096 [bytes]
+
+Keyslots:
+  0: luks2
+\tKey:        512 bits
+\tCipher:     aes-xts-plain64
+\tCipher key: 512 bits
+";
+
+    /// `cryptsetup luksDump` of a LUKS1 container (trimmed)
+    const LUKS1_DUMP: &str = "\
+LUKS header information for data.sqfs_luks.img
+
+Version:       \t1
+Cipher name:   \taes
+Cipher mode:   \tcbc-essiv:sha256
+Hash spec:     \tsha256
+Payload offset:\t4096
+UUID:          \t0f9a33f2-7d1c-4b8e-a8f1-0d1e6c2b4a55
+";
+
+    #[test]
+    fn test_parse_luks_header() {
+        let header = LuksHeader::parse(LUKS2_DUMP);
+        assert_eq!(header.luks_version, Some(2));
+        assert_eq!(header.uuid.as_deref(), Some("5b1e2c7e-8f0a-4a7e-9d55-3c1f2a0b9e11"));
+        assert_eq!(header.cipher.as_deref(), Some("aes-xts-plain64"));
+
+        let header = LuksHeader::parse(LUKS1_DUMP);
+        assert_eq!(header.luks_version, Some(1));
+        assert_eq!(header.cipher.as_deref(), Some("aes-cbc-essiv:sha256"));
+        assert_eq!(header.uuid.as_deref(), Some("0f9a33f2-7d1c-4b8e-a8f1-0d1e6c2b4a55"));
+        assert_eq!(LuksHeader::parse(""), LuksHeader::default());
+    }
+
+    fn output(code: i32, stdout: &str) -> std::io::Result<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: b"Can't find a SQUASHFS superblock".to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_archive_info_plain_and_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("data.sqfs");
+        std::fs::write(&image, vec![0u8; 4096]).unwrap();
+
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args[0] == "-s")
+            .returning(|_, _| output(0, OUTPUT_4_5));
+        let info = ArchiveInfo::read(&image, &mock).unwrap();
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["size"], 4096);
+        assert_eq!(json["encrypted"], false);
+        assert_eq!(json["compression"], "zstd");
+        assert_eq!(json["block_size"], 131072);
+        assert_eq!(json["inodes"], 7);
+        assert!(info.render().contains("Compression:  zstd (level 19)"));
+
+        // The LUKS header magic makes it a container
+        let luks_image = dir.path().join("data.sqfs_luks.img");
+        let mut header = b"LUKS\xba\xbe".to_vec();
+        header.resize(4096, 0);
+        std::fs::write(&luks_image, header).unwrap();
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[0] == "luksDump")
+            .returning(|_, _| output(0, LUKS2_DUMP));
+        let info = ArchiveInfo::read(&luks_image, &mock).unwrap();
+        assert!(info.encrypted && info.squashfs.is_none());
+        let json = serde_json::to_value(&info).unwrap();
+        assert!(json.get("compression").is_none());
+        assert_eq!(json["luks"]["cipher"], "aes-xts-plain64");
+        assert!(info.render().contains("Encrypted:    yes (LUKS2, aes-xts-plain64)"), "{}", info.render());
+
+        // Neither LUKS nor SquashFS
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args[0] == "-s")
+            .returning(|_, _| output(1, ""));
+        let err = ArchiveInfo::read(&image, &mock).unwrap_err();
+        assert!(err.to_string().contains("not a valid SquashFS image"), "{}", err);
+    }
+
+    #[test]
+    fn test_archive_info_payload_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("data.sqfs_luks.img");
+        let mut header = b"LUKS\xba\xbe".to_vec();
+        header.resize(4096, 0);
+        std::fs::write(&image, header).unwrap();
+
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[0] == "luksDump")
+            .returning(|_, _| output(0, &format!("{}Tokens:\n  0: 0k-meta\nDigests:\n", LUKS2_DUMP)));
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "cryptsetup" && args[..4] == ["token", "export", "--token-id", "0"])
+            .returning(|_, _| {
+                output(0, r#"{"type":"0k-meta","keyslots":[],"payload_sha256":"ab12","archive_id":"5f3c"}"#)
+            });
+        let info = ArchiveInfo::read(&image, &mock).unwrap();
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["payload_digest"]["payload_sha256"], "ab12");
+        assert_eq!(json["payload_digest"]["source"], "luks_token");
+        let rendered = info.render();
+        assert!(rendered.contains("Payload:      sha256 ab12 (LUKS token)"), "{}", rendered);
+        assert!(rendered.contains("Archive ID:   5f3c"), "{}", rendered);
+    }
+
+    const LISTING: &str = "\
+Parallel unsquashfs: Using 4 processors
+5 inodes (5 blocks) to write
+
+drwxr-xr-x user/user                49 2026-10-16 12:00 squashfs-root
+-rw-r--r-- user/user               212 2026-10-16 12:00 squashfs-root/list.yaml
+drwxr-xr-x user/user                61 2026-10-16 12:00 squashfs-root/to_restore
+drwxr-xr-x user/user                40 2026-10-16 12:00 squashfs-root/to_restore/1
+drwxr-xr-x user/user                35 2026-10-16 12:00 squashfs-root/to_restore/1/docs
+-rw-r--r-- user/user              4096 2026-10-16 12:00 squashfs-root/to_restore/1/docs/a b.txt
+lrwxrwxrwx user/user                 7 2026-10-16 12:00 squashfs-root/to_restore/1/docs/link -> a b.txt
+-rw-r--r-- user/user               100 2026-10-16 12:00 squashfs-root/to_restore/1/docs/sub/c.txt
+-rw-r--r-- user/user                 9 2026-10-16 12:00 squashfs-root/to_restore/1/docs-old/d.txt
+crw-r--r-- root/root             1,  3 2026-10-16 12:00 squashfs-root/to_restore/2/null
+-rw-r--r-- 1000/1000                 6 2026-10-16 12:00 squashfs-root/to_restore_2/3/e.txt
+";
+
+    #[test]
+    fn test_size_index() {
+        let index = SizeIndex::parse(LISTING);
+        assert_eq!(index.bytes(Path::new("to_restore/1/docs")), 4196);
+        assert_eq!(index.bytes(Path::new("to_restore/1/docs/a b.txt")), 4096);
+        assert_eq!(index.bytes(Path::new("to_restore/1")), 4205);
+        assert_eq!(index.bytes(Path::new("to_restore/2")), 0);
+        assert_eq!(index.bytes(Path::new("to_restore_2/3/e.txt")), 6);
+        assert_eq!(index.bytes(Path::new("")), 4423);
+        assert_eq!(index.bytes(Path::new("missing")), 0);
+        assert_eq!(SizeIndex::parse("Parallel unsquashfs: Using 4 processors\n"), SizeIndex::default());
+    }
+
+    #[test]
+    fn test_size_index_read() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args == ["-lls", "/tmp/a.sqfs"])
+            .returning(|_, _| output(0, LISTING));
+        mock.expect_run().returning(|_, _| output(1, ""));
+        assert_eq!(SizeIndex::read(Path::new("/tmp/a.sqfs"), &mock).unwrap().bytes(Path::new("")), 4423);
+        assert!(SizeIndex::read(Path::new("/tmp/b.sqfs"), &mock).is_err());
+    }
+
+    #[test]
+    fn test_cat() {
+        let mut mock = MockCommandExecutor::new();
+        mock.expect_run()
+            .withf(|program, args: &[&str]| program == "unsquashfs" && args == ["-cat", "/tmp/a.sqfs", "list.yaml"])
+            .returning(|_, _| output(0, "metadata:\n"));
+        mock.expect_run().returning(|_, _| output(1, ""));
+        assert_eq!(cat(Path::new("/tmp/a.sqfs"), Path::new("list.yaml"), &mock).unwrap(), b"metadata:\n");
+        assert!(cat(Path::new("/tmp/a.sqfs"), Path::new("missing"), &mock).is_err());
+    }
+
+    #[test]
+    fn test_parse_partial_and_garbage() {
+        let info = SquashfsInfo::parse("Filesystem size 500000 bytes (488.28 Kbytes / 0.48 Mbytes)\n").unwrap();
+        assert_eq!(info.fs_size, Some(500000));
+        assert_eq!(info.compression, None);
+        assert_eq!(SquashfsInfo::parse("Can't find a SQUASHFS superblock on x\n"), None);
+        assert_eq!(SquashfsInfo::parse(""), None);
+    }
+}
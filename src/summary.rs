@@ -0,0 +1,337 @@
+//! Final summaries of `check`, `unfreeze`, `diff` and `verify`: aligned tables rendered from
+//! the very reports the JSON outputs carry, so the two cannot disagree.
+//!
+//! Counts are colored when they are not zero (green for matched/restored, yellow for
+//! skipped, red for mismatched/missing), unless `NO_COLOR` is set or stdout is not a terminal.
+
+use crate::report::{CheckReport, CheckedEntry, DiffReport, UnfreezeReport, VerifyReport};
+use crate::utils::format_size;
+use std::io::IsTerminal;
+
+const RULE: &str = "---------------------------------------------------";
+
+/// How a non-zero value is colored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tone {
+    Plain,
+    Good,
+    Warn,
+    Bad,
+}
+
+impl Tone {
+    fn ansi(self) -> Option<&'static str> {
+        match self {
+            Tone::Plain => None,
+            Tone::Good => Some("\x1b[32m"),
+            Tone::Warn => Some("\x1b[33m"),
+            Tone::Bad => Some("\x1b[31m"),
+        }
+    }
+}
+
+struct Row {
+    label: &'static str,
+    value: String,
+    /// Counts are right-aligned, and colored by `tone` when not zero
+    count: Option<u64>,
+    tone: Tone,
+}
+
+fn count(label: &'static str, n: u64, tone: Tone) -> Row {
+    Row { label, value: n.to_string(), count: Some(n), tone }
+}
+
+fn text(label: &'static str, value: String) -> Row {
+    Row { label, value, count: None, tone: Tone::Plain }
+}
+
+/// Colors for stdout: on a terminal, unless `NO_COLOR` is set (to anything non-empty).
+pub fn color_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal()
+}
+
+/// Labels padded to one column, values in the next (counts right-aligned).
+fn render(title: &str, rows: &[Row], color: bool) -> String {
+    let label_width = rows.iter().map(|r| r.label.len()).max().unwrap_or(0);
+    let count_width = rows.iter().filter(|r| r.count.is_some()).map(|r| r.value.len()).max().unwrap_or(0);
+    let mut lines = vec![RULE.to_string(), title.to_string()];
+    for row in rows {
+        let value = match (row.count, row.tone.ansi()) {
+            (Some(n), Some(code)) if color && n > 0 => {
+                format!("{}{:>width$}\x1b[0m", code, row.value, width = count_width)
+            }
+            (Some(_), _) => format!("{:>width$}", row.value, width = count_width),
+            (None, _) => row.value.clone(),
+        };
+        lines.push(format!("  {:<width$}  {}", row.label, value, width = label_width));
+    }
+    lines.join("\n")
+}
+
+/// One line of `check` output: `STATUS (Category): path (detail)`.
+pub fn check_line(entry: &CheckedEntry) -> String {
+    let mut line = entry.status.to_string();
+    if let Some(category) = entry.category {
+        line.push_str(&format!(" ({})", category));
+    }
+    line.push_str(&format!(": {}", entry.path.display()));
+    if let Some(detail) = &entry.detail {
+        line.push_str(&format!(" ({})", detail));
+    }
+    line
+}
+
+/// `check` summary. `quick` adds the likely-changed count, `delete` what was deleted and
+/// reclaimed; live paths the archive does not have and paths left out by `--exclude` are
+/// counted if there were any.
+pub fn check(report: &CheckReport, quick: bool, delete: bool, color: bool) -> String {
+    let mut rows = vec![
+        count("Indexed paths", report.indexed_paths as u64, Tone::Plain),
+        count("Files matched", report.files_matched as u64, Tone::Good),
+        count("Dirs matched", report.dirs_matched as u64, Tone::Good),
+        count("Links matched", report.links_matched as u64, Tone::Good),
+        count("Mismatched", report.mismatched as u64, Tone::Bad),
+        count("Missing", report.missing as u64, Tone::Bad),
+        count("Skipped (newer)", report.skipped as u64, Tone::Warn),
+    ];
+    if quick {
+        rows.push(count("Likely changed", report.likely_changed as u64, Tone::Warn));
+    }
+    if report.extra > 0 {
+        rows.push(count("Extra (not in archive)", report.extra as u64, Tone::Warn));
+    }
+    if report.excluded > 0 {
+        rows.push(count("Excluded", report.excluded as u64, Tone::Plain));
+    }
+    if delete {
+        rows.extend([
+            count("Files deleted", report.files_deleted as u64, Tone::Plain),
+            count("Dirs deleted", report.dirs_deleted as u64, Tone::Plain),
+            count("Links deleted", report.links_deleted as u64, Tone::Plain),
+            count("Dirs pruned", report.dirs_pruned as u64, Tone::Plain),
+            count("Changed during run", report.changed_during_run as u64, Tone::Warn),
+            text(
+                "Reclaimed",
+                format!(
+                    "{} apparent, {} on disk",
+                    format_size(report.reclaimed_bytes),
+                    format_size(report.reclaimed_disk_bytes)
+                ),
+            ),
+        ]);
+        if report.skipped > 0 {
+            rows.push(text(
+                "Not reclaimed",
+                format!(
+                    "{} apparent, {} on disk",
+                    format_size(report.skipped_bytes),
+                    format_size(report.skipped_disk_bytes)
+                ),
+            ));
+        }
+    }
+    render("Check summary", &rows, color)
+}
+
+/// `unfreeze` summary.
+pub fn unfreeze(report: &UnfreezeReport, color: bool) -> String {
+    let rows = [
+        count("Restored", report.restored as u64, Tone::Good),
+        count("Skipped", report.skipped as u64, Tone::Warn),
+        text("Copied", format_size(report.bytes)),
+    ];
+    render("Unfreeze summary", &rows, color)
+}
+
+/// `diff` summary.
+pub fn diff(report: &DiffReport, color: bool) -> String {
+    let rows = [
+        count("Added", report.added as u64, Tone::Good),
+        count("Removed", report.removed as u64, Tone::Bad),
+        count("Modified", report.modified as u64, Tone::Warn),
+        count("Unchanged", report.unchanged as u64, Tone::Plain),
+    ];
+    render("Diff summary", &rows, color)
+}
+
+/// `verify` summary. `deep` adds what was read back.
+pub fn verify(report: &VerifyReport, deep: bool, color: bool) -> String {
+    let mut rows = vec![
+        count("Entries", report.entries as u64, Tone::Plain),
+        count("Entries verified", report.entries_verified as u64, Tone::Good),
+    ];
+    if deep {
+        rows.extend([
+            count("Files read", report.files_read as u64, Tone::Good),
+            count("Checksums verified", report.checksums_verified as u64, Tone::Good),
+            text("Read", format_size(report.bytes_read)),
+        ]);
+    }
+    if let Some(source) = report.payload_digest {
+        rows.push(text("Payload digest", format!("checked ({})", source)));
+    }
+    rows.push(count("Errors", report.errors.len() as u64, Tone::Bad));
+    render("Verify summary", &rows, color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_report() -> CheckReport {
+        CheckReport {
+            files_matched: 1204,
+            dirs_matched: 37,
+            links_matched: 2,
+            mismatched: 1,
+            missing: 0,
+            skipped: 3,
+            files_deleted: 1204,
+            dirs_deleted: 37,
+            links_deleted: 2,
+            reclaimed_bytes: 5 * 1024 * 1024,
+            reclaimed_disk_bytes: 6 * 1024 * 1024,
+            skipped_bytes: 2048,
+            skipped_disk_bytes: 12288,
+            indexed_paths: 1250,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_summary_snapshot() {
+        assert_eq!(
+            check(&check_report(), false, false, false),
+            "\
+---------------------------------------------------
+Check summary
+  Indexed paths    1250
+  Files matched    1204
+  Dirs matched       37
+  Links matched       2
+  Mismatched          1
+  Missing             0
+  Skipped (newer)     3"
+        );
+        assert_eq!(
+            check(&check_report(), true, true, false),
+            "\
+---------------------------------------------------
+Check summary
+  Indexed paths       1250
+  Files matched       1204
+  Dirs matched          37
+  Links matched          2
+  Mismatched             1
+  Missing                0
+  Skipped (newer)        3
+  Likely changed         0
+  Files deleted       1204
+  Dirs deleted          37
+  Links deleted          2
+  Dirs pruned            0
+  Changed during run     0
+  Reclaimed           5.0 MiB apparent, 6.0 MiB on disk
+  Not reclaimed       2.0 KiB apparent, 12.0 KiB on disk"
+        );
+    }
+
+    #[test]
+    fn test_check_lines() {
+        use crate::events::CheckStatus;
+        use crate::report::CheckCategory;
+        let line = |status, category, detail: Option<&str>| {
+            check_line(&CheckedEntry {
+                path: "/home/user/a.txt".into(),
+                status,
+                category,
+                detail: detail.map(str::to_string),
+            })
+        };
+        assert_eq!(line(CheckStatus::Match, None, None), "MATCH: /home/user/a.txt");
+        assert_eq!(line(CheckStatus::Deleted, Some(CheckCategory::Dir), None), "DELETED (Dir): /home/user/a.txt");
+        assert_eq!(
+            line(CheckStatus::Mismatch, Some(CheckCategory::Size), Some("Live: 3, Archive: 2")),
+            "MISMATCH (Size): /home/user/a.txt (Live: 3, Archive: 2)"
+        );
+        assert_eq!(
+            line(CheckStatus::LikelyChanged, Some(CheckCategory::LinkTarget), None),
+            "LIKELY-CHANGED (Link Target): /home/user/a.txt"
+        );
+        assert_eq!(
+            line(CheckStatus::ChangedDuringRun, None, Some("kept")),
+            "CHANGED DURING RUN: /home/user/a.txt (kept)"
+        );
+        assert_eq!(line(CheckStatus::Extra, None, Some("directory")), "EXTRA: /home/user/a.txt (directory)");
+    }
+
+    #[test]
+    fn test_unfreeze_summary_snapshot() {
+        let report = UnfreezeReport { restored: 12, skipped: 0, bytes: 3 * 1024 * 1024 * 1024 };
+        assert_eq!(
+            unfreeze(&report, false),
+            "\
+---------------------------------------------------
+Unfreeze summary
+  Restored  12
+  Skipped    0
+  Copied    3.0 GiB"
+        );
+    }
+
+    #[test]
+    fn test_diff_summary_snapshot() {
+        let report = DiffReport { added: 3, removed: 0, modified: 12, unchanged: 1480, changes: vec![] };
+        assert_eq!(
+            diff(&report, false),
+            "\
+---------------------------------------------------
+Diff summary
+  Added         3
+  Removed       0
+  Modified     12
+  Unchanged  1480"
+        );
+    }
+
+    #[test]
+    fn test_verify_summary_snapshot() {
+        let report = VerifyReport {
+            entries: 3,
+            entries_verified: 2,
+            files_read: 41,
+            bytes_read: 1024 * 1024,
+            checksums_verified: 0,
+            payload_digest: None,
+            errors: vec!["entry 3: gone".into()],
+        };
+        assert_eq!(
+            verify(&report, true, false),
+            "\
+---------------------------------------------------
+Verify summary
+  Entries              3
+  Entries verified     2
+  Files read          41
+  Checksums verified   0
+  Read                1.0 MiB
+  Errors               1"
+        );
+        assert!(!verify(&report, false, false).contains("Files read"));
+
+        let report = VerifyReport { payload_digest: Some(crate::luks_token::DigestSource::LuksToken), ..report };
+        let summary = verify(&report, false, false);
+        assert!(summary.contains("  Payload digest    checked (LUKS token)\n"), "{}", summary);
+    }
+
+    #[test]
+    fn test_summary_colors_only_nonzero_counts() {
+        let colored = check(&check_report(), false, false, true);
+        assert!(colored.contains("Files matched    \x1b[32m1204\x1b[0m"), "{:?}", colored);
+        assert!(colored.contains("Mismatched       \x1b[31m   1\x1b[0m"), "{:?}", colored);
+        assert!(colored.contains("Skipped (newer)  \x1b[33m   3\x1b[0m"), "{:?}", colored);
+        assert!(colored.contains("Missing             0\n"), "{:?}", colored);
+        assert!(colored.contains("Indexed paths    1250"), "{:?}", colored);
+    }
+}
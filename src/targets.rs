@@ -0,0 +1,147 @@
+//! Freeze targets as given on the command line and in `--read` / `--read0` lists.
+//!
+//! Manifest ids are assigned in target order. So that they are stable for an identical set of
+//! targets, however it is listed, [`normalize`] drops duplicates (the same path twice, or
+//! reached through a symlinked directory) and sorts the rest by their resolved path.
+//! `freeze --preserve-order` keeps the order as given instead.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A target and where it was given, for messages: `argument 2`, `line 7 of list.txt`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub path: PathBuf,
+    pub origin: String,
+}
+
+impl Target {
+    pub fn new(path: impl Into<PathBuf>, origin: impl Into<String>) -> Self {
+        Target { path: path.into(), origin: origin.into() }
+    }
+}
+
+/// A target left out because an earlier one names the same file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    pub dropped: Target,
+    pub kept: Target,
+}
+
+impl std::fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) is the same as {} ({})",
+            self.dropped.path.display(),
+            self.dropped.origin,
+            self.kept.path.display(),
+            self.kept.origin
+        )
+    }
+}
+
+/// The targets without duplicates, sorted by resolved path unless `preserve_order`. Each one
+/// is kept as first given. `dereferences` tells which targets are frozen as what they point
+/// to: only those are resolved in full; for the others only the parent directory is, since a
+/// symlink and its referent are different entries.
+pub fn normalize(
+    targets: Vec<Target>,
+    dereferences: impl Fn(&Path) -> bool,
+    preserve_order: bool,
+) -> (Vec<PathBuf>, Vec<Duplicate>) {
+    let mut kept: Vec<(PathBuf, Target)> = Vec::new();
+    // Resolved path -> its index in `kept`
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for target in targets {
+        let key = resolved(&target.path, dereferences(&target.path));
+        match seen.get(&key) {
+            Some(&first) => duplicates.push(Duplicate { dropped: target, kept: kept[first].1.clone() }),
+            None => {
+                seen.insert(key.clone(), kept.len());
+                kept.push((key, target));
+            }
+        }
+    }
+    if !preserve_order {
+        kept.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+    (kept.into_iter().map(|(_, t)| t.path).collect(), duplicates)
+}
+
+/// What `path` names: the canonical parent joined with the name (or all of it canonical if
+/// `dereference`). Paths that do not exist are only made absolute.
+fn resolved(path: &Path, dereference: bool) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if dereference {
+        return fs::canonicalize(&absolute).unwrap_or(absolute);
+    }
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent).map(|p| p.join(name)).unwrap_or(absolute),
+        _ => fs::canonicalize(&absolute).unwrap_or(absolute),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(paths: &[&Path]) -> Vec<Target> {
+        paths.iter().enumerate().map(|(i, p)| Target::new(*p, format!("line {}", i + 1))).collect()
+    }
+
+    #[test]
+    fn test_duplicate_paths_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        fs::create_dir(&docs).unwrap();
+        let same = dir.path().join("docs/../docs");
+
+        let (kept, duplicates) = normalize(targets(&[&docs, &same, &docs]), |_| false, false);
+        assert_eq!(kept, vec![docs.clone()]);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0].dropped.origin, "line 2");
+        assert_eq!(duplicates[1].kept.origin, "line 1");
+        assert!(duplicates[1].to_string().contains("(line 3) is the same as"), "{}", duplicates[1]);
+    }
+
+    #[test]
+    fn test_symlink_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("real/docs")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("alias")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real/docs"), dir.path().join("docs-link")).unwrap();
+        let docs = dir.path().join("real/docs");
+        let through_alias = dir.path().join("alias/docs");
+        let link = dir.path().join("docs-link");
+
+        // Reached through a symlinked directory: the same entry either way
+        let (kept, duplicates) = normalize(targets(&[&docs, &through_alias]), |_| false, false);
+        assert_eq!((kept, duplicates.len()), (vec![docs.clone()], 1));
+
+        // A symlink to the directory is an entry of its own, unless it is dereferenced
+        let (kept, _) = normalize(targets(&[&docs, &link]), |_| false, false);
+        assert_eq!(kept.len(), 2);
+        let (kept, _) = normalize(targets(&[&docs, &link]), |t| t == link, false);
+        assert_eq!(kept, vec![docs]);
+    }
+
+    #[test]
+    fn test_order_is_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        let [a, b, c] = ["a", "b", "c"].map(|n| dir.path().join(n));
+        for p in [&a, &b, &c] {
+            fs::write(p, "x").unwrap();
+        }
+
+        let (one, _) = normalize(targets(&[&c, &a, &b]), |_| false, false);
+        let (other, _) = normalize(targets(&[&b, &c, &a]), |_| false, false);
+        assert_eq!(one, vec![a.clone(), b.clone(), c.clone()]);
+        assert_eq!(one, other);
+
+        let (given, _) = normalize(targets(&[&c, &a, &b]), |_| false, true);
+        assert_eq!(given, vec![c, a, b]);
+    }
+}
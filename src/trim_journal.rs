@@ -0,0 +1,188 @@
+//! Crash-safety for the LUKS container trim (`0k-core create -e`, step 7).
+//!
+//! The container is allocated with headroom and truncated to the used size once the mapper
+//! is closed. Before truncating, `<output>.trim-journal` records the original and the target
+//! length; it is removed once the new length is verified. A journal left behind means the
+//! process died around `set_len`, where some filesystems leave the length indeterminate: the
+//! next operation on that archive ([`recover`]) finishes or reports the trim.
+
+use crate::error::ZkError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrimJournal {
+    pub original_len: u64,
+    pub target_len: u64,
+}
+
+/// What [`recover`] found (and did) for a leftover journal.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// The trim had completed; only the journal was left
+    AlreadyTrimmed,
+    /// The file was longer than the target and has been truncated again
+    Retrimmed { from: u64, to: u64 },
+    /// The file is shorter than the recorded target: the container may be damaged
+    /// (the journal is kept for inspection)
+    TooShort { len: u64, target_len: u64 },
+    /// The journal could not be parsed (removed; nothing is known about the trim)
+    Unreadable,
+}
+
+/// `<output>.trim-journal`
+pub fn journal_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".trim-journal");
+    PathBuf::from(name)
+}
+
+/// Truncates `output` to `target_len`, journaled. Does nothing if the file is not longer.
+/// Returns the length before truncating.
+pub fn truncate(output: &Path, target_len: u64) -> Result<u64, ZkError> {
+    let file = fs::File::options().write(true).open(output)?;
+    let original_len = file.metadata()?.len();
+    if target_len >= original_len {
+        return Ok(original_len);
+    }
+    let journal = journal_path(output);
+    write_journal(&journal, &TrimJournal { original_len, target_len })?;
+    file.set_len(target_len)?;
+    file.sync_all()?;
+    verify_len(output, target_len)?;
+    fs::remove_file(&journal)?;
+    Ok(original_len)
+}
+
+/// Finishes or reports a trim interrupted on `output`; None if there is no journal.
+pub fn recover(output: &Path) -> Result<Option<Recovery>, ZkError> {
+    let journal = journal_path(output);
+    let content = match fs::read_to_string(&journal) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ZkError::IoError(e)),
+    };
+    let Ok(record) = serde_json::from_str::<TrimJournal>(&content) else {
+        fs::remove_file(&journal)?;
+        return Ok(Some(Recovery::Unreadable));
+    };
+    let len = fs::metadata(output)?.len();
+    let recovery = if len == record.target_len {
+        Recovery::AlreadyTrimmed
+    } else if len > record.target_len {
+        let file = fs::File::options().write(true).open(output)?;
+        file.set_len(record.target_len)?;
+        file.sync_all()?;
+        verify_len(output, record.target_len)?;
+        Recovery::Retrimmed { from: len, to: record.target_len }
+    } else {
+        return Ok(Some(Recovery::TooShort { len, target_len: record.target_len }));
+    };
+    fs::remove_file(&journal)?;
+    Ok(Some(recovery))
+}
+
+/// One line for the user about a [`recover`] outcome.
+pub fn describe(output: &Path, recovery: &Recovery) -> String {
+    match recovery {
+        Recovery::AlreadyTrimmed => {
+            format!("Note: removed a stale trim journal of {} (the trim had completed).", output.display())
+        }
+        Recovery::Retrimmed { from, to } => format!(
+            "Note: finished an interrupted trim of {}: {} -> {} bytes.",
+            output.display(),
+            from,
+            to
+        ),
+        Recovery::TooShort { len, target_len } => format!(
+            "Warning: {} is {} bytes, shorter than the {} bytes recorded before an interrupted trim; \
+             the container may be damaged (see {}).",
+            output.display(),
+            len,
+            target_len,
+            journal_path(output).display()
+        ),
+        Recovery::Unreadable => {
+            format!("Warning: removed an unreadable trim journal of {}.", output.display())
+        }
+    }
+}
+
+fn write_journal(journal: &Path, record: &TrimJournal) -> Result<(), ZkError> {
+    let json = serde_json::to_string(record)
+        .map_err(|e| ZkError::OperationFailed(format!("Cannot encode trim journal: {}", e)))?;
+    let mut file = fs::File::create(journal)?;
+    writeln!(file, "{}", json)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn verify_len(output: &Path, target_len: u64) -> Result<(), ZkError> {
+    let len = fs::metadata(output)?.len();
+    if len != target_len {
+        return Err(ZkError::OperationFailed(format!(
+            "Trim of {} failed: length is {} bytes, expected {}",
+            output.display(),
+            len,
+            target_len
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(dir: &Path, len: usize) -> PathBuf {
+        let path = dir.join("data.sqfs_luks.img");
+        fs::write(&path, vec![7u8; len]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_truncate_removes_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = container(dir.path(), 8192);
+        assert_eq!(truncate(&output, 4096).unwrap(), 8192);
+        assert_eq!(fs::metadata(&output).unwrap().len(), 4096);
+        assert!(!journal_path(&output).exists());
+        // Never grows the file
+        truncate(&output, 6000).unwrap();
+        assert_eq!(fs::metadata(&output).unwrap().len(), 4096);
+        assert_eq!(recover(&output).unwrap(), None);
+    }
+
+    #[test]
+    fn test_recover_journal_states() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = container(dir.path(), 8192);
+        let journal = journal_path(&output);
+        let record = TrimJournal { original_len: 8192, target_len: 4096 };
+
+        // Died before set_len: truncated now
+        write_journal(&journal, &record).unwrap();
+        assert_eq!(recover(&output).unwrap(), Some(Recovery::Retrimmed { from: 8192, to: 4096 }));
+        assert_eq!(fs::metadata(&output).unwrap().len(), 4096);
+        assert!(!journal.exists());
+
+        // Died after set_len, before removing the journal
+        write_journal(&journal, &record).unwrap();
+        assert_eq!(recover(&output).unwrap(), Some(Recovery::AlreadyTrimmed));
+        assert!(!journal.exists());
+
+        // Shorter than the target: reported, journal kept
+        fs::File::options().write(true).open(&output).unwrap().set_len(1000).unwrap();
+        write_journal(&journal, &record).unwrap();
+        let recovery = recover(&output).unwrap().unwrap();
+        assert_eq!(recovery, Recovery::TooShort { len: 1000, target_len: 4096 });
+        assert!(describe(&output, &recovery).contains("may be damaged"));
+        assert!(journal.exists());
+
+        fs::write(&journal, "garbage").unwrap();
+        assert_eq!(recover(&output).unwrap(), Some(Recovery::Unreadable));
+        assert!(!journal.exists());
+    }
+}
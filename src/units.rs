@@ -0,0 +1,177 @@
+//! Sizes and durations with units, for every flag (and config value) that takes one.
+//!
+//! Self-contained on purpose: the CLI definitions use the clap adapters below, and build.rs
+//! compiles those definitions without the rest of the crate. Errors are plain strings that
+//! name the accepted units; [`crate::utils::parse_size`] and [`crate::utils::parse_duration`]
+//! wrap them for callers that report `ZkError`s.
+
+const SIZE_UNITS: &str = "K, M, G, T, P or KiB..PiB (powers of 1024), KB..PB (powers of 1000)";
+const DURATION_UNITS: &str = "s, m, h, d, w or y (365 days)";
+
+/// Parses a size: plain bytes or a number with a unit, case-insensitive. `K`, `M`, `G`, `T`,
+/// `P` and `KiB`..`PiB` are binary, `KB`..`PB` decimal: `4096`, `512M`, `1.5GiB`, `2GB`.
+pub fn parse_size(raw: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "Invalid size '{}': expected a number with an optional unit {} (e.g. 512M or 1.5G)",
+            raw, SIZE_UNITS
+        )
+    };
+    let s = raw.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, base) = match unit.strip_suffix("IB") {
+        Some(prefix) if !prefix.is_empty() => (prefix, 1024f64),
+        _ => match unit.strip_suffix('B') {
+            Some(prefix) if !prefix.is_empty() => (prefix, 1000f64),
+            _ => (unit.as_str(), 1024f64),
+        },
+    };
+    let power = match prefix {
+        "" | "B" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return Err(invalid()),
+    };
+    let value: f64 = number.parse().map_err(|_| invalid())?;
+    let bytes = value * base.powi(power);
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+/// Parses a duration into seconds: one or more numbers with a unit, case-insensitive,
+/// e.g. `90s`, `1h30m`, `1.5d`, `2w`.
+pub fn parse_duration(raw: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration '{}': expected numbers with a unit {} (e.g. 30d or 1h30m)",
+            raw, DURATION_UNITS
+        )
+    };
+    let mut rest = raw.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut seconds = 0f64;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+        let value: f64 = rest[..split].parse().map_err(|_| invalid())?;
+        let unit = rest[split..].chars().next().ok_or_else(invalid)?;
+        let factor = match unit.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86_400,
+            'w' => 7 * 86_400,
+            'y' => 365 * 86_400,
+            _ => return Err(invalid()),
+        };
+        seconds += value * factor as f64;
+        rest = rest[split + unit.len_utf8()..].trim_start();
+    }
+    if seconds >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(seconds.round() as u64)
+}
+
+/// Parses a percentage `0%`..`100%` (fractions allowed); None if `raw` does not end in `%`.
+pub fn parse_percent(raw: &str) -> Option<Result<f64, String>> {
+    let number = raw.trim().strip_suffix('%')?;
+    Some(match number.trim().parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("Invalid percentage '{}': expected 0% to 100% (e.g. 5%)", raw)),
+    })
+}
+
+/// clap `value_parser` for a size flag: bytes.
+pub fn size_arg(raw: &str) -> Result<u64, String> {
+    parse_size(raw)
+}
+
+/// clap `value_parser` for a duration flag: seconds, at least one.
+pub fn duration_arg(raw: &str) -> Result<u64, String> {
+    match parse_duration(raw)? {
+        0 => Err(format!("Invalid duration '{}': must be at least 1s", raw)),
+        seconds => Ok(seconds),
+    }
+}
+
+/// clap `value_parser` for a `SIZE|PERCENT` flag (`--reserve`): the value as given, once it
+/// parses, since 0k passes it on to 0k-core verbatim.
+pub fn size_or_percent_arg(raw: &str) -> Result<String, String> {
+    match parse_percent(raw) {
+        Some(percent) => percent.map(|_| raw.to_string()),
+        None => parse_size(raw).map(|_| raw.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        const GIB: u64 = 1 << 30;
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("512m").unwrap(), 512 << 20);
+        assert_eq!(parse_size("4G").unwrap(), 4 * GIB);
+        assert_eq!(parse_size("1.5G").unwrap(), 3 * GIB / 2);
+        assert_eq!(parse_size("1.5GiB").unwrap(), 3 * GIB / 2);
+        assert_eq!(parse_size("2 gib").unwrap(), 2 * GIB);
+        assert_eq!(parse_size("1P").unwrap(), 1 << 50);
+        // Decimal units
+        assert_eq!(parse_size("2GB").unwrap(), 2_000_000_000);
+        assert_eq!(parse_size("2 gb").unwrap(), 2_000_000_000);
+        assert_eq!(parse_size("1.5kB").unwrap(), 1500);
+        assert_eq!(parse_size("3TB").unwrap(), 3_000_000_000_000);
+        for bad in ["", "G", "12X", "1.2.3G", "-1G", "5 GiBs", "1iB", "99999999999P"] {
+            let e = parse_size(bad).unwrap_err();
+            assert!(e.contains("KiB..PiB") && e.contains("KB..PB"), "{}: {}", bad, e);
+        }
+        assert_eq!(size_arg("1K"), Ok(1024));
+        assert!(size_arg("lots").unwrap_err().contains("powers of 1024"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s").unwrap(), 45);
+        assert_eq!(parse_duration("90m").unwrap(), 5400);
+        assert_eq!(parse_duration("1h30m").unwrap(), 5400);
+        assert_eq!(parse_duration("1h 30m").unwrap(), 5400);
+        assert_eq!(parse_duration("1.5h").unwrap(), 5400);
+        assert_eq!(parse_duration("2H").unwrap(), 7200);
+        assert_eq!(parse_duration("30d").unwrap(), 30 * 86_400);
+        assert_eq!(parse_duration("2w").unwrap(), 14 * 86_400);
+        assert_eq!(parse_duration("1y").unwrap(), 365 * 86_400);
+        assert_eq!(parse_duration("0s").unwrap(), 0);
+        for bad in ["", "30", "30x", "h", "1h30", "-5m", "1..5h", "3 days", "99999999999999999999d"] {
+            let e = parse_duration(bad).unwrap_err();
+            assert!(e.contains("s, m, h, d, w or y"), "{}: {}", bad, e);
+        }
+        assert_eq!(duration_arg("12h"), Ok(43_200));
+        assert!(duration_arg("0m").unwrap_err().contains("at least 1s"));
+        assert!(duration_arg("soon").unwrap_err().contains("e.g. 30d"));
+    }
+
+    #[test]
+    fn test_parse_percent_and_reserve_arg() {
+        assert_eq!(parse_percent("5%"), Some(Ok(5.0)));
+        assert_eq!(parse_percent(" 2.5 % "), Some(Ok(2.5)));
+        assert!(parse_percent("150%").unwrap().is_err());
+        assert_eq!(parse_percent("5G"), None);
+        assert_eq!(size_or_percent_arg("2G"), Ok("2G".to_string()));
+        assert_eq!(size_or_percent_arg("5%"), Ok("5%".to_string()));
+        assert!(size_or_percent_arg("-1%").unwrap_err().contains("0% to 100%"));
+        assert!(size_or_percent_arg("2X").unwrap_err().contains("powers of 1000"));
+    }
+}
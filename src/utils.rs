@@ -1,6 +1,6 @@
 use crate::error::ZkError;
-use crate::executor::CommandExecutor;
 use log::warn;
+use std::ffi::OsStr;
 use std::fs;
 use std::path::Path;
 
@@ -22,6 +22,12 @@ pub fn get_file_type(path: &Path) -> Result<ArchiveType, ZkError> {
         return Err(ZkError::InvalidPath(path.to_path_buf()));
     }
     
+    // infer has no SquashFS matcher: its superblock starts with the magic `hsqs`
+    let mut magic = [0u8; 4];
+    if fs::File::open(path).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic)).is_ok() && &magic == b"hsqs" {
+        return Ok(ArchiveType::Squashfs);
+    }
+
     // Check using infer (magic numbers)
     // We read the first few bytes
     let kind = infer::get_from_path(path)
@@ -55,25 +61,69 @@ pub fn get_file_type(path: &Path) -> Result<ArchiveType, ZkError> {
     }
 }
 
-pub fn is_luks_image(image_path: &Path, executor: &impl CommandExecutor) -> bool {
-    let img_str = match image_path.to_str() {
-        Some(s) => s,
-        None => return false,
-    };
+/// Header magic of LUKS1 and LUKS2 containers, at offset 0
+const LUKS_MAGIC: &[u8; 6] = b"LUKS\xba\xbe";
 
-    // Run cryptsetup isLuks directly (no sudo needed - just reads file header)
-    if let Ok(output) = executor.run("cryptsetup", &["isLuks", img_str]) {
-        output.status.success()
-    } else {
-        false
-    }
+/// Whether `image_path` starts with a LUKS header. The magic is read directly rather than
+/// asked of `cryptsetup isLuks`, so a missing cryptsetup cannot make a container look like a
+/// plain archive. A file that cannot be read is not taken for a container.
+pub fn is_luks_image(image_path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0u8; LUKS_MAGIC.len()];
+    fs::File::open(image_path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == LUKS_MAGIC
 }
 
-// Stub implementation for TDD phase
-
 pub fn get_current_uid() -> Result<u32, ZkError> {
-    let content = fs::read_to_string("/proc/self/status").map_err(ZkError::IoError)?;
-    parse_uid_from_status(&content)
+    Ok(current_uid_with(|path| fs::read_to_string(path)))
+}
+
+/// Effective uid from /proc/self/status read with `read`; straight from geteuid(2) where
+/// /proc is not mounted (or the file has no usable Uid line).
+fn current_uid_with(read: impl Fn(&Path) -> std::io::Result<String>) -> u32 {
+    read(Path::new("/proc/self/status"))
+        .ok()
+        .and_then(|status| parse_uid_from_status(&status).ok())
+        // SAFETY: geteuid cannot fail and has no preconditions
+        .unwrap_or_else(|| unsafe { libc::geteuid() })
+}
+
+/// True if /proc is mounted. Without it squashfuse mounts cannot be found and mount
+/// discovery falls back to /etc/mtab (see [`crate::mounts`]).
+pub fn proc_mounted() -> bool {
+    Path::new("/proc/self/stat").exists()
+}
+
+/// Processes descended from `pid` (its children, then theirs), from the parent ids in
+/// /proc/<pid>/stat; empty without /proc.
+pub fn descendant_pids(pid: u32) -> Vec<u32> {
+    let parents: Vec<(u32, u32)> = fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let child = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            Some((child, parse_stat_ppid(&stat)?))
+        })
+        .collect();
+    let mut descendants = Vec::new();
+    let mut next = vec![pid];
+    while let Some(parent) = next.pop() {
+        for &(child, _) in parents.iter().filter(|(_, ppid)| *ppid == parent) {
+            if !descendants.contains(&child) {
+                descendants.push(child);
+                next.push(child);
+            }
+        }
+    }
+    descendants
+}
+
+/// The parent pid in /proc/<pid>/stat content: the second field after the `)` closing the
+/// command name, which may itself contain spaces and parentheses.
+fn parse_stat_ppid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
 }
 
 pub fn is_root() -> Result<bool, ZkError> {
@@ -91,6 +141,125 @@ pub fn get_superuser_command() -> Option<String> {
     None
 }
 
+/// External tools an operation shells out to, checked up front by [`check_dependencies`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dependency {
+    Mksquashfs,
+    Unshare,
+    Rsync,
+    Cryptsetup,
+    Tar2sqfs,
+    Squashfuse,
+    Fusermount,
+}
+
+impl Dependency {
+    pub fn program(self) -> &'static str {
+        match self {
+            Dependency::Mksquashfs => "mksquashfs",
+            Dependency::Unshare => "unshare",
+            Dependency::Rsync => "rsync",
+            Dependency::Cryptsetup => "cryptsetup",
+            Dependency::Tar2sqfs => "tar2sqfs",
+            Dependency::Squashfuse => "squashfuse",
+            Dependency::Fusermount => "fusermount",
+        }
+    }
+
+    /// The package that ships the tool (the name is the same on the common distributions)
+    pub fn package(self) -> &'static str {
+        match self {
+            Dependency::Mksquashfs => "squashfs-tools",
+            Dependency::Unshare => "util-linux",
+            Dependency::Rsync => "rsync",
+            Dependency::Cryptsetup => "cryptsetup",
+            Dependency::Tar2sqfs => "squashfs-tools-ng",
+            Dependency::Squashfuse => "squashfuse",
+            Dependency::Fusermount => "fuse3 (or fuse)",
+        }
+    }
+}
+
+/// Tools needed to mount an archive: cryptsetup for a LUKS container, squashfuse (and
+/// fusermount to unmount it again) for a plain image.
+pub fn mount_dependencies(encrypted: bool) -> &'static [Dependency] {
+    if encrypted {
+        &[Dependency::Cryptsetup]
+    } else {
+        &[Dependency::Squashfuse, Dependency::Fusermount]
+    }
+}
+
+/// Fails with every tool of `deps` that is not on PATH, before any work is done.
+pub fn check_dependencies(deps: &[Dependency]) -> Result<(), ZkError> {
+    check_dependencies_in(deps, std::env::var_os("PATH"))
+}
+
+fn check_dependencies_in(deps: &[Dependency], path: Option<std::ffi::OsString>) -> Result<(), ZkError> {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| "/".into());
+    let mut missing: Vec<Dependency> = Vec::new();
+    for dep in deps {
+        if !missing.contains(dep) && which::which_in(dep.program(), path.as_ref(), &cwd).is_err() {
+            missing.push(*dep);
+        }
+    }
+    if missing.is_empty() { Ok(()) } else { Err(ZkError::MissingDependencies(missing)) }
+}
+
+/// Escape a string for safe use inside single quotes in POSIX shell.
+/// Single quotes prevent ALL interpretation ($, `, \, etc.).
+/// The only character that needs escaping is `'` itself: `'` -> `'\''`
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Renders a command for the user to copy into bash or zsh. Words made only of
+/// characters no shell treats specially are left bare; everything else is quoted, and
+/// words that are not UTF-8 keep their exact bytes in `$'...'` quotes.
+pub fn suggest_command(parts: &[&OsStr]) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    parts
+        .iter()
+        .map(|part| match part.to_str() {
+            Some(word) => {
+                let plain = !word.is_empty()
+                    && word.chars().all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+                if plain { word.to_string() } else { shell_quote(word) }
+            }
+            None => ansi_c_quote(part.as_bytes()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `$'...'` quoting (bash, zsh): printable ASCII as is, every other byte as `\xHH`.
+fn ansi_c_quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("$'");
+    for &b in bytes {
+        match b {
+            b'\'' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(b as char);
+            }
+            b' '..=b'~' => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// The command line of this process, ready to be rerun as root.
+fn current_command() -> String {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    suggest_command(&args.iter().map(|a| a.as_os_str()).collect::<Vec<_>>())
+}
+
+/// Advice for an operation that needs root: the current command prefixed with sudo.
+pub fn sudo_advice() -> String {
+    format!("Please run it with sudo: sudo {}", current_command())
+}
+
 pub fn check_root_or_get_runner(reason: &str) -> Result<Option<String>, ZkError> {
     if is_root()? {
         return Ok(None);
@@ -104,9 +273,10 @@ pub fn check_root_or_get_runner(reason: &str) -> Result<Option<String>, ZkError>
         return Ok(Some(runner));
     }
 
-    Err(ZkError::OperationFailed(
-        "Root privileges required but no elevation tool (sudo, doas, etc.) found.".to_string(),
-    ))
+    Err(ZkError::OperationFailed(format!(
+        "Root privileges required but no elevation tool (sudo, doas, etc.) found. Run it as root: {}",
+        current_command()
+    )))
 }
 
 pub fn is_permission_denied(err: &ZkError) -> bool {
@@ -131,6 +301,7 @@ pub fn re_exec_with_runner(runner: &str) -> Result<(), ZkError> {
     // args[0] is the current binary path
     let program = &args[0];
     let cmd_args = &args[1..];
+    crate::events::release_stdout();
 
     // Construct command: runner program args...
     // But Runner might be "sudo".
@@ -156,6 +327,7 @@ pub fn re_exec_with_runner_custom_args(runner: &str, new_args: &[String]) -> Res
     
     let current_args: Vec<String> = std::env::args().collect();
     let program = &current_args[0];
+    crate::events::release_stdout();
 
     // Construct command: runner program new_args...
     let err = std::process::Command::new(runner)
@@ -193,6 +365,92 @@ fn parse_uid_from_status(content: &str) -> Result<u32, ZkError> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_shell_quote() {
+        // Normal string
+        assert_eq!(shell_quote("hello"), "'hello'");
+        // String with single quote
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        // Dangerous characters that double-quotes would NOT protect from
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+        assert_eq!(shell_quote("`malicious`"), "'`malicious`'");
+        assert_eq!(shell_quote("path with $VAR"), "'path with $VAR'");
+        assert_eq!(shell_quote("back\\slash"), "'back\\slash'");
+    }
+
+    #[test]
+    fn test_is_luks_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("data.sqfs_luks.img");
+        let mut header = LUKS_MAGIC.to_vec();
+        header.extend([0, 2]);
+        fs::write(&image, &header).unwrap();
+        assert!(is_luks_image(&image));
+
+        fs::write(&image, b"hsqs").unwrap();
+        assert!(!is_luks_image(&image));
+        fs::write(&image, b"LUKS").unwrap(); // too short
+        assert!(!is_luks_image(&image));
+        assert!(!is_luks_image(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_suggest_command() {
+        let cmd = |parts: &[&str]| suggest_command(&parts.iter().map(OsStr::new).collect::<Vec<_>>());
+        // Plain words stay readable
+        assert_eq!(cmd(&["0k-core", "umount", "--lazy", "/mnt/data_1"]), "0k-core umount --lazy /mnt/data_1");
+        // Spaces and shell syntax
+        assert_eq!(cmd(&["umount", "/mnt/my disk"]), "umount '/mnt/my disk'");
+        assert_eq!(cmd(&["umount", "/mnt/$HOME;rm"]), "umount '/mnt/$HOME;rm'");
+        // Quotes
+        assert_eq!(cmd(&["umount", "/mnt/it's"]), "umount '/mnt/it'\\''s'");
+        assert_eq!(cmd(&["umount", "/mnt/\"x\""]), "umount '/mnt/\"x\"'");
+        // Unicode is quoted, not mangled
+        assert_eq!(cmd(&["umount", "/mnt/Фото 📷"]), "umount '/mnt/Фото 📷'");
+        assert_eq!(cmd(&["umount", "/mnt/données"]), "umount '/mnt/données'");
+        // An empty argument must not disappear
+        assert_eq!(cmd(&["echo", ""]), "echo ''");
+        // Bytes that are not UTF-8 are kept exactly
+        use std::os::unix::ffi::OsStrExt;
+        let raw = OsStr::from_bytes(b"/mnt/caf\xe9 it's\\");
+        assert_eq!(suggest_command(&[OsStr::new("umount"), raw]), "umount $'/mnt/caf\\xe9 it\\'s\\\\'");
+    }
+
+    #[test]
+    fn test_get_hostname_matches_uname() {
+        let host = get_hostname().unwrap();
+        assert!(!host.is_empty());
+        if let Ok(out) = std::process::Command::new("uname").arg("-n").output() {
+            assert_eq!(host, String::from_utf8_lossy(&out.stdout).trim());
+        }
+    }
+
+    #[test]
+    fn test_check_dependencies_with_fake_path() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = tempfile::tempdir().unwrap();
+        for tool in ["mksquashfs", "unshare"] {
+            let path = bin.path().join(tool);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        // Present but not executable: not usable
+        fs::write(bin.path().join("rsync"), "").unwrap();
+        let path = Some(bin.path().as_os_str().to_os_string());
+
+        check_dependencies_in(&[Dependency::Mksquashfs, Dependency::Unshare], path.clone()).unwrap();
+
+        let deps = [Dependency::Unshare, Dependency::Rsync, Dependency::Cryptsetup, Dependency::Rsync];
+        let err = check_dependencies_in(&deps, path).unwrap_err();
+        assert!(matches!(&err, ZkError::MissingDependencies(m) if m == &[Dependency::Rsync, Dependency::Cryptsetup]));
+        assert_eq!(err.to_string(), "Missing required tools: rsync, cryptsetup");
+        let hint = err.friendly_message().unwrap();
+        assert!(hint.contains("rsync (package: rsync)"), "{}", hint);
+        assert!(hint.contains("cryptsetup (package: cryptsetup)"), "{}", hint);
+
+        assert_eq!(mount_dependencies(true), [Dependency::Cryptsetup]);
+    }
+
     // --- is_root() tests ---
     // Since is_root interacts with OS, we test the parsing logic primarily
 
@@ -220,6 +478,16 @@ mod tests {
         assert_eq!(uid, 1000);
     }
 
+    #[test]
+    fn test_current_uid_without_proc() {
+        // /proc mounted: the effective uid of the status file
+        assert_eq!(current_uid_with(|_| Ok("Uid:\t0\t4242\t0\t0\n".to_string())), 4242);
+        // Not mounted (or a status file without Uid): geteuid
+        let euid = unsafe { libc::geteuid() };
+        assert_eq!(current_uid_with(|_| Err(std::io::ErrorKind::NotFound.into())), euid);
+        assert_eq!(current_uid_with(|_| Ok("Name:\tzks\n".to_string())), euid);
+    }
+
     // --- check_root_or_get_runner tests ---
     // We can't easily mock is_root() and get_superuser_command() here without dependency injection or conditional compilation mocking.
     // For now, we will verify the parser logic as requested in the Prompt.
@@ -261,6 +529,52 @@ mod tests {
         let paths = vec![file];
         assert!(!check_read_permissions(&paths).unwrap());
     }
+
+    #[test]
+    fn test_find_dangling_symlinks() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("data");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("file.txt"), "x").unwrap();
+        std::os::unix::fs::symlink("file.txt", root.join("good")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", root.join("broken")).unwrap();
+        std::os::unix::fs::symlink("loop", root.join("sub/loop")).unwrap();
+
+        let mut found = find_dangling_symlinks(&root, false);
+        found.sort();
+        assert_eq!(found, vec![root.join("broken"), root.join("sub/loop")]);
+        assert_eq!(find_dangling_symlinks(&root.join("broken"), false), vec![root.join("broken")]);
+        assert!(find_dangling_symlinks(&root.join("good"), false).is_empty());
+
+        // A symlinked root is walked into when dereferenced
+        std::os::unix::fs::symlink(root.join("sub"), temp.path().join("sublink")).unwrap();
+        assert!(find_dangling_symlinks(&temp.path().join("sublink"), false).is_empty());
+        assert_eq!(find_dangling_symlinks(&temp.path().join("sublink"), true), vec![temp.path().join("sublink/loop")]);
+    }
+
+    #[test]
+    fn test_find_unreadable() {
+        // Root can read everything
+        if is_root().unwrap() {
+            return;
+        }
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("data");
+        fs::create_dir_all(root.join("locked/inner")).unwrap();
+        fs::write(root.join("ok.txt"), "ok").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        fs::set_permissions(root.join("secret.txt"), fs::Permissions::from_mode(0o000)).unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+
+        let mut found = find_unreadable(&root);
+        found.sort();
+        // The locked directory is reported once, its contents are not visited
+        assert_eq!(found, vec![root.join("locked"), root.join("secret.txt")]);
+
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o700)).unwrap();
+    }
 }
 
 use std::path::PathBuf;
@@ -302,20 +616,209 @@ pub fn ensure_read_permissions(paths: &[PathBuf]) -> Result<(), ZkError> {
     Ok(())
 }
 
+/// True if `path` is a directory we cannot list or a regular file we cannot open.
+/// Anything else (symlinks, devices, fifos) is never reported: opening a fifo would block.
+pub fn is_unreadable(path: &Path) -> bool {
+    let denied = |r: std::io::Result<()>| {
+        matches!(r, Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied)
+    };
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => denied(fs::read_dir(path).map(|_| ())),
+        Ok(m) if m.is_file() => denied(fs::File::open(path).map(|_| ())),
+        _ => false,
+    }
+}
+
+/// Recursively collects the paths under `root` that `is_unreadable` (for `freeze --skip-unreadable`).
+/// An unreadable directory is reported once; nothing below it is visited.
+pub fn find_unreadable(root: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut walker = walkdir::WalkDir::new(root).follow_links(false).into_iter();
+    while let Some(item) = walker.next() {
+        let entry = match item {
+            Ok(e) => e,
+            Err(e) => {
+                // Listing failed in between our check and the walk: treat it as unreadable too
+                if let Some(p) = e.path()
+                    && e.io_error().map(|io| io.kind()) == Some(std::io::ErrorKind::PermissionDenied)
+                    && !found.iter().any(|f: &PathBuf| f == p)
+                {
+                    found.push(p.to_path_buf());
+                }
+                continue;
+            }
+        };
+        if is_unreadable(entry.path()) {
+            if entry.file_type().is_dir() {
+                walker.skip_current_dir();
+            }
+            found.push(entry.into_path());
+        }
+    }
+    found
+}
+
+/// Symlinks at or below `root` that point nowhere (missing target, or a loop). A symlinked
+/// `root` is itself checked, or with `follow_root` walked into (as freeze `-L` archives it).
+pub fn find_dangling_symlinks(root: &Path, follow_root: bool) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .follow_root_links(follow_root)
+        .into_iter()
+        .filter_map(|item| item.ok())
+        .filter(|entry| entry.path_is_symlink() && fs::metadata(entry.path()).is_err())
+        .map(walkdir::DirEntry::into_path)
+        .collect()
+}
+
+/// True if `path` itself (symlinks are not followed) carries extended attributes.
+/// POSIX ACLs and SELinux labels are stored as such (system.posix_acl_*, security.selinux).
+pub fn has_xattrs(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: c_path is NUL-terminated; a null buffer of size 0 only asks for the list length
+    let len = unsafe { libc::llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    len > 0
+}
+
+/// True if some entry under `root` (or `root` itself) carries extended attributes.
+/// Gives up after `XATTR_PROBE_LIMIT` entries: large archives are not walked twice.
+pub fn tree_has_xattrs(root: &Path) -> bool {
+    walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .take(crate::constants::XATTR_PROBE_LIMIT)
+        .any(|e| has_xattrs(e.path()))
+}
+
+/// `MemTotal` and `MemAvailable` from /proc/meminfo, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemInfo {
+    pub total: u64,
+    pub available: u64,
+}
+
+/// Parses /proc/meminfo content (`MemAvailable:  1843200 kB`); None if a field is missing.
+pub fn parse_meminfo(content: &str) -> Option<MemInfo> {
+    let field = |name: &str| {
+        content.lines().find_map(|line| {
+            let rest = line.strip_prefix(name)?.strip_prefix(':')?;
+            let mut words = rest.split_whitespace();
+            let value: u64 = words.next()?.parse().ok()?;
+            match words.next() {
+                Some("kB") => Some(value * 1024),
+                None => Some(value),
+                Some(_) => None,
+            }
+        })
+    };
+    Some(MemInfo { total: field("MemTotal")?, available: field("MemAvailable")? })
+}
+
+/// This machine's memory (None if /proc/meminfo is unavailable, e.g. on old kernels).
+pub fn read_meminfo() -> Option<MemInfo> {
+    parse_meminfo(&fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+/// `mksquashfs -mem` when `--mem` is not given: 25% of the available memory on machines with
+/// less than 4 GiB, where mksquashfs's own default gets freezes OOM-killed. None keeps the
+/// mksquashfs default.
+pub fn default_mksquashfs_mem(mem: MemInfo) -> Option<u64> {
+    use crate::constants::{MKSQUASHFS_MIN_MEM_BYTES, SMALL_MACHINE_MEM_BYTES};
+    (mem.total < SMALL_MACHINE_MEM_BYTES).then(|| (mem.available / 4).max(MKSQUASHFS_MIN_MEM_BYTES))
+}
+
+/// Number of physical cores from `(physical_package_id, core_id)` pairs, one per logical CPU
+/// (hyper-threads share a pair).
+fn count_physical_cores(topology: impl IntoIterator<Item = (String, String)>) -> usize {
+    topology.into_iter().collect::<std::collections::HashSet<_>>().len()
+}
+
+/// This machine's physical cores (sysfs CPU topology), else its logical CPUs; at least 1.
+pub fn physical_cores() -> usize {
+    let read = |cpu: &Path, file: &str| fs::read_to_string(cpu.join("topology").join(file)).ok();
+    let topology: Vec<(String, String)> = fs::read_dir("/sys/devices/system/cpu")
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            name.strip_prefix("cpu").is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .filter_map(|e| Some((read(&e.path(), "physical_package_id")?, read(&e.path(), "core_id")?)))
+        .collect();
+    match count_physical_cores(topology) {
+        0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        cores => cores,
+    }
+}
+
+/// Threads the whole pipeline may use: `--threads`, else `threads` in the config file,
+/// else the number of physical cores.
+pub fn resolve_threads(flag: Option<u32>) -> u32 {
+    let (threads, source) = match flag {
+        Some(n) => (n, "--threads"),
+        None => match crate::config::Config::load().threads.filter(|n| *n > 0) {
+            Some(n) => (n, "config"),
+            None => (physical_cores() as u32, "physical cores"),
+        },
+    };
+    let threads = threads.max(1);
+    log::debug!("threads: {} ({})", threads, source);
+    threads
+}
+
+/// True if SELinux is enabled and in enforcing mode.
+pub fn selinux_enforcing() -> bool {
+    fs::read_to_string("/sys/fs/selinux/enforce").is_ok_and(|v| v.trim() == "1")
+}
+
+/// The closest existing ancestor of `path` (or `path` itself): where a file about to be
+/// created will live.
+pub fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .map(|p| if p.as_os_str().is_empty() { Path::new(".") } else { p })
+        .find(|p| p.exists())
+}
+
 /// Returns the path to $TMPDIR/0k-cache-<uid> (or /tmp/0k-cache-<uid> if TMPDIR not set)
-/// without ensuring it exists.
-pub fn get_0k_temp_dir_path() -> Result<PathBuf, ZkError> {
+/// without ensuring it exists. `base` replaces $TMPDIR (freeze staging, see [`staging_base`]).
+pub fn get_0k_temp_dir_path(base: Option<&Path>) -> Result<PathBuf, ZkError> {
     let uid = get_current_uid()?;
-    let tmp_base = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
-    Ok(PathBuf::from(format!("{}/0k-cache-{}", tmp_base, uid)))
+    let tmp_base = match base {
+        Some(base) => base.to_path_buf(),
+        None => PathBuf::from(std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string())),
+    };
+    Ok(tmp_base.join(format!("0k-cache-{}", uid)))
 }
 
-/// Returns the path to /tmp/0k-cache-<uid> and ensures it exists with 0700 permissions.
-/// Uses atomic mkdir + ownership verification to prevent symlink attacks (TOCTOU).
-pub fn get_0k_temp_dir() -> Result<PathBuf, ZkError> {
+/// Base directory of freeze staging: `flag` (`--staging-dir`), else `$ZK_STAGING_DIR`, made
+/// absolute; None for $TMPDIR.
+pub fn staging_base(flag: Option<&Path>) -> Option<PathBuf> {
+    staging_base_from(flag, std::env::var_os(crate::constants::STAGING_DIR_ENV))
+}
+
+fn staging_base_from(flag: Option<&Path>, env: Option<std::ffi::OsString>) -> Option<PathBuf> {
+    let base = flag.map(Path::to_path_buf).or_else(|| env.filter(|v| !v.is_empty()).map(PathBuf::from))?;
+    Some(std::path::absolute(&base).unwrap_or(base))
+}
+
+/// Returns the path to /tmp/0k-cache-<uid> (below `base` if given) and ensures it exists
+/// with 0700 permissions. Uses atomic mkdir + ownership verification to prevent symlink
+/// attacks (TOCTOU).
+pub fn get_0k_temp_dir(base: Option<&Path>) -> Result<PathBuf, ZkError> {
     use std::os::unix::fs::MetadataExt;
     use std::os::unix::fs::PermissionsExt;
-    let path = get_0k_temp_dir_path()?;
+    if let Some(base) = base
+        && !base.is_dir()
+    {
+        return Err(ZkError::StagingError(format!("Staging directory {:?} does not exist or is not a directory", base)));
+    }
+    let path = get_0k_temp_dir_path(base)?;
     let uid = get_current_uid()?;
 
     // Attempt atomic create (not create_dir_all — that follows symlinks).
@@ -369,6 +872,230 @@ pub fn get_0k_temp_dir() -> Result<PathBuf, ZkError> {
     Ok(path)
 }
 
+/// Hostname of this machine (gethostname(2), as `uname -n` prints it), recorded in manifests
+/// and usable in name templates.
+pub fn get_hostname() -> Result<String, ZkError> {
+    // HOST_NAME_MAX is 64 on Linux; the rest is room for the terminating NUL
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return Err(ZkError::OperationFailed(format!(
+            "Failed to read the hostname: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec())
+        .map(|s| s.trim().to_string())
+        .map_err(|e| ZkError::OperationFailed(format!("Hostname is not valid UTF-8: {}", e)))
+}
+
+/// Default template for auto-generated archive names: `prefix_unixtime_random`.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{prefix}_{time}_{rand}";
+
+/// Template for temporary mount point names (see [`create_temp_mount_point`]).
+const MOUNT_NAME_TEMPLATE: &str = "mount_{prefix}_{rand}";
+
+const NAME_PLACEHOLDERS: &[&str] = &["prefix", "date", "time", "host", "rand"];
+
+/// Checks a `--name-template`: only known placeholders (`{prefix}`, `{date}`, `{time}`,
+/// `{host}`, `{rand}`) and path-safe literal characters (`A-Z a-z 0-9 . _ -`) are allowed.
+pub fn validate_name_template(template: &str) -> Result<(), ZkError> {
+    let invalid = |why: String| {
+        ZkError::OperationFailed(format!("Invalid name template '{}': {}", template, why))
+    };
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            let end = rest.find('}').ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+            let name = &rest[1..end];
+            if !NAME_PLACEHOLDERS.contains(&name) {
+                return Err(invalid(format!("unknown placeholder {{{}}}", name)));
+            }
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+            rest = &rest[1..];
+        } else {
+            return Err(invalid(format!("character '{}' is not allowed", c)));
+        }
+    }
+    if template.is_empty() || template.starts_with('.') {
+        return Err(invalid("must not be empty or start with '.'".to_string()));
+    }
+    Ok(())
+}
+
+/// Makes `raw` safe as part of a generated name: characters other than `A-Z a-z 0-9 _` and
+/// `extra` become `_`, runs of `_` collapse into one, `_` at either end is dropped, and at
+/// most `max_len` characters are kept. The result may be empty.
+pub fn sanitize_name_part(raw: &str, extra: &[char], max_len: usize) -> String {
+    let mut out = String::new();
+    for c in raw.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '_' || extra.contains(&c) { c } else { '_' };
+        if !(c == '_' && out.ends_with('_')) {
+            out.push(c);
+        }
+    }
+    out.truncate(max_len);
+    out.trim_matches('_').to_string()
+}
+
+/// Normalizes a `{prefix}` for auto-generated archive names to the characters a name
+/// template may contain (`A-Z a-z 0-9 . _ -`, see [`validate_name_template`]), without a
+/// leading `.`. Falls back to `archive` if nothing usable is left (e.g. only emoji).
+pub fn sanitize_prefix(raw: &str) -> String {
+    let cleaned = sanitize_name_part(raw, &['.', '-'], crate::constants::NAME_PREFIX_MAX_LEN);
+    let cleaned = cleaned.trim_start_matches(['.', '_']);
+    if cleaned.is_empty() { "archive".to_string() } else { cleaned.to_string() }
+}
+
+/// Formats a unix timestamp as a UTC `YYYYMMDD-HHMMSS` string (for `{date}`).
+pub fn format_utc_date(secs: u64) -> String {
+    // Civil-from-days (Howard Hinnant), valid for all dates after 1970
+    let days = (secs / 86400) as i64;
+    let tod = secs % 86400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, tod / 3600, tod % 3600 / 60, tod % 60
+    )
+}
+
+/// Renders a validated name template; `{rand}` gets a fresh 6-digit number on every call.
+fn render_name_template(template: &str, prefix: &str) -> Result<String, ZkError> {
+    use rand::Rng;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| ZkError::OperationFailed(format!("Time error: {}", e)))?
+        .as_secs();
+    let mut name = template
+        .replace("{prefix}", prefix)
+        .replace("{time}", &now.to_string())
+        .replace("{date}", &format_utc_date(now))
+        .replace("{rand}", &rand::rng().random_range(100000..999999u32).to_string());
+    if name.contains("{host}") {
+        name = name.replace("{host}", &get_hostname()?);
+    }
+    if name.contains('/') || name.contains('\0') || name == ".." {
+        return Err(ZkError::OperationFailed(format!(
+            "Generated name '{}' is not a valid file name",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+/// Generates `<dir>/<template>.<ext>` for a path that does not exist yet, retrying with a new
+/// `{rand}` on collision (bounded by `NAME_GENERATION_ATTEMPTS`).
+pub fn generate_unique_path(dir: &Path, template: &str, prefix: &str, ext: &str) -> Result<PathBuf, ZkError> {
+    validate_name_template(template)?;
+    for _ in 0..crate::constants::NAME_GENERATION_ATTEMPTS {
+        let path = dir.join(format!("{}.{}", render_name_template(template, prefix)?, ext));
+        if fs::symlink_metadata(&path).is_err() {
+            return Ok(path);
+        }
+    }
+    Err(ZkError::OperationFailed(format!(
+        "Could not generate a free file name in {} (template '{}' keeps colliding)",
+        dir.display(),
+        template
+    )))
+}
+
+/// Creates a fresh, private (0700) mount point `mount_<tag>_<random>` inside the
+/// hardened 0k temp root (see [`get_0k_temp_dir`]), so temporary mounts never depend on
+/// the permissions or mount options (noexec/nodev) of an arbitrary `$TMPDIR` subdirectory.
+pub fn create_temp_mount_point(tag: &str) -> Result<PathBuf, ZkError> {
+    use std::os::unix::fs::DirBuilderExt;
+    let root = get_0k_temp_dir(None)?;
+    for _ in 0..crate::constants::NAME_GENERATION_ATTEMPTS {
+        let path = root.join(render_name_template(MOUNT_NAME_TEMPLATE, tag)?);
+        match fs::DirBuilder::new().mode(0o700).create(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(ZkError::IoError(e)),
+        }
+    }
+    Err(ZkError::OperationFailed(format!(
+        "Could not create a unique mount point in {}",
+        root.display()
+    )))
+}
+
+/// Parses an octal permission string like "600" or "0640" (`--mode`).
+pub fn parse_octal_mode(s: &str) -> Result<u32, ZkError> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(ZkError::OperationFailed(format!(
+            "Invalid mode: '{}'. Expected octal permissions like 600 or 0640.",
+            s
+        ))),
+    }
+}
+
+/// Checks `--mksquashfs-arg` values before they are appended to the mksquashfs command.
+/// Each value is one argv element: no whitespace or control characters (nothing that reads
+/// as several arguments once it passes through a script), and no paths, so an extra value can
+/// neither add a source nor redirect the output. A bare value must follow an option (`-b 1M`).
+/// Exclusions (`-e`, `-ef`) are refused: the manifest would still list what they leave out.
+pub fn validate_mksquashfs_args(args: &[String]) -> Result<(), ZkError> {
+    let invalid = |arg: &str, why: &str| {
+        Err(ZkError::OperationFailed(format!("Invalid --mksquashfs-arg '{}': {}", arg.escape_default(), why)))
+    };
+    let mut after_option = false;
+    for arg in args {
+        if arg.is_empty() {
+            return invalid(arg, "empty argument");
+        }
+        if arg.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return invalid(arg, "whitespace and control characters are not allowed (pass each word separately)");
+        }
+        if arg.contains('/') || arg.starts_with('~') || arg == "." || arg == ".." {
+            return invalid(arg, "paths are not allowed (the source and the archive are set by 0k-core)");
+        }
+        if arg == "-e" || arg == "-ef" {
+            return invalid(arg, "exclusions are not allowed (use freeze --exclude or create --exclude-file, which the manifest follows)");
+        }
+        if arg.starts_with('-') {
+            after_option = true;
+        } else if after_option {
+            after_option = false;
+        } else {
+            return invalid(arg, "a value must follow an option");
+        }
+    }
+    Ok(())
+}
+
+/// Sets the exact permission bits of `path` (not affected by umask).
+pub fn set_file_mode(path: &Path, mode: u32) -> Result<(), ZkError> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(ZkError::IoError)
+}
+
+/// Creates an empty output file with exactly `mode` permissions before a tool writes into it,
+/// so the archive is never readable by others, even while it is being written.
+/// Fails if the file already exists.
+pub fn create_file_with_mode(path: &Path, mode: u32) -> Result<(), ZkError> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .map_err(ZkError::IoError)?;
+    // OpenOptions::mode is masked by umask; force the exact bits
+    set_file_mode(path, mode)
+}
+
 /// Unescape octal sequences in /proc/self/mountinfo and /proc/mounts paths (e.g., \040 → space).
 pub fn unescape_mountinfo_octal(s: &str) -> String {
     let bytes = s.as_bytes();
@@ -411,6 +1138,244 @@ pub fn expand_tilde(path_str: &str) -> PathBuf {
     PathBuf::from(path_str)
 }
 
+/// Returns true if an unmount error message means the mount point is still in use (EBUSY).
+pub fn is_busy_error(stderr: &str) -> bool {
+    let lower = stderr.to_lowercase();
+    lower.contains("device or resource busy") || lower.contains("target is busy")
+}
+
+/// Returns true if `cryptsetup open` failed because of a wrong passphrase
+/// (as opposed to a missing device, corrupt header, permission problem, ...).
+pub fn is_bad_passphrase_error(stderr: &str) -> bool {
+    stderr.contains("No key available with this passphrase")
+}
+
+/// A process holding a path inside a mount point open (fallback for `fuser -vm`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountHolder {
+    pub pid: u32,
+    pub command: String,
+    pub path: PathBuf,
+}
+
+/// Scans `/proc/*/{cwd,root,fd/*}` for processes using paths under `mount_point`.
+/// Processes we cannot inspect (other users without root) are silently skipped.
+pub fn find_mount_holders(mount_point: &Path) -> Vec<MountHolder> {
+    let mut holders = Vec::new();
+    let Ok(proc_dir) = fs::read_dir("/proc") else {
+        return holders;
+    };
+
+    for entry in proc_dir.flatten().take(crate::constants::PROC_SCAN_LIMIT) {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let proc_path = entry.path();
+        let command = fs::read_to_string(proc_path.join("comm"))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default();
+
+        let mut links = vec![proc_path.join("cwd"), proc_path.join("root")];
+        if let Ok(fds) = fs::read_dir(proc_path.join("fd")) {
+            links.extend(fds.flatten().map(|fd| fd.path()));
+        }
+
+        for link in links {
+            if let Ok(target) = fs::read_link(&link)
+                && target.starts_with(mount_point)
+                && !holders.iter().any(|h| h.pid == pid && h.path == target)
+            {
+                holders.push(MountHolder { pid, command: command.clone(), path: target });
+            }
+        }
+    }
+    holders
+}
+
+/// A process holding a file under a freeze target open for writing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenWriter {
+    pub pid: u32,
+    pub command: String,
+    pub path: PathBuf,
+}
+
+/// True if the `flags:` line of `/proc/<pid>/fdinfo/<fd>` has O_WRONLY or O_RDWR set.
+fn fdinfo_is_writable(fdinfo: &str) -> bool {
+    fdinfo
+        .lines()
+        .find_map(|l| l.strip_prefix("flags:"))
+        .and_then(|f| u32::from_str_radix(f.trim(), 8).ok())
+        .is_some_and(|flags| flags & 0o3 != 0)
+}
+
+/// Scans `/proc/*/fd` for files under `targets` that some process has open for writing
+/// (a snapshot of such files, e.g. a live browser profile or sqlite database, is likely torn).
+/// Processes we cannot inspect (other users without root) are silently skipped.
+pub fn find_open_writers(targets: &[PathBuf]) -> Vec<OpenWriter> {
+    let roots: Vec<PathBuf> = targets
+        .iter()
+        .map(|t| fs::canonicalize(t).unwrap_or_else(|_| t.clone()))
+        .collect();
+    let mut writers = Vec::new();
+    let Ok(proc_dir) = fs::read_dir("/proc") else {
+        return writers;
+    };
+
+    for entry in proc_dir.flatten().take(crate::constants::PROC_SCAN_LIMIT) {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let proc_path = entry.path();
+        let Ok(fds) = fs::read_dir(proc_path.join("fd")) else {
+            continue;
+        };
+        let mut command = None;
+
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            if !roots.iter().any(|r| target.starts_with(r)) {
+                continue;
+            }
+            let fdinfo = fs::read_to_string(proc_path.join("fdinfo").join(fd.file_name()))
+                .unwrap_or_default();
+            if fdinfo_is_writable(&fdinfo) && !writers.iter().any(|w: &OpenWriter| w.pid == pid && w.path == target) {
+                let command = command
+                    .get_or_insert_with(|| {
+                        fs::read_to_string(proc_path.join("comm"))
+                            .map(|s| s.trim().to_string())
+                            .unwrap_or_default()
+                    })
+                    .clone();
+                writers.push(OpenWriter { pid, command, path: target });
+            }
+        }
+    }
+    writers
+}
+
+/// Final stable stdout line naming the written archive (`ARCHIVE: <path>`), so scripts
+/// can learn auto-generated filenames without parsing human-readable messages.
+pub fn archive_result_line(path: &Path) -> String {
+    format!("ARCHIVE: {}", path.display())
+}
+
+/// [`crate::units::parse_size`] for callers that report [`ZkError`]s.
+pub fn parse_size(raw: &str) -> Result<u64, ZkError> {
+    crate::units::parse_size(raw).map_err(ZkError::OperationFailed)
+}
+
+/// [`crate::units::parse_duration`] for callers that report [`ZkError`]s.
+pub fn parse_duration(raw: &str) -> Result<u64, ZkError> {
+    crate::units::parse_duration(raw).map_err(ZkError::OperationFailed)
+}
+
+/// Human-readable binary size: `512 B`, `1.5 KiB`, `12.3 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Rough human-readable age: `just now`, `12 minutes`, `5 hours`, `3 days`.
+pub fn format_age(seconds: u64) -> String {
+    let plural = |n: u64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => plural(seconds / 60, "minute"),
+        3600..86400 => plural(seconds / 3600, "hour"),
+        _ => plural(seconds / 86400, "day"),
+    }
+}
+
+/// Resolves the packing log path: an explicit `--log-file` wins,
+/// `--debug-log` falls back to `<output>.log`, otherwise no log is written.
+pub fn resolve_log_path(log_file: Option<PathBuf>, debug_log: bool, output: &Path) -> Option<PathBuf> {
+    log_file.or_else(|| {
+        if debug_log {
+            let mut s = output.as_os_str().to_os_string();
+            s.push(".log");
+            Some(PathBuf::from(s))
+        } else {
+            None
+        }
+    })
+}
+
+/// Short, stable (across builds) hex hash of a path, used to tell apart
+/// LUKS mappers for identically named images in different directories.
+/// FNV-1a: `DefaultHasher` is not guaranteed to be stable between Rust releases.
+pub fn short_path_hash(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in path.as_os_str().as_bytes() {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:08x}", (hash >> 32) as u32 ^ hash as u32)
+}
+
+/// Directory holding mapper name reservations: `<0k-cache>/mappers`.
+pub fn get_mapper_registry_dir() -> Result<PathBuf, ZkError> {
+    let dir = get_0k_temp_dir(None)?.join("mappers");
+    match fs::create_dir(&dir) {
+        Ok(()) => Ok(dir),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(dir),
+        Err(e) => Err(ZkError::IoError(e)),
+    }
+}
+
+/// A claimed mapper name. The reservation file stays flock'ed while this value lives
+/// and is removed on drop; a leftover file from a crashed process is simply re-locked.
+#[derive(Debug)]
+pub struct MapperReservation {
+    pub name: String,
+    path: PathBuf,
+    _lock: crate::locks::LockGuard,
+}
+
+impl Drop for MapperReservation {
+    fn drop(&mut self) {
+        // Unlink while still holding the lock, see the inode check in reserve_mapper_name
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Tries to reserve `name` in `registry`. Returns `Ok(None)` if another process
+/// (or thread) currently holds it.
+pub fn reserve_mapper_name(registry: &Path, name: &str) -> Result<Option<MapperReservation>, ZkError> {
+    use crate::locks::{self, LockClass};
+    use std::os::unix::fs::MetadataExt;
+
+    let path = registry.join(format!("{}.lock", name));
+    loop {
+        let Some(lock) = locks::try_lock(LockClass::Mapper, &path)? else {
+            return Ok(None);
+        };
+        // The previous holder may have unlinked the file between our open() and flock();
+        // in that case we locked an orphaned inode and must start over.
+        let locked = lock.file().metadata()?;
+        match fs::metadata(&path) {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(Some(MapperReservation { name: name.to_string(), path, _lock: lock }));
+            }
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(ZkError::IoError(e)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests_expand {
     use super::*;
@@ -432,6 +1397,321 @@ mod tests_expand {
         );
     }
 
+    #[test]
+    fn test_parse_octal_mode() {
+        assert_eq!(parse_octal_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_octal_mode("0640").unwrap(), 0o640);
+        assert!(parse_octal_mode("rw").is_err());
+        assert!(parse_octal_mode("999").is_err());
+        assert!(parse_octal_mode("17777").is_err());
+    }
+
+    #[test]
+    fn test_validate_mksquashfs_args() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(validate_mksquashfs_args(&[]).is_ok());
+        assert!(validate_mksquashfs_args(&args(&["-nopad", "-noI", "-b", "1M"])).is_ok());
+        assert!(validate_mksquashfs_args(&args(&["-nopad -noI"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-nopad\n-noI"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-b\t1M"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&[""])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-log", "/tmp/out"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["backup.sqfs"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-b", "1M", "extra"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-e", "secret"])).is_err());
+        assert!(validate_mksquashfs_args(&args(&["-ef", "excludes"])).is_err());
+    }
+
+    #[test]
+    fn test_create_file_with_mode_ignores_umask() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("out.sqfs");
+        create_file_with_mode(&path, 0o640).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o640);
+        // Must not clobber an existing file
+        assert!(create_file_with_mode(&path, 0o600).is_err());
+    }
+
+    #[test]
+    fn test_create_temp_mount_point_is_private_and_unique() {
+        use std::os::unix::fs::PermissionsExt;
+        let a = create_temp_mount_point("test").unwrap();
+        let b = create_temp_mount_point("test").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a.parent().unwrap(), get_0k_temp_dir(None).unwrap());
+        assert!(a.file_name().unwrap().to_str().unwrap().starts_with("mount_test_"));
+        assert_eq!(fs::metadata(&a).unwrap().permissions().mode() & 0o777, 0o700);
+        fs::remove_dir(&a).unwrap();
+        fs::remove_dir(&b).unwrap();
+    }
+
+    #[test]
+    fn test_validate_name_template() {
+        assert!(validate_name_template(DEFAULT_NAME_TEMPLATE).is_ok());
+        assert!(validate_name_template("{host}-{date}_{rand}.v2").is_ok());
+        assert!(validate_name_template("../{rand}").is_err());
+        assert!(validate_name_template("a b").is_err());
+        assert!(validate_name_template("{user}").is_err());
+        assert!(validate_name_template("{rand").is_err());
+        assert!(validate_name_template("").is_err());
+    }
+
+    #[test]
+    fn test_descendant_pids() {
+        assert_eq!(parse_stat_ppid("4242 (0k (copy) x) S 17 4242 4242 0 -1"), Some(17));
+        assert_eq!(parse_stat_ppid("4242 (sh) S"), None);
+
+        // sh -> sleep: both are found from this process
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 5 & wait"])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let mut grandchildren = Vec::new();
+        for _ in 0..50 {
+            grandchildren = descendant_pids(pid);
+            if !grandchildren.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let found = descendant_pids(std::process::id());
+        let _ = child.kill();
+        let _ = child.wait();
+        for grandchild in &grandchildren {
+            // SAFETY: kill(2) on the sleep started above
+            unsafe { libc::kill(*grandchild as libc::pid_t, libc::SIGKILL) };
+        }
+        assert!(found.contains(&pid), "{:?}", found);
+        assert_eq!(grandchildren.len(), 1, "{:?}", grandchildren);
+        assert!(found.contains(&grandchildren[0]), "{:?}", found);
+    }
+
+    #[test]
+    fn test_staging_base_precedence() {
+        let env = |v: &str| Some(std::ffi::OsString::from(v));
+        // The flag wins over the environment, which wins over $TMPDIR (None)
+        assert_eq!(staging_base_from(Some(Path::new("/big/flag")), env("/big/env")), Some(PathBuf::from("/big/flag")));
+        assert_eq!(staging_base_from(None, env("/big/env")), Some(PathBuf::from("/big/env")));
+        assert_eq!(staging_base_from(None, env("")), None);
+        assert_eq!(staging_base_from(None, None), None);
+        // Relative to the working directory, as given on the command line
+        let relative = staging_base_from(Some(Path::new("scratch")), None).unwrap();
+        assert_eq!(relative, std::env::current_dir().unwrap().join("scratch"));
+    }
+
+    #[test]
+    fn test_temp_dir_below_base() {
+        use std::os::unix::fs::PermissionsExt;
+        let base = tempfile::tempdir().unwrap();
+        let dir = get_0k_temp_dir(Some(base.path())).unwrap();
+        assert_eq!(dir, base.path().join(format!("0k-cache-{}", get_current_uid().unwrap())));
+        assert_eq!(get_0k_temp_dir_path(Some(base.path())).unwrap(), dir);
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        // Existing: loosened permissions are fixed, a symlink is refused
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        get_0k_temp_dir(Some(base.path())).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+        let other = tempfile::tempdir().unwrap();
+        fs::remove_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(other.path(), &dir).unwrap();
+        assert!(matches!(get_0k_temp_dir(Some(base.path())), Err(ZkError::StagingError(_))));
+
+        // A base that does not exist is not created
+        let missing = base.path().join("missing");
+        assert!(matches!(get_0k_temp_dir(Some(&missing)), Err(ZkError::StagingError(_))));
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_format_utc_date() {
+        assert_eq!(format_utc_date(0), "19700101-000000");
+        assert_eq!(format_utc_date(951_782_400), "20000229-000000");
+        assert_eq!(format_utc_date(1_700_000_000), "20231114-221320");
+    }
+
+    #[test]
+    fn test_generate_unique_path_skips_existing() {
+        let temp = tempfile::tempdir().unwrap();
+        let first = generate_unique_path(temp.path(), "fixed", "", "sqfs").unwrap();
+        assert_eq!(first, temp.path().join("fixed.sqfs"));
+        fs::write(&first, b"").unwrap();
+        // Without {rand} every retry yields the same taken name
+        assert!(generate_unique_path(temp.path(), "fixed", "", "sqfs").is_err());
+
+        let a = generate_unique_path(temp.path(), DEFAULT_NAME_TEMPLATE, "nightly", "sqfs").unwrap();
+        fs::write(&a, b"").unwrap();
+        let b = generate_unique_path(temp.path(), DEFAULT_NAME_TEMPLATE, "nightly", "sqfs").unwrap();
+        assert_ne!(a, b);
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_is_busy_error() {
+        assert!(is_busy_error("umount: /mnt: target is busy."));
+        assert!(is_busy_error("fusermount: failed to unmount /mnt: Device or resource busy"));
+        assert!(!is_busy_error("umount: /mnt: not mounted."));
+    }
+
+    #[test]
+    fn test_is_bad_passphrase_error() {
+        assert!(is_bad_passphrase_error("No key available with this passphrase.\n"));
+        assert!(!is_bad_passphrase_error("Device /tmp/x.img does not exist or access denied."));
+        assert!(!is_bad_passphrase_error(""));
+    }
+
+    #[test]
+    fn test_find_mount_holders_sees_own_open_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let file_path = temp.path().join("held.txt");
+        let _held = fs::File::create(&file_path).unwrap();
+
+        let holders = find_mount_holders(temp.path());
+        let me = std::process::id();
+        assert!(
+            holders.iter().any(|h| h.pid == me && h.path == file_path),
+            "Own open file not found in {:?}",
+            holders
+        );
+    }
+
+    #[test]
+    fn test_fdinfo_is_writable() {
+        assert!(fdinfo_is_writable("pos:\t0\nflags:\t0100001\nmnt_id:\t30\n"));
+        assert!(fdinfo_is_writable("pos:\t0\nflags:\t02\n"));
+        assert!(!fdinfo_is_writable("pos:\t0\nflags:\t0100000\n"));
+        assert!(!fdinfo_is_writable(""));
+    }
+
+    #[test]
+    fn test_find_open_writers_sees_only_writable_handles() {
+        let temp = tempfile::tempdir().unwrap();
+        let written = temp.path().join("db.sqlite");
+        let read_only = temp.path().join("readme.txt");
+        let _w = fs::File::create(&written).unwrap();
+        fs::write(&read_only, b"x").unwrap();
+        let _r = fs::File::open(&read_only).unwrap();
+
+        let writers = find_open_writers(&[temp.path().to_path_buf()]);
+        let me = std::process::id();
+        assert!(writers.iter().any(|w| w.pid == me && w.path == written), "{:?}", writers);
+        assert!(!writers.iter().any(|w| w.path == read_only), "{:?}", writers);
+    }
+
+    #[test]
+    fn test_archive_result_line() {
+        assert_eq!(
+            archive_result_line(Path::new("/mnt/cold/nightly_1700000000_123456.sqfs")),
+            "ARCHIVE: /mnt/cold/nightly_1700000000_123456.sqfs"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_prefix() {
+        assert_eq!(sanitize_prefix("docs"), "docs");
+        assert_eq!(sanitize_prefix("my docs (old)!"), "my_docs_old");
+        assert_eq!(sanitize_prefix("v1.2-final"), "v1.2-final");
+        assert_eq!(sanitize_prefix("Документы 2024"), "2024");
+        assert_eq!(sanitize_prefix("café_notes"), "caf_notes");
+        assert_eq!(sanitize_prefix("📁 photos 🎉"), "photos");
+        assert_eq!(sanitize_prefix("🎉🎉"), "archive");
+        assert_eq!(sanitize_prefix(".hidden"), "hidden");
+        assert_eq!(sanitize_prefix(&"x".repeat(200)).len(), crate::constants::NAME_PREFIX_MAX_LEN);
+        // Whatever comes out is accepted in a name template
+        for raw in ["my docs (old)!", "🎉🎉", "a/b\\c", "..."] {
+            assert!(validate_name_template(&sanitize_prefix(raw)).is_ok(), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_sanitize_name_part() {
+        assert_eq!(sanitize_name_part("backup.sqfs", &[], 64), "backup_sqfs");
+        assert_eq!(sanitize_name_part("a  --  b", &['-'], 64), "a_--_b");
+        assert_eq!(sanitize_name_part("__x__", &[], 64), "x");
+        assert_eq!(sanitize_name_part("abcdef", &[], 3), "abc");
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(5), "just now");
+        assert_eq!(format_age(60), "1 minute");
+        assert_eq!(format_age(3 * 3600 + 59), "3 hours");
+        assert_eq!(format_age(10 * 86400), "10 days");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(13_207_024_435), "12.3 GiB");
+        assert_eq!(format_size(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    fn test_resolve_log_path() {
+        let out = Path::new("/backups/data.sqfs");
+        assert_eq!(resolve_log_path(None, false, out), None);
+        assert_eq!(
+            resolve_log_path(None, true, out),
+            Some(PathBuf::from("/backups/data.sqfs.log"))
+        );
+        assert_eq!(
+            resolve_log_path(Some(PathBuf::from("/tmp/x.log")), true, out),
+            Some(PathBuf::from("/tmp/x.log"))
+        );
+    }
+
+    #[test]
+    fn test_short_path_hash_is_stable_and_distinct() {
+        let a = short_path_hash(Path::new("/home/alice/data.sqfs"));
+        assert_eq!(a, short_path_hash(Path::new("/home/alice/data.sqfs")));
+        assert_ne!(a, short_path_hash(Path::new("/mnt/backup/data.sqfs")));
+        assert_eq!(a.len(), 8);
+    }
+
+    #[test]
+    fn test_reserve_mapper_name_is_exclusive_until_drop() {
+        let temp = tempfile::tempdir().unwrap();
+        let first = reserve_mapper_name(temp.path(), "zrklvdata").unwrap().unwrap();
+        assert!(reserve_mapper_name(temp.path(), "zrklvdata").unwrap().is_none());
+        assert!(reserve_mapper_name(temp.path(), "zrklvdata_2").unwrap().is_some());
+        drop(first);
+        assert!(!temp.path().join("zrklvdata.lock").exists());
+        assert!(reserve_mapper_name(temp.path(), "zrklvdata").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_count_physical_cores() {
+        let pair = |p: &str, c: &str| (p.to_string(), c.to_string());
+        // 2 packages x 2 cores, hyper-threaded
+        let cpus = [pair("0", "0"), pair("0", "1"), pair("1", "0"), pair("1", "1")];
+        assert_eq!(count_physical_cores(cpus.iter().cloned().chain(cpus.iter().cloned())), 4);
+        assert_eq!(count_physical_cores(Vec::new()), 0);
+        assert!(physical_cores() >= 1);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:        2014104 kB\nMemFree:          120344 kB\nMemAvailable:     812760 kB\nBuffers:           40960 kB\n";
+        let mem = parse_meminfo(meminfo).unwrap();
+        assert_eq!(mem, MemInfo { total: 2014104 * 1024, available: 812760 * 1024 });
+        // 2 GB VPS: a quarter of what is available
+        assert_eq!(default_mksquashfs_mem(mem), Some(812760 * 1024 / 4));
+        // Nearly nothing available: the floor
+        let starved = MemInfo { total: 1 << 30, available: 100 << 20 };
+        assert_eq!(default_mksquashfs_mem(starved), Some(64 << 20));
+        // Big machines keep the mksquashfs default
+        assert_eq!(default_mksquashfs_mem(MemInfo { total: 16 << 30, available: 8 << 30 }), None);
+
+        // Old kernels have no MemAvailable; MemTotalX is not MemTotal
+        assert_eq!(parse_meminfo("MemTotal: 2014104 kB\nMemFree: 120344 kB\n"), None);
+        assert_eq!(parse_meminfo("MemTotalX: 1 kB\nMemAvailable: 1 kB\n"), None);
+        assert_eq!(parse_meminfo(""), None);
+    }
+
     #[test]
     fn test_no_expand_absolute() {
         let path = "/tmp/file";
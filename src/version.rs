@@ -0,0 +1,149 @@
+//! Machine-readable version and capability report (`--version --json`, `version --json`).
+//!
+//! Wrapper tools use it to find out what this build and this system can do before they call
+//! into `0k` / `0k-core`. The probes are quick and side-effect free: PATH lookups, reads of
+//! world-readable files under `/dev`, `/proc`, `/sys` and `/boot` (and `zcat /proc/config.gz`).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// External tools the binaries may call, reported as present or missing on PATH.
+const PROBED_TOOLS: [&str; 11] = [
+    "mksquashfs",
+    "unsquashfs",
+    "squashfuse",
+    "fusermount",
+    "tar2sqfs",
+    "cryptsetup",
+    "rsync",
+    "restorecon",
+    "lsof",
+    "rclone",
+    "age",
+];
+
+/// Cargo features of this package, with whether this build has them enabled.
+const FEATURES: [(&str, bool); 4] = [
+    ("testing", cfg!(feature = "testing")),
+    ("integration-tests", cfg!(feature = "integration-tests")),
+    ("ffi", cfg!(feature = "ffi")),
+    ("mmap-compare", cfg!(feature = "mmap-compare")),
+];
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct VersionReport {
+    pub name: String,
+    pub version: String,
+    /// Commit the binary was built from (absent outside a git checkout)
+    pub git_hash: Option<String>,
+    /// Enabled cargo features
+    pub features: Vec<String>,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Capabilities {
+    /// Tool name -> found on PATH
+    pub tools: BTreeMap<String, bool>,
+    /// /dev/fuse exists (squashfuse mounts without root)
+    pub fuse_device: bool,
+    /// Unprivileged user namespaces are enabled (unshare without root)
+    pub unprivileged_userns: bool,
+    /// The kernel can mount zstd SquashFS (LUKS containers); null if its config is unreadable
+    pub kernel_squashfs_zstd: Option<bool>,
+    pub selinux_enforcing: bool,
+    pub running_as_root: bool,
+    /// /proc is mounted; without it squashfuse mounts cannot be found and the mount table
+    /// is read from /etc/mtab
+    pub proc_mounted: bool,
+    /// `cryptsetup --version`; absent if cryptsetup is missing or its version unreadable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cryptsetup: Option<Cryptsetup>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Cryptsetup {
+    pub version: String,
+    /// Optional luksFormat flags this version takes (see [`crate::luks::OPTIONAL_FLAGS`])
+    pub optional_flags: Vec<String>,
+}
+
+/// Builds the report for the binary `name` (`0k` or `0k-core`).
+pub fn report(name: &str) -> VersionReport {
+    VersionReport {
+        name: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: option_env!("ZK_GIT_HASH").map(String::from),
+        features: FEATURES.iter().filter(|(_, on)| *on).map(|(f, _)| f.to_string()).collect(),
+        capabilities: Capabilities {
+            tools: PROBED_TOOLS.iter().map(|t| (t.to_string(), which::which(t).is_ok())).collect(),
+            fuse_device: Path::new("/dev/fuse").exists(),
+            unprivileged_userns: unprivileged_userns(),
+            kernel_squashfs_zstd: crate::kernel_squashfs::probe(&crate::executor::RealSystem),
+            selinux_enforcing: crate::utils::selinux_enforcing(),
+            running_as_root: crate::utils::is_root().unwrap_or(false),
+            proc_mounted: crate::utils::proc_mounted(),
+            cryptsetup: crate::luks::probe(&crate::executor::RealSystem).map(|v| Cryptsetup {
+                version: v.to_string(),
+                optional_flags: crate::luks::enabled_flags(Some(v)),
+            }),
+        },
+    }
+}
+
+/// Prints the report as one JSON line on stdout.
+pub fn print_json(name: &str) -> Result<(), crate::error::ZkError> {
+    crate::report::print_json(&report(name), "version report")
+}
+
+/// True for a top-level `--version --json` (or `-V --json`, in either order).
+pub fn is_json_version_request(args: &[String]) -> bool {
+    let rest = args.get(1..).unwrap_or_default();
+    rest.len() == 2 && rest.iter().any(|a| a == "--json") && rest.iter().any(|a| a == "--version" || a == "-V")
+}
+
+/// Both knobs distributions use to switch user namespaces off must allow them
+/// (a missing file means the kernel has no such switch).
+fn unprivileged_userns() -> bool {
+    let enabled = |path: &str| fs::read_to_string(path).map_or(true, |v| v.trim() != "0");
+    enabled("/proc/sys/kernel/unprivileged_userns_clone") && enabled("/proc/sys/user/max_user_namespaces")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_json_shape() {
+        let value = serde_json::to_value(report("0k")).unwrap();
+        assert_eq!(value["name"], "0k");
+        assert_eq!(value["version"], env!("CARGO_PKG_VERSION"));
+        assert!(value["features"].as_array().unwrap().iter().any(|f| f == "testing") == cfg!(feature = "testing"));
+        assert!(value["capabilities"]["tools"]["mksquashfs"].is_boolean());
+        assert!(value["capabilities"]["unprivileged_userns"].is_boolean());
+    }
+
+    #[test]
+    fn test_features_match_manifest() {
+        let manifest = include_str!("../Cargo.toml");
+        let section = manifest.split("[features]").nth(1).unwrap().split("\n[").next().unwrap();
+        let declared: Vec<&str> = section
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name.trim()))
+            .collect();
+        let listed: Vec<&str> = FEATURES.iter().map(|(name, _)| *name).collect();
+        assert_eq!(listed, declared);
+    }
+
+    #[test]
+    fn test_is_json_version_request() {
+        let argv = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_json_version_request(&argv(&["0k", "--version", "--json"])));
+        assert!(is_json_version_request(&argv(&["0k", "--json", "-V"])));
+        assert!(!is_json_version_request(&argv(&["0k", "--version"])));
+        assert!(!is_json_version_request(&argv(&["0k", "check", "--json", "--version"])));
+    }
+}
@@ -132,3 +132,20 @@ teardown() {
     rmdir "$path1" 2>/dev/null || true
     rmdir "$path2" 2>/dev/null || true
 }
+
+@test "Error: Non-empty mount point is refused without --nonempty" {
+    mkdir -p "$TMP_ENV/mnt_busy"
+    echo "keep" > "$TMP_ENV/mnt_busy/existing.txt"
+
+    run $ZKS_SQM_BIN mount "$GOLDEN_ARCHIVE" "$TMP_ENV/mnt_busy"
+    [ "$status" -ne 0 ]
+    [[ "$output" == *"existing.txt"* ]]
+    [[ "$output" == *"--nonempty"* ]]
+
+    run $ZKS_SQM_BIN mount "$GOLDEN_ARCHIVE" "$TMP_ENV/mnt_busy" --nonempty
+    [ "$status" -eq 0 ]
+    [ ! -e "$TMP_ENV/mnt_busy/existing.txt" ]
+
+    $ZKS_SQM_BIN umount "$TMP_ENV/mnt_busy"
+    [ -f "$TMP_ENV/mnt_busy/existing.txt" ]
+}
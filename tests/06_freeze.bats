@@ -59,6 +59,14 @@ teardown() {
     assert_output --partial "Squashfs filesystem"
 }
 
+@test "Freeze: Last stdout line names the auto-generated archive" {
+    local last_line
+    last_line=$($ZKS_BIN freeze "$SRC" "$TEST_DIR" --prefix scripted 2>/dev/null | tail -n 1)
+
+    [[ "$last_line" == "ARCHIVE: $TEST_DIR/scripted_"*.sqfs ]]
+    [ -f "${last_line#ARCHIVE: }" ]
+}
+
 @test "Freeze: Auto-generate name with interactive prefix (via stdin)" {
     # Pipe prefix via stdin to simulate interactive input
     run bash -c "echo 'interactive_test' | \"$ZKS_BIN\" freeze \"$SRC\" \"$TEST_DIR\""
@@ -265,3 +273,54 @@ teardown() {
     # "customprefix" should NOT appear inside the archive
     refute_output --partial "customprefix"
 }
+
+@test "Freeze: --skip-unreadable leaves out unreadable files and records them" {
+    if ! command -v unsquashfs >/dev/null; then
+        skip "unsquashfs not found"
+    fi
+    if [ "$(id -u)" -eq 0 ]; then
+        skip "root can read everything"
+    fi
+
+    echo "secret" > "$SRC/secret.txt"
+    chmod 000 "$SRC/secret.txt"
+    OUT="$TEST_DIR/skip.sqfs"
+
+    run $ZKS_BIN freeze "$SRC" "$OUT" --skip-unreadable
+    chmod 600 "$SRC/secret.txt"
+    assert_success
+    assert_output --partial "Skipping 1 unreadable path(s)"
+
+    run unsquashfs -l "$OUT"
+    refute_output --partial "secret.txt"
+
+    run unsquashfs -cat "$OUT" list.yaml
+    assert_output --partial "skipped_unreadable"
+    assert_output --partial "secret.txt"
+}
+
+@test "Freeze: --overwrite-files appends targets to the archive manifest" {
+    if ! command -v unsquashfs >/dev/null; then
+        skip "unsquashfs not found"
+    fi
+
+    OTHER="$TEST_DIR/other"
+    mkdir -p "$OTHER"
+    echo "more" > "$OTHER/more.txt"
+    OUT="$TEST_DIR/append.sqfs"
+
+    run $ZKS_BIN freeze "$SRC" "$OUT"
+    assert_success
+
+    run $ZKS_BIN freeze "$OTHER" "$OUT" --overwrite-files
+    assert_success
+
+    run $ZKS_BIN check "$OUT"
+    assert_success
+    assert_output --regexp "Indexed paths +2"
+
+    # Appending the same target again would restore to the same place
+    run $ZKS_BIN freeze "$SRC" "$OUT" --overwrite-files
+    assert_failure
+    assert_output --partial "already has it"
+}
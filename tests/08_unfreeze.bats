@@ -112,3 +112,31 @@ teardown() {
     # Verify missing file restored
     [ -f "$SRC/dir/file2.txt" ]
 }
+
+@test "Unfreeze: Top-level directory mtime round-trips" {
+    touch -d "2020-01-02 03:04:05" "$SRC/dir"
+    ORIG_MTIME=$(stat -c %Y "$SRC/dir")
+
+    run $ZKS_BIN freeze "$SRC/dir" "$TEST_DIR/archive.sqfs" --no-progress
+    assert_success
+    rm -rf "$SRC/dir"
+
+    run $ZKS_BIN unfreeze "$TEST_DIR/archive.sqfs"
+    assert_success
+    run stat -c %Y "$SRC/dir"
+    assert_output "$ORIG_MTIME"
+}
+
+@test "Unfreeze: --no-times keeps restored-now timestamps" {
+    touch -d "2020-01-02 03:04:05" "$SRC/dir"
+    ORIG_MTIME=$(stat -c %Y "$SRC/dir")
+
+    run $ZKS_BIN freeze "$SRC/dir" "$TEST_DIR/archive.sqfs" --no-progress
+    assert_success
+    rm -rf "$SRC/dir"
+
+    run $ZKS_BIN unfreeze "$TEST_DIR/archive.sqfs" --no-times
+    assert_success
+    run stat -c %Y "$SRC/dir"
+    refute_output "$ORIG_MTIME"
+}
@@ -32,7 +32,7 @@ teardown() {
     run 0k check "$ARCHIVE"
     assert_success
     assert_output --partial "MATCH"
-    assert_output --partial "Matched:"
+    assert_output --partial "Files matched"
     assert_output --partial "src/file1.txt"
     assert_output --partial "src/dir/file2.txt"
 }
@@ -96,3 +96,29 @@ teardown() {
     assert_output --partial "DELETED"
     assert [ ! -f "$SRC/file1.txt" ]
 }
+
+@test "Check: Quick mode compares recorded size/mtime" {
+    FILE_ARCHIVE="$TEST_DIR/files.sqfs"
+    run 0k freeze "$SRC/file1.txt" "$SRC/dir/file2.txt" "$FILE_ARCHIVE" --no-progress
+    assert_success
+
+    run 0k check "$FILE_ARCHIVE" --quick
+    assert_success
+    assert_output --partial "MATCH: $SRC/file1.txt"
+
+    echo "longer content" > "$SRC/file1.txt"
+    rm "$SRC/dir/file2.txt"
+
+    run 0k check "$FILE_ARCHIVE" --quick
+    assert_success
+    assert_output --partial "LIKELY-CHANGED"
+    assert_output --partial "MISSING: $SRC/dir/file2.txt"
+}
+
+@test "Check: Quick mode rejects --use-cmp and --delete" {
+    run 0k check "$ARCHIVE" --quick --use-cmp
+    assert_failure
+
+    run 0k check "$ARCHIVE" --quick --delete
+    assert_failure
+}
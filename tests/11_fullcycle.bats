@@ -76,7 +76,7 @@ teardown() {
     # 5. Final Integrity Check
     run "$ZKS_BIN" check "$ARCHIVE_PATH" --use-cmp
     assert_success
-    assert_output --partial "Files Matched: 2"
-    assert_output --partial "Mismatched: 0"
-    assert_output --partial "Missing: 0"
+    assert_output --regexp "Files matched +2"
+    assert_output --regexp "Mismatched +0"
+    assert_output --regexp "Missing +0"
 }
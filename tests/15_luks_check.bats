@@ -55,7 +55,7 @@ teardown() {
     run bash -c "printf 'testpass\n' | ${ROOT_CMD:-} \"$ZKS_BIN\" check \"$ARCHIVE\""
     assert_success
     assert_output --partial "MATCH"
-    assert_output --partial "Matched:"
+    assert_output --partial "Files matched"
     assert_output --partial "src/file1.txt"
     assert_output --partial "src/dir/file2.txt"
 }
@@ -87,7 +87,7 @@ teardown() {
     # 5. Final Integrity Check
     run bash -c "printf 'testpass\n' | ${ROOT_CMD:-} \"$ZKS_BIN\" check \"$ARCHIVE_PATH\" --use-cmp"
     assert_success
-    assert_output --partial "Files Matched: 2"
-    assert_output --partial "Mismatched: 0"
-    assert_output --partial "Missing: 0"
+    assert_output --regexp "Files matched +2"
+    assert_output --regexp "Mismatched +0"
+    assert_output --regexp "Missing +0"
 }
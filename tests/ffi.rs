@@ -0,0 +1,49 @@
+//! The C ABI (src/ffi.rs) driven from Python through ctypes: tests/ffi/zk_ctypes.py.
+//!
+//! Opt-in: `cargo test --features ffi --test ffi`. Needs python3; without it the test
+//! prints so and passes without doing anything.
+#![cfg(feature = "ffi")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Builds the cdylib the way the header says to (it is not part of the default build), in a
+/// target directory of its own: this test's cargo still holds the lock on the main one.
+fn build_library() -> PathBuf {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cdylib");
+    let status = Command::new(env!("CARGO"))
+        .args(["rustc", "--lib", "--features", "ffi", "--crate-type", "cdylib", "--target-dir"])
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "cargo rustc --crate-type cdylib failed");
+    target_dir.join("debug/libzero_kelvin.so")
+}
+
+#[test]
+fn ctypes_example() {
+    let Ok(python) = which::which("python3") else {
+        eprintln!("Skipping the ctypes example: python3 is not installed");
+        return;
+    };
+    let library = build_library();
+    assert!(library.exists(), "{} was not built", library.display());
+
+    // The library shells out to `0k-core`: put the freshly built one first on PATH
+    let core = PathBuf::from(env!("CARGO_BIN_EXE_0k-core"));
+    let mut dirs = vec![core.parent().unwrap().to_path_buf()];
+    dirs.extend(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()));
+
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/ffi/zk_ctypes.py");
+    let output = Command::new(python)
+        .arg(&script)
+        .arg(&library)
+        .env("PATH", std::env::join_paths(dirs).unwrap())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "ctypes example failed:\n{}\n{}", stdout, stderr);
+    assert!(stdout.trim_end().ends_with("ok"), "{}", stdout);
+}
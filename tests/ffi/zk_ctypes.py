@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Calls libzero_kelvin.so through ctypes, the way a Python service would.
+
+Usage: zk_ctypes.py /path/to/libzero_kelvin.so
+
+Checks the ABI of src/ffi.rs: return codes, report ownership and zk_last_error_message.
+With mksquashfs, squashfuse, fusermount, rsync and unshare installed (and /dev/fuse), it
+also freezes, checks and unfreezes a small tree; otherwise that part is skipped.
+"""
+
+import ctypes
+import json
+import os
+import shutil
+import sys
+import tempfile
+
+ZK_OK = 0
+ZK_ERR_INVALID_ARGUMENT = 1
+
+
+def load(path):
+    lib = ctypes.CDLL(path)
+    for name in ("zk_freeze", "zk_check", "zk_unfreeze"):
+        fn = getattr(lib, name)
+        # The report is a c_void_p, not c_char_p: ctypes must not copy it and drop the pointer
+        fn.argtypes = [ctypes.c_char_p, ctypes.POINTER(ctypes.c_void_p)]
+        fn.restype = ctypes.c_int
+    lib.zk_free_report.argtypes = [ctypes.c_void_p]
+    lib.zk_free_report.restype = None
+    lib.zk_last_error_message.argtypes = []
+    lib.zk_last_error_message.restype = ctypes.c_char_p
+    return lib
+
+
+def call(lib, name, options):
+    """Returns (code, report dict or None, error message or None)."""
+    raw = options if isinstance(options, bytes) else json.dumps(options).encode()
+    report = ctypes.c_void_p()
+    code = getattr(lib, name)(raw, ctypes.byref(report))
+    if report.value is None:
+        parsed = None
+    else:
+        try:
+            parsed = json.loads(ctypes.string_at(report.value).decode())
+        finally:
+            lib.zk_free_report(report)
+    error = lib.zk_last_error_message()
+    return code, parsed, error.decode() if error is not None else None
+
+
+def check_errors(lib):
+    code, report, error = call(lib, "zk_check", b"{not json")
+    assert code == ZK_ERR_INVALID_ARGUMENT and report is None, (code, report)
+    assert error.startswith("Invalid options"), error
+
+    code, _, error = call(lib, "zk_unfreeze", {"archive": "/nonexistent.sqfs", "overwirte": True})
+    assert code == ZK_ERR_INVALID_ARGUMENT and "overwirte" in error, (code, error)
+
+    code, report, error = call(lib, "zk_check", {"archive": "/nonexistent/zk-ffi.sqfs"})
+    assert code not in (ZK_OK, ZK_ERR_INVALID_ARGUMENT) and report is None, (code, report)
+    assert error, "a failed call must leave an error message"
+
+    lib.zk_free_report(None)
+
+
+def round_trip(lib):
+    tools = ["mksquashfs", "squashfuse", "fusermount", "rsync", "unshare"]
+    missing = [t for t in tools if shutil.which(t) is None]
+    if not os.path.exists("/dev/fuse"):
+        missing.append("/dev/fuse")
+    if missing:
+        print("round trip skipped, missing: " + ", ".join(missing))
+        return
+
+    with tempfile.TemporaryDirectory() as tmp:
+        data = os.path.join(tmp, "data")
+        os.makedirs(os.path.join(data, "sub"))
+        with open(os.path.join(data, "sub", "a b.txt"), "w") as f:
+            f.write("hello")
+        archive = os.path.join(tmp, "data.sqfs")
+
+        code, report, error = call(lib, "zk_freeze", {"targets": [data], "output": archive, "yes": True})
+        assert code == ZK_OK, error
+        assert report["operation"] == "freeze" and report["entries"] == 1, report
+        assert lib.zk_last_error_message() is None
+
+        code, report, error = call(lib, "zk_check", {"archive": archive})
+        assert code == ZK_OK, error
+        assert report["operation"] == "check" and report["files_matched"] == 1, report
+
+        restored = os.path.join(tmp, "restored")
+        code, report, error = call(lib, "zk_unfreeze", {"archive": archive, "target_dir": restored})
+        assert code == ZK_OK, error
+        assert report["operation"] == "unfreeze" and report["restored"] == 1, report
+        with open(restored + os.path.join(data, "sub", "a b.txt")) as f:
+            assert f.read() == "hello"
+
+
+def main():
+    lib = load(sys.argv[1])
+    check_errors(lib)
+    round_trip(lib)
+    print("ok")
+
+
+if __name__ == "__main__":
+    main()
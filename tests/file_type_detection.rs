@@ -43,3 +43,15 @@ fn test_reproduce_misleading_extension() {
         Err(e) => panic!("Failed to detect type of real archive: {}", e),
     }
 }
+
+#[test]
+fn test_squashfs_magic() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let image = temp_dir.path().join("archive.bin");
+    fs::write(&image, b"hsqs\x05\x00\x00\x00").unwrap();
+    assert!(matches!(utils::get_file_type(&image), Ok(utils::ArchiveType::Squashfs)));
+
+    // Too short to hold the magic
+    fs::write(&image, b"hs").unwrap();
+    assert!(!matches!(utils::get_file_type(&image), Ok(utils::ArchiveType::Squashfs)));
+}
@@ -0,0 +1,111 @@
+//! `0k-core create` stopped by SIGTERM (systemd, `timeout`) in the middle of packing cleans
+//! up as on Ctrl+C: no partial archive, no `sq_*` mapper, no loop device left behind.
+//!
+//! Opt-in: `cargo test --features integration-tests --test interrupt`.
+//! Needs mksquashfs; the encrypted case also needs root and cryptsetup. Without them the
+//! tests print what is missing and pass without doing anything.
+#![cfg(feature = "integration-tests")]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Incompressible data, enough for mksquashfs to be still packing when the signal comes.
+fn write_input(root: &Path) {
+    fs::create_dir_all(root).unwrap();
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    for i in 0..16 {
+        let mut data = Vec::with_capacity(8 << 20);
+        while data.len() < 8 << 20 {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            data.extend_from_slice(&state.to_le_bytes());
+        }
+        fs::write(root.join(format!("blob_{}", i)), data).unwrap();
+    }
+}
+
+fn sq_mappers() -> Vec<PathBuf> {
+    let mut mappers: Vec<PathBuf> = fs::read_dir("/dev/mapper")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("sq_"))
+        .map(|e| e.path())
+        .collect();
+    mappers.sort();
+    mappers
+}
+
+fn wait_until(what: &str, timeout: Duration, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + timeout;
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Sends SIGTERM to `child` and waits for it: it must exit with 130 like on Ctrl+C.
+fn terminate(mut child: Child) {
+    // SAFETY: kill(2) on the child started by the test
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(130), "{:?}", status);
+}
+
+fn create(input: &Path, output: &Path, encrypt: bool) -> Child {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_0k-core"));
+    command.arg("create").arg(input).arg(output).args(["--no-progress", "-c", "19"]);
+    if encrypt {
+        command.arg("-e").stdin(Stdio::piped());
+    }
+    let mut child = command.spawn().unwrap();
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(b"testpassword\ntestpassword\ntestpassword\n").unwrap();
+    }
+    child
+}
+
+#[test]
+fn test_sigterm_removes_partial_plain_archive() {
+    if which::which("mksquashfs").is_err() {
+        eprintln!("skipping SIGTERM test: missing mksquashfs");
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("out.sqfs");
+    write_input(&input);
+
+    let child = create(&input, &output, false);
+    wait_until("mksquashfs to start writing", Duration::from_secs(30), || output.exists());
+    terminate(child);
+    assert!(!output.exists(), "the partial archive must be removed");
+}
+
+#[test]
+fn test_sigterm_closes_mapper_of_partial_container() {
+    let missing: Vec<&str> = ["mksquashfs", "cryptsetup"].into_iter().filter(|t| which::which(t).is_err()).collect();
+    // SAFETY: geteuid cannot fail and has no preconditions
+    if !missing.is_empty() || unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping encrypted SIGTERM test: needs root and {}", missing.join(", "));
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("out.sqfs");
+    write_input(&input);
+
+    let before = sq_mappers();
+    let child = create(&input, &output, true);
+    wait_until("the LUKS mapper to open", Duration::from_secs(60), || sq_mappers().len() > before.len());
+    terminate(child);
+    assert_eq!(sq_mappers(), before, "the sq_* mapper must be closed");
+    assert!(!output.exists(), "the partial container must be removed");
+    let loops = Command::new("losetup").arg("-j").arg(&output).output().unwrap();
+    assert!(loops.stdout.is_empty(), "{}", String::from_utf8_lossy(&loops.stdout));
+}
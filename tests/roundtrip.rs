@@ -0,0 +1,227 @@
+//! End-to-end freeze/check/unfreeze cycles with the real tools (no mocks).
+//!
+//! Opt-in: `cargo test --features integration-tests --test roundtrip`.
+//! Needs mksquashfs, unsquashfs, squashfuse and fusermount (and /dev/fuse); without them
+//! the tests print what is missing and pass without doing anything.
+#![cfg(feature = "integration-tests")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
+use zero_kelvin::engine::{self, CheckOptions, FreezeOptions, ProgressMode, UnfreezeOptions};
+use zero_kelvin::executor::RealSystem;
+
+const REQUIRED_TOOLS: [&str; 4] = ["mksquashfs", "unsquashfs", "squashfuse", "fusermount"];
+
+/// The tests change PATH and XDG_DATA_HOME: they run one at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Names of the required tools that are not installed (plus /dev/fuse if it is missing).
+fn missing_tools() -> Vec<String> {
+    let mut missing: Vec<String> = REQUIRED_TOOLS
+        .iter()
+        .filter(|tool| which::which(tool).is_err())
+        .map(|tool| tool.to_string())
+        .collect();
+    if !Path::new("/dev/fuse").exists() {
+        missing.push("/dev/fuse".to_string());
+    }
+    missing
+}
+
+/// The library shells out to `0k-core`: put the freshly built one first on PATH.
+fn use_built_core() -> PathBuf {
+    let core = PathBuf::from(env!("CARGO_BIN_EXE_0k-core"));
+    let bin_dir = core.parent().unwrap().to_path_buf();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut dirs = vec![bin_dir];
+    dirs.extend(std::env::split_paths(&path));
+    // SAFETY: callers hold SERIAL; nothing else reads the environment concurrently
+    unsafe { std::env::set_var("PATH", std::env::join_paths(dirs).unwrap()) };
+    core
+}
+
+fn write_fixture(root: &Path) {
+    fs::create_dir_all(root.join("sub/empty")).unwrap();
+    fs::write(root.join("a.txt"), "alpha\n").unwrap();
+    fs::write(root.join("sub/b.txt"), "bravo\n").unwrap();
+    fs::write(root.join("sub/with space.txt"), "charlie\n").unwrap();
+    std::os::unix::fs::symlink("a.txt", root.join("link")).unwrap();
+}
+
+fn assert_fixture(root: &Path) {
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "alpha\n");
+    assert_eq!(fs::read_to_string(root.join("sub/b.txt")).unwrap(), "bravo\n");
+    assert_eq!(fs::read_to_string(root.join("sub/with space.txt")).unwrap(), "charlie\n");
+    assert!(root.join("sub/empty").is_dir());
+    assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("a.txt"));
+}
+
+fn check_options(delete: bool) -> CheckOptions {
+    CheckOptions {
+        use_cmp: true,
+        delete,
+        force_delete: false,
+        prune_dirs: false,
+        show_extra: false,
+        exclude: Vec::new(),
+        report: None,
+        quick: false,
+        checksums: false,
+        no_manifest_target: None,
+        only_targets: vec![],
+        remap: vec![],
+        cancel: None,
+        progress: None,
+    }
+}
+
+/// Skips (returns None) without the tools; otherwise serializes the test, puts the built
+/// `0k-core` on PATH and keeps the archive catalog inside the returned work directory.
+fn setup(test: &str) -> Option<(MutexGuard<'static, ()>, PathBuf, tempfile::TempDir)> {
+    let missing = missing_tools();
+    if !missing.is_empty() {
+        eprintln!("skipping {test}: missing {}", missing.join(", "));
+        return None;
+    }
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let core = use_built_core();
+    let work = tempfile::tempdir().unwrap();
+    // Keep the freeze out of the user's archive catalog
+    // SAFETY: as in use_built_core
+    unsafe { std::env::set_var("XDG_DATA_HOME", work.path().join("share")) };
+    Some((guard, core, work))
+}
+
+fn freeze_options(output: &Path, checksums: bool) -> FreezeOptions {
+    FreezeOptions {
+        encrypt: false,
+        output: output.to_path_buf(),
+        overwrite_files: false,
+        overwrite_luks_content: false,
+        progress_mode: ProgressMode::None,
+        compression: None,
+        dereference: false,
+        dereference_targets: vec![],
+        log_file: None,
+        keep_log: false,
+        keep_staging: false,
+        staging_dir: None,
+        on_staging: None,
+        mode: None,
+        check_open_files: false,
+        allow_open_files: false,
+        skip_unreadable: false,
+        no_xattrs: false,
+        no_space_check: false,
+        reserve: None,
+        yes: true,
+        mksquashfs_args: vec![],
+        exclude: vec![],
+        no_ignore_files: false,
+        mem: None,
+        threads: None,
+        checksums,
+        auto_fallback_compression: false,
+        skip_broken_symlinks: false,
+        no_recovery: false,
+        integrity_token: false,
+        preserve_order: false,
+        cancel: None,
+        progress: None,
+    }
+}
+
+fn unfreeze_options() -> UnfreezeOptions {
+    UnfreezeOptions {
+        overwrite: false,
+        skip_existing: false,
+        force_unfreeze: false,
+        verify: true,
+        follow_dest_symlinks: false,
+        no_times: false,
+        no_xattrs: false,
+        no_restorecon: false,
+        parent_mode: None,
+        no_manifest_target: None,
+        resume: false,
+        restart: false,
+        allow_fs_change: false,
+        target_dir: None,
+        only: vec![],
+        dry_run: false,
+        no_progress: true,
+        remap: vec![],
+        cancel: None,
+        progress: None,
+    }
+}
+
+#[test]
+fn test_plain_roundtrip() {
+    let Some((_serial, core, work)) = setup("round-trip test") else {
+        return;
+    };
+
+    let data = work.path().join("data");
+    write_fixture(&data);
+    let archive = work.path().join("data.sqfs");
+
+    // 1. Freeze
+    engine::freeze(std::slice::from_ref(&data), &freeze_options(&archive, false), &RealSystem).unwrap();
+    assert!(archive.is_file(), "archive was not created");
+
+    // 2. Mount: the payload layout is list.yaml + to_restore/<id>/<name>
+    let mount_point = work.path().join("mnt");
+    let status = Command::new(&core).arg("mount").arg(&archive).arg(&mount_point).status().unwrap();
+    assert!(status.success(), "0k-core mount failed");
+    let layout = (
+        mount_point.join("list.yaml").is_file(),
+        mount_point.join("to_restore/1/data/a.txt").is_file(),
+    );
+    let status = Command::new(&core).arg("umount").arg(&mount_point).status().unwrap();
+    assert!(status.success(), "0k-core umount failed");
+    assert_eq!(layout, (true, true), "unexpected archive layout");
+
+    // 3. Check against the untouched originals
+    engine::check(&archive, &check_options(false), &RealSystem).unwrap();
+
+    // 4. Unfreeze after the originals are gone
+    fs::remove_dir_all(&data).unwrap();
+    engine::unfreeze(&archive, &unfreeze_options(), &RealSystem).unwrap();
+    assert_fixture(&data);
+
+    // 5. check --delete removes what the archive holds
+    engine::check(&archive, &check_options(true), &RealSystem).unwrap();
+    assert!(!data.join("a.txt").exists());
+    assert!(!data.join("sub/b.txt").exists());
+    assert!(archive.is_file(), "check --delete must not touch the archive");
+}
+
+#[test]
+fn test_checksums_roundtrip() {
+    let Some((_serial, _core, work)) = setup("checksums round-trip test") else {
+        return;
+    };
+
+    let data = work.path().join("data");
+    write_fixture(&data);
+    let archive = work.path().join("data.sqfs");
+
+    // 1. Freeze with --checksums
+    engine::freeze(std::slice::from_ref(&data), &freeze_options(&archive, true), &RealSystem).unwrap();
+    assert!(archive.is_file(), "archive was not created");
+
+    // 2. check --checksums judges the originals by the recorded digests
+    let by_digest = CheckOptions { use_cmp: false, checksums: true, ..check_options(false) };
+    engine::check(&archive, &by_digest, &RealSystem).unwrap();
+
+    // 3. Unfreeze after the originals are gone (--verify checks the restored files' digests)
+    fs::remove_dir_all(&data).unwrap();
+    engine::unfreeze(&archive, &unfreeze_options(), &RealSystem).unwrap();
+    assert_fixture(&data);
+
+    // 4. The restored files still match the digests
+    engine::check(&archive, &by_digest, &RealSystem).unwrap();
+}